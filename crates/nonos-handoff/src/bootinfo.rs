@@ -0,0 +1,262 @@
+//! Secondary boot records that travel next to `BootHandoffV1`.
+//!
+//! - `ZeroStateBootInfo`: capsule-level record built while the capsule is
+//!   parsed (commitment hash, entropy, RTC). It never leaves loader memory on
+//!   its own; the kernel receives the derived fields through the handoff.
+//! - `BootInfoPage`: compact page the loader may publish and whose physical
+//!   address it stores in `BootHandoffV1.reserved0`. It holds a `BootInfoV1`
+//!   summary of the boot, signed with a per-boot Ed25519 key.
+//!
+//! Signing: the loader derives a fresh key from boot entropy, extends the
+//! public key into `PCR_BOOTINFO_KEY` before ExitBootServices, and signs
+//! `SHA-256(signing_input(&info))` after the handoff is final. The secret is
+//! dropped before the kernel runs, so nothing after the loader can produce a
+//! valid signature. A verifier checks the signature with `pubkey`, then
+//! checks `pubkey` against the TPM quote / event log for that PCR.
+//!
+//! `handoff_sha256` covers the handoff header, its tag list
+//! (`handoff_size` bytes from `handoff_phys`) and then the `MemoryRegion[]`
+//! the header points at. Other referenced buffers are measured separately
+//! (kernel PCR 4, modules PCR 9); the boot log is diagnostic and covered by
+//! neither.
+
+use core::fmt;
+use core::mem::{offset_of, size_of};
+use core::ops::{BitOr, BitOrAssign};
+
+pub const ZERO_STATE_MAGIC: u32 = u32::from_le_bytes(*b"NZSB");
+pub const ZERO_STATE_VERSION: u16 = 1;
+
+pub const BOOTINFO_MAGIC: u32 = u32::from_le_bytes(*b"NBI1");
+pub const BOOTINFO_VERSION: u16 = 1;
+
+/// PCR the loader extends the BootInfo public key into
+pub const PCR_BOOTINFO_KEY: u32 = 12;
+/// Domain separator prefixed to the signed BootInfo bytes
+pub const BOOTINFO_SIG_DOMAIN: &[u8; 16] = b"NONOS-BOOTINFO1\0";
+pub const SIGNING_INPUT_LEN: usize = BOOTINFO_SIG_DOMAIN.len() + size_of::<BootInfoV1>();
+
+/// `BootInfoPage.sig_alg` values
+pub mod sig_alg {
+    pub const NONE: u32 = 0;
+    pub const ED25519_SHA256: u32 = 1;
+}
+
+/// `BootInfoPage.sig_flags` bits
+pub mod sig_flags {
+    /// `signature` is filled in
+    pub const SIGNED: u32 = 1 << 0;
+    /// `pubkey` was extended into `PCR_BOOTINFO_KEY`
+    pub const KEY_MEASURED: u32 = 1 << 1;
+}
+
+/// How the platform came up for this boot
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootModeFlags(u32);
+
+impl BootModeFlags {
+    pub const SECURE_BOOT: Self = Self(1 << 0);
+    pub const MEASURED_BOOT: Self = Self(1 << 1);
+    pub const COLD_START: Self = Self(1 << 2);
+    pub const WARM_RESTART: Self = Self(1 << 3);
+    pub const RECOVERY: Self = Self(1 << 4);
+
+    pub const fn empty() -> Self {
+        Self(0)
+    }
+
+    pub const fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Keeps unknown bits so newer loaders do not lose information on old kernels
+    pub const fn from_bits_retain(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl BitOr for BootModeFlags {
+    type Output = Self;
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for BootModeFlags {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct ZeroStateBootInfo {
+    pub magic: u32,
+    pub version: u16,
+    pub size: u16,
+    pub boot_flags: BootModeFlags,
+    pub reserved: u32,
+    pub capsule_base: u64,
+    pub capsule_size: u64,
+    pub memory_start: u64,
+    pub memory_size: u64,
+    /// BLAKE3 of the signed capsule manifest
+    pub capsule_hash: [u8; 32],
+    pub entropy: [u8; 64],
+    /// Packed RTC time, see `entropy::get_rtc_timestamp` in the loader
+    pub rtc_utc: [u8; 8],
+}
+
+impl fmt::Debug for ZeroStateBootInfo {
+    // entropy is seed material and stays out of logs
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ZeroStateBootInfo")
+            .field("version", &self.version)
+            .field("boot_flags", &self.boot_flags)
+            .field("capsule_base", &format_args!("{:#x}", self.capsule_base))
+            .field("capsule_size", &self.capsule_size)
+            .field("memory_start", &format_args!("{:#x}", self.memory_start))
+            .field("memory_size", &self.memory_size)
+            .field("capsule_hash", &self.capsule_hash)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Inputs to `build_bootinfo`
+#[derive(Clone, Copy)]
+pub struct BootInfoParams {
+    pub capsule_base: u64,
+    pub capsule_size: u64,
+    pub capsule_hash: [u8; 32],
+    pub memory_start: u64,
+    pub memory_size: u64,
+    pub entropy64: [u8; 64],
+    pub rtc_utc: [u8; 8],
+    pub boot_flags: BootModeFlags,
+}
+
+pub fn build_bootinfo(p: BootInfoParams) -> ZeroStateBootInfo {
+    ZeroStateBootInfo {
+        magic: ZERO_STATE_MAGIC,
+        version: ZERO_STATE_VERSION,
+        size: size_of::<ZeroStateBootInfo>() as u16,
+        boot_flags: p.boot_flags,
+        reserved: 0,
+        capsule_base: p.capsule_base,
+        capsule_size: p.capsule_size,
+        memory_start: p.memory_start,
+        memory_size: p.memory_size,
+        capsule_hash: p.capsule_hash,
+        entropy: p.entropy64,
+        rtc_utc: p.rtc_utc,
+    }
+}
+
+/// Signed summary of the boot, first member of `BootInfoPage`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootInfoV1 {
+    pub magic: u32,
+    pub version: u16,
+    pub size: u16,
+    pub flags: u32,
+    pub reserved: u32,
+    pub kernel_phys: u64,
+    pub kernel_size: u64,
+    pub kernel_entry: u64,
+    /// Milliseconds since the Unix epoch, 0 if the RTC was unavailable
+    pub timestamp: u64,
+    /// SHA-256 of the loaded kernel payload (same value as `meas.kernel_sha256`)
+    pub capsule_payload_hash: [u8; 32],
+    pub handoff_phys: u64,
+    pub handoff_size: u64,
+    pub handoff_sha256: [u8; 32],
+}
+
+impl BootInfoV1 {
+    pub fn empty() -> Self {
+        BootInfoV1 {
+            magic: BOOTINFO_MAGIC,
+            version: BOOTINFO_VERSION,
+            size: size_of::<Self>() as u16,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_valid(&self) -> bool {
+        self.magic == BOOTINFO_MAGIC && self.version == BOOTINFO_VERSION && self.size as usize == size_of::<Self>()
+    }
+}
+
+/// Bytes whose SHA-256 is signed: `BOOTINFO_SIG_DOMAIN` then the raw `BootInfoV1`
+pub fn signing_input(info: &BootInfoV1) -> [u8; SIGNING_INPUT_LEN] {
+    let mut out = [0u8; SIGNING_INPUT_LEN];
+    out[..BOOTINFO_SIG_DOMAIN.len()].copy_from_slice(BOOTINFO_SIG_DOMAIN);
+    // safe: BootInfoV1 is repr(C) without implicit padding (asserted below)
+    unsafe {
+        core::ptr::write_unaligned(out.as_mut_ptr().add(BOOTINFO_SIG_DOMAIN.len()) as *mut BootInfoV1, *info);
+    }
+    out
+}
+
+/// The page at `BootHandoffV1.reserved0`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BootInfoPage {
+    pub info: BootInfoV1,
+    pub sig_alg: u32,
+    pub sig_flags: u32,
+    pub pubkey: [u8; 32],
+    pub signature: [u8; 64],
+}
+
+impl BootInfoPage {
+    pub fn is_signed(&self) -> bool {
+        self.sig_alg == sig_alg::ED25519_SHA256 && self.sig_flags & sig_flags::SIGNED != 0
+    }
+}
+
+const _: () = {
+    assert!(size_of::<BootModeFlags>() == 4);
+    assert!(offset_of!(ZeroStateBootInfo, capsule_base) == 16);
+    assert!(offset_of!(ZeroStateBootInfo, capsule_hash) == 48);
+    assert!(offset_of!(ZeroStateBootInfo, entropy) == 80);
+    assert!(offset_of!(ZeroStateBootInfo, rtc_utc) == 144);
+    assert!(size_of::<ZeroStateBootInfo>() == 152);
+
+    assert!(offset_of!(BootInfoV1, kernel_phys) == 16);
+    assert!(offset_of!(BootInfoV1, timestamp) == 40);
+    assert!(offset_of!(BootInfoV1, capsule_payload_hash) == 48);
+    assert!(offset_of!(BootInfoV1, handoff_phys) == 80);
+    assert!(offset_of!(BootInfoV1, handoff_sha256) == 96);
+    assert!(size_of::<BootInfoV1>() == 128);
+
+    assert!(offset_of!(BootInfoPage, sig_alg) == 128);
+    assert!(offset_of!(BootInfoPage, pubkey) == 136);
+    assert!(offset_of!(BootInfoPage, signature) == 168);
+    assert!(size_of::<BootInfoPage>() == 232);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signing_input_binds_every_field() {
+        let mut info = BootInfoV1::empty();
+        info.kernel_entry = 0xFFFF_FFFF_8010_0000;
+        info.handoff_sha256 = [0x11; 32];
+        let a = signing_input(&info);
+        assert_eq!(&a[..16], BOOTINFO_SIG_DOMAIN);
+        assert_eq!(&a[16..20], &BOOTINFO_MAGIC.to_le_bytes());
+        assert_eq!(&a[SIGNING_INPUT_LEN - 32..], &[0x11; 32]);
+
+        info.handoff_size = 1;
+        assert_ne!(signing_input(&info), a);
+    }
+}
@@ -0,0 +1,198 @@
+//! Bootloader log handed to the kernel (`tag::BOOT_LOG`).
+//!
+//! The tag payload is a `BootLogTag` pointing at a LOADER_DATA region:
+//!
+//! ```text
+//!   BootLogHeader
+//!   record: LogRecordHeader, category[category_len], message[message_len], pad to 8
+//!   ...     (oldest first, `records` of them, `data_size` bytes in total)
+//! ```
+//!
+//! The loader keeps a fixed ring and drops the oldest records when it is
+//! full; `dropped` says how many were lost. Timestamps are raw TSC values,
+//! `tsc_hz` converts them.
+
+use core::mem::size_of;
+
+pub const BOOT_LOG_MAGIC: u32 = u32::from_le_bytes(*b"NBLG");
+pub const BOOT_LOG_VERSION: u16 = 1;
+
+/// `LogRecordHeader.level` values
+pub mod log_level {
+    pub const CRITICAL: u8 = 0;
+    pub const ERROR: u8 = 1;
+    pub const WARN: u8 = 2;
+    pub const INFO: u8 = 3;
+    pub const DEBUG: u8 = 4;
+
+    pub fn name(level: u8) -> &'static str {
+        match level {
+            CRITICAL => "CRIT",
+            ERROR => "ERROR",
+            WARN => "WARN",
+            INFO => "INFO",
+            DEBUG => "DEBUG",
+            _ => "?",
+        }
+    }
+}
+
+/// `tag::BOOT_LOG` v1 payload
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootLogTag {
+    pub ptr: u64,
+    /// Bytes of the region, header included
+    pub size: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BootLogHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub header_size: u16,
+    pub records: u32,
+    pub dropped: u32,
+    /// Record bytes following the header
+    pub data_size: u64,
+    pub tsc_hz: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LogRecordHeader {
+    pub tsc: u64,
+    pub level: u8,
+    pub category_len: u8,
+    pub message_len: u16,
+    pub reserved: u32,
+}
+
+impl LogRecordHeader {
+    /// Total record size, header and padding included
+    pub const fn record_len(&self) -> usize {
+        record_len(self.category_len as usize, self.message_len as usize)
+    }
+}
+
+pub const fn record_len(category_len: usize, message_len: usize) -> usize {
+    size_of::<LogRecordHeader>() + ((category_len + message_len + 7) & !7)
+}
+
+/// One decoded record; text is lossy only if the loader wrote invalid UTF-8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRecord<'a> {
+    pub tsc: u64,
+    pub level: u8,
+    pub category: &'a str,
+    pub message: &'a str,
+}
+
+/// Validated view of a boot log region
+pub struct BootLog<'a> {
+    pub header: BootLogHeader,
+    data: &'a [u8],
+}
+
+impl<'a> BootLog<'a> {
+    pub fn parse(region: &'a [u8]) -> Option<Self> {
+        if region.len() < size_of::<BootLogHeader>() {
+            return None;
+        }
+        // safe: length checked; any bit pattern is a valid header
+        let header = unsafe { core::ptr::read_unaligned(region.as_ptr() as *const BootLogHeader) };
+        if header.magic != BOOT_LOG_MAGIC || header.version != BOOT_LOG_VERSION {
+            return None;
+        }
+        let start = header.header_size as usize;
+        let end = start.checked_add(usize::try_from(header.data_size).ok()?)?;
+        Some(BootLog { header, data: region.get(start..end)? })
+    }
+
+    pub fn records(&self) -> Records<'a> {
+        Records { data: self.data, off: 0 }
+    }
+}
+
+pub struct Records<'a> {
+    data: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = LogRecord<'a>;
+
+    fn next(&mut self) -> Option<LogRecord<'a>> {
+        let raw = self.data.get(self.off..self.off + size_of::<LogRecordHeader>())?;
+        // safe: raw is exactly one header long
+        let h = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const LogRecordHeader) };
+        let text_off = self.off + size_of::<LogRecordHeader>();
+        let cat_end = text_off + h.category_len as usize;
+        let category = self.data.get(text_off..cat_end)?;
+        let message = self.data.get(cat_end..cat_end + h.message_len as usize)?;
+        self.off += h.record_len();
+        Some(LogRecord {
+            tsc: h.tsc,
+            level: h.level,
+            category: core::str::from_utf8(category).unwrap_or("?"),
+            message: core::str::from_utf8(message).unwrap_or("<invalid utf-8>"),
+        })
+    }
+}
+
+const _: () = {
+    assert!(size_of::<BootLogTag>() == 16);
+    assert!(size_of::<BootLogHeader>() == 32);
+    assert!(size_of::<LogRecordHeader>() == 16);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_record(out: &mut Vec<u8>, tsc: u64, level: u8, category: &str, message: &str) {
+        let h = LogRecordHeader {
+            tsc,
+            level,
+            category_len: category.len() as u8,
+            message_len: message.len() as u16,
+            reserved: 0,
+        };
+        let start = out.len();
+        out.extend_from_slice(&h.tsc.to_le_bytes());
+        out.extend_from_slice(&[h.level, h.category_len]);
+        out.extend_from_slice(&h.message_len.to_le_bytes());
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(category.as_bytes());
+        out.extend_from_slice(message.as_bytes());
+        out.resize(start + h.record_len(), 0);
+    }
+
+    #[test]
+    fn parses_records_in_order_and_rejects_overruns() {
+        let mut data = Vec::new();
+        put_record(&mut data, 100, log_level::INFO, "boot", "hello");
+        put_record(&mut data, 250, log_level::WARN, "handoff", "tag list dropped");
+        assert_eq!(data.len(), 32 + 40);
+
+        let mut region = vec![0u8; size_of::<BootLogHeader>()];
+        region[..4].copy_from_slice(&BOOT_LOG_MAGIC.to_le_bytes());
+        region[4..6].copy_from_slice(&BOOT_LOG_VERSION.to_le_bytes());
+        region[6..8].copy_from_slice(&32u16.to_le_bytes());
+        region[8..12].copy_from_slice(&2u32.to_le_bytes());
+        region[16..24].copy_from_slice(&(data.len() as u64).to_le_bytes());
+        region.extend_from_slice(&data);
+
+        let log = BootLog::parse(&region).unwrap();
+        let recs: Vec<_> = log.records().collect();
+        assert_eq!(recs.len(), 2);
+        assert_eq!((recs[0].tsc, recs[0].category, recs[0].message), (100, "boot", "hello"));
+        assert_eq!(log_level::name(recs[1].level), "WARN");
+        assert_eq!(recs[1].message, "tag list dropped");
+
+        // data_size past the region end
+        region[16] += 8;
+        assert!(BootLog::parse(&region).is_none());
+    }
+}
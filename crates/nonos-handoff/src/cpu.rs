@@ -0,0 +1,100 @@
+//! Boot CPU features (`tag::CPU`).
+//!
+//! `cpu_feature` bits are shared by three places: the tag's `available`
+//! bitmap, the kernel's `NT_NONOS_CPU_FEATURES` note and the loader's own
+//! required-feature policy. A bit set in `available` means CPUID reports the
+//! feature on the boot CPU; it says nothing about whether the loader
+//! switched it on.
+
+use core::mem::size_of;
+
+/// Feature bits; the note format fixes bits 0-3, later bits are append-only
+pub mod cpu_feature {
+    pub const NXE: u64 = 1 << 0;
+    pub const SMEP: u64 = 1 << 1;
+    pub const SMAP: u64 = 1 << 2;
+    pub const UMIP: u64 = 1 << 3;
+    /// CET shadow stacks and indirect branch tracking
+    pub const CET_SS: u64 = 1 << 4;
+    pub const CET_IBT: u64 = 1 << 5;
+    /// Protection keys for user pages
+    pub const PKU: u64 = 1 << 6;
+    /// 5-level paging
+    pub const LA57: u64 = 1 << 7;
+    pub const RDRAND: u64 = 1 << 8;
+    pub const RDSEED: u64 = 1 << 9;
+    pub const AES_NI: u64 = 1 << 10;
+    pub const SHA: u64 = 1 << 11;
+    pub const AVX2: u64 = 1 << 12;
+    /// Intel total memory encryption
+    pub const TME: u64 = 1 << 13;
+    /// AMD secure memory encryption
+    pub const SME: u64 = 1 << 14;
+    /// Running under a hypervisor (CPUID.1:ECX[31])
+    pub const HYPERVISOR: u64 = 1 << 15;
+    pub const KNOWN: u64 = (1 << 16) - 1;
+
+    const NAMES: [&str; 16] = [
+        "nxe", "smep", "smap", "umip", "cet-ss", "cet-ibt", "pku", "la57", "rdrand", "rdseed", "aes", "sha", "avx2",
+        "tme", "sme", "hypervisor",
+    ];
+
+    /// Lower-case name of a single bit, as used in manifests and messages
+    pub fn name(bit: u64) -> Option<&'static str> {
+        (bit.is_power_of_two() && bit & KNOWN != 0).then(|| NAMES[bit.trailing_zeros() as usize])
+    }
+
+    pub fn from_name(name: &str) -> Option<u64> {
+        NAMES.iter().position(|n| n.eq_ignore_ascii_case(name)).map(|i| 1 << i)
+    }
+
+    /// Names of the known bits set in `bits`, lowest first
+    pub fn names(bits: u64) -> impl Iterator<Item = &'static str> {
+        (0..NAMES.len()).filter(move |i| bits & (1 << i) != 0).map(|i| NAMES[i])
+    }
+}
+
+/// `tag::CPU` v1 payload
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CpuTag {
+    /// `cpu_feature` bits CPUID reports
+    pub available: u64,
+    /// Bits the kernel notes, capsule manifest and loader configuration
+    /// required; all of them are in `available` or the loader refused to boot
+    pub required: u64,
+    /// CPUID.1:EAX (stepping, model, family)
+    pub signature: u32,
+    /// Address widths from CPUID.80000008h
+    pub phys_bits: u8,
+    pub virt_bits: u8,
+    pub reserved: u16,
+    /// CPUID.0 vendor ("GenuineIntel", "AuthenticAMD")
+    pub vendor: [u8; 12],
+    /// CPUID.40000000h vendor ("KVMKVMKVM", "Microsoft Hv"), zero without a hypervisor
+    pub hypervisor: [u8; 12],
+    /// LOADER_DATA pages mapped as supervisor shadow stack, zero unless the
+    /// kernel asked for CET shadow stacks; live only with `flags::CET_SS`
+    pub shadow_stack: u64,
+    pub shadow_stack_size: u64,
+}
+
+const _: () = assert!(size_of::<CpuTag>() == 64);
+
+#[cfg(test)]
+mod tests {
+    use super::cpu_feature::*;
+
+    #[test]
+    fn names_round_trip() {
+        for i in 0..16 {
+            let bit = 1u64 << i;
+            assert_eq!(from_name(name(bit).unwrap()), Some(bit));
+        }
+        assert_eq!(name(1 << 16), None);
+        assert_eq!(name(SMEP | SMAP), None);
+        assert_eq!(from_name("AVX2"), Some(AVX2));
+        assert_eq!(from_name("avx512"), None);
+        assert!(names(CET_SS | SHA | 1 << 40).eq(["cet-ss", "sha"]));
+    }
+}
@@ -0,0 +1,76 @@
+//! UEFI runtime services handed to the kernel (`tag::EFI_RUNTIME`).
+//!
+//! The tag payload is an `EfiRuntimeTag` followed by `range_count`
+//! `EfiRuntimeRange`s, one per firmware descriptor with `EFI_MEMORY_RUNTIME`
+//! set, in firmware order.
+//!
+//! Without `efi_runtime_flags::VIRTUAL` the loader did not call
+//! SetVirtualAddressMap: runtime services are still in physical mode and
+//! `runtime_services` is a physical address. With it, SetVirtualAddressMap
+//! succeeded and every range must be mapped at `virt` (`phys + virt_offset`)
+//! before the first runtime call; `runtime_services` and the pointers inside
+//! the system table are then virtual. `system_table` is always physical.
+
+use core::mem::size_of;
+
+/// `EfiRuntimeTag.flags` bits
+pub mod efi_runtime_flags {
+    /// SetVirtualAddressMap succeeded with the `virt` addresses below
+    pub const VIRTUAL: u32 = 1 << 0;
+}
+
+/// Vendor GUID of the loader's variables (`NonosSlotSuccess`, `NonosBootedSlot`,
+/// `NonosSlotState`, `NonosBootEntryNNNN`), 2a010f0e-6b84-4601-83f3-0a18f01624b7,
+/// in EFI_GUID byte order as GetVariable/SetVariable take it
+pub const NONOS_VENDOR_GUID: [u8; 16] =
+    [0x0e, 0x0f, 0x01, 0x2a, 0x84, 0x6b, 0x01, 0x46, 0x83, 0xf3, 0x0a, 0x18, 0xf0, 0x16, 0x24, 0xb7];
+
+/// EFI_MEMORY_RUNTIME descriptor attribute
+pub const EFI_MEMORY_RUNTIME: u64 = 1 << 63;
+
+/// `tag::EFI_RUNTIME` v1 payload header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EfiRuntimeTag {
+    /// Physical address of the EFI system table
+    pub system_table: u64,
+    /// EFI_RUNTIME_SERVICES to call through, virtual when `VIRTUAL` is set
+    pub runtime_services: u64,
+    /// `virt - phys` for every range, wrapping; 0 without `VIRTUAL`
+    pub virt_offset: u64,
+    /// EFI_STATUS returned by SetVirtualAddressMap, 0 if it was not called
+    pub status: u64,
+    pub flags: u32,
+    pub range_count: u32,
+}
+
+impl EfiRuntimeTag {
+    pub fn is_virtual(&self) -> bool {
+        self.flags & efi_runtime_flags::VIRTUAL != 0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EfiRuntimeRange {
+    pub phys: u64,
+    /// Where firmware expects the range; equal to `phys` without `VIRTUAL`
+    pub virt: u64,
+    pub pages: u64,
+    /// UEFI attribute bits, `EFI_MEMORY_RUNTIME` included
+    pub attribute: u64,
+    /// UEFI memory type (runtime code/data, MMIO, ...)
+    pub efi_type: u32,
+    pub reserved: u32,
+}
+
+impl EfiRuntimeRange {
+    pub fn size(&self) -> u64 {
+        self.pages.saturating_mul(0x1000)
+    }
+}
+
+const _: () = {
+    assert!(size_of::<EfiRuntimeTag>() == 40);
+    assert!(size_of::<EfiRuntimeRange>() == 40);
+};
@@ -0,0 +1,367 @@
+//! NØN•OS boot handoff ABI.
+//!
+//! The bootloader fills a `BootHandoffV1` page and jumps to the kernel with
+//! its physical address in RDI. This crate is the single definition of that
+//! layout: the loader writes it, the kernel reads it through `HandoffReader`.
+//! Every struct is `repr(C)` with explicit padding, and the `const` blocks
+//! below pin sizes and offsets so a layout change fails to compile on both
+//! sides instead of corrupting a boot.
+//!
+//! Layout changes bump `HANDOFF_VERSION` and are recorded in
+//! `src/handoff/CONTRIBUTING.md` of the bootloader.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod bootinfo;
+pub mod bootlog;
+pub mod cpu;
+pub mod efi_runtime;
+pub mod memmap;
+pub mod memtest;
+pub mod pci;
+pub mod reader;
+pub mod smbios;
+pub mod smp;
+pub mod storage;
+pub mod tags;
+
+pub use bootinfo::{build_bootinfo, BootInfoPage, BootInfoParams, BootInfoV1, BootModeFlags, ZeroStateBootInfo};
+pub use bootlog::{BootLog, BootLogTag, LogRecord};
+pub use cpu::{cpu_feature, CpuTag};
+pub use efi_runtime::{EfiRuntimeRange, EfiRuntimeTag, NONOS_VENDOR_GUID};
+pub use memmap::{region_kind, MemoryRegion, MMAP_FORMAT_NONOS_V1};
+pub use memtest::{BadPage, BadPagesTag};
+pub use pci::{PciBar, PciFunction};
+pub use reader::{HandoffError, HandoffReader};
+pub use smbios::{SmbiosIdentity, SmbiosMemoryDevice};
+pub use smp::{ApMailbox, SmpCpu};
+pub use storage::{BlockDevice, GptPartition};
+pub use tags::{tag, Tag, TagWriter, Tags};
+
+use core::mem::{offset_of, size_of};
+
+pub const HANDOFF_MAGIC: u32 = 0x4E_4F_4E_4F;
+pub const HANDOFF_VERSION: u16 = 2;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct FramebufferInfo {
+    pub ptr: u64,
+    pub size: u64,
+    pub width: u32,
+    pub height: u32,
+    pub stride: u32,
+    pub pixel_format: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub ptr: u64,
+    pub entry_size: u32,
+    pub entry_count: u32,
+    pub desc_version: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct AcpiInfo {
+    pub rsdp: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SmbiosInfo {
+    pub entry: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Module {
+    pub base: u64,
+    pub size: u64,
+    pub kind: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Modules {
+    pub ptr: u64,
+    pub count: u32,
+    pub reserved: u32,
+}
+
+/// Kernel `.symtab` (Elf64_Sym array) and its string table, copied into
+/// LOADER_DATA when the capsule manifest has `symbols on`. Zero otherwise.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    pub symtab_ptr: u64,
+    pub symtab_size: u64,
+    pub symtab_entsize: u64,
+    pub strtab_ptr: u64,
+    pub strtab_size: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Timing {
+    pub tsc_hz: u64,
+    pub unix_epoch_ms: u64,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Measurements {
+    pub kernel_sha256: [u8; 32],
+    pub kernel_sig_ok: u8,
+    pub secure_boot: u8,
+    pub reserved: [u8; 6],
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RngSeed {
+    pub seed32: [u8; 32],
+}
+
+/// `FramebufferInfo.pixel_format` values (mirror UEFI GOP PixelFormat ordinals)
+pub mod pixel_format {
+    pub const RGBX8: u32 = 0;
+    pub const BGRX8: u32 = 1;
+    pub const BITMASK: u32 = 2;
+    pub const BLT_ONLY: u32 = 3;
+}
+
+/// `Module.kind` values
+#[repr(u32)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModuleKind {
+    Initrd = 1,
+    Driver = 2,
+    Config = 3,
+    Policy = 4,
+}
+
+impl ModuleKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "initrd" => Some(ModuleKind::Initrd),
+            "driver" => Some(ModuleKind::Driver),
+            "config" => Some(ModuleKind::Config),
+            "policy" => Some(ModuleKind::Policy),
+            _ => None,
+        }
+    }
+
+    pub fn from_raw(kind: u32) -> Option<Self> {
+        match kind {
+            1 => Some(ModuleKind::Initrd),
+            2 => Some(ModuleKind::Driver),
+            3 => Some(ModuleKind::Config),
+            4 => Some(ModuleKind::Policy),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ModuleKind::Initrd => "initrd",
+            ModuleKind::Driver => "driver",
+            ModuleKind::Config => "config",
+            ModuleKind::Policy => "policy",
+        }
+    }
+}
+
+/// `BootHandoffV1.flags` bits
+///
+/// The hardening bits (NXE, SMEP, SMAP, UMIP, WP, CET_*, SYSCALL) describe
+/// the BSP's registers at the jump, read back after the loader wrote them.
+/// Parked APs are not touched.
+pub mod flags {
+    pub const WX: u64 = 1 << 0;
+    /// EFER.NXE
+    pub const NXE: u64 = 1 << 1;
+    /// CR4.SMEP, CR4.SMAP, CR4.UMIP
+    pub const SMEP: u64 = 1 << 2;
+    pub const SMAP: u64 = 1 << 3;
+    pub const UMIP: u64 = 1 << 4;
+    pub const IDMAP_PRESERVED: u64 = 1 << 5;
+    /// A tag list follows the header (see `tags`)
+    pub const TAGS: u64 = 1 << 6;
+    /// CR0.WP: supervisor writes honour read-only pages
+    pub const WP: u64 = 1 << 7;
+    /// CET supervisor shadow stack live on `CpuTag.shadow_stack`
+    pub const CET_SS: u64 = 1 << 8;
+    /// CET indirect branch tracking (S_CET.ENDBR_EN)
+    pub const CET_IBT: u64 = 1 << 9;
+    /// STAR/LSTAR/SFMASK programmed from the kernel's syscall note, EFER.SCE set
+    pub const SYSCALL: u64 = 1 << 10;
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct BootHandoffV1 {
+    pub magic: u32,
+    pub version: u16,
+    pub size: u16,
+    pub flags: u64,
+    pub entry_point: u64,
+    pub fb: FramebufferInfo,
+    pub mmap: MemoryMap,
+    pub acpi: AcpiInfo,
+    pub smbios: SmbiosInfo,
+    pub modules: Modules,
+    pub timing: Timing,
+    pub meas: Measurements,
+    pub rng: RngSeed,
+    pub cmdline_ptr: u64,
+    // reserved0 is available for bootloader to surface auxiliary info (e.g. bootinfo phys)
+    pub reserved0: u64,
+    // v2
+    pub symbols: SymbolTable,
+}
+
+impl BootHandoffV1 {
+    /// Zeroed handoff with magic, version and size filled in
+    pub fn new() -> Self {
+        BootHandoffV1 {
+            magic: HANDOFF_MAGIC,
+            version: HANDOFF_VERSION,
+            size: size_of::<Self>() as u16,
+            ..Default::default()
+        }
+    }
+
+    #[inline]
+    pub fn is_valid(&self) -> bool {
+        self.validate().is_ok()
+    }
+
+    pub fn validate(&self) -> Result<(), HandoffError> {
+        if self.magic != HANDOFF_MAGIC {
+            return Err(HandoffError::BadMagic(self.magic));
+        }
+        if self.version != HANDOFF_VERSION {
+            return Err(HandoffError::UnsupportedVersion(self.version));
+        }
+        if self.size as usize != size_of::<Self>() {
+            return Err(HandoffError::SizeMismatch { expected: size_of::<Self>(), found: self.size as usize });
+        }
+        Ok(())
+    }
+
+    /// Offset of the tag list from the start of the handoff
+    pub fn tags_offset(&self) -> usize {
+        (self.size as usize + tags::TAG_ALIGN - 1) & !(tags::TAG_ALIGN - 1)
+    }
+
+    /// Raw little-endian image of the handoff, as the kernel sees it in memory
+    pub fn to_bytes(&self) -> [u8; HANDOFF_SIZE] {
+        let mut out = [0u8; HANDOFF_SIZE];
+        // safe: repr(C) with every padding byte spelled out as a field (see asserts below)
+        unsafe { core::ptr::write_unaligned(out.as_mut_ptr() as *mut Self, *self) };
+        out
+    }
+
+    /// Parse and validate a handoff from raw bytes (no alignment requirement)
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, HandoffError> {
+        if bytes.len() < HANDOFF_SIZE {
+            return Err(HandoffError::Truncated { needed: HANDOFF_SIZE, found: bytes.len() });
+        }
+        // safe: length checked; every bit pattern is a valid BootHandoffV1
+        let bh = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Self) };
+        bh.validate()?;
+        Ok(bh)
+    }
+}
+
+pub const HANDOFF_SIZE: usize = 256;
+
+// ABI pins. Any change here is a version bump and a coordinated kernel change.
+const _: () = {
+    assert!(size_of::<FramebufferInfo>() == 32);
+    assert!(size_of::<MemoryMap>() == 24);
+    assert!(size_of::<AcpiInfo>() == 8);
+    assert!(size_of::<SmbiosInfo>() == 8);
+    assert!(size_of::<Module>() == 24);
+    assert!(size_of::<Modules>() == 16);
+    assert!(size_of::<SymbolTable>() == 40);
+    assert!(size_of::<Timing>() == 16);
+    assert!(size_of::<Measurements>() == 40);
+    assert!(size_of::<RngSeed>() == 32);
+    assert!(size_of::<MemoryRegion>() == 32);
+
+    assert!(offset_of!(BootHandoffV1, magic) == 0);
+    assert!(offset_of!(BootHandoffV1, version) == 4);
+    assert!(offset_of!(BootHandoffV1, size) == 6);
+    assert!(offset_of!(BootHandoffV1, flags) == 8);
+    assert!(offset_of!(BootHandoffV1, entry_point) == 16);
+    assert!(offset_of!(BootHandoffV1, fb) == 24);
+    assert!(offset_of!(BootHandoffV1, mmap) == 56);
+    assert!(offset_of!(BootHandoffV1, acpi) == 80);
+    assert!(offset_of!(BootHandoffV1, smbios) == 88);
+    assert!(offset_of!(BootHandoffV1, modules) == 96);
+    assert!(offset_of!(BootHandoffV1, timing) == 112);
+    assert!(offset_of!(BootHandoffV1, meas) == 128);
+    assert!(offset_of!(BootHandoffV1, rng) == 168);
+    assert!(offset_of!(BootHandoffV1, cmdline_ptr) == 200);
+    assert!(offset_of!(BootHandoffV1, reserved0) == 208);
+    assert!(offset_of!(BootHandoffV1, symbols) == 216);
+    assert!(size_of::<BootHandoffV1>() == HANDOFF_SIZE);
+    // must fit the single page the loader allocates
+    assert!(HANDOFF_SIZE <= 4096);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> BootHandoffV1 {
+        let mut bh = BootHandoffV1::new();
+        bh.flags = flags::NXE | flags::SMEP;
+        bh.entry_point = 0xFFFF_FFFF_8010_0000;
+        bh.fb = FramebufferInfo { ptr: 0x8000_0000, size: 0x30_0000, width: 1024, height: 768, stride: 1024, pixel_format: pixel_format::BGRX8 };
+        bh.acpi.rsdp = 0x7FE1_4000;
+        bh.timing = Timing { tsc_hz: 2_400_000_000, unix_epoch_ms: 1_700_000_000_000 };
+        bh.meas.kernel_sha256 = [0x5A; 32];
+        bh.meas.secure_boot = 1;
+        bh.rng.seed32 = [7; 32];
+        bh.symbols.strtab_size = 99;
+        bh
+    }
+
+    #[test]
+    fn byte_round_trip() {
+        let bh = sample();
+        let bytes = bh.to_bytes();
+        assert_eq!(&bytes[0..4], &HANDOFF_MAGIC.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([bytes[6], bytes[7]]) as usize, HANDOFF_SIZE);
+        assert_eq!(BootHandoffV1::from_bytes(&bytes), Ok(bh));
+
+        // unaligned source buffers are fine
+        let mut shifted = [0u8; HANDOFF_SIZE + 1];
+        shifted[1..].copy_from_slice(&bytes);
+        assert_eq!(BootHandoffV1::from_bytes(&shifted[1..]), Ok(bh));
+    }
+
+    #[test]
+    fn rejects_foreign_handoffs() {
+        let bytes = sample().to_bytes();
+        assert_eq!(
+            BootHandoffV1::from_bytes(&bytes[..100]),
+            Err(HandoffError::Truncated { needed: HANDOFF_SIZE, found: 100 })
+        );
+
+        let mut bad = sample();
+        bad.magic = 0;
+        assert_eq!(BootHandoffV1::from_bytes(&bad.to_bytes()), Err(HandoffError::BadMagic(0)));
+
+        let mut old = sample();
+        old.version = 1;
+        assert_eq!(BootHandoffV1::from_bytes(&old.to_bytes()), Err(HandoffError::UnsupportedVersion(1)));
+    }
+}
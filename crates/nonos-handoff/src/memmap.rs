@@ -0,0 +1,46 @@
+//! NONOS memory map entries.
+//!
+//! When `BootHandoffV1.mmap.desc_version == MMAP_FORMAT_NONOS_V1`,
+//! `mmap.ptr` points at a `MemoryRegion[]` sorted by base, with contiguous
+//! regions of equal kind and attributes merged. Any other value means the
+//! buffer holds raw UEFI descriptors of `mmap.entry_size` bytes each.
+
+pub const MMAP_FORMAT_NONOS_V1: u32 = 0x4E4D_0001;
+
+/// `MemoryRegion.kind` values
+pub mod region_kind {
+    /// Free RAM (conventional + boot services)
+    pub const USABLE: u32 = 1;
+    /// Loader code/data: kernel image, handoff, modules, stacks. Reusable once
+    /// the kernel has consumed what it needs.
+    pub const LOADER_RECLAIMABLE: u32 = 2;
+    pub const ACPI_RECLAIMABLE: u32 = 3;
+    pub const ACPI_NVS: u32 = 4;
+    pub const MMIO: u32 = 5;
+    /// UEFI runtime services code/data; must stay mapped for runtime calls
+    pub const RUNTIME: u32 = 6;
+    pub const RESERVED: u32 = 7;
+    pub const BAD: u32 = 8;
+    pub const PERSISTENT: u32 = 9;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MemoryRegion {
+    pub base: u64,
+    pub length: u64,
+    pub kind: u32,
+    pub reserved: u32,
+    /// UEFI attribute bits (EFI_MEMORY_UC/WB/RUNTIME/...)
+    pub attributes: u64,
+}
+
+impl MemoryRegion {
+    pub fn end(&self) -> u64 {
+        self.base.saturating_add(self.length)
+    }
+
+    pub fn is_usable(&self) -> bool {
+        self.kind == region_kind::USABLE
+    }
+}
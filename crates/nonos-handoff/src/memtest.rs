@@ -0,0 +1,74 @@
+//! Loader memory test results (`tag::BAD_PAGES`).
+//!
+//! Only present when the boot went through a memory test entry whose options
+//! ask for the results to be handed over. The payload is a `BadPagesTag`
+//! followed by `page_count` `BadPage`s sorted by address, one per 4 KiB page
+//! on which any pattern read back wrong.
+//!
+//! Pages with `bad_page_flags::RESERVED` were also allocated as
+//! EfiUnusableMemory before ExitBootServices, so they show up as
+//! `region_kind::BAD` in the NONOS memory map. A page without it is still in
+//! a usable region and the kernel has to carve it out itself. Memory outside
+//! `tested_bytes` (firmware-owned, or the loader's own allocations) was not
+//! tested at all, so a missing entry only vouches for tested ranges.
+
+use core::mem::size_of;
+
+/// Patterns the test ran (`BadPagesTag.tests`, `BadPage.tests`)
+pub mod memtest_test {
+    /// `1 << bit` rotated through every word, all 64 bit positions
+    pub const WALKING_ONES: u16 = 1 << 0;
+    /// Fill, then ascending verify-and-invert, then descending verify-and-restore
+    pub const MOVING_INVERSIONS: u16 = 1 << 1;
+    /// Each word holds its own address, then its complement
+    pub const ADDRESS: u16 = 1 << 2;
+    pub const ALL: u16 = WALKING_ONES | MOVING_INVERSIONS | ADDRESS;
+}
+
+/// `BadPagesTag.flags` bits
+pub mod bad_pages_flags {
+    /// More pages failed than the list holds; the tail was dropped
+    pub const TRUNCATED: u32 = 1 << 0;
+}
+
+/// `BadPage.flags` bits
+pub mod bad_page_flags {
+    /// Allocated as EfiUnusableMemory; `region_kind::BAD` in the memory map
+    pub const RESERVED: u16 = 1 << 0;
+}
+
+/// `tag::BAD_PAGES` v1 payload header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BadPagesTag {
+    /// Bytes every pattern ran over, per pass
+    pub tested_bytes: u64,
+    /// Usable bytes the loader could not claim for testing
+    pub skipped_bytes: u64,
+    /// Mismatching words across all passes, listed pages or not
+    pub error_count: u64,
+    pub passes: u32,
+    pub tests: u16,
+    pub reserved: u16,
+    pub flags: u32,
+    pub page_count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BadPage {
+    /// Physical address, 4 KiB aligned
+    pub phys: u64,
+    /// OR of `expected ^ actual` over every mismatch on the page
+    pub failed_bits: u64,
+    /// Mismatching words on the page, saturating
+    pub errors: u32,
+    /// `memtest_test` bits of the patterns that caught it
+    pub tests: u16,
+    pub flags: u16,
+}
+
+const _: () = {
+    assert!(size_of::<BadPagesTag>() == 40);
+    assert!(size_of::<BadPage>() == 24);
+};
@@ -0,0 +1,101 @@
+//! PCI functions found by the loader (`tag::PCI`).
+//!
+//! The tag payload is `PciFunction[]` in scan order (segment, bus, device,
+//! function). BARs are as firmware programmed them; sizes were probed with
+//! decoding disabled and the original values restored.
+
+use core::mem::size_of;
+
+/// `PciBar.flags` bits
+pub mod pci_bar_flags {
+    /// I/O space; otherwise memory
+    pub const IO: u32 = 1 << 0;
+    /// 64-bit memory BAR; the next BAR slot is its upper half and left empty
+    pub const MEM64: u32 = 1 << 1;
+    pub const PREFETCHABLE: u32 = 1 << 2;
+}
+
+/// Class codes the loader refers to
+pub mod pci_class {
+    pub const UNCLASSIFIED: u8 = 0x00;
+    pub const STORAGE: u8 = 0x01;
+    pub const NETWORK: u8 = 0x02;
+    pub const DISPLAY: u8 = 0x03;
+    pub const MULTIMEDIA: u8 = 0x04;
+    pub const MEMORY: u8 = 0x05;
+    pub const BRIDGE: u8 = 0x06;
+    pub const COMMUNICATION: u8 = 0x07;
+    pub const SYSTEM: u8 = 0x08;
+    pub const INPUT: u8 = 0x09;
+    pub const PROCESSOR: u8 = 0x0B;
+    pub const SERIAL_BUS: u8 = 0x0C;
+    pub const WIRELESS: u8 = 0x0D;
+    pub const ENCRYPTION: u8 = 0x10;
+    pub const ACCELERATOR: u8 = 0x12;
+
+    pub fn name(class: u8) -> &'static str {
+        match class {
+            UNCLASSIFIED => "unclassified",
+            STORAGE => "storage",
+            NETWORK => "network",
+            DISPLAY => "display",
+            MULTIMEDIA => "multimedia",
+            MEMORY => "memory",
+            BRIDGE => "bridge",
+            COMMUNICATION => "communication",
+            SYSTEM => "system",
+            INPUT => "input",
+            PROCESSOR => "processor",
+            SERIAL_BUS => "serial bus",
+            WIRELESS => "wireless",
+            ENCRYPTION => "encryption",
+            ACCELERATOR => "accelerator",
+            _ => "other",
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PciBar {
+    /// Address with the type bits masked off, 0 if unimplemented or unassigned
+    pub base: u64,
+    /// Decoded size in bytes, 0 if unimplemented
+    pub size: u64,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PciFunction {
+    pub segment: u16,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+    /// Header layout (0 endpoint, 1 PCI bridge, 2 CardBus), multifunction bit cleared
+    pub header_type: u8,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub subsystem_vendor_id: u16,
+    pub subsystem_id: u16,
+    pub reserved: [u8; 4],
+    /// Six BARs for endpoints, two for bridges, none for CardBus
+    pub bars: [PciBar; 6],
+}
+
+impl PciFunction {
+    /// 24-bit class code: class, subclass, programming interface
+    pub fn class_code(&self) -> u32 {
+        (self.class as u32) << 16 | (self.subclass as u32) << 8 | self.prog_if as u32
+    }
+}
+
+const _: () = {
+    assert!(size_of::<PciBar>() == 24);
+    assert!(size_of::<PciFunction>() == 168);
+};
@@ -0,0 +1,305 @@
+//! Kernel-side view of a handoff.
+//!
+//! The handoff stores physical addresses. The kernel decides where physical
+//! memory is mapped (identity during early boot, a direct-map window later)
+//! and passes that as `phys_offset`; the reader adds it to every pointer and
+//! bounds each slice by the counts in the handoff. Constructing a reader is
+//! the single `unsafe` promise; the accessors are safe afterwards.
+
+use crate::memmap::{MemoryRegion, MMAP_FORMAT_NONOS_V1};
+use crate::tags::{TagListHeader, Tags, TscCalibration, TAG_LIST_MAX};
+use crate::bootlog::{BootLog, BootLogTag};
+use crate::efi_runtime::{EfiRuntimeRange, EfiRuntimeTag};
+use crate::memtest::{BadPage, BadPagesTag};
+use crate::smbios::{SmbiosIdentity, SmbiosMemoryDevice};
+use crate::storage::{BlockDevice, GptPartition};
+use crate::{flags, tag, BootHandoffV1, BootInfoPage, FramebufferInfo, Module, SymbolTable};
+use core::fmt;
+use core::mem::{align_of, size_of};
+
+/// Longest command line the reader will scan for its NUL terminator
+pub const CMDLINE_MAX: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandoffError {
+    Null,
+    Misaligned(u64),
+    Truncated { needed: usize, found: usize },
+    BadMagic(u32),
+    UnsupportedVersion(u16),
+    SizeMismatch { expected: usize, found: usize },
+}
+
+impl fmt::Display for HandoffError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandoffError::Null => write!(f, "null handoff pointer"),
+            HandoffError::Misaligned(a) => write!(f, "handoff at {:#x} is misaligned", a),
+            HandoffError::Truncated { needed, found } => {
+                write!(f, "handoff truncated: need {} bytes, have {}", needed, found)
+            }
+            HandoffError::BadMagic(m) => write!(f, "bad handoff magic {:#010x}", m),
+            HandoffError::UnsupportedVersion(v) => write!(f, "unsupported handoff version {}", v),
+            HandoffError::SizeMismatch { expected, found } => {
+                write!(f, "handoff size {} does not match ABI size {}", found, expected)
+            }
+        }
+    }
+}
+
+pub struct HandoffReader<'a> {
+    handoff: &'a BootHandoffV1,
+    phys_offset: u64,
+}
+
+impl<'a> HandoffReader<'a> {
+    /// Validate `handoff` and wrap it.
+    ///
+    /// # Safety
+    /// For `'a`, every physical range the handoff references (memory map,
+    /// module table and payloads, cmdline, symbol tables) must be readable at
+    /// `phys + phys_offset` and not mutated. With `flags::TAGS` set, the tag
+    /// list directly after `handoff` must be readable as well, and so must
+    /// the BootInfo page when `reserved0` is non-zero.
+    pub unsafe fn new(handoff: &'a BootHandoffV1, phys_offset: u64) -> Result<Self, HandoffError> {
+        handoff.validate()?;
+        Ok(HandoffReader { handoff, phys_offset })
+    }
+
+    /// Wrap the handoff at physical address `phys` (the value the loader put in RDI).
+    ///
+    /// # Safety
+    /// Same as `new`, and the handoff page itself must be mapped at `phys + phys_offset`.
+    pub unsafe fn from_phys(phys: u64, phys_offset: u64) -> Result<Self, HandoffError> {
+        if phys == 0 {
+            return Err(HandoffError::Null);
+        }
+        let addr = phys.wrapping_add(phys_offset);
+        if !(addr as usize).is_multiple_of(align_of::<BootHandoffV1>()) {
+            return Err(HandoffError::Misaligned(phys));
+        }
+        // safe: caller guarantees the page is mapped; alignment checked above
+        Self::new(&*(addr as *const BootHandoffV1), phys_offset)
+    }
+
+    pub fn handoff(&self) -> &'a BootHandoffV1 {
+        self.handoff
+    }
+
+    /// `phys..phys + count * T` as a slice, or empty when absent or malformed
+    fn slice<T>(&self, phys: u64, count: usize) -> &'a [T] {
+        let addr = phys.wrapping_add(self.phys_offset) as usize;
+        if phys == 0 || count == 0 || !addr.is_multiple_of(align_of::<T>()) || count.checked_mul(size_of::<T>()).is_none() {
+            return &[];
+        }
+        // safe: mapping promised by the constructor; alignment and size overflow checked
+        unsafe { core::slice::from_raw_parts(addr as *const T, count) }
+    }
+
+    /// Normalized memory map, `None` if the loader handed over raw UEFI descriptors
+    pub fn memory_regions(&self) -> Option<&'a [MemoryRegion]> {
+        let m = &self.handoff.mmap;
+        if m.desc_version != MMAP_FORMAT_NONOS_V1 || m.entry_size as usize != size_of::<MemoryRegion>() {
+            return None;
+        }
+        Some(self.slice(m.ptr, m.entry_count as usize))
+    }
+
+    pub fn modules(&self) -> &'a [Module] {
+        let m = &self.handoff.modules;
+        self.slice(m.ptr, m.count as usize)
+    }
+
+    pub fn module_data(&self, module: &Module) -> &'a [u8] {
+        self.slice(module.base, module.size as usize)
+    }
+
+    /// NUL-terminated command line; `None` if absent, unterminated within
+    /// `CMDLINE_MAX`, or not UTF-8
+    pub fn cmdline(&self) -> Option<&'a str> {
+        let ptr = self.handoff.cmdline_ptr;
+        if ptr == 0 {
+            return None;
+        }
+        let base = ptr.wrapping_add(self.phys_offset) as *const u8;
+        // safe: the loader allocates len + 1 bytes and terminates them; the scan is bounded
+        let len = (0..CMDLINE_MAX).find(|&i| unsafe { *base.add(i) } == 0)?;
+        core::str::from_utf8(self.slice(ptr, len)).ok()
+    }
+
+    pub fn framebuffer(&self) -> Option<&'a FramebufferInfo> {
+        let fb = &self.handoff.fb;
+        (fb.ptr != 0 && fb.size != 0).then_some(fb)
+    }
+
+    /// Raw `.symtab` bytes (`symtab_entsize` stride) and `.strtab`, if exported
+    pub fn symbols(&self) -> Option<(&'a [u8], &'a [u8])> {
+        let s: &SymbolTable = &self.handoff.symbols;
+        if s.symtab_ptr == 0 || s.symtab_entsize == 0 {
+            return None;
+        }
+        Some((self.slice(s.symtab_ptr, s.symtab_size as usize), self.slice(s.strtab_ptr, s.strtab_size as usize)))
+    }
+
+    /// Tag list after the header, `None` if the loader wrote none or it is malformed
+    pub fn tags(&self) -> Option<Tags<'a>> {
+        if self.handoff.flags & flags::TAGS == 0 {
+            return None;
+        }
+        let base = (self.handoff as *const BootHandoffV1 as *const u8).wrapping_add(self.handoff.tags_offset());
+        // safe: the constructor contract covers the tag list; read its header, then bound by total_size
+        let header = unsafe { core::ptr::read_unaligned(base as *const TagListHeader) };
+        let total = (header.total_size as usize).clamp(core::mem::size_of::<TagListHeader>(), TAG_LIST_MAX);
+        Tags::parse(unsafe { core::slice::from_raw_parts(base, total) })
+    }
+
+    /// BootInfo page published by loaders built with `bootinfo`, 0 otherwise
+    pub fn bootinfo_phys(&self) -> u64 {
+        self.handoff.reserved0
+    }
+
+    /// The BootInfo page, if published and well formed. Its signature is not
+    /// checked here; see `bootinfo` for the verification recipe.
+    pub fn bootinfo(&self) -> Option<&'a BootInfoPage> {
+        let page = self.slice::<BootInfoPage>(self.handoff.reserved0, 1).first()?;
+        page.info.is_valid().then_some(page)
+    }
+
+    /// Loader log from the `BOOT_LOG` tag, `None` if absent or malformed
+    pub fn boot_log(&self) -> Option<BootLog<'a>> {
+        let t = self.tags()?.get(tag::BOOT_LOG)?;
+        let loc: BootLogTag = t.read()?;
+        BootLog::parse(self.slice(loc.ptr, usize::try_from(loc.size).ok()?))
+    }
+
+    /// Runtime services header and its ranges from the `EFI_RUNTIME` tag
+    pub fn efi_runtime(&self) -> Option<(EfiRuntimeTag, impl Iterator<Item = EfiRuntimeRange> + 'a)> {
+        let t = self.tags()?.get(tag::EFI_RUNTIME)?;
+        let head: EfiRuntimeTag = t.read()?;
+        let ranges = t.items_from::<EfiRuntimeRange>(size_of::<EfiRuntimeTag>()).take(head.range_count as usize);
+        Some((head, ranges))
+    }
+
+    /// System identity and memory devices from the `SMBIOS_INFO` tag
+    pub fn smbios_info(&self) -> Option<(SmbiosIdentity, impl Iterator<Item = SmbiosMemoryDevice> + 'a)> {
+        let t = self.tags()?.get(tag::SMBIOS_INFO)?;
+        let head: SmbiosIdentity = t.read()?;
+        let devices = t.items_from::<SmbiosMemoryDevice>(size_of::<SmbiosIdentity>()).take(head.memory_device_count as usize);
+        Some((head, devices))
+    }
+
+    /// How the loader calibrated `timing.tsc_hz`, from the `TSC` tag
+    pub fn tsc(&self) -> Option<TscCalibration> {
+        self.tags()?.get(tag::TSC)?.read()
+    }
+
+    /// Memory test summary and failing pages from the `BAD_PAGES` tag
+    pub fn bad_pages(&self) -> Option<(BadPagesTag, impl Iterator<Item = BadPage> + 'a)> {
+        let t = self.tags()?.get(tag::BAD_PAGES)?;
+        let head: BadPagesTag = t.read()?;
+        let pages = t.items_from::<BadPage>(size_of::<BadPagesTag>()).take(head.page_count as usize);
+        Some((head, pages))
+    }
+
+    /// Whole block devices from the `BLOCK_DEVICES` tag
+    pub fn block_devices(&self) -> Option<impl Iterator<Item = BlockDevice> + 'a> {
+        Some(self.tags()?.get(tag::BLOCK_DEVICES)?.items())
+    }
+
+    /// GPT partitions from the `PARTITIONS` tag; `device` indexes `block_devices`
+    pub fn partitions(&self) -> Option<impl Iterator<Item = GptPartition> + 'a> {
+        Some(self.tags()?.get(tag::PARTITIONS)?.items())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{region_kind, MemoryMap, ModuleKind, Modules};
+
+    // Host stand-in for physical memory: pointers in the handoff are host
+    // addresses and the reader runs with phys_offset 0.
+    fn addr<T>(v: &[T]) -> u64 {
+        v.as_ptr() as u64
+    }
+
+    #[test]
+    fn serialized_handoff_reads_back() {
+        let regions = [
+            MemoryRegion { base: 0, length: 0x9F000, kind: region_kind::USABLE, reserved: 0, attributes: 0xF },
+            MemoryRegion { base: 0x10_0000, length: 0x40_0000, kind: region_kind::LOADER_RECLAIMABLE, reserved: 0, attributes: 0xF },
+        ];
+        let initrd = *b"initrd-bytes";
+        let modules = [Module { base: addr(&initrd), size: initrd.len() as u64, kind: ModuleKind::Initrd as u32, reserved: 0 }];
+        let cmdline = *b"console=ttyS0 quiet\0";
+        let symtab = [0u8; 48];
+        let strtab = *b"\0_start\0";
+
+        let mut bh = BootHandoffV1::new();
+        bh.mmap = MemoryMap {
+            ptr: addr(&regions),
+            entry_size: size_of::<MemoryRegion>() as u32,
+            entry_count: regions.len() as u32,
+            desc_version: MMAP_FORMAT_NONOS_V1,
+            reserved: 0,
+        };
+        bh.modules = Modules { ptr: addr(&modules), count: 1, reserved: 0 };
+        bh.cmdline_ptr = addr(&cmdline);
+        bh.symbols = SymbolTable {
+            symtab_ptr: addr(&symtab),
+            symtab_size: 48,
+            symtab_entsize: 24,
+            strtab_ptr: addr(&strtab),
+            strtab_size: strtab.len() as u64,
+        };
+
+        // serialize, then read back from an aligned copy the way the kernel would
+        let page = Box::new(BootHandoffV1::from_bytes(&bh.to_bytes()).unwrap());
+        let r = unsafe { HandoffReader::from_phys(&*page as *const BootHandoffV1 as u64, 0) }.unwrap();
+
+        assert_eq!(r.memory_regions(), Some(&regions[..]));
+        assert_eq!(r.modules().len(), 1);
+        assert_eq!(ModuleKind::from_raw(r.modules()[0].kind), Some(ModuleKind::Initrd));
+        assert_eq!(r.module_data(&r.modules()[0]), b"initrd-bytes");
+        assert_eq!(r.cmdline(), Some("console=ttyS0 quiet"));
+        assert_eq!(r.symbols(), Some((&symtab[..], &strtab[..])));
+        assert!(r.framebuffer().is_none());
+    }
+
+    #[test]
+    fn absent_and_foreign_fields() {
+        let mut bh = BootHandoffV1::new();
+        bh.mmap.desc_version = 1; // raw UEFI map
+        bh.mmap.ptr = 0x1000;
+        let r = unsafe { HandoffReader::new(&bh, 0) }.unwrap();
+        assert_eq!(r.memory_regions(), None);
+        assert!(r.modules().is_empty());
+        assert_eq!(r.cmdline(), None);
+        assert_eq!(r.symbols(), None);
+
+        assert_eq!(unsafe { HandoffReader::from_phys(0, 0) }.err(), Some(HandoffError::Null));
+        bh.version = 3;
+        assert_eq!(unsafe { HandoffReader::new(&bh, 0) }.err(), Some(HandoffError::UnsupportedVersion(3)));
+    }
+
+    #[test]
+    fn tags_follow_the_header() {
+        // one 8-aligned allocation holding header + tag list, as the loader lays it out
+        let mut page = vec![0u64; 128];
+        let bytes = unsafe { core::slice::from_raw_parts_mut(page.as_mut_ptr() as *mut u8, 1024) };
+        let mut bh = BootHandoffV1::new();
+        bh.flags = flags::TAGS;
+        bytes[..crate::HANDOFF_SIZE].copy_from_slice(&bh.to_bytes());
+        let mut w = crate::TagWriter::new(&mut bytes[bh.tags_offset()..]).unwrap();
+        w.push(crate::tag::CMDLINE, 1, b"init=/sbin/nonos\0").unwrap();
+        w.finish().unwrap();
+
+        let r = unsafe { HandoffReader::from_phys(page.as_ptr() as u64, 0) }.unwrap();
+        let tags = r.tags().unwrap();
+        assert_eq!(tags.get(crate::tag::CMDLINE).and_then(|t| t.as_str()), Some("init=/sbin/nonos"));
+
+        let mut plain = BootHandoffV1::new();
+        plain.flags = 0;
+        assert!(unsafe { HandoffReader::new(&plain, 0) }.unwrap().tags().is_none());
+    }
+}
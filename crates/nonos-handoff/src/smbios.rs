@@ -0,0 +1,93 @@
+//! System identity and memory devices from SMBIOS (`tag::SMBIOS_INFO`).
+//!
+//! The tag payload is an `SmbiosIdentity` followed by `memory_device_count`
+//! `SmbiosMemoryDevice`s, one per type 17 structure in table order (empty
+//! slots included). `BootHandoffV1.smbios` and `tag::SMBIOS` still point at
+//! the raw entry point for kernels that parse the table themselves.
+//!
+//! Strings are copied NUL-padded and cut at the field size; `text` reads
+//! them back.
+
+use core::mem::size_of;
+
+/// `SmbiosIdentity.flags` bits
+pub mod smbios_flags {
+    /// `uuid` holds a real system UUID (not absent, all-zero or all-ones)
+    pub const UUID_VALID: u32 = 1 << 0;
+}
+
+/// A NUL-padded string field up to its first NUL; invalid UTF-8 reads as empty
+pub fn text(field: &[u8]) -> &str {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    core::str::from_utf8(&field[..end]).unwrap_or("")
+}
+
+/// `tag::SMBIOS_INFO` v1 payload header
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SmbiosIdentity {
+    /// Type 1 UUID in RFC 4122 byte order, whatever the SMBIOS version stored
+    pub uuid: [u8; 16],
+    pub flags: u32,
+    pub memory_device_count: u32,
+    /// SMBIOS version from the entry point
+    pub major: u8,
+    pub minor: u8,
+    pub reserved: [u8; 6],
+    /// Type 1 system manufacturer, product name and serial number
+    pub manufacturer: [u8; 64],
+    pub product: [u8; 64],
+    pub serial: [u8; 64],
+    /// Type 0 BIOS vendor and version
+    pub bios_vendor: [u8; 64],
+    pub bios_version: [u8; 64],
+}
+
+impl Default for SmbiosIdentity {
+    fn default() -> Self {
+        SmbiosIdentity {
+            uuid: [0; 16],
+            flags: 0,
+            memory_device_count: 0,
+            major: 0,
+            minor: 0,
+            reserved: [0; 6],
+            manufacturer: [0; 64],
+            product: [0; 64],
+            serial: [0; 64],
+            bios_vendor: [0; 64],
+            bios_version: [0; 64],
+        }
+    }
+}
+
+impl SmbiosIdentity {
+    pub fn uuid(&self) -> Option<[u8; 16]> {
+        (self.flags & smbios_flags::UUID_VALID != 0).then_some(self.uuid)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SmbiosMemoryDevice {
+    /// Bytes installed, 0 for an empty slot or unknown size
+    pub size: u64,
+    /// Rated and configured speed in MT/s, 0 if unknown
+    pub speed_mts: u32,
+    pub configured_speed_mts: u32,
+    /// Data width in bits
+    pub data_width: u16,
+    /// SMBIOS memory type (0x1A DDR4, 0x22 DDR5, ...) and form factor (0x09 DIMM, 0x0D SODIMM, ...)
+    pub memory_type: u8,
+    pub form_factor: u8,
+    pub reserved: u32,
+    /// Device locator ("DIMM_A1"), module manufacturer and part number
+    pub locator: [u8; 32],
+    pub manufacturer: [u8; 32],
+    pub part_number: [u8; 32],
+}
+
+const _: () = {
+    assert!(size_of::<SmbiosIdentity>() == 352);
+    assert!(size_of::<SmbiosMemoryDevice>() == 120);
+};
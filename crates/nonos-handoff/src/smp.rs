@@ -0,0 +1,83 @@
+//! Application processors parked by the loader (`tag::SMP`).
+//!
+//! The tag payload is `SmpCpu[]`, one entry per enabled MADT processor, the
+//! BSP included. Every parked AP spins on its own `ApMailbox`:
+//!
+//! 1. The kernel stores `extra_argument` (optional), then `goto_address`
+//!    with Release ordering.
+//! 2. The AP sees a non-zero `goto_address`, sets `state` to `STARTED` and
+//!    jumps there with RDI = mailbox address, interrupts disabled.
+//!
+//! At that point the AP runs in 64-bit mode on the loader's parking stack
+//! (`stack_top`, LOADER_DATA), the loader's GDT (code 0x08, data 0x10, in a
+//! LOADER_CODE page below 1 MiB), no IDT, and the page tables the BSP had at
+//! ExitBootServices. The kernel must load its own GDT, IDT, CR3 and stack
+//! before it reclaims boot services memory, and must not reclaim loader
+//! memory while any AP is still parked.
+
+use core::mem::{offset_of, size_of};
+use core::sync::atomic::{AtomicU32, AtomicU64};
+
+/// `ApMailbox.state` values
+pub mod ap_state {
+    /// AP has not reached the parking loop (yet)
+    pub const OFFLINE: u32 = 0;
+    pub const PARKED: u32 = 1;
+    /// AP read `goto_address` and left the loader
+    pub const STARTED: u32 = 2;
+}
+
+/// `SmpCpu.flags` bits
+pub mod smp_cpu_flags {
+    pub const BSP: u32 = 1 << 0;
+    /// `mailbox` is valid and the AP reached the parking loop
+    pub const PARKED: u32 = 1 << 1;
+    /// Listed as online-capable rather than enabled; the loader left it alone
+    pub const ONLINE_CAPABLE: u32 = 1 << 2;
+    /// Came from an x2APIC entry (APIC ID may exceed 255)
+    pub const X2APIC: u32 = 1 << 3;
+}
+
+/// Per-AP mailbox, one cache line each
+#[repr(C, align(64))]
+#[derive(Debug, Default)]
+pub struct ApMailbox {
+    pub apic_id: u32,
+    pub state: AtomicU32,
+    /// Written by the kernel; `extern "sysv64" fn(*mut ApMailbox) -> !`
+    pub goto_address: AtomicU64,
+    pub extra_argument: AtomicU64,
+    /// Top of the parking stack the AP is running on
+    pub stack_top: u64,
+    pub reserved: [u64; 4],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SmpCpu {
+    pub apic_id: u32,
+    /// ACPI processor UID from the MADT
+    pub acpi_uid: u32,
+    pub flags: u32,
+    pub reserved: u32,
+    /// Physical address of the `ApMailbox`, 0 unless `PARKED`
+    pub mailbox: u64,
+}
+
+impl SmpCpu {
+    pub fn is_bsp(&self) -> bool {
+        self.flags & smp_cpu_flags::BSP != 0
+    }
+
+    pub fn is_parked(&self) -> bool {
+        self.flags & smp_cpu_flags::PARKED != 0 && self.mailbox != 0
+    }
+}
+
+const _: () = {
+    assert!(size_of::<ApMailbox>() == 64);
+    assert!(offset_of!(ApMailbox, goto_address) == 8);
+    assert!(offset_of!(ApMailbox, extra_argument) == 16);
+    assert!(offset_of!(ApMailbox, stack_top) == 24);
+    assert!(size_of::<SmpCpu>() == 24);
+};
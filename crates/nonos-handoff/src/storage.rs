@@ -0,0 +1,127 @@
+//! Block devices and GPT partitions handed to the kernel.
+//!
+//! `tag::BLOCK_DEVICES` is a `BlockDevice[]` in discovery order and
+//! `tag::PARTITIONS` a `GptPartition[]` whose `device` indexes it. Only
+//! whole devices are listed; firmware's per-partition BlockIO instances
+//! show up as partitions of their disk instead.
+//!
+//! GUIDs are in the on-disk (EFI_GUID, mixed-endian) byte order. A partition
+//! is listed only if the GPT it came from passed both CRC32 checks;
+//! `gpt_flags` says which of the primary and backup copies did.
+
+use core::mem::size_of;
+
+/// `BlockDevice.flags` bits
+pub mod block_flags {
+    pub const REMOVABLE: u32 = 1 << 0;
+    pub const MEDIA_PRESENT: u32 = 1 << 1;
+    pub const READ_ONLY: u32 = 1 << 2;
+    /// The device path did not fit `device_path` and was cut short
+    pub const PATH_TRUNCATED: u32 = 1 << 3;
+}
+
+/// `BlockDevice.gpt_flags` bits
+pub mod gpt_flags {
+    /// Primary header at LBA 1 and its entry array passed their CRC32
+    pub const PRIMARY_OK: u32 = 1 << 0;
+    /// Backup header at the alternate LBA and its entry array passed their CRC32
+    pub const BACKUP_OK: u32 = 1 << 1;
+    /// Both copies are valid but describe different partitions
+    pub const MISMATCH: u32 = 1 << 2;
+    /// LBA 0 holds a protective MBR (type 0xEE)
+    pub const PROTECTIVE_MBR: u32 = 1 << 3;
+}
+
+/// GPT type GUID (c4b5b386-9f19-4b03-85b8-67e41a7a13cc) of a partition
+/// that holds a signed kernel capsule as raw bytes from LBA 0 of the
+/// partition, with no filesystem. On-disk byte order, like `type_guid`.
+pub const NONOS_CAPSULE_TYPE: [u8; 16] = [
+    0x86, 0xb3, 0xb5, 0xc4, 0x19, 0x9f, 0x03, 0x4b, 0x85, 0xb8, 0x67, 0xe4, 0x1a, 0x7a, 0x13, 0xcc,
+];
+
+/// Bytes of `BlockDevice.device_path`
+pub const DEVICE_PATH_LEN: usize = 128;
+/// UTF-16 code units of a GPT partition name
+pub const PARTITION_NAME_LEN: usize = 36;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockDevice {
+    pub media_id: u32,
+    pub block_size: u32,
+    /// Last addressable LBA; the device holds `last_block + 1` blocks
+    pub last_block: u64,
+    pub flags: u32,
+    pub gpt_flags: u32,
+    /// GPT disk GUID, zero without a valid GPT
+    pub disk_guid: [u8; 16],
+    /// UEFI text form of the device path, ASCII, NUL padded
+    pub device_path: [u8; DEVICE_PATH_LEN],
+}
+
+impl Default for BlockDevice {
+    fn default() -> Self {
+        BlockDevice {
+            media_id: 0,
+            block_size: 0,
+            last_block: 0,
+            flags: 0,
+            gpt_flags: 0,
+            disk_guid: [0; 16],
+            device_path: [0; DEVICE_PATH_LEN],
+        }
+    }
+}
+
+impl BlockDevice {
+    pub fn size(&self) -> u64 {
+        (self.last_block + 1).saturating_mul(self.block_size as u64)
+    }
+
+    pub fn has_gpt(&self) -> bool {
+        self.gpt_flags & (gpt_flags::PRIMARY_OK | gpt_flags::BACKUP_OK) != 0
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GptPartition {
+    /// Index into the `BlockDevice` list
+    pub device: u32,
+    /// 1-based slot in the GPT entry array
+    pub number: u32,
+    pub type_guid: [u8; 16],
+    pub unique_guid: [u8; 16],
+    pub first_lba: u64,
+    /// Inclusive
+    pub last_lba: u64,
+    pub attributes: u64,
+    /// UTF-16LE, NUL padded
+    pub name: [u16; PARTITION_NAME_LEN],
+}
+
+impl Default for GptPartition {
+    fn default() -> Self {
+        GptPartition {
+            device: 0,
+            number: 0,
+            type_guid: [0; 16],
+            unique_guid: [0; 16],
+            first_lba: 0,
+            last_lba: 0,
+            attributes: 0,
+            name: [0; PARTITION_NAME_LEN],
+        }
+    }
+}
+
+impl GptPartition {
+    pub fn blocks(&self) -> u64 {
+        self.last_lba.saturating_sub(self.first_lba) + 1
+    }
+}
+
+const _: () = {
+    assert!(size_of::<BlockDevice>() == 168);
+    assert!(size_of::<GptPartition>() == 136);
+};
@@ -0,0 +1,613 @@
+//! Tag list following the fixed `BootHandoffV1` header.
+//!
+//! When `flags::TAGS` is set, a tag list starts at the first 8-byte boundary
+//! after `BootHandoffV1.size` bytes, in the same allocation as the header:
+//!
+//! ```text
+//!   TagListHeader { magic "NTAG", version, total_size, count }
+//!   TagHeader { tag, version, size } payload[size] pad to 8
+//!   ...
+//!   TagHeader { tag: END, size: 0 }
+//! ```
+//!
+//! Each tag carries its own version, so payloads can grow (append-only, a
+//! larger `size` with the same version is fine) or change (new version)
+//! without touching `HANDOFF_VERSION`. Kernels skip tags they do not know
+//! and tags whose version they do not understand.
+//!
+//! | tag          | v | payload                                               |
+//! |--------------|---|-------------------------------------------------------|
+//! | CMDLINE      | 1 | UTF-8 bytes, NUL terminated                           |
+//! | MEMORY_MAP   | 1 | `MemoryMap` (same meaning as the header field)        |
+//! | FRAMEBUFFERS | 1 | `FramebufferInfo[]`, the active mode first            |
+//! | MODULES      | 1 | `Module[]`                                            |
+//! | ACPI         | 1 | `AcpiTag`                                             |
+//! | SMBIOS       | 1 | `SmbiosTag`                                           |
+//! | EVENT_LOG    | 1 | `EventLogTag`                                         |
+//! | BOOT_LOG     | 1 | `BootLogTag`, region layout in `bootlog`              |
+//! | SMP          | 1 | `SmpCpu[]`, BSP included; protocol in `smp`           |
+//! | ATTESTATION  | 1 | `AttestationTag`                                      |
+//! | TIMING       | 1 | `PhaseTiming[]`, `boot_phase::LOADER` first           |
+//! | EFI_RUNTIME  | 1 | `EfiRuntimeTag` + `EfiRuntimeRange[]`                 |
+//! | ACPI_TABLES  | 1 | `AcpiTableEntry[]`, checksummed tables only           |
+//! | PCI          | 1 | `PciFunction[]` in scan order                         |
+//! | SMBIOS_INFO  | 1 | `SmbiosIdentity` + `SmbiosMemoryDevice[]`             |
+//! | CPU          | 1 | `CpuTag`, `cpu_feature` bitmaps, shadow stack         |
+//! | TSC          | 1 | `TscCalibration`, how `timing.tsc_hz` was obtained    |
+//! | BAD_PAGES    | 1 | `BadPagesTag` + `BadPage[]` from the memory test      |
+//! | BLOCK_DEVICES| 1 | `BlockDevice[]`, whole devices in discovery order     |
+//! | PARTITIONS   | 1 | `GptPartition[]` from CRC-checked GPTs                |
+//!
+//! Values from `tag::VENDOR_BASE` upward are free for out-of-tree loaders.
+
+use crate::bootlog::BootLogTag;
+use crate::cpu::CpuTag;
+use crate::efi_runtime::{EfiRuntimeRange, EfiRuntimeTag};
+use crate::memtest::{BadPage, BadPagesTag};
+use crate::pci::PciFunction;
+use crate::smbios::{SmbiosIdentity, SmbiosMemoryDevice};
+use crate::smp::SmpCpu;
+use crate::storage::{BlockDevice, GptPartition};
+use crate::{FramebufferInfo, MemoryMap, MemoryRegion, Module};
+use core::fmt;
+use core::mem::{offset_of, size_of};
+
+pub const TAG_LIST_MAGIC: u32 = u32::from_le_bytes(*b"NTAG");
+pub const TAG_LIST_VERSION: u16 = 1;
+pub const TAG_ALIGN: usize = 8;
+/// Upper bound a reader accepts for `TagListHeader.total_size`
+pub const TAG_LIST_MAX: usize = 1024 * 1024;
+
+/// `TagHeader.tag` values
+pub mod tag {
+    pub const END: u32 = 0;
+    pub const CMDLINE: u32 = 1;
+    pub const MEMORY_MAP: u32 = 2;
+    pub const FRAMEBUFFERS: u32 = 3;
+    pub const MODULES: u32 = 4;
+    pub const ACPI: u32 = 5;
+    pub const SMBIOS: u32 = 6;
+    pub const EVENT_LOG: u32 = 7;
+    pub const BOOT_LOG: u32 = 8;
+    pub const SMP: u32 = 9;
+    pub const ATTESTATION: u32 = 10;
+    pub const TIMING: u32 = 11;
+    pub const EFI_RUNTIME: u32 = 12;
+    pub const ACPI_TABLES: u32 = 13;
+    pub const PCI: u32 = 14;
+    pub const SMBIOS_INFO: u32 = 15;
+    pub const CPU: u32 = 16;
+    pub const TSC: u32 = 17;
+    pub const BAD_PAGES: u32 = 18;
+    pub const BLOCK_DEVICES: u32 = 19;
+    pub const PARTITIONS: u32 = 20;
+    pub const VENDOR_BASE: u32 = 0x8000_0000;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TagListHeader {
+    pub magic: u32,
+    pub version: u16,
+    pub reserved: u16,
+    /// Bytes from the start of this header through the END tag
+    pub total_size: u32,
+    /// Tags before END
+    pub count: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TagHeader {
+    pub tag: u32,
+    pub version: u16,
+    pub reserved: u16,
+    /// Payload bytes, excluding this header and trailing padding
+    pub size: u32,
+    pub reserved2: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AcpiTag {
+    pub rsdp: u64,
+    /// RSDP revision: 0 = ACPI 1.0 (RSDT only), 2+ = XSDT available
+    pub revision: u8,
+    pub reserved: [u8; 7],
+}
+
+/// One ACPI table the loader found through the RSDT/XSDT (or the FADT, for
+/// the DSDT) and whose checksum it verified
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AcpiTableEntry {
+    pub signature: [u8; 4],
+    pub length: u32,
+    pub phys: u64,
+    pub revision: u8,
+    pub reserved: [u8; 7],
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SmbiosTag {
+    pub entry: u64,
+    pub major: u8,
+    pub minor: u8,
+    /// 2 for a `_SM_` entry point, 3 for `_SM3_`
+    pub entry_kind: u8,
+    pub reserved: [u8; 5],
+}
+
+/// `EventLogTag.format` values (EFI_TCG2_EVENT_LOG_FORMAT)
+pub mod event_log_format {
+    pub const TCG_1_2: u32 = 1;
+    pub const TCG_2: u32 = 2;
+}
+
+/// `EventLogTag.flags` bits
+pub mod event_log_flags {
+    /// Firmware ran out of log space; the log does not cover every extend
+    pub const TRUNCATED: u32 = 1 << 0;
+}
+
+/// TPM event log copied into loader memory before ExitBootServices.
+/// Events logged after the copy are in the firmware final events table.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EventLogTag {
+    pub format: u32,
+    pub flags: u32,
+    pub ptr: u64,
+    pub size: u64,
+    /// EFI_TCG2_FINAL_EVENTS_TABLE, 0 if firmware has none
+    pub final_events: u64,
+}
+
+/// `AttestationTag.flags` bits
+pub mod attestation_flags {
+    pub const KERNEL_SIG_OK: u32 = 1 << 0;
+    pub const SECURE_BOOT: u32 = 1 << 1;
+    pub const MEASURED_BOOT: u32 = 1 << 2;
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AttestationTag {
+    pub kernel_sha256: [u8; 32],
+    pub flags: u32,
+    /// PCR the kernel image was extended into
+    pub kernel_pcr: u32,
+    /// PCR boot modules were extended into
+    pub modules_pcr: u32,
+    pub reserved: u32,
+}
+
+/// `PhaseTiming.phase` values
+pub mod boot_phase {
+    /// Loader entry up to the jump into the kernel; spans all the others
+    pub const LOADER: u32 = 0;
+    pub const CONFIG: u32 = 1;
+    pub const SECURITY: u32 = 2;
+    pub const HARDWARE: u32 = 3;
+    pub const NETWORK: u32 = 4;
+    /// Kernel capsule read, signature check and measurement
+    pub const VERIFICATION: u32 = 5;
+    /// ELF segments and boot modules placed
+    pub const LOAD: u32 = 6;
+    pub const EXIT_BOOT_SERVICES: u32 = 7;
+    pub const COUNT: usize = 8;
+
+    pub fn name(phase: u32) -> &'static str {
+        match phase {
+            LOADER => "loader",
+            CONFIG => "config",
+            SECURITY => "security",
+            HARDWARE => "hardware",
+            NETWORK => "network",
+            VERIFICATION => "verification",
+            LOAD => "load",
+            EXIT_BOOT_SERVICES => "exit-boot-services",
+            _ => "?",
+        }
+    }
+}
+
+/// One loader phase in raw TSC ticks; `BootHandoffV1.timing.tsc_hz` converts them
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PhaseTiming {
+    pub phase: u32,
+    pub reserved: u32,
+    pub start_tsc: u64,
+    pub end_tsc: u64,
+}
+
+impl PhaseTiming {
+    pub fn ticks(&self) -> u64 {
+        self.end_tsc.saturating_sub(self.start_tsc)
+    }
+
+    /// Duration in microseconds, 0 when `tsc_hz` is unknown
+    pub fn micros(&self, tsc_hz: u64) -> u64 {
+        (self.ticks() as u128 * 1_000_000).checked_div(tsc_hz as u128).unwrap_or(0) as u64
+    }
+}
+
+/// `TscCalibration.method` values, in the order the loader tries them
+pub mod tsc_method {
+    pub const NONE: u32 = 0;
+    /// CPUID 15h crystal frequency times the TSC/crystal ratio
+    pub const CPUID_15H: u32 = 1;
+    /// CPUID 15h ratio with the crystal derived from the CPUID 16h base frequency
+    pub const CPUID_16H: u32 = 2;
+    /// Measured against the ACPI PM timer (3.579545 MHz)
+    pub const PM_TIMER: u32 = 3;
+    /// Measured against the HPET main counter
+    pub const HPET: u32 = 4;
+    /// Measured over a firmware Stall() window
+    pub const STALL: u32 = 5;
+
+    pub fn name(method: u32) -> &'static str {
+        match method {
+            NONE => "none",
+            CPUID_15H => "cpuid-15h",
+            CPUID_16H => "cpuid-16h",
+            PM_TIMER => "pm-timer",
+            HPET => "hpet",
+            STALL => "stall",
+            _ => "?",
+        }
+    }
+}
+
+/// `TscCalibration.flags` bits
+pub mod tsc_flags {
+    /// CPUID.80000007h:EDX[8]: the rate survives P-, C- and T-state changes
+    pub const INVARIANT: u32 = 1 << 0;
+}
+
+/// `tag::TSC` v1 payload
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TscCalibration {
+    /// Same value as `BootHandoffV1.timing.tsc_hz`; 0 if every method failed
+    pub hz: u64,
+    pub method: u32,
+    /// Error bound of the method in parts per million, not counting the
+    /// reference oscillator's own tolerance (so 0 for `CPUID_15H`)
+    pub error_ppm: u32,
+    pub flags: u32,
+    pub reserved: u32,
+}
+
+/// Types that can be copied byte-for-byte into and out of a tag.
+///
+/// # Safety
+/// Implementors are `repr(C)` with no implicit padding and accept any bit pattern.
+pub unsafe trait TagPayload: Copy {}
+
+unsafe impl TagPayload for u8 {}
+unsafe impl TagPayload for u32 {}
+unsafe impl TagPayload for u64 {}
+unsafe impl TagPayload for MemoryMap {}
+unsafe impl TagPayload for MemoryRegion {}
+unsafe impl TagPayload for FramebufferInfo {}
+unsafe impl TagPayload for Module {}
+unsafe impl TagPayload for AcpiTag {}
+unsafe impl TagPayload for AcpiTableEntry {}
+unsafe impl TagPayload for SmbiosTag {}
+unsafe impl TagPayload for EventLogTag {}
+unsafe impl TagPayload for AttestationTag {}
+unsafe impl TagPayload for BootLogTag {}
+unsafe impl TagPayload for PhaseTiming {}
+unsafe impl TagPayload for SmpCpu {}
+unsafe impl TagPayload for EfiRuntimeTag {}
+unsafe impl TagPayload for EfiRuntimeRange {}
+unsafe impl TagPayload for PciFunction {}
+unsafe impl TagPayload for SmbiosIdentity {}
+unsafe impl TagPayload for SmbiosMemoryDevice {}
+unsafe impl TagPayload for CpuTag {}
+unsafe impl TagPayload for TscCalibration {}
+unsafe impl TagPayload for BadPagesTag {}
+unsafe impl TagPayload for BadPage {}
+unsafe impl TagPayload for BlockDevice {}
+unsafe impl TagPayload for GptPartition {}
+
+fn bytes_of<T: TagPayload>(items: &[T]) -> &[u8] {
+    // safe: TagPayload types have no padding, so every byte is initialised
+    unsafe { core::slice::from_raw_parts(items.as_ptr() as *const u8, core::mem::size_of_val(items)) }
+}
+
+const fn align_up(n: usize) -> usize {
+    (n + TAG_ALIGN - 1) & !(TAG_ALIGN - 1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagError {
+    Misaligned,
+    NoSpace { tag: u32, needed: usize, free: usize },
+    TooLarge(u32),
+}
+
+impl fmt::Display for TagError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::Misaligned => write!(f, "tag buffer is not 8-byte aligned"),
+            TagError::NoSpace { tag, needed, free } => {
+                write!(f, "tag {} needs {} bytes, {} free", tag, needed, free)
+            }
+            TagError::TooLarge(tag) => write!(f, "tag {} payload exceeds u32", tag),
+        }
+    }
+}
+
+/// Appends tags into a fixed buffer; no allocation, usable after ExitBootServices.
+pub struct TagWriter<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    count: u32,
+}
+
+impl<'a> TagWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Result<Self, TagError> {
+        if !(buf.as_ptr() as usize).is_multiple_of(TAG_ALIGN) {
+            return Err(TagError::Misaligned);
+        }
+        // END must always fit after the list header
+        let reserve = size_of::<TagListHeader>() + size_of::<TagHeader>();
+        if buf.len() < reserve {
+            return Err(TagError::NoSpace { tag: tag::END, needed: reserve, free: buf.len() });
+        }
+        Ok(TagWriter { buf, len: size_of::<TagListHeader>(), count: 0 })
+    }
+
+    fn put(&mut self, tag: u32, version: u16, parts: &[&[u8]]) -> Result<usize, TagError> {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+        let size = u32::try_from(len).map_err(|_| TagError::TooLarge(tag))?;
+        let needed = size_of::<TagHeader>() + align_up(len);
+        // keep room for the END tag unless this is it
+        let end_room = if tag == tag::END { 0 } else { size_of::<TagHeader>() };
+        let free = self.buf.len() - self.len;
+        if needed + end_room > free {
+            return Err(TagError::NoSpace { tag, needed, free: free.saturating_sub(end_room) });
+        }
+        let header = TagHeader { tag, version, reserved: 0, size, reserved2: 0 };
+        // safe: bounds checked above; self.len stays 8-aligned so the header write is aligned
+        unsafe { core::ptr::write(self.buf.as_mut_ptr().add(self.len) as *mut TagHeader, header) };
+        let payload_off = self.len + size_of::<TagHeader>();
+        let mut at = payload_off;
+        for p in parts {
+            self.buf[at..at + p.len()].copy_from_slice(p);
+            at += p.len();
+        }
+        self.buf[at..self.len + needed].fill(0);
+        self.len += needed;
+        Ok(payload_off)
+    }
+
+    /// Append a raw payload; returns its offset within the buffer.
+    pub fn push(&mut self, tag: u32, version: u16, payload: &[u8]) -> Result<usize, TagError> {
+        self.count += 1;
+        self.put(tag, version, &[payload]).inspect_err(|_| self.count -= 1)
+    }
+
+    /// Append `s` plus a NUL terminator (counted in the payload size)
+    pub fn push_str(&mut self, tag: u32, version: u16, s: &str) -> Result<usize, TagError> {
+        self.count += 1;
+        self.put(tag, version, &[s.as_bytes(), &[0]]).inspect_err(|_| self.count -= 1)
+    }
+
+    pub fn push_struct<T: TagPayload>(&mut self, tag: u32, version: u16, value: &T) -> Result<usize, TagError> {
+        self.push(tag, version, bytes_of(core::slice::from_ref(value)))
+    }
+
+    pub fn push_slice<T: TagPayload>(&mut self, tag: u32, version: u16, items: &[T]) -> Result<usize, TagError> {
+        self.push(tag, version, bytes_of(items))
+    }
+
+    /// Append `head` directly followed by `items` as one payload
+    pub fn push_struct_slice<H: TagPayload, T: TagPayload>(
+        &mut self,
+        tag: u32,
+        version: u16,
+        head: &H,
+        items: &[T],
+    ) -> Result<usize, TagError> {
+        self.count += 1;
+        self.put(tag, version, &[bytes_of(core::slice::from_ref(head)), bytes_of(items)]).inspect_err(|_| self.count -= 1)
+    }
+
+    /// Terminate the list and write its header; returns the total size.
+    pub fn finish(mut self) -> Result<usize, TagError> {
+        self.put(tag::END, 0, &[])?;
+        let header = TagListHeader {
+            magic: TAG_LIST_MAGIC,
+            version: TAG_LIST_VERSION,
+            reserved: 0,
+            total_size: self.len as u32,
+            count: self.count,
+        };
+        // safe: the buffer is 8-aligned and at least a list header long (checked in new)
+        unsafe { core::ptr::write(self.buf.as_mut_ptr() as *mut TagListHeader, header) };
+        Ok(self.len)
+    }
+}
+
+/// One entry of a parsed tag list
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tag<'a> {
+    pub tag: u32,
+    pub version: u16,
+    pub data: &'a [u8],
+}
+
+impl<'a> Tag<'a> {
+    /// Leading `T` of the payload; `None` if the payload is shorter
+    pub fn read<T: TagPayload>(&self) -> Option<T> {
+        if self.data.len() < size_of::<T>() {
+            return None;
+        }
+        // safe: length checked; TagPayload accepts any bit pattern
+        Some(unsafe { core::ptr::read_unaligned(self.data.as_ptr() as *const T) })
+    }
+
+    /// Payload as a packed array of `T`; a trailing partial element is ignored
+    pub fn items<T: TagPayload>(&self) -> impl Iterator<Item = T> + 'a {
+        self.items_from(0)
+    }
+
+    /// Packed array of `T` starting `offset` bytes into the payload
+    pub fn items_from<T: TagPayload>(&self, offset: usize) -> impl Iterator<Item = T> + 'a {
+        let data = self.data.get(offset..).unwrap_or(&[]);
+        let n = data.len().checked_div(size_of::<T>()).unwrap_or(0);
+        // safe: i < n keeps every read inside data
+        (0..n).map(move |i| unsafe { core::ptr::read_unaligned(data.as_ptr().add(i * size_of::<T>()) as *const T) })
+    }
+
+    /// CMDLINE payload without its terminator
+    pub fn as_str(&self) -> Option<&'a str> {
+        let bytes = self.data.split(|&b| b == 0).next()?;
+        core::str::from_utf8(bytes).ok()
+    }
+}
+
+/// Iterator over a validated tag list. Stops at END or at the first tag that
+/// would run past `total_size`.
+#[derive(Clone)]
+pub struct Tags<'a> {
+    data: &'a [u8],
+    off: usize,
+}
+
+impl<'a> Tags<'a> {
+    /// Validate the list header at the start of `data`
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        if data.len() < size_of::<TagListHeader>() {
+            return None;
+        }
+        // safe: length checked
+        let h = unsafe { core::ptr::read_unaligned(data.as_ptr() as *const TagListHeader) };
+        let total = h.total_size as usize;
+        if h.magic != TAG_LIST_MAGIC || h.version != TAG_LIST_VERSION || total > data.len() || total > TAG_LIST_MAX {
+            return None;
+        }
+        Some(Tags { data: &data[..total], off: size_of::<TagListHeader>() })
+    }
+
+    pub fn get(&self, tag: u32) -> Option<Tag<'a>> {
+        self.clone().find(|t: &Tag<'a>| t.tag == tag)
+    }
+}
+
+impl<'a> Iterator for Tags<'a> {
+    type Item = Tag<'a>;
+
+    fn next(&mut self) -> Option<Tag<'a>> {
+        let hdr_end = self.off.checked_add(size_of::<TagHeader>())?;
+        let raw = self.data.get(self.off..hdr_end)?;
+        // safe: raw is exactly one TagHeader long
+        let h = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const TagHeader) };
+        if h.tag == tag::END {
+            self.off = self.data.len();
+            return None;
+        }
+        let data = self.data.get(hdr_end..hdr_end.checked_add(h.size as usize)?)?;
+        self.off = hdr_end + align_up(h.size as usize);
+        Some(Tag { tag: h.tag, version: h.version, data })
+    }
+}
+
+const _: () = {
+    assert!(size_of::<TagListHeader>() == 16);
+    assert!(size_of::<TagHeader>() == 16);
+    assert!(offset_of!(TagHeader, size) == 8);
+    assert!(size_of::<AcpiTag>() == 16);
+    assert!(size_of::<AcpiTableEntry>() == 24);
+    assert!(size_of::<SmbiosTag>() == 16);
+    assert!(size_of::<EventLogTag>() == 32);
+    assert!(size_of::<AttestationTag>() == 48);
+    assert!(size_of::<PhaseTiming>() == 24);
+    assert!(size_of::<TscCalibration>() == 24);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ModuleKind;
+
+    #[test]
+    fn write_then_parse() {
+        let mut backing = [0u64; 64];
+        // safe: viewing the u64 array as bytes keeps 8-byte alignment
+        let buf = unsafe { core::slice::from_raw_parts_mut(backing.as_mut_ptr() as *mut u8, 512) };
+        let mut w = TagWriter::new(buf).unwrap();
+        w.push_str(tag::CMDLINE, 1, "root=/dev/nvme0n1p2").unwrap();
+        w.push_struct(tag::ACPI, 1, &AcpiTag { rsdp: 0x7FE1_4000, revision: 2, reserved: [0; 7] }).unwrap();
+        w.push(0x8000_0042, 3, &[1, 2, 3]).unwrap();
+        let mods = [
+            Module { base: 0x20_0000, size: 0x1000, kind: ModuleKind::Initrd as u32, reserved: 0 },
+            Module { base: 0x30_0000, size: 0x2000, kind: ModuleKind::Config as u32, reserved: 0 },
+        ];
+        w.push_slice(tag::MODULES, 1, &mods).unwrap();
+        let total = w.finish().unwrap();
+
+        let tags = Tags::parse(&buf[..total]).unwrap();
+        assert_eq!(tags.clone().count(), 4);
+        assert_eq!(tags.get(tag::CMDLINE).unwrap().as_str(), Some("root=/dev/nvme0n1p2"));
+        assert_eq!(tags.get(tag::ACPI).unwrap().read::<AcpiTag>().unwrap().revision, 2);
+        // an unknown vendor tag is skipped over cleanly
+        let vendor = tags.get(0x8000_0042).unwrap();
+        assert_eq!((vendor.version, vendor.data), (3, &[1u8, 2, 3][..]));
+        let back: [Module; 2] = {
+            let mut it = tags.get(tag::MODULES).unwrap().items::<Module>();
+            [it.next().unwrap(), it.next().unwrap()]
+        };
+        assert_eq!(back, mods);
+        assert!(tags.get(tag::SMP).is_none());
+    }
+
+    #[test]
+    fn header_then_items() {
+        let mut backing = [0u64; 32];
+        // safe: as above
+        let buf = unsafe { core::slice::from_raw_parts_mut(backing.as_mut_ptr() as *mut u8, 256) };
+        let mut w = TagWriter::new(buf).unwrap();
+        let ranges = [
+            EfiRuntimeRange { phys: 0x7F00_0000, virt: 0x7F00_0000, pages: 16, attribute: 1 << 63, efi_type: 5, reserved: 0 },
+            EfiRuntimeRange { phys: 0xFED0_0000, virt: 0xFED0_0000, pages: 1, attribute: 1 << 63, efi_type: 11, reserved: 0 },
+        ];
+        let head = EfiRuntimeTag { system_table: 0x7F10_0018, range_count: 2, ..Default::default() };
+        w.push_struct_slice(tag::EFI_RUNTIME, 1, &head, &ranges).unwrap();
+        let total = w.finish().unwrap();
+
+        let t = Tags::parse(&buf[..total]).unwrap().get(tag::EFI_RUNTIME).unwrap();
+        assert_eq!(t.data.len(), 40 + 2 * 40);
+        assert_eq!(t.read::<EfiRuntimeTag>(), Some(head));
+        let back: [EfiRuntimeRange; 2] = {
+            let mut it = t.items_from::<EfiRuntimeRange>(size_of::<EfiRuntimeTag>());
+            [it.next().unwrap(), it.next().unwrap()]
+        };
+        assert_eq!(back, ranges);
+    }
+
+    #[test]
+    fn bounded_and_defensive() {
+        let mut backing = [0u64; 8];
+        // safe: as above
+        let buf = unsafe { core::slice::from_raw_parts_mut(backing.as_mut_ptr() as *mut u8, 64) };
+        let mut w = TagWriter::new(buf).unwrap();
+        // 16 list + 16 tag + 8 payload + 16 END; a second 24-byte tag no longer fits
+        w.push(tag::CMDLINE, 1, b"quiet\0").unwrap();
+        assert!(matches!(w.push(tag::CMDLINE, 1, b"x"), Err(TagError::NoSpace { .. })));
+        let total = w.finish().unwrap();
+        assert_eq!(total, 56);
+
+        // a tag claiming more than the list holds ends iteration instead of overreading
+        buf[16 + 8..16 + 12].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(Tags::parse(buf).unwrap().count(), 0);
+
+        buf[0] ^= 0xFF;
+        assert!(Tags::parse(buf).is_none());
+    }
+}
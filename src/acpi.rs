@@ -0,0 +1,607 @@
+//! ACPI table discovery from the RSDP.
+//!
+//! Tables are read in place from firmware memory (ACPI reclaim/NVS, still
+//! mapped 1:1 under boot services). `walk` lists every table the RSDT/XSDT
+//! points at, plus the FADT's DSDT, with its checksum status; `find_table`
+//! only returns tables that passed. The parsers below work on plain byte
+//! slices so they can be tested against fixtures.
+
+#![allow(dead_code)]
+
+use crate::bytes::{u16_at, u32_at, u64_at};
+
+/// Standard SDT header length
+pub const SDT_HEADER_LEN: usize = 36;
+/// Largest table we agree to map as a slice
+const MAX_TABLE_LEN: usize = 1024 * 1024;
+
+/// Processors the loader keeps track of
+pub const MAX_CPUS: usize = 256;
+/// Directory entries kept by `walk`
+pub const MAX_TABLES: usize = 64;
+pub const MAX_IOAPICS: usize = 16;
+pub const MAX_OVERRIDES: usize = 16;
+pub const MAX_ECAM: usize = 16;
+
+/// MADT entry types
+const MADT_LOCAL_APIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_INT_OVERRIDE: u8 = 2;
+const MADT_LOCAL_APIC_OVERRIDE: u8 = 5;
+const MADT_LOCAL_X2APIC: u8 = 9;
+/// MADT processor flags
+const MADT_ENABLED: u32 = 1 << 0;
+const MADT_ONLINE_CAPABLE: u32 = 1 << 1;
+/// MADT header flags
+const MADT_PCAT_COMPAT: u32 = 1 << 0;
+
+/// FADT field offsets (ACPI 6.5, table 5.9)
+const FADT_FIRMWARE_CTRL: usize = 36;
+const FADT_DSDT: usize = 40;
+const FADT_SCI_INT: usize = 46;
+const FADT_PM_TMR_BLK: usize = 76;
+const FADT_PM_TMR_LEN: usize = 91;
+const FADT_FLAGS: usize = 112;
+const FADT_RESET_REG: usize = 116;
+const FADT_RESET_VALUE: usize = 128;
+const FADT_X_FIRMWARE_CTRL: usize = 132;
+const FADT_X_DSDT: usize = 140;
+const FADT_X_PM_TMR_BLK: usize = 208;
+/// FADT flags
+const FADT_TMR_VAL_EXT: u32 = 1 << 8;
+const FADT_RESET_REG_SUP: u32 = 1 << 10;
+const FADT_HW_REDUCED: u32 = 1 << 20;
+
+pub fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == 0
+}
+
+/// RSDP checks: signature and v1 checksum, plus length and extended
+/// checksum for revision 2+. `bytes` covers at least the v1 structure.
+pub fn rsdp_valid(bytes: &[u8]) -> bool {
+    if bytes.len() < 20 || &bytes[..8] != b"RSD PTR " || !checksum_ok(&bytes[..20]) {
+        return false;
+    }
+    if bytes[15] < 2 {
+        return true;
+    }
+    match u32_at(bytes, 20) {
+        Some(len) if len >= 36 && len as usize <= bytes.len() => checksum_ok(&bytes[..len as usize]),
+        _ => false,
+    }
+}
+
+/// RSDP bytes at `phys`, 36 for revision 2+ and 20 otherwise
+///
+/// # Safety
+/// `phys` must point at readable memory of at least 36 bytes (the RSDP config table entry).
+pub unsafe fn rsdp_at(phys: u64) -> &'static [u8] {
+    let v1 = core::slice::from_raw_parts(phys as *const u8, 20);
+    let len = if v1[15] >= 2 { 36 } else { 20 };
+    core::slice::from_raw_parts(phys as *const u8, len)
+}
+
+/// Table at `phys`, bounded by its length field but not checksummed
+///
+/// # Safety
+/// `phys` must point at an SDT in memory that stays mapped while the slice is used.
+unsafe fn raw_table(phys: u64) -> Option<&'static [u8]> {
+    if phys == 0 {
+        return None;
+    }
+    let head = core::slice::from_raw_parts(phys as *const u8, SDT_HEADER_LEN);
+    let len = u32_at(head, 4)? as usize;
+    if !(SDT_HEADER_LEN..=MAX_TABLE_LEN).contains(&len) {
+        return None;
+    }
+    Some(core::slice::from_raw_parts(phys as *const u8, len))
+}
+
+/// Length and checksum checked table at `phys`
+///
+/// # Safety
+/// As for `raw_table`.
+unsafe fn table_at(phys: u64) -> Option<&'static [u8]> {
+    raw_table(phys).filter(|t| checksum_ok(t))
+}
+
+/// Root table (XSDT when the RSDP has one, else RSDT) and its entry width
+fn root_table(rsdp: u64) -> Option<(&'static [u8], usize)> {
+    // safe: discover_acpi_rsdp validated the RSDP (both checksums for revision 2+)
+    let r = unsafe { rsdp_at(rsdp) };
+    if let Some(xsdt) = u64_at(r, 24).filter(|&x| x != 0) {
+        // safe: firmware-provided table address
+        if let Some(t) = unsafe { table_at(xsdt) } {
+            if &t[..4] == b"XSDT" {
+                return Some((t, 8));
+            }
+        }
+    }
+    // safe: firmware-provided table address
+    let rsdt = unsafe { table_at(u32_at(r, 16)? as u64)? };
+    (&rsdt[..4] == b"RSDT").then_some((rsdt, 4))
+}
+
+/// Physical addresses listed in an RSDT (4-byte) or XSDT (8-byte) body
+pub fn root_entries(root: &[u8], width: usize) -> impl Iterator<Item = u64> + '_ {
+    root.get(SDT_HEADER_LEN..).unwrap_or(&[]).chunks_exact(width).map(move |c| match width {
+        8 => u64::from_le_bytes(c.try_into().unwrap_or([0; 8])),
+        _ => u32::from_le_bytes(c.try_into().unwrap_or([0; 4])) as u64,
+    })
+}
+
+/// First table with `signature` reachable from the RSDP
+pub fn find_table(rsdp: u64, signature: &[u8; 4]) -> Option<&'static [u8]> {
+    let (root, width) = root_table(rsdp)?;
+    root_entries(root, width)
+        // safe: addresses come from a checksummed root table
+        .filter_map(|phys| unsafe { table_at(phys) })
+        .find(|t| &t[..4] == signature)
+}
+
+/// One table reachable from the RSDP
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TableRef {
+    pub signature: [u8; 4],
+    pub phys: u64,
+    pub length: u32,
+    pub revision: u8,
+    pub checksum_ok: bool,
+}
+
+impl TableRef {
+    fn of(phys: u64, t: &[u8]) -> Self {
+        TableRef {
+            signature: [t[0], t[1], t[2], t[3]],
+            phys,
+            length: t.len() as u32,
+            revision: t[8],
+            checksum_ok: checksum_ok(t),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        core::str::from_utf8(&self.signature).unwrap_or("????")
+    }
+}
+
+/// Every table in the root table, then the DSDT named by a valid FADT.
+/// Returns the number of entries written to `out`.
+pub fn walk(rsdp: u64, out: &mut [TableRef]) -> usize {
+    let Some((root, width)) = root_table(rsdp) else { return 0 };
+    let mut n = 0;
+    let mut dsdt = 0;
+    for phys in root_entries(root, width) {
+        // safe: addresses come from a checksummed root table
+        let Some(t) = (unsafe { raw_table(phys) }) else { continue };
+        let r = TableRef::of(phys, t);
+        if &r.signature == b"FACP" && r.checksum_ok {
+            dsdt = parse_fadt(t).map_or(0, |f| f.dsdt);
+        }
+        if n == out.len() {
+            return n;
+        }
+        out[n] = r;
+        n += 1;
+    }
+    // safe: address from a checksummed FADT
+    if let (Some(t), true) = (unsafe { raw_table(dsdt) }, n < out.len()) {
+        out[n] = TableRef::of(dsdt, t);
+        n += 1;
+    }
+    n
+}
+
+/// Entries after the MADT header as (type, bytes); stops at a malformed length
+fn madt_entries(madt: &[u8]) -> impl Iterator<Item = (u8, &[u8])> {
+    let mut off = SDT_HEADER_LEN + 8;
+    core::iter::from_fn(move || {
+        let (&ty, &len) = (madt.get(off)?, madt.get(off + 1)?);
+        let e = madt.get(off..off + len as usize).filter(|_| len >= 2)?;
+        off += len as usize;
+        Some((ty, e))
+    })
+}
+
+/// One processor from the MADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MadtCpu {
+    pub apic_id: u32,
+    pub acpi_uid: u32,
+    pub enabled: bool,
+    pub online_capable: bool,
+    pub x2apic: bool,
+}
+
+/// Processor entries of a MADT; disabled and not online-capable ones are
+/// skipped, duplicates (same APIC ID in both entry kinds) are listed once.
+pub fn madt_cpus(madt: &[u8], out: &mut [MadtCpu]) -> usize {
+    let mut n = 0;
+    for (ty, e) in madt_entries(madt) {
+        let cpu = match ty {
+            MADT_LOCAL_APIC if e.len() >= 8 => u32_at(e, 4).map(|flags| (e[3] as u32, e[2] as u32, flags, false)),
+            MADT_LOCAL_X2APIC if e.len() >= 16 => {
+                u32_at(e, 4).zip(u32_at(e, 8)).zip(u32_at(e, 12)).map(|((id, flags), uid)| (id, uid, flags, true))
+            }
+            _ => None,
+        };
+        let Some((apic_id, acpi_uid, flags, x2apic)) = cpu else { continue };
+        if flags & (MADT_ENABLED | MADT_ONLINE_CAPABLE) == 0 || out[..n].iter().any(|c| c.apic_id == apic_id) {
+            continue;
+        }
+        if n == out.len() {
+            break;
+        }
+        out[n] = MadtCpu {
+            apic_id,
+            acpi_uid,
+            enabled: flags & MADT_ENABLED != 0,
+            online_capable: flags & MADT_ENABLED == 0,
+            x2apic,
+        };
+        n += 1;
+    }
+    n
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IoApic {
+    pub id: u8,
+    pub address: u32,
+    pub gsi_base: u32,
+}
+
+/// ISA interrupt source override
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct IntOverride {
+    pub bus: u8,
+    pub source: u8,
+    pub gsi: u32,
+    /// MPS INTI flags: polarity bits 0-1, trigger mode bits 2-3
+    pub flags: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Madt {
+    /// Local APIC base, 64-bit override applied
+    pub local_apic: u64,
+    /// Dual 8259s present and must be masked
+    pub pcat_compat: bool,
+    pub enabled_cpus: usize,
+    pub online_capable_cpus: usize,
+    pub ioapics: [IoApic; MAX_IOAPICS],
+    pub ioapic_count: usize,
+    pub overrides: [IntOverride; MAX_OVERRIDES],
+    pub override_count: usize,
+}
+
+pub fn parse_madt(madt: &[u8]) -> Option<Madt> {
+    let mut m = Madt {
+        local_apic: u32_at(madt, SDT_HEADER_LEN)? as u64,
+        pcat_compat: u32_at(madt, SDT_HEADER_LEN + 4)? & MADT_PCAT_COMPAT != 0,
+        ..Default::default()
+    };
+    let mut cpus = [MadtCpu::default(); MAX_CPUS];
+    let n = madt_cpus(madt, &mut cpus);
+    m.enabled_cpus = cpus[..n].iter().filter(|c| c.enabled).count();
+    m.online_capable_cpus = n - m.enabled_cpus;
+    for (ty, e) in madt_entries(madt) {
+        match ty {
+            MADT_IO_APIC if e.len() >= 12 && m.ioapic_count < MAX_IOAPICS => {
+                m.ioapics[m.ioapic_count] = IoApic { id: e[2], address: u32_at(e, 4)?, gsi_base: u32_at(e, 8)? };
+                m.ioapic_count += 1;
+            }
+            MADT_INT_OVERRIDE if e.len() >= 10 && m.override_count < MAX_OVERRIDES => {
+                m.overrides[m.override_count] =
+                    IntOverride { bus: e[2], source: e[3], gsi: u32_at(e, 4)?, flags: u16_at(e, 8)? };
+                m.override_count += 1;
+            }
+            MADT_LOCAL_APIC_OVERRIDE if e.len() >= 12 => m.local_apic = u64_at(e, 4)?,
+            _ => {}
+        }
+    }
+    Some(m)
+}
+
+/// ACPI Generic Address Structure
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GenericAddress {
+    /// 0 = system memory, 1 = system I/O
+    pub space_id: u8,
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64,
+}
+
+pub const GAS_MEMORY: u8 = 0;
+pub const GAS_IO: u8 = 1;
+
+impl GenericAddress {
+    fn parse(b: &[u8], off: usize) -> Option<Self> {
+        let g = b.get(off..off + 12)?;
+        Some(GenericAddress {
+            space_id: g[0],
+            bit_width: g[1],
+            bit_offset: g[2],
+            access_size: g[3],
+            address: u64_at(g, 4)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Fadt {
+    /// DSDT address, X_DSDT preferred
+    pub dsdt: u64,
+    /// FACS address, X_FIRMWARE_CTRL preferred
+    pub facs: u64,
+    pub sci_irq: u16,
+    /// ACPI PM timer, `None` on hardware-reduced platforms
+    pub pm_timer: Option<GenericAddress>,
+    /// PM timer counts 32 bits instead of 24
+    pub pm_timer_32bit: bool,
+    /// Reset register and the value to write, when RESET_REG_SUP is set
+    pub reset: Option<(GenericAddress, u8)>,
+    pub hw_reduced: bool,
+}
+
+pub fn parse_fadt(fadt: &[u8]) -> Option<Fadt> {
+    let flags = u32_at(fadt, FADT_FLAGS).unwrap_or(0);
+    let prefer = |x: Option<u64>, legacy: Option<u32>| x.filter(|&a| a != 0).or(legacy.map(u64::from)).unwrap_or(0);
+
+    let x_pm = GenericAddress::parse(fadt, FADT_X_PM_TMR_BLK).filter(|g| g.address != 0);
+    let legacy_pm = u32_at(fadt, FADT_PM_TMR_BLK).filter(|&p| p != 0 && fadt.get(FADT_PM_TMR_LEN) == Some(&4));
+    let pm_timer = x_pm.or(legacy_pm.map(|port| GenericAddress {
+        space_id: GAS_IO,
+        bit_width: 32,
+        bit_offset: 0,
+        access_size: 3,
+        address: port as u64,
+    }));
+
+    let reset = (flags & FADT_RESET_REG_SUP != 0)
+        .then(|| GenericAddress::parse(fadt, FADT_RESET_REG).zip(fadt.get(FADT_RESET_VALUE).copied()))
+        .flatten()
+        .filter(|(g, _)| g.address != 0);
+
+    Some(Fadt {
+        dsdt: prefer(u64_at(fadt, FADT_X_DSDT), u32_at(fadt, FADT_DSDT)),
+        facs: prefer(u64_at(fadt, FADT_X_FIRMWARE_CTRL), u32_at(fadt, FADT_FIRMWARE_CTRL)),
+        sci_irq: u16_at(fadt, FADT_SCI_INT)?,
+        pm_timer: pm_timer.filter(|_| flags & FADT_HW_REDUCED == 0),
+        pm_timer_32bit: flags & FADT_TMR_VAL_EXT != 0,
+        reset,
+        hw_reduced: flags & FADT_HW_REDUCED != 0,
+    })
+}
+
+/// One PCIe ECAM window from the MCFG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct EcamRegion {
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+pub fn mcfg_regions(mcfg: &[u8], out: &mut [EcamRegion]) -> usize {
+    let entries = mcfg.get(SDT_HEADER_LEN + 8..).unwrap_or(&[]).chunks_exact(16);
+    let mut n = 0;
+    for (slot, e) in out.iter_mut().zip(entries) {
+        *slot = EcamRegion { base: u64_at(e, 0).unwrap_or(0), segment: u16_at(e, 8).unwrap_or(0), start_bus: e[10], end_bus: e[11] };
+        n += 1;
+    }
+    n
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Hpet {
+    pub base: GenericAddress,
+    pub number: u8,
+    pub comparators: u8,
+    pub counter_64bit: bool,
+    pub pci_vendor: u16,
+    /// Minimum periodic tick, in main counter ticks
+    pub min_tick: u16,
+}
+
+pub fn parse_hpet(hpet: &[u8]) -> Option<Hpet> {
+    let id = u32_at(hpet, SDT_HEADER_LEN)?;
+    Some(Hpet {
+        base: GenericAddress::parse(hpet, SDT_HEADER_LEN + 4).filter(|g| g.address != 0)?,
+        number: *hpet.get(SDT_HEADER_LEN + 16)?,
+        comparators: ((id >> 8) & 0x1F) as u8 + 1,
+        counter_64bit: id & (1 << 13) != 0,
+        pci_vendor: (id >> 16) as u16,
+        min_tick: u16_at(hpet, SDT_HEADER_LEN + 17)?,
+    })
+}
+
+/// Everything the loader reads out of ACPI
+#[derive(Debug, Clone, Copy)]
+pub struct AcpiInfo {
+    pub revision: u8,
+    pub tables: [TableRef; MAX_TABLES],
+    pub table_count: usize,
+    pub madt: Option<Madt>,
+    pub fadt: Option<Fadt>,
+    pub ecam: [EcamRegion; MAX_ECAM],
+    pub ecam_count: usize,
+    pub hpet: Option<Hpet>,
+}
+
+impl AcpiInfo {
+    pub fn tables(&self) -> &[TableRef] {
+        &self.tables[..self.table_count]
+    }
+
+    pub fn bad_tables(&self) -> usize {
+        self.tables().iter().filter(|t| !t.checksum_ok).count()
+    }
+
+    pub fn ecam(&self) -> &[EcamRegion] {
+        &self.ecam[..self.ecam_count]
+    }
+}
+
+/// Walk and parse the tables reachable from a validated RSDP
+pub fn discover(rsdp: u64) -> AcpiInfo {
+    let mut info = AcpiInfo {
+        // safe: validated RSDP
+        revision: unsafe { rsdp_at(rsdp) }[15],
+        tables: [TableRef::default(); MAX_TABLES],
+        table_count: 0,
+        madt: find_table(rsdp, b"APIC").and_then(parse_madt),
+        fadt: find_table(rsdp, b"FACP").and_then(parse_fadt),
+        ecam: [EcamRegion::default(); MAX_ECAM],
+        ecam_count: 0,
+        hpet: find_table(rsdp, b"HPET").and_then(parse_hpet),
+    };
+    info.table_count = walk(rsdp, &mut info.tables);
+    if let Some(mcfg) = find_table(rsdp, b"MCFG") {
+        info.ecam_count = mcfg_regions(mcfg, &mut info.ecam);
+    }
+    info
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    /// SDT with a QEMU-style header ("BOCHS ", "BXPC    ") around `body`
+    fn sdt(signature: &[u8; 4], revision: u8, body: &[u8]) -> Vec<u8> {
+        let mut t = Vec::new();
+        t.extend_from_slice(signature);
+        t.extend_from_slice(&((SDT_HEADER_LEN + body.len()) as u32).to_le_bytes());
+        t.extend_from_slice(&[revision, 0]);
+        t.extend_from_slice(b"BOCHS BXPC    ");
+        t.extend_from_slice(&1u32.to_le_bytes());
+        t.extend_from_slice(b"BXPC");
+        t.extend_from_slice(&1u32.to_le_bytes());
+        t.extend_from_slice(body);
+        let sum = t.iter().fold(0u8, |a, &b| a.wrapping_add(b));
+        t[9] = 0u8.wrapping_sub(sum);
+        t
+    }
+
+    // QEMU q35, 2 vCPUs: MADT body as emitted by build_madt()
+    const QEMU_MADT: &[u8] = &[
+        0x00, 0x00, 0xE0, 0xFE, 0x01, 0x00, 0x00, 0x00, // local APIC 0xFEE00000, PCAT_COMPAT
+        0x00, 0x08, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // CPU uid 0 apic 0 enabled
+        0x00, 0x08, 0x01, 0x01, 0x01, 0x00, 0x00, 0x00, // CPU uid 1 apic 1 enabled
+        0x01, 0x0C, 0x00, 0x00, 0x00, 0x00, 0xC0, 0xFE, 0x00, 0x00, 0x00, 0x00, // IOAPIC 0 @ 0xFEC00000
+        0x02, 0x0A, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, // IRQ0 -> GSI2
+        0x02, 0x0A, 0x00, 0x05, 0x05, 0x00, 0x00, 0x00, 0x0D, 0x00, // IRQ5 level/high
+        0x02, 0x0A, 0x00, 0x09, 0x09, 0x00, 0x00, 0x00, 0x0D, 0x00, // IRQ9 (SCI)
+        0x02, 0x0A, 0x00, 0x0A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x00,
+        0x02, 0x0A, 0x00, 0x0B, 0x0B, 0x00, 0x00, 0x00, 0x0D, 0x00,
+        0x04, 0x06, 0xFF, 0x00, 0x00, 0x01, // LAPIC NMI, all CPUs, LINT1
+    ];
+
+    // QEMU q35 MCFG body: reserved, then one window for segment 0, buses 0-255
+    const QEMU_MCFG: &[u8] = &[
+        0, 0, 0, 0, 0, 0, 0, 0, //
+        0x00, 0x00, 0x00, 0xB0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    // QEMU HPET body: 8086:A201 (3 comparators, 64-bit, legacy routing), MMIO 0xFED00000
+    const QEMU_HPET: &[u8] = &[
+        0x01, 0xA2, 0x86, 0x80, //
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xD0, 0xFE, 0x00, 0x00, 0x00, 0x00, //
+        0x00, 0x00, 0x00, 0x00,
+    ];
+
+    /// QEMU q35 FADT (revision 3, 244 bytes): PM block at 0x600, reset via 0xCF9
+    fn qemu_fadt() -> Vec<u8> {
+        let mut b = alloc::vec![0u8; 244 - SDT_HEADER_LEN];
+        let mut put = |off: usize, bytes: &[u8]| b[off - SDT_HEADER_LEN..off - SDT_HEADER_LEN + bytes.len()].copy_from_slice(bytes);
+        put(FADT_FIRMWARE_CTRL, &0x7FFE_0000u32.to_le_bytes());
+        put(FADT_DSDT, &0x7FFE_0040u32.to_le_bytes());
+        put(FADT_SCI_INT, &9u16.to_le_bytes());
+        put(FADT_PM_TMR_BLK, &0x608u32.to_le_bytes());
+        put(FADT_PM_TMR_LEN, &[4]);
+        put(FADT_FLAGS, &0x0000_84A5u32.to_le_bytes());
+        put(FADT_RESET_REG, &[GAS_IO, 8, 0, 0, 0xF9, 0x0C, 0, 0, 0, 0, 0, 0]);
+        put(FADT_RESET_VALUE, &[0x0F]);
+        put(FADT_X_DSDT, &0x7FFE_0040u64.to_le_bytes());
+        put(FADT_X_PM_TMR_BLK, &[GAS_IO, 32, 0, 0, 0x08, 0x06, 0, 0, 0, 0, 0, 0]);
+        sdt(b"FACP", 3, &b)
+    }
+
+    #[test]
+    fn madt_lists_enabled_and_online_capable_cpus_once() {
+        let bsp = [0u8, 8, 0, 0, 1, 0, 0, 0];
+        let ap = [0u8, 8, 1, 2, 1, 0, 0, 0];
+        let disabled = [0u8, 8, 2, 4, 0, 0, 0, 0];
+        let hotplug = [0u8, 8, 3, 6, 2, 0, 0, 0];
+        let ioapic = [1u8, 12, 0, 0, 0, 0, 0xC0, 0xFE, 0, 0, 0, 0];
+        let mut x2 = [0u8; 16];
+        x2[..2].copy_from_slice(&[9, 16]);
+        x2[4..8].copy_from_slice(&300u32.to_le_bytes());
+        x2[8..12].copy_from_slice(&1u32.to_le_bytes());
+        x2[12..16].copy_from_slice(&7u32.to_le_bytes());
+        let mut dup = x2;
+        dup[4..8].copy_from_slice(&2u32.to_le_bytes());
+
+        let mut body = alloc::vec![0x00, 0x00, 0xE0, 0xFE, 1, 0, 0, 0];
+        for e in [&bsp[..], &ioapic, &ap, &disabled, &hotplug, &x2, &dup] {
+            body.extend_from_slice(e);
+        }
+        let t = sdt(b"APIC", 1, &body);
+        assert!(checksum_ok(&t));
+        let mut out = [MadtCpu::default(); 8];
+        let n = madt_cpus(&t, &mut out);
+        let ids: Vec<u32> = out[..n].iter().map(|c| c.apic_id).collect();
+        assert_eq!(ids, [0, 2, 6, 300]);
+        assert!(out[2].online_capable && !out[2].enabled);
+        assert!(out[3].x2apic && out[3].acpi_uid == 7);
+    }
+
+    #[test]
+    fn parses_qemu_tables() {
+        let madt = sdt(b"APIC", 1, QEMU_MADT);
+        assert_eq!(madt.len(), 0x80);
+        assert!(checksum_ok(&madt));
+        let m = parse_madt(&madt).unwrap();
+        assert_eq!((m.local_apic, m.pcat_compat, m.enabled_cpus), (0xFEE0_0000, true, 2));
+        assert_eq!(m.ioapics[..m.ioapic_count], [IoApic { id: 0, address: 0xFEC0_0000, gsi_base: 0 }]);
+        assert_eq!(m.override_count, 5);
+        assert_eq!(m.overrides[0], IntOverride { bus: 0, source: 0, gsi: 2, flags: 0 });
+        assert_eq!(m.overrides[2].flags, 0x0D);
+
+        let f = parse_fadt(&qemu_fadt()).unwrap();
+        assert_eq!((f.dsdt, f.facs, f.sci_irq), (0x7FFE_0040, 0x7FFE_0000, 9));
+        assert_eq!(f.pm_timer.map(|g| (g.space_id, g.address)), Some((GAS_IO, 0x608)));
+        assert!(!f.pm_timer_32bit && !f.hw_reduced);
+        assert_eq!(f.reset.map(|(g, v)| (g.address, v)), Some((0xCF9, 0x0F)));
+
+        let mut ecam = [EcamRegion::default(); 4];
+        assert_eq!(mcfg_regions(&sdt(b"MCFG", 1, QEMU_MCFG), &mut ecam), 1);
+        assert_eq!(ecam[0], EcamRegion { base: 0xB000_0000, segment: 0, start_bus: 0, end_bus: 0xFF });
+
+        let h = parse_hpet(&sdt(b"HPET", 1, QEMU_HPET)).unwrap();
+        assert_eq!((h.base.address, h.comparators, h.counter_64bit, h.pci_vendor), (0xFED0_0000, 3, true, 0x8086));
+    }
+
+    #[test]
+    fn rsdp_checks_both_checksums() {
+        let mut r = alloc::vec![0u8; 36];
+        r[..8].copy_from_slice(b"RSD PTR ");
+        r[9..15].copy_from_slice(b"BOCHS ");
+        r[15] = 2;
+        r[20..24].copy_from_slice(&36u32.to_le_bytes());
+        r[24..32].copy_from_slice(&0x7FFE_1000u64.to_le_bytes());
+        r[8] = 0u8.wrapping_sub(r[..20].iter().fold(0u8, |a, &b| a.wrapping_add(b)));
+        r[32] = 0u8.wrapping_sub(r.iter().fold(0u8, |a, &b| a.wrapping_add(b)));
+        assert!(rsdp_valid(&r));
+        // v1 part still sums to zero, the extended checksum no longer does
+        r[33] = 1;
+        assert!(!rsdp_valid(&r));
+        r[33] = 0;
+        // the revision byte is covered by the v1 checksum
+        r[15] = 0;
+        assert!(!rsdp_valid(&r[..20]));
+    }
+}
@@ -0,0 +1,47 @@
+//! Little-endian field readers for firmware tables and image headers.
+//!
+//! `u16_at`/`u32_at`/`u64_at` return `None` past the end of the slice; the
+//! `le*` forms and `guid_at` are for offsets the caller already bounds-checked.
+
+pub fn u16_at(b: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(b.get(off..off.checked_add(2)?)?.try_into().ok()?))
+}
+
+pub fn u32_at(b: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(b.get(off..off.checked_add(4)?)?.try_into().ok()?))
+}
+
+pub fn u64_at(b: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(b.get(off..off.checked_add(8)?)?.try_into().ok()?))
+}
+
+pub fn le16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([b[off], b[off + 1]])
+}
+
+pub fn le32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes(b[off..off + 4].try_into().unwrap())
+}
+
+pub fn le64(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+/// 16 raw bytes, e.g. an EFI_GUID in on-disk order
+pub fn guid_at(b: &[u8], off: usize) -> [u8; 16] {
+    b[off..off + 16].try_into().unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_readers_stop_at_the_end() {
+        let b = [1u8, 2, 3, 4, 5, 6, 7, 8];
+        assert_eq!(u16_at(&b, 6), Some(0x0807));
+        assert_eq!(u32_at(&b, 5), None);
+        assert_eq!(u64_at(&b, 0), Some(le64(&b, 0)));
+        assert_eq!(u16_at(&b, usize::MAX), None);
+    }
+}
@@ -0,0 +1,67 @@
+//! `.nonos.manifest` reader.
+//!
+//! The manifest is signed together with the capsule, so anything pinned in
+//! it (module hashes, feature flags) is trusted. Format is line based ASCII:
+//! `<key> <value...>`, `#` starts a comment, unknown keys are ignored so
+//! older loaders keep booting newer capsules.
+
+#![allow(dead_code)]
+
+use nonos_handoff::cpu::cpu_feature;
+
+/// Iterate `(key, rest)` pairs of a manifest, skipping blanks and comments.
+pub fn entries(manifest: &[u8]) -> impl Iterator<Item = (&str, &str)> {
+    core::str::from_utf8(manifest)
+        .unwrap_or("")
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or("").trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| match l.split_once(char::is_whitespace) {
+            Some((k, v)) => (k, v.trim()),
+            None => (l, ""),
+        })
+}
+
+/// `require-cpu <feature>...` lines folded into one `cpu_feature` mask. An
+/// unknown feature name is the error: the loader cannot promise it.
+pub fn required_cpu_features(manifest: &[u8]) -> Result<u64, &str> {
+    let mut bits = 0;
+    for (_, names) in entries(manifest).filter(|(k, _)| *k == "require-cpu") {
+        for name in names.split_whitespace() {
+            bits |= cpu_feature::from_name(name).ok_or(name)?;
+        }
+    }
+    Ok(bits)
+}
+
+/// Decode a 64 char hex string into a SHA-256 digest.
+pub fn parse_sha256(hex: &str) -> Option<[u8; 32]> {
+    let b = hex.as_bytes();
+    if b.len() != 64 {
+        return None;
+    }
+    let nibble = |c: u8| match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None,
+    };
+    let mut out = [0u8; 32];
+    for (i, o) in out.iter_mut().enumerate() {
+        *o = (nibble(b[2 * i])? << 4) | nibble(b[2 * i + 1])?;
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collects_required_cpu_features() {
+        let m = b"symbols on\nrequire-cpu avx2 aes # crypto fast paths\nrequire-cpu cet-ss\n";
+        assert_eq!(required_cpu_features(m), Ok(cpu_feature::AVX2 | cpu_feature::AES_NI | cpu_feature::CET_SS));
+        assert_eq!(required_cpu_features(b"require-cpu sha avx512f\n"), Err("avx512f"));
+        assert_eq!(required_cpu_features(b"symbols on\n"), Ok(0));
+    }
+}
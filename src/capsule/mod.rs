@@ -1,3 +1,4 @@
+pub mod manifest;
 pub mod zkmeta;
 
 use crate::handoff::ZeroStateBootInfo;
@@ -11,6 +12,8 @@ pub struct Capsule {
     pub size: usize,
     pub entry_point: usize,
     pub handoff: ZeroStateBootInfo,
+    /// The manifest signature was checked against the keyring (never under mock-proof)
+    pub signature_verified: bool,
 }
 
 impl Capsule {
@@ -42,7 +45,7 @@ impl Capsule {
 
         // Signature verification - skip when mock-proof feature is enabled for testing
         #[cfg(not(feature = "mock-proof"))]
-        {
+        let signature_verified = {
             // Find .nonos.manifest section for signature verification
             let manifest_section = elf
                 .find_section_by_name(".nonos.manifest")
@@ -71,12 +74,12 @@ impl Capsule {
             if !verify_ed25519_signature(manifest_data, signature_data)? {
                 return Err("Cryptographic signature verification failed");
             }
-        }
+            true
+        };
 
+        // Skip signature verification in mock-proof mode for testing
         #[cfg(feature = "mock-proof")]
-        {
-            // Skip signature verification in mock-proof mode for testing
-        }
+        let signature_verified = false;
 
         // Calculate total memory size needed for all LOAD segments
         let mut _total_size = 0;
@@ -144,6 +147,7 @@ impl Capsule {
             size: data.len(), // Use actual file size, not virtual memory size
             entry_point,
             handoff,
+            signature_verified,
         })
     }
 
@@ -166,4 +170,14 @@ impl Capsule {
     pub fn payload(&self) -> &[u8] {
         unsafe { core::slice::from_raw_parts(self.base, self.size) }
     }
+
+    /// Contents of the signed `.nonos.manifest` section, if present
+    pub fn manifest(&self) -> Option<&[u8]> {
+        let elf = ElfFile::new(self.payload()).ok()?;
+        let section = elf.find_section_by_name(".nonos.manifest")?;
+        match section.get_data(&elf).ok()? {
+            xmas_elf::sections::SectionData::Undefined(data) => Some(data),
+            _ => None,
+        }
+    }
 }
@@ -0,0 +1,306 @@
+//! Chainloading of signed UEFI applications and bootloaders.
+//!
+//! Used for `BootEntryType::UefiApplication` and `ChainloadBootloader`.
+//! The PE image is verified against the NONOS keyring before firmware ever
+//! sees it, measured into PCR 4, then handed to `LoadImage`/`StartImage`
+//! with the entry's command line as UCS-2 load options.
+//!
+//! Signature placement (checked in this order):
+//! - embedded: a `.nonosig` PE section holding a raw 64-byte Ed25519
+//!   signature over every file byte preceding the section's raw data. The
+//!   section must be the last data in the file (trailing zero padding only).
+//! - detached: `<path>.sig` next to the image, a raw 64-byte Ed25519
+//!   signature over the whole file.
+
+#![allow(dead_code)]
+
+use crate::bytes::{u16_at, u32_at};
+use crate::crypto::sig::{SignatureResult, SignatureVerifier, VerifyError, SIG_LEN};
+use crate::loader::{read_file, FileBuffer, LoaderError};
+use crate::log::logger::{log_error, log_info, log_warn};
+use crate::multiboot::{BootEntry, BootEntryType};
+use alloc::format;
+use core::fmt;
+use uefi::prelude::*;
+use uefi::proto::loaded_image::LoadedImage;
+use uefi::table::boot::LoadImageSource;
+
+/// Section name carrying an embedded signature (exactly 8 bytes, PE limit)
+pub const SIG_SECTION_NAME: [u8; 8] = *b".nonosig";
+
+/// PCR used for chainloaded images (TCG: boot manager code)
+const CHAINLOAD_PCR: u32 = 4;
+
+const IMAGE_FILE_MACHINE_AMD64: u16 = 0x8664;
+const PE_SECTION_HEADER_SIZE: usize = 40;
+const MAX_LOAD_OPTIONS: usize = 256;
+
+#[derive(Debug)]
+pub enum ChainloadError {
+    WrongEntryType,
+    File(LoaderError),
+    MalformedPe(&'static str),
+    SignatureMissing,
+    Signature(VerifyError),
+    LoadImage(Status),
+    StartImage(Status),
+}
+
+impl fmt::Display for ChainloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainloadError::WrongEntryType => write!(f, "entry is not a UEFI application"),
+            ChainloadError::File(e) => write!(f, "image read failed: {}", e),
+            ChainloadError::MalformedPe(s) => write!(f, "malformed PE image: {}", s),
+            ChainloadError::SignatureMissing => write!(f, "no embedded or detached signature"),
+            ChainloadError::Signature(e) => write!(f, "signature rejected: {:?}", e),
+            ChainloadError::LoadImage(s) => write!(f, "LoadImage failed: {:?}", s),
+            ChainloadError::StartImage(s) => write!(f, "StartImage failed: {:?}", s),
+        }
+    }
+}
+
+/// Location of a PE section's raw data within the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeSection {
+    pub raw_offset: usize,
+    pub raw_size: usize,
+}
+
+/// Validate MZ/PE headers for an x86_64 image and look up a section by name.
+pub fn find_pe_section(image: &[u8], name: &[u8; 8]) -> Result<Option<PeSection>, &'static str> {
+    if image.get(0..2) != Some(b"MZ") {
+        return Err("missing MZ header");
+    }
+    let pe_off = u32_at(image, 0x3C).ok_or("truncated DOS header")? as usize;
+    if image.get(pe_off..pe_off + 4) != Some(b"PE\0\0") {
+        return Err("missing PE signature");
+    }
+    let coff = pe_off + 4;
+    let machine = u16_at(image, coff).ok_or("truncated COFF header")?;
+    if machine != IMAGE_FILE_MACHINE_AMD64 {
+        return Err("not an x86_64 image");
+    }
+    let nsections = u16_at(image, coff + 2).ok_or("truncated COFF header")? as usize;
+    let opt_size = u16_at(image, coff + 16).ok_or("truncated COFF header")? as usize;
+
+    let table = coff + 20 + opt_size;
+    let table_end = nsections
+        .checked_mul(PE_SECTION_HEADER_SIZE)
+        .and_then(|n| n.checked_add(table))
+        .ok_or("section table overflow")?;
+    if table_end > image.len() {
+        return Err("section table out of bounds");
+    }
+
+    for i in 0..nsections {
+        let hdr = &image[table + i * PE_SECTION_HEADER_SIZE..table + (i + 1) * PE_SECTION_HEADER_SIZE];
+        if &hdr[0..8] != name {
+            continue;
+        }
+        let raw_size = u32_at(hdr, 16).ok_or("truncated section header")? as usize;
+        let raw_offset = u32_at(hdr, 20).ok_or("truncated section header")? as usize;
+        if raw_offset.checked_add(raw_size).map_or(true, |end| end > image.len()) {
+            return Err("section data out of bounds");
+        }
+        return Ok(Some(PeSection { raw_offset, raw_size }));
+    }
+    Ok(None)
+}
+
+/// Split an image into (signed bytes, signature) using the embedded section.
+pub fn embedded_signature(image: &[u8]) -> Result<Option<(&[u8], &[u8])>, &'static str> {
+    let sec = match find_pe_section(image, &SIG_SECTION_NAME)? {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+    if sec.raw_size < SIG_LEN {
+        return Err("signature section too small");
+    }
+    let sig_end = sec.raw_offset + SIG_LEN;
+    // anything after the signature must be file-alignment padding
+    if image[sig_end..].iter().any(|&b| b != 0) {
+        return Err("signature section is not the last data in the file");
+    }
+    Ok(Some((&image[..sec.raw_offset], &image[sec.raw_offset..sig_end])))
+}
+
+fn verify(signed: &[u8], sig: &[u8]) -> Result<(), ChainloadError> {
+    match SignatureVerifier::verify_against_all(signed, sig) {
+        SignatureResult::Valid(_) => Ok(()),
+        SignatureResult::Err(e) => Err(ChainloadError::Signature(e)),
+    }
+}
+
+/// Verify an image against the keyring, trying the embedded signature first.
+/// Non-PE blobs (initrds, modules) only support the detached form.
+pub(crate) fn verify_image(
+    bs: &uefi::table::boot::BootServices,
+    image_handle: Handle,
+    path: &str,
+    image: &[u8],
+) -> Result<(), ChainloadError> {
+    if image.starts_with(b"MZ") {
+        if let Some((signed, sig)) = embedded_signature(image).map_err(ChainloadError::MalformedPe)? {
+            log_info("chainload", "Using embedded .nonosig signature");
+            return verify(signed, sig);
+        }
+    }
+
+    let sig_path = format!("{}.sig", path);
+    let sig_file = match read_file(bs, image_handle, &sig_path) {
+        Ok(f) => f,
+        Err(_) => return Err(ChainloadError::SignatureMissing),
+    };
+    log_info("chainload", "Using detached signature");
+    let res = if sig_file.len == SIG_LEN {
+        verify(image, sig_file.as_slice())
+    } else {
+        Err(ChainloadError::Signature(VerifyError::MalformedSignature))
+    };
+    sig_file.free(bs);
+    res
+}
+
+/// Read `path`, check its signature and, with measured boot on, extend `pcr`
+/// with it. The buffer is freed again if verification fails.
+pub fn read_verified(
+    st: &mut SystemTable<Boot>,
+    image_handle: Handle,
+    path: &str,
+    pcr: u32,
+    event: &[u8],
+    measured_boot: bool,
+) -> Result<FileBuffer, ChainloadError> {
+    let file = read_file(st.boot_services(), image_handle, path).map_err(ChainloadError::File)?;
+    if let Err(e) = verify_image(st.boot_services(), image_handle, path, file.as_slice()) {
+        file.free(st.boot_services());
+        return Err(e);
+    }
+    if measured_boot && !crate::security::extend_pcr_measurement_with_event(st, pcr, file.as_slice(), event) {
+        log_warn("loader", &format!("PCR {} measurement of {} failed", pcr, path));
+    }
+    Ok(file)
+}
+
+/// Encode the entry command line as NUL-terminated UCS-2 load options.
+fn encode_load_options(cmdline: &str, out: &mut [u16; MAX_LOAD_OPTIONS]) -> usize {
+    let mut n = 0;
+    for c in cmdline.chars().take(MAX_LOAD_OPTIONS - 1) {
+        out[n] = if (c as u32) < 0x10000 { c as u16 } else { b'?' as u16 };
+        n += 1;
+    }
+    out[n] = 0;
+    n + 1
+}
+
+/// Verify, measure and start the image referenced by `entry`.
+/// Returns the exit status of the started image if it returns control.
+pub fn chainload_entry(
+    image_handle: Handle,
+    st: &mut SystemTable<Boot>,
+    entry: &BootEntry,
+    measured_boot: bool,
+) -> Result<Status, ChainloadError> {
+    if !matches!(entry.entry_type, BootEntryType::UefiApplication | BootEntryType::ChainloadBootloader) {
+        return Err(ChainloadError::WrongEntryType);
+    }
+    let path = entry.path_str();
+    log_info("chainload", &format!("Chainloading {}", path));
+
+    let file = read_verified(st, image_handle, path, CHAINLOAD_PCR, b"NONOS chainload", measured_boot).map_err(|e| {
+        log_error("chainload", &format!("{}: {}", path, e));
+        e
+    })?;
+    log_info("chainload", "Image signature verified");
+
+    let bs = st.boot_services();
+    let child = bs
+        .load_image(
+            image_handle,
+            LoadImageSource::FromBuffer { buffer: file.as_slice(), file_path: None },
+        )
+        .map_err(|e| ChainloadError::LoadImage(e.status()));
+    // firmware copied the image; our buffer is no longer needed
+    file.free(bs);
+    let child = child?;
+
+    // load options must outlive StartImage, keep them on this frame
+    let mut options = [0u16; MAX_LOAD_OPTIONS];
+    let opt_len = encode_load_options(entry.command_line_str(), &mut options);
+    if opt_len > 1 {
+        match bs.open_protocol_exclusive::<LoadedImage>(child) {
+            Ok(mut li) => unsafe {
+                // safe: `options` lives until start_image returns
+                li.set_load_options(options.as_ptr() as *const u8, (opt_len * 2) as u32);
+            },
+            Err(e) => log_warn("chainload", &format!("LoadedImage unavailable: {:?}", e.status())),
+        }
+    }
+
+    log_info("chainload", "Starting image");
+    match bs.start_image(child) {
+        Ok(()) => {
+            log_info("chainload", "Chainloaded image returned");
+            Ok(Status::SUCCESS)
+        }
+        Err(e) => {
+            let _ = bs.unload_image(child);
+            Err(ChainloadError::StartImage(e.status()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    /// Minimal PE: DOS stub, PE header at 0x40, no optional header, one section.
+    fn pe_with_section(name: &[u8; 8], raw_offset: u32, raw_size: u32, total: usize) -> Vec<u8> {
+        let mut img = vec![0u8; total];
+        img[0..2].copy_from_slice(b"MZ");
+        img[0x3C..0x40].copy_from_slice(&0x40u32.to_le_bytes());
+        img[0x40..0x44].copy_from_slice(b"PE\0\0");
+        img[0x44..0x46].copy_from_slice(&IMAGE_FILE_MACHINE_AMD64.to_le_bytes());
+        img[0x46..0x48].copy_from_slice(&1u16.to_le_bytes());
+        let sec = 0x44 + 20;
+        img[sec..sec + 8].copy_from_slice(name);
+        img[sec + 16..sec + 20].copy_from_slice(&raw_size.to_le_bytes());
+        img[sec + 20..sec + 24].copy_from_slice(&raw_offset.to_le_bytes());
+        img
+    }
+
+    #[test]
+    fn finds_signature_section() {
+        let img = pe_with_section(&SIG_SECTION_NAME, 0x200, 0x200, 0x400);
+        let (signed, sig) = embedded_signature(&img).unwrap().unwrap();
+        assert_eq!(signed.len(), 0x200);
+        assert_eq!(sig.len(), SIG_LEN);
+    }
+
+    #[test]
+    fn rejects_data_after_signature() {
+        let mut img = pe_with_section(&SIG_SECTION_NAME, 0x200, 0x200, 0x400);
+        img[0x3FF] = 1;
+        assert!(embedded_signature(&img).is_err());
+    }
+
+    #[test]
+    fn missing_section_and_bad_machine() {
+        let img = pe_with_section(b".text\0\0\0", 0x200, 0x40, 0x240);
+        assert_eq!(embedded_signature(&img), Ok(None));
+
+        let mut bad = img.clone();
+        bad[0x44] = 0x4C;
+        assert!(find_pe_section(&bad, &SIG_SECTION_NAME).is_err());
+    }
+
+    #[test]
+    fn load_options_are_nul_terminated() {
+        let mut out = [0xFFFFu16; MAX_LOAD_OPTIONS];
+        assert_eq!(encode_load_options("fw.bin", &mut out), 7);
+        assert_eq!(out[6], 0);
+    }
+}
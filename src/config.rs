@@ -14,10 +14,17 @@ use crate::hardware::HardwareInfo;
 use crate::log::logger::{log_debug, log_error, log_info, log_warn};
 use crate::network::NetworkBootContext;
 use crate::security::SecurityContext;
+use alloc::format;
 use alloc::string::String;
 use uefi::prelude::*;
+use uefi::table::runtime::VariableVendor;
 use uefi::{cstr16, CStr16};
 
+/// Vendor GUID of the loader's own variables (boot entries, slot state).
+/// Firmware rejects unknown names under EFI_GLOBAL_VARIABLE, so they live here;
+/// the kernel finds the same GUID as `nonos_handoff::NONOS_VENDOR_GUID`.
+pub const NONOS_VENDOR: VariableVendor = VariableVendor(uefi::Guid::from_bytes(nonos_handoff::NONOS_VENDOR_GUID));
+
 /// Bootloader configuration structure
 #[derive(Debug, Clone)]
 pub struct BootloaderConfig {
@@ -42,11 +49,15 @@ pub struct BootloaderConfig {
     pub cpu_optimizations: bool,
     pub memory_management_mode: MemoryManagementMode,
     pub acpi_enabled: bool,
+    /// `cpu_feature` bits the machine must have; boot is refused otherwise
+    pub required_cpu_features: u64,
 
     // Boot behavior
     pub boot_timeout_seconds: u32,
     pub auto_boot_enabled: bool,
     pub fallback_behavior: FallbackBehavior,
+    /// Boot the ESP kernel slots when a PARTUUID=/PARTTYPE= capsule fails; off by default
+    pub raw_partition_fallback: bool,
     pub kernel_command_line: String,
 }
 
@@ -130,10 +141,12 @@ impl Default for BootloaderConfig {
             cpu_optimizations: true,
             memory_management_mode: MemoryManagementMode::Secure,
             acpi_enabled: true,
+            required_cpu_features: 0,
 
             boot_timeout_seconds: 10,
             auto_boot_enabled: true,
             fallback_behavior: FallbackBehavior::Continue,
+            raw_partition_fallback: false,
             kernel_command_line: String::new(),
         }
     }
@@ -218,6 +231,16 @@ pub fn load_bootloader_config(system_table: &mut SystemTable<Boot>) -> Bootloade
         log_info("config", "Graphics mode loaded from NVRAM");
     }
 
+    // Load required CPU features
+    let required_cpu = {
+        let rt = system_table.runtime_services();
+        load_required_cpu_features(rt)
+    };
+    if let Some(bits) = required_cpu {
+        config.required_cpu_features = bits;
+        log_info("config", &format!("Required CPU features loaded from NVRAM: 0x{:x}", bits));
+    }
+
     // Load verbose logging setting
     config.verbose_logging = {
         let rt = system_table.runtime_services();
@@ -227,6 +250,10 @@ pub fn load_bootloader_config(system_table: &mut SystemTable<Boot>) -> Bootloade
         let rt = system_table.runtime_services();
         load_diagnostic_output(rt)
     };
+    config.raw_partition_fallback = {
+        let rt = system_table.runtime_services();
+        load_raw_partition_fallback(rt)
+    };
 
     system_table
         .stdout()
@@ -338,6 +365,36 @@ fn load_verbose_logging(rt: &uefi::table::runtime::RuntimeServices) -> bool {
     }
 }
 
+/// Load required CPU features (`cpu_feature` mask, exactly 8 bytes little endian)
+fn load_required_cpu_features(rt: &uefi::table::runtime::RuntimeServices) -> Option<u64> {
+    let mut buffer = [0u8; 8];
+    let var_name = cstr16!("NonosRequiredCpuFeatures");
+
+    match rt.get_variable(var_name, &NONOS_VENDOR, &mut buffer) {
+        Ok((data, _)) if data.len() == 8 => Some(u64::from_le_bytes(buffer)),
+        Ok((data, _)) => {
+            log_error("config", &format!("NonosRequiredCpuFeatures is {} bytes, expected 8; ignored", data.len()));
+            None
+        }
+        Err(e) if e.status() == Status::BUFFER_TOO_SMALL => {
+            log_error("config", "NonosRequiredCpuFeatures is longer than 8 bytes; ignored");
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+/// Load whether a failed raw partition capsule may fall back to the kernel slots
+fn load_raw_partition_fallback(rt: &uefi::table::runtime::RuntimeServices) -> bool {
+    let mut buffer = [0u8; 1];
+    let var_name = cstr16!("NonosRawPartitionFallback");
+
+    match rt.get_variable(var_name, &NONOS_VENDOR, &mut buffer) {
+        Ok(_) => buffer[0] != 0,
+        Err(_) => false,
+    }
+}
+
 /// Load diagnostic output setting
 fn load_diagnostic_output(rt: &uefi::table::runtime::RuntimeServices) -> bool {
     let mut buffer = [0u8; 1];
@@ -184,7 +184,7 @@ pub fn seed_entropy(info: &mut ZeroStateBootInfo, bs: &BootServices) {
 /* --------------------- helpers --------------------- */
 
 #[inline(always)]
-fn scrub(b: &mut [u8]) {
+pub(crate) fn scrub(b: &mut [u8]) {
     for x in b {
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::SeqCst);
         *x = 0;
@@ -0,0 +1,194 @@
+//! TPM event log handed to the kernel.
+//!
+//! The TCG2 log lives in boot services memory, so it is copied into
+//! LOADER_DATA before ExitBootServices. Firmware keeps logging extends that
+//! happen after `GetEventLog` in the final events table, whose address is
+//! passed along unchanged.
+//!
+//! `GetEventLog` only reports the first and last entry, so the log length is
+//! the last entry's offset plus its size. Crypto-agile (TCG_2) logs start
+//! with a SHA1-format "Spec ID Event03" whose algorithm table gives the
+//! digest sizes needed to walk a `TCG_PCR_EVENT2`.
+
+#![allow(dead_code)]
+
+use crate::bytes::{u16_at, u32_at};
+use crate::log::logger::{log_info, log_warn};
+use alloc::format;
+use nonos_handoff::tags::{event_log_flags, event_log_format, EventLogTag};
+use r_efi::efi;
+use uefi::prelude::*;
+use uefi::proto::tcg::v2::Tcg;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+const FINAL_EVENTS_TABLE_GUID: uefi::Guid = uefi::guid!("1e2ed096-30e2-4254-bd89-863bbef82325");
+
+/// TCG_PCR_EVENT header: pcrIndex, eventType, SHA1 digest, eventSize
+const LEGACY_HEADER: usize = 4 + 4 + 20 + 4;
+/// Bytes looked at past the last entry's start to find its size
+const LAST_ENTRY_WINDOW: usize = 1024;
+const MAX_ALGORITHMS: usize = 8;
+const SPEC_ID_SIGNATURE: &[u8; 16] = b"Spec ID Event03\0";
+
+/// EFI_TCG2_PROTOCOL up to GetEventLog; the remaining members are not used
+#[repr(C)]
+struct RawTcg2 {
+    get_capability: usize,
+    get_event_log: unsafe extern "efiapi" fn(
+        this: *mut RawTcg2,
+        format: u32,
+        location: *mut u64,
+        last_entry: *mut u64,
+        truncated: *mut u8,
+    ) -> efi::Status,
+}
+
+/// Size of the legacy-format event at the start of `b`
+fn legacy_event_len(b: &[u8]) -> Option<usize> {
+    LEGACY_HEADER.checked_add(u32_at(b, LEGACY_HEADER - 4)? as usize)
+}
+
+/// (algorithm id, digest size) pairs from the Spec ID event at the start of the log
+fn spec_id_digest_sizes(log: &[u8], out: &mut [(u16, u16); MAX_ALGORITHMS]) -> Option<usize> {
+    let event = log.get(LEGACY_HEADER..legacy_event_len(log)?)?;
+    if event.get(..16)? != SPEC_ID_SIGNATURE {
+        return None;
+    }
+    // signature[16] platformClass u32 minor/major/errata/uintnSize u8 numberOfAlgorithms u32
+    let n = u32_at(event, 24)? as usize;
+    if n == 0 || n > MAX_ALGORITHMS {
+        return None;
+    }
+    for (i, slot) in out.iter_mut().take(n).enumerate() {
+        *slot = (u16_at(event, 28 + 4 * i)?, u16_at(event, 30 + 4 * i)?);
+    }
+    Some(n)
+}
+
+/// Size of the TCG_PCR_EVENT2 at the start of `b`
+fn event2_len(b: &[u8], sizes: &[(u16, u16)]) -> Option<usize> {
+    let count = u32_at(b, 8)? as usize;
+    if count > MAX_ALGORITHMS {
+        return None;
+    }
+    let mut off = 12;
+    for _ in 0..count {
+        let alg = u16_at(b, off)?;
+        let (_, size) = sizes.iter().find(|(id, _)| *id == alg)?;
+        off += 2 + *size as usize;
+    }
+    off.checked_add(4)?.checked_add(u32_at(b, off)? as usize)
+}
+
+/// Total log length given the log from its start through the window after the last entry
+fn log_len(log: &[u8], format: u32, last_off: usize) -> Option<usize> {
+    let last = log.get(last_off..)?;
+    let tail = if format == event_log_format::TCG_1_2 || last_off == 0 {
+        legacy_event_len(last)?
+    } else {
+        let mut sizes = [(0u16, 0u16); MAX_ALGORITHMS];
+        let n = spec_id_digest_sizes(log, &mut sizes)?;
+        event2_len(last, &sizes[..n])?
+    };
+    last_off.checked_add(tail)
+}
+
+/// Copy the firmware TPM event log into LOADER_DATA. `None` without a TPM or log.
+pub fn copy_event_log(st: &SystemTable<Boot>) -> Option<EventLogTag> {
+    let bs = st.boot_services();
+    let handle = bs.get_handle_for_protocol::<Tcg>().ok()?;
+    let mut tcg = bs.open_protocol_exclusive::<Tcg>(handle).ok()?;
+    let raw = &mut *tcg as *mut Tcg as *mut RawTcg2;
+
+    let final_events = st
+        .config_table()
+        .iter()
+        .find(|e| e.guid == FINAL_EVENTS_TABLE_GUID)
+        .map_or(0, |e| e.address as u64);
+
+    for format in [event_log_format::TCG_2, event_log_format::TCG_1_2] {
+        let (mut location, mut last, mut truncated) = (0u64, 0u64, 0u8);
+        // safe: raw is the live TCG2 protocol instance we hold open; out-params are locals
+        let status = unsafe { ((*raw).get_event_log)(raw, format, &mut location, &mut last, &mut truncated) };
+        if status != efi::Status::SUCCESS || location == 0 {
+            continue;
+        }
+        if last == 0 {
+            // empty log in this format
+            return Some(EventLogTag { format, flags: 0, ptr: 0, size: 0, final_events });
+        }
+        let last_off = (last - location) as usize;
+        // safe: firmware guarantees the log from `location` through the last entry; the
+        // window past it stays inside the firmware log buffer (always pages, never tight)
+        let window = unsafe { core::slice::from_raw_parts(location as *const u8, last_off + LAST_ENTRY_WINDOW) };
+        let Some(len) = log_len(window, format, last_off) else {
+            log_warn("tpm", "TPM event log not parseable; not handed over");
+            return None;
+        };
+
+        let pages = len.div_ceil(0x1000);
+        let copy = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages).ok()?;
+        // safe: fresh pages hold len bytes; source validated above
+        unsafe { core::ptr::copy_nonoverlapping(location as *const u8, copy as *mut u8, len) };
+        log_info("tpm", &format!("TPM event log ({} bytes, format {}) copied for the kernel", len, format));
+        return Some(EventLogTag {
+            format,
+            flags: if truncated != 0 { event_log_flags::TRUNCATED } else { 0 },
+            ptr: copy,
+            size: len as u64,
+            final_events,
+        });
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn legacy(event: &[u8]) -> Vec<u8> {
+        let mut e = Vec::new();
+        e.extend_from_slice(&0u32.to_le_bytes());
+        e.extend_from_slice(&3u32.to_le_bytes()); // EV_NO_ACTION
+        e.extend_from_slice(&[0u8; 20]);
+        e.extend_from_slice(&(event.len() as u32).to_le_bytes());
+        e.extend_from_slice(event);
+        e
+    }
+
+    #[test]
+    fn walks_crypto_agile_log() {
+        let mut spec = Vec::new();
+        spec.extend_from_slice(SPEC_ID_SIGNATURE);
+        spec.extend_from_slice(&[0u8; 8]); // platformClass, versions, uintnSize
+        spec.extend_from_slice(&2u32.to_le_bytes());
+        for (alg, size) in [(0x0004u16, 20u16), (0x000B, 32)] {
+            spec.extend_from_slice(&alg.to_le_bytes());
+            spec.extend_from_slice(&size.to_le_bytes());
+        }
+        spec.push(0); // vendorInfoSize
+        let mut log = legacy(&spec);
+        let last_off = log.len();
+
+        // TCG_PCR_EVENT2 with SHA1 + SHA256 digests and a 5 byte event
+        log.extend_from_slice(&4u32.to_le_bytes());
+        log.extend_from_slice(&0xDu32.to_le_bytes());
+        log.extend_from_slice(&2u32.to_le_bytes());
+        log.extend_from_slice(&0x0004u16.to_le_bytes());
+        log.extend_from_slice(&[0xAA; 20]);
+        log.extend_from_slice(&0x000Bu16.to_le_bytes());
+        log.extend_from_slice(&[0xBB; 32]);
+        log.extend_from_slice(&5u32.to_le_bytes());
+        log.extend_from_slice(b"NONOS");
+        let total = log.len();
+        log.extend_from_slice(&[0xFF; 64]); // unrelated bytes after the log
+
+        assert_eq!(log_len(&log, event_log_format::TCG_2, last_off), Some(total));
+        // only the Spec ID event logged so far
+        assert_eq!(log_len(&log, event_log_format::TCG_2, 0), Some(last_off));
+        // an unknown digest algorithm cannot be sized
+        log[last_off + 12] = 0x12;
+        assert_eq!(log_len(&log, event_log_format::TCG_2, last_off), None);
+    }
+}
@@ -0,0 +1,418 @@
+//! GUID Partition Table reader (UEFI 2.10 §5.3).
+//!
+//! Both copies are read and checked: the primary header at LBA 1 and the
+//! backup at the header's `alternate_lba` (the last block when the primary
+//! is unreadable). A copy counts as valid only if its header CRC32, its
+//! `my_lba` and its entry array CRC32 all check out. Partitions come from
+//! the primary when it is valid, otherwise from the backup. Entries with an
+//! unused type GUID or a range outside the usable LBAs are dropped.
+//!
+//! GUIDs stay in their on-disk EFI_GUID byte order; `format_guid` and
+//! `parse_guid` convert to and from the usual text form.
+
+#![allow(dead_code)]
+
+use crate::bytes::{guid_at, le32, le64};
+use crate::smbios::format_uuid;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use nonos_handoff::storage::{GptPartition, PARTITION_NAME_LEN};
+
+pub const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Smallest header the spec allows (revision 1.0)
+const HEADER_MIN: usize = 92;
+const ENTRY_MIN: usize = 128;
+/// Largest entry array read per copy; the usual one is 128 x 128 bytes
+pub const MAX_ENTRY_BYTES: usize = 1 << 20;
+const MBR_TYPE_PROTECTIVE: u8 = 0xEE;
+
+/// Whole-block reads from one device
+pub trait BlockRead {
+    fn block_size(&self) -> usize;
+    fn last_block(&self) -> u64;
+    /// Fill `buf`, a whole number of blocks, starting at `lba`; false on a device error
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> bool;
+}
+
+/// Why one copy of the table was rejected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TableError {
+    Unreadable,
+    NoSignature,
+    /// Size, location or entry geometry out of range
+    BadHeader,
+    HeaderCrc,
+    EntriesCrc,
+}
+
+impl fmt::Display for TableError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            TableError::Unreadable => "read error",
+            TableError::NoSignature => "no GPT signature",
+            TableError::BadHeader => "malformed header",
+            TableError::HeaderCrc => "header CRC32 mismatch",
+            TableError::EntriesCrc => "entry array CRC32 mismatch",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The fields of a GPT header this reader uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub my_lba: u64,
+    pub alternate_lba: u64,
+    pub first_usable: u64,
+    pub last_usable: u64,
+    pub disk_guid: [u8; 16],
+    pub entries_lba: u64,
+    pub entry_count: u32,
+    pub entry_size: u32,
+    pub entries_crc: u32,
+}
+
+/// Both copies of a device's GPT and the partitions taken from the valid one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Gpt {
+    pub primary: Result<Header, TableError>,
+    pub backup: Result<Header, TableError>,
+    /// Both copies valid but with different disk GUIDs or entry arrays
+    pub mismatch: bool,
+    pub protective_mbr: bool,
+    pub partitions: Vec<GptPartition>,
+}
+
+impl Gpt {
+    /// Header partitions were taken from
+    pub fn header(&self) -> Option<&Header> {
+        self.primary.as_ref().or(self.backup.as_ref()).ok()
+    }
+}
+
+/// CRC-32/ISO-HDLC (the zlib one), continuing from `crc`; start with 0
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c ^= b as u32;
+        for _ in 0..8 {
+            c = (c >> 1) ^ (0xEDB8_8320 & (c & 1).wrapping_neg());
+        }
+    }
+    !c
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// Check and decode the header in `block`, which was read from `lba`
+pub fn parse_header(block: &[u8], lba: u64) -> Result<Header, TableError> {
+    if block.len() < HEADER_MIN || &block[0..8] != GPT_SIGNATURE {
+        return Err(TableError::NoSignature);
+    }
+    let size = le32(block, 12) as usize;
+    if !(HEADER_MIN..=block.len()).contains(&size) {
+        return Err(TableError::BadHeader);
+    }
+    // the CRC covers the header with its own CRC field zeroed
+    let crc = crc32_update(crc32_update(crc32(&block[..16]), &[0; 4]), &block[20..size]);
+    if crc != le32(block, 16) {
+        return Err(TableError::HeaderCrc);
+    }
+    let h = Header {
+        my_lba: le64(block, 24),
+        alternate_lba: le64(block, 32),
+        first_usable: le64(block, 40),
+        last_usable: le64(block, 48),
+        disk_guid: guid_at(block, 56),
+        entries_lba: le64(block, 72),
+        entry_count: le32(block, 80),
+        entry_size: le32(block, 84),
+        entries_crc: le32(block, 88),
+    };
+    let entry_size = h.entry_size as usize;
+    let array = (h.entry_count as usize).checked_mul(entry_size);
+    if h.my_lba != lba
+        || entry_size < ENTRY_MIN
+        || !entry_size.is_power_of_two()
+        || array.is_none_or(|n| n > MAX_ENTRY_BYTES)
+        || h.first_usable > h.last_usable
+    {
+        return Err(TableError::BadHeader);
+    }
+    Ok(h)
+}
+
+/// Used entries of a CRC-checked array; `device` is left at 0
+pub fn parse_entries(array: &[u8], h: &Header) -> Vec<GptPartition> {
+    let mut out = Vec::new();
+    for (i, e) in array.chunks_exact(h.entry_size as usize).take(h.entry_count as usize).enumerate() {
+        let type_guid = guid_at(e, 0);
+        let (first_lba, last_lba) = (le64(e, 32), le64(e, 40));
+        if type_guid == [0; 16] || first_lba > last_lba || first_lba < h.first_usable || last_lba > h.last_usable {
+            continue;
+        }
+        let mut name = [0u16; PARTITION_NAME_LEN];
+        for (d, c) in name.iter_mut().zip(e[56..56 + 2 * PARTITION_NAME_LEN].chunks_exact(2)) {
+            *d = u16::from_le_bytes([c[0], c[1]]);
+        }
+        out.push(GptPartition {
+            device: 0,
+            number: i as u32 + 1,
+            type_guid,
+            unique_guid: guid_at(e, 16),
+            first_lba,
+            last_lba,
+            attributes: le64(e, 48),
+            name,
+        });
+    }
+    out
+}
+
+/// Read, check and decode the copy whose header is at `lba`
+fn read_table<B: BlockRead>(dev: &mut B, lba: u64) -> Result<(Header, Vec<GptPartition>), TableError> {
+    let bs = dev.block_size();
+    if bs < HEADER_MIN || lba == 0 || lba > dev.last_block() {
+        return Err(TableError::BadHeader);
+    }
+    let mut block = vec![0u8; bs];
+    if !dev.read(lba, &mut block) {
+        return Err(TableError::Unreadable);
+    }
+    let h = parse_header(&block, lba)?;
+    let bytes = h.entry_count as usize * h.entry_size as usize;
+    let blocks = bytes.div_ceil(bs) as u64;
+    if h.entries_lba == 0 || h.entries_lba.checked_add(blocks).is_none_or(|end| end > dev.last_block() + 1) {
+        return Err(TableError::BadHeader);
+    }
+    let mut array = vec![0u8; blocks as usize * bs];
+    if !dev.read(h.entries_lba, &mut array) {
+        return Err(TableError::Unreadable);
+    }
+    if crc32(&array[..bytes]) != h.entries_crc {
+        return Err(TableError::EntriesCrc);
+    }
+    Ok((h, parse_entries(&array[..bytes], &h)))
+}
+
+/// LBA 0 holds an MBR with a 0xEE partition record
+fn protective_mbr<B: BlockRead>(dev: &mut B) -> bool {
+    let mut block = vec![0u8; dev.block_size()];
+    if block.len() < 512 || !dev.read(0, &mut block) || block[510..512] != [0x55, 0xAA] {
+        return false;
+    }
+    (0..4).any(|i| block[446 + 16 * i + 4] == MBR_TYPE_PROTECTIVE)
+}
+
+/// Both GPT copies of `dev`; `None` when neither carries a GPT signature
+pub fn read_gpt<B: BlockRead>(dev: &mut B) -> Option<Gpt> {
+    let primary = read_table(dev, 1);
+    let backup_lba = match &primary {
+        Ok((h, _)) => h.alternate_lba,
+        Err(_) => dev.last_block(),
+    };
+    let backup = read_table(dev, backup_lba);
+    if primary == Err(TableError::NoSignature) && backup == Err(TableError::NoSignature) {
+        return None;
+    }
+    let mismatch = match (&primary, &backup) {
+        (Ok((p, pe)), Ok((b, be))) => p.disk_guid != b.disk_guid || p.entries_crc != b.entries_crc || pe != be,
+        _ => false,
+    };
+    let partitions = match (&primary, &backup) {
+        (Ok((_, e)), _) | (_, Ok((_, e))) => e.clone(),
+        _ => Vec::new(),
+    };
+    Some(Gpt {
+        primary: primary.map(|t| t.0),
+        backup: backup.map(|t| t.0),
+        mismatch,
+        protective_mbr: protective_mbr(dev),
+        partitions,
+    })
+}
+
+/// EFI_GUID byte order <-> RFC 4122 byte order (the first three fields swap endianness)
+fn swap_guid(g: &[u8; 16]) -> [u8; 16] {
+    let mut out = *g;
+    out[0..4].reverse();
+    out[4..6].reverse();
+    out[6..8].reverse();
+    out
+}
+
+/// Text form of an on-disk GUID, lower case
+pub fn format_guid(g: &[u8; 16]) -> String {
+    format_uuid(&swap_guid(g))
+}
+
+/// On-disk bytes of `xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx`, either case
+pub fn parse_guid(s: &str) -> Option<[u8; 16]> {
+    let s = s.as_bytes();
+    if s.len() != 36 {
+        return None;
+    }
+    let mut rfc = [0u8; 16];
+    let mut n = 0;
+    let mut i = 0;
+    while i < s.len() {
+        if matches!(i, 8 | 13 | 18 | 23) {
+            if s[i] != b'-' {
+                return None;
+            }
+            i += 1;
+            continue;
+        }
+        // from_str_radix alone would take "+f"
+        if !s[i..i + 2].iter().all(u8::is_ascii_hexdigit) {
+            return None;
+        }
+        let hex = core::str::from_utf8(&s[i..i + 2]).ok()?;
+        rfc[n] = u8::from_str_radix(hex, 16).ok()?;
+        n += 1;
+        i += 2;
+    }
+    Some(swap_guid(&rfc))
+}
+
+/// Partition name up to its first NUL; unpaired surrogates become U+FFFD
+pub fn partition_name(p: &GptPartition) -> String {
+    let end = p.name.iter().position(|&c| c == 0).unwrap_or(PARTITION_NAME_LEN);
+    char::decode_utf16(p.name[..end].iter().copied()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BS: usize = 512;
+    const BLOCKS: u64 = 64;
+    const ESP: &str = "c12a7328-f81f-11d2-ba4b-00a0c93ec93b";
+
+    struct MemDisk(Vec<u8>);
+
+    impl BlockRead for MemDisk {
+        fn block_size(&self) -> usize {
+            BS
+        }
+
+        fn last_block(&self) -> u64 {
+            BLOCKS - 1
+        }
+
+        fn read(&mut self, lba: u64, buf: &mut [u8]) -> bool {
+            let off = lba as usize * BS;
+            match self.0.get(off..off + buf.len()) {
+                Some(src) => {
+                    buf.copy_from_slice(src);
+                    true
+                }
+                None => false,
+            }
+        }
+    }
+
+    fn write_header(disk: &mut [u8], lba: u64, alternate: u64, entries_lba: u64, entries_crc: u32) {
+        let h = &mut disk[lba as usize * BS..][..BS];
+        h[0..8].copy_from_slice(GPT_SIGNATURE);
+        h[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        h[12..16].copy_from_slice(&92u32.to_le_bytes());
+        h[24..32].copy_from_slice(&lba.to_le_bytes());
+        h[32..40].copy_from_slice(&alternate.to_le_bytes());
+        h[40..48].copy_from_slice(&3u64.to_le_bytes());
+        h[48..56].copy_from_slice(&(BLOCKS - 3).to_le_bytes());
+        h[56..72].copy_from_slice(&[0xD1; 16]);
+        h[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        h[80..84].copy_from_slice(&4u32.to_le_bytes());
+        h[84..88].copy_from_slice(&128u32.to_le_bytes());
+        h[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+        let crc = crc32(&h[..92]);
+        h[16..20].copy_from_slice(&crc.to_le_bytes());
+    }
+
+    /// 64-block disk: protective MBR, two partitions, backup at the end
+    fn disk() -> Vec<u8> {
+        let mut d = vec![0u8; BLOCKS as usize * BS];
+        d[446 + 4] = MBR_TYPE_PROTECTIVE;
+        d[510] = 0x55;
+        d[511] = 0xAA;
+        let mut entries = [0u8; BS];
+        entries[0..16].copy_from_slice(&parse_guid(ESP).unwrap());
+        entries[16..32].copy_from_slice(&[0xA1; 16]);
+        entries[32..40].copy_from_slice(&3u64.to_le_bytes());
+        entries[40..48].copy_from_slice(&20u64.to_le_bytes());
+        for (i, c) in "ESP".encode_utf16().enumerate() {
+            entries[56 + 2 * i..58 + 2 * i].copy_from_slice(&c.to_le_bytes());
+        }
+        // slot 2 unused, slot 3 a second partition
+        entries[256..272].copy_from_slice(&[0x77; 16]);
+        entries[272..288].copy_from_slice(&[0xA3; 16]);
+        entries[288..296].copy_from_slice(&21u64.to_le_bytes());
+        entries[296..304].copy_from_slice(&(BLOCKS - 3).to_le_bytes());
+        let crc = crc32(&entries);
+        d[2 * BS..3 * BS].copy_from_slice(&entries);
+        d[(BLOCKS as usize - 2) * BS..(BLOCKS as usize - 1) * BS].copy_from_slice(&entries);
+        write_header(&mut d, 1, BLOCKS - 1, 2, crc);
+        write_header(&mut d, BLOCKS - 1, 1, BLOCKS - 2, crc);
+        d
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32_update(crc32(b"1234"), b"56789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn guid_text_round_trip() {
+        let g = parse_guid(ESP).unwrap();
+        assert_eq!(g[..4], [0x28, 0x73, 0x2A, 0xC1]);
+        assert_eq!(g[8..], [0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B]);
+        assert_eq!(format_guid(&g), ESP);
+        assert_eq!(parse_guid(&ESP.to_uppercase()), Some(g));
+        assert_eq!(parse_guid("c12a7328f81f-11d2-ba4b-00a0c93ec93b0"), None);
+        assert_eq!(parse_guid("+12a7328-f81f-11d2-ba4b-00a0c93ec93b"), None);
+    }
+
+    #[test]
+    fn reads_both_copies() {
+        let gpt = read_gpt(&mut MemDisk(disk())).unwrap();
+        assert!(gpt.protective_mbr && !gpt.mismatch);
+        assert_eq!(gpt.primary.unwrap().alternate_lba, BLOCKS - 1);
+        assert_eq!(gpt.backup.unwrap().my_lba, BLOCKS - 1);
+        assert_eq!(gpt.partitions.len(), 2);
+        let esp = &gpt.partitions[0];
+        assert_eq!((esp.number, esp.first_lba, esp.last_lba, esp.blocks()), (1, 3, 20, 18));
+        assert_eq!(format_guid(&esp.type_guid), ESP);
+        assert_eq!(partition_name(esp), "ESP");
+        assert_eq!(gpt.partitions[1].number, 3);
+    }
+
+    #[test]
+    fn falls_back_to_backup_and_reports_why() {
+        let mut d = disk();
+        d[2 * BS + 100] ^= 1;
+        let gpt = read_gpt(&mut MemDisk(d.clone())).unwrap();
+        assert_eq!(gpt.primary, Err(TableError::EntriesCrc));
+        assert!(gpt.backup.is_ok());
+        assert_eq!(gpt.partitions.len(), 2);
+
+        // a broken primary header: the backup is found at the last block
+        d[BS + 60] ^= 1;
+        let gpt = read_gpt(&mut MemDisk(d.clone())).unwrap();
+        assert_eq!(gpt.primary, Err(TableError::HeaderCrc));
+        assert_eq!(gpt.header().unwrap().my_lba, BLOCKS - 1);
+
+        d[(BLOCKS as usize - 1) * BS] = 0;
+        let gpt = read_gpt(&mut MemDisk(d.clone())).unwrap();
+        assert_eq!(gpt.backup, Err(TableError::NoSignature));
+        assert!(gpt.partitions.is_empty() && gpt.header().is_none());
+
+        d[BS] = 0;
+        assert_eq!(read_gpt(&mut MemDisk(d)), None);
+    }
+}
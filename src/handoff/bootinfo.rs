@@ -0,0 +1,215 @@
+//! Signed BootInfo page (feature `bootinfo`).
+//!
+//! Before ExitBootServices: derive a one-boot Ed25519 key from boot entropy,
+//! extend its public key into `PCR_BOOTINFO_KEY` and publish an unsigned
+//! page. After the handoff and memory map are final: hash them into the
+//! page, sign, store the signature and drop the key. Page layout and the
+//! verification recipe live in `nonos_handoff::bootinfo`.
+
+#![allow(dead_code)]
+
+use crate::entropy::{collect_boot_entropy, scrub};
+use crate::loader::{KernelImage, LoaderError};
+use crate::log::logger::{log_info, log_warn};
+use crate::security::extend_pcr_measurement_with_event;
+use alloc::format;
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, Signer};
+use nonos_handoff::bootinfo::{sig_alg, sig_flags, signing_input, PCR_BOOTINFO_KEY};
+use nonos_handoff::{BootInfoPage, BootInfoV1, MemoryRegion};
+use sha2::{Digest, Sha256};
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType};
+
+const KEY_DOMAIN: &[u8] = b"NONOS-BOOTINFO-EPHEMERAL-KEY";
+
+/// Published page waiting for its final contents
+pub struct PendingBootInfo {
+    pub phys: u64,
+    info: BootInfoV1,
+    keypair: Option<Keypair>,
+}
+
+/// Fresh Ed25519 key for this boot only; `None` if the key cannot be formed
+pub fn ephemeral_keypair(bs: &BootServices) -> Option<Keypair> {
+    keypair_from_entropy(collect_boot_entropy(bs))
+}
+
+/// Domain-separated key from 64 bytes of boot entropy; scrubs the inputs
+fn keypair_from_entropy(mut entropy: [u8; 64]) -> Option<Keypair> {
+    let mut seed: [u8; 32] = Sha256::new().chain_update(KEY_DOMAIN).chain_update(entropy).finalize().into();
+    scrub(&mut entropy);
+    let secret = SecretKey::from_bytes(&seed).ok();
+    scrub(&mut seed);
+    let secret = secret?;
+    let public = PublicKey::from(&secret);
+    Some(Keypair { secret, public })
+}
+
+/// Allocate and write the page with `info` and, if given, the public key. Unsigned until sealed.
+pub fn publish_bootinfo_page(bs: &BootServices, info: &BootInfoV1, kp: Option<&Keypair>) -> Result<u64, LoaderError> {
+    let phys = bs
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1)
+        .map_err(|e| LoaderError::UefiError { desc: "BootInfo alloc failed", status: e.status() })?;
+    let page = BootInfoPage {
+        info: *info,
+        sig_alg: if kp.is_some() { sig_alg::ED25519_SHA256 } else { sig_alg::NONE },
+        sig_flags: 0,
+        pubkey: kp.map_or([0; 32], |k| k.public.to_bytes()),
+        signature: [0; 64],
+    };
+    // safe: fresh page, large enough for BootInfoPage (asserted in nonos_handoff)
+    unsafe {
+        core::ptr::write_bytes(phys as *mut u8, 0, 0x1000);
+        core::ptr::write(phys as *mut BootInfoPage, page);
+    }
+    Ok(phys)
+}
+
+/// Ed25519 signature over SHA-256 of `signing_input(info)`
+pub fn sign_bootinfo_hash(kp: &Keypair, info: &BootInfoV1) -> [u8; 64] {
+    let digest: [u8; 32] = Sha256::digest(signing_input(info)).into();
+    kp.sign(&digest).to_bytes()
+}
+
+/// Store the final `info` and its signature. Plain memory writes; valid after ExitBootServices.
+pub fn write_signed_bootinfo_page(phys: u64, info: &BootInfoV1, signature: Option<&[u8; 64]>) {
+    let page = phys as *mut BootInfoPage;
+    // safe: phys is the page returned by publish_bootinfo_page, still owned by the loader
+    unsafe {
+        (*page).info = *info;
+        if let Some(sig) = signature {
+            (*page).signature = *sig;
+            (*page).sig_flags |= sig_flags::SIGNED;
+        }
+    }
+}
+
+/// Build the key, measure it and publish the page. `None` leaves `reserved0` zero.
+pub fn prepare(
+    st: &mut SystemTable<Boot>,
+    kernel: &KernelImage,
+    timestamp_ms: u64,
+    measured_boot: bool,
+) -> Option<PendingBootInfo> {
+    let mut info = BootInfoV1::empty();
+    info.kernel_phys = kernel.address as u64;
+    info.kernel_size = kernel.size as u64;
+    info.kernel_entry = kernel.entry_point as u64;
+    info.timestamp = timestamp_ms;
+    info.capsule_payload_hash = kernel.image_sha256;
+
+    let keypair = ephemeral_keypair(st.boot_services());
+    if keypair.is_none() {
+        log_warn("bootinfo", "Ephemeral key derivation failed; publishing unsigned BootInfo");
+    }
+    let measured = match &keypair {
+        Some(kp) if measured_boot => {
+            let ok = extend_pcr_measurement_with_event(st, PCR_BOOTINFO_KEY, kp.public.as_bytes(), b"NONOS bootinfo key");
+            if !ok {
+                log_warn("bootinfo", "BootInfo key measurement failed");
+            }
+            ok
+        }
+        _ => false,
+    };
+
+    match publish_bootinfo_page(st.boot_services(), &info, keypair.as_ref()) {
+        Ok(phys) => {
+            if measured {
+                // safe: page just written by publish_bootinfo_page
+                unsafe { (*(phys as *mut BootInfoPage)).sig_flags |= sig_flags::KEY_MEASURED };
+            }
+            log_info("bootinfo", &format!("BootInfo page @ 0x{:x} (key measured: {})", phys, measured));
+            Some(PendingBootInfo { phys, info, keypair })
+        }
+        Err(e) => {
+            log_warn("bootinfo", &format!("BootInfo not published: {}", e));
+            None
+        }
+    }
+}
+
+impl PendingBootInfo {
+    /// Hash the final handoff (header + tags) and memory map into the page,
+    /// sign it and drop the key. Runs after ExitBootServices: no firmware calls.
+    pub fn seal(mut self, handoff_phys: u64, handoff: &[u8], regions: &[MemoryRegion]) {
+        // safe: MemoryRegion is repr(C) without padding (asserted in nonos_handoff)
+        let region_bytes = unsafe {
+            core::slice::from_raw_parts(regions.as_ptr() as *const u8, core::mem::size_of_val(regions))
+        };
+        self.info.handoff_phys = handoff_phys;
+        self.info.handoff_size = handoff.len() as u64;
+        self.info.handoff_sha256 = Sha256::new().chain_update(handoff).chain_update(region_bytes).finalize().into();
+        let signature = self.keypair.as_ref().map(|kp| sign_bootinfo_hash(kp, &self.info));
+        write_signed_bootinfo_page(self.phys, &self.info, signature.as_ref());
+        // keypair dropped here; ed25519-dalek zeroizes SecretKey on drop
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::boxed::Box;
+    use core::convert::TryFrom;
+    use ed25519_dalek::{Signature, Verifier};
+
+    fn region(base: u64, length: u64) -> MemoryRegion {
+        MemoryRegion { base, length, kind: 1, reserved: 0, attributes: 0xF }
+    }
+
+    fn verifies(page: &BootInfoPage) -> bool {
+        let digest: [u8; 32] = Sha256::digest(signing_input(&page.info)).into();
+        let public = PublicKey::from_bytes(&page.pubkey).unwrap();
+        let signature = Signature::try_from(&page.signature[..]).unwrap();
+        public.verify(&digest, &signature).is_ok()
+    }
+
+    #[test]
+    fn ephemeral_key_signature_verifies_with_public_key() {
+        let kp = keypair_from_entropy([0x5A; 64]).unwrap();
+        let mut info = BootInfoV1::empty();
+        info.kernel_phys = 0x20_0000;
+        info.kernel_entry = 0x20_1000;
+        let mut page = BootInfoPage {
+            info,
+            sig_alg: sig_alg::ED25519_SHA256,
+            sig_flags: sig_flags::SIGNED,
+            pubkey: kp.public.to_bytes(),
+            signature: sign_bootinfo_hash(&kp, &info),
+        };
+        assert!(verifies(&page));
+
+        page.info.kernel_entry += 1;
+        assert!(!verifies(&page));
+    }
+
+    #[test]
+    fn seal_covers_handoff_and_region_bytes() {
+        let seal = |handoff: &[u8], regions: &[MemoryRegion]| -> BootInfoPage {
+            let kp = keypair_from_entropy([0x5A; 64]).unwrap();
+            let mut page = Box::new(BootInfoPage {
+                info: BootInfoV1::empty(),
+                sig_alg: sig_alg::ED25519_SHA256,
+                sig_flags: 0,
+                pubkey: kp.public.to_bytes(),
+                signature: [0; 64],
+            });
+            let phys = &mut *page as *mut BootInfoPage as u64;
+            PendingBootInfo { phys, info: BootInfoV1::empty(), keypair: Some(kp) }.seal(0x9000, handoff, regions);
+            *page
+        };
+
+        let handoff = [0xA5u8; 48];
+        let regions = [region(0, 0x9F000), region(0x10_0000, 0x7F00_0000)];
+        let page = seal(&handoff, &regions);
+        assert!(page.is_signed() && verifies(&page));
+        assert_eq!(page.info.handoff_phys, 0x9000);
+        assert_eq!(page.info.handoff_size, handoff.len() as u64);
+
+        let mut tampered = handoff;
+        tampered[47] ^= 1;
+        assert_ne!(seal(&tampered, &regions).info.handoff_sha256, page.info.handoff_sha256);
+        let moved = [region(0, 0x9F000), region(0x10_0000, 0x7F00_1000)];
+        assert_ne!(seal(&handoff, &moved).info.handoff_sha256, page.info.handoff_sha256);
+    }
+}
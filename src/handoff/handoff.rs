@@ -1,196 +1,176 @@
 #![allow(dead_code)]
 #![no_std]
 
+use core::convert::Infallible;
 use core::mem::size_of;
 use uefi::prelude::*;
-use uefi::table::boot::{AllocateType, MemoryType, MemoryMapKey, MemoryDescriptor};
+use r_efi::efi;
+use uefi::table::boot::{AllocateType, MemoryType};
+use uefi::table::runtime::ResetType;
 
 #[cfg(feature = "bootinfo")]
-use crate::handoff as bootinfo_mod;
-#[cfg(feature = "bootinfo")]
-use crate::handoff::BootInfoV1;
-
-#[cfg(feature = "ed25519")]
-use ed25519_dalek::Keypair;
-
+use crate::handoff::bootinfo;
+
+use crate::handoff::harden;
+use crate::handoff::memmap::{self, MemoryRegion, MMAP_FORMAT_NONOS_V1};
+use crate::handoff::runtime;
+use crate::handoff::tags::{tag_bytes, BootTags};
+use crate::entropy::collect_boot_entropy;
+use crate::hardware::{discover_acpi_rsdp, query_framebuffer, CpuFeatures};
+use crate::memtest;
+use crate::timing::{calibrate, now_us, set_loader_time_var, unix_epoch_ms, BootTimeline, LOADER_TIME_EXEC};
+use sha2::{Digest, Sha256};
+use crate::loader::notes::{fb_request, smp_request};
+use crate::smbios::{self, Inventory};
+use crate::smp;
 use crate::log::logger::{log_error, log_info, log_warn};
+use crate::log::ring;
 use crate::loader::{KernelImage, LoaderError};
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct FramebufferInfo {
-    pub ptr: u64,
-    pub size: u64,
-    pub width: u32,
-    pub height: u32,
-    pub stride: u32,
-    pub pixel_format: u32,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct MemoryMap {
-    pub ptr: u64,
-    pub entry_size: u32,
-    pub entry_count: u32,
-    pub desc_version: u32,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct AcpiInfo {
-    pub rsdp: u64,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct SmbiosInfo {
-    pub entry: u64,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct Module {
-    pub base: u64,
-    pub size: u64,
-    pub kind: u32,
-    pub reserved: u32,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct Modules {
-    pub ptr: u64,
-    pub count: u32,
-    pub reserved: u32,
-}
-
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct Timing {
-    pub tsc_hz: u64,
-    pub unix_epoch_ms: u64,
-}
+// The ABI itself lives in the `nonos-handoff` crate, shared with the kernel.
+pub use nonos_handoff::{
+    flags, pixel_format, AcpiInfo, BootHandoffV1, FramebufferInfo, Measurements, MemoryMap, Module, ModuleKind,
+    Modules, RngSeed, SmbiosInfo, SymbolTable, Timing, HANDOFF_MAGIC, HANDOFF_SIZE, HANDOFF_VERSION,
+};
+use nonos_handoff::tags::{boot_phase, PhaseTiming};
+use nonos_handoff::efi_runtime::efi_runtime_flags;
+use nonos_handoff::{tag, BlockDevice, EfiRuntimeRange, EfiRuntimeTag, GptPartition, PciFunction, SmpCpu, TagWriter};
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct Measurements {
-    pub kernel_sha256: [u8; 32],
-    pub kernel_sig_ok: u8,
-    pub secure_boot: u8,
-    pub reserved: [u8; 6],
-}
+pub type KernelEntry = extern "C" fn(u64) -> !;
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct RngSeed {
-    pub seed32: [u8; 32],
+/// What the boot flow learned before handoff and `exit_and_jump` cannot rediscover.
+#[derive(Clone, Copy)]
+pub struct HandoffParams<'a> {
+    pub cmdline: Option<&'a str>,
+    pub modules: Modules,
+    /// GOP mode selected during graphics init
+    pub framebuffer: Option<FramebufferInfo>,
+    /// Capsule signature verified against the keyring
+    pub kernel_sig_ok: bool,
+    /// From `SecurityContext::secure_boot_enabled`
+    pub secure_boot: bool,
+    /// From `SecurityContext::measured_boot_active`; hands over the TPM event log
+    pub measured_boot: bool,
+    /// PCI inventory from hardware discovery
+    pub pci: &'a [PciFunction],
+    /// Whole block devices and their GPT partitions from hardware discovery
+    pub block_devices: &'a [BlockDevice],
+    pub partitions: &'a [GptPartition],
+    /// Parsed SMBIOS identity and memory devices, if firmware has a table
+    pub smbios: Option<&'a Inventory>,
+    /// Boot CPU features from hardware discovery
+    pub cpu: CpuFeatures,
+    /// `cpu_feature` bits the loader configuration required (kernel and manifest bits are in `KernelImage`)
+    pub required_cpu_features: u64,
+    /// Phase marks so far; ExitBootServices and the LOADER end are added here
+    pub timeline: BootTimeline,
+    /// Memory test results to hand over, failing pages already reserved
+    pub bad_pages: Option<&'a memtest::Report>,
 }
 
-pub const HANDOFF_MAGIC: u32 = 0x4E_4F_4E_4F;
-pub const HANDOFF_VERSION: u16 = 1;
-
-pub mod flags {
-    pub const WX: u64 = 1 << 0;
-    pub const NXE: u64 = 1 << 1;
-    pub const SMEP: u64 = 1 << 2;
-    pub const SMAP: u64 = 1 << 3;
-    pub const UMIP: u64 = 1 << 4;
-    pub const IDMAP_PRESERVED: u64 = 1 << 5;
-}
+/// GetMemoryMap/ExitBootServices attempts before giving up on a moving map key.
+const EBS_ATTEMPTS: usize = 8;
+/// Spare descriptors reserved on top of the current map size: our own
+/// allocations below, plus whatever firmware events add before ExitBootServices.
+const MMAP_SLACK_ENTRIES: usize = 32;
 
-#[repr(C)]
-#[derive(Copy, Clone)]
-pub struct BootHandoffV1 {
-    pub magic: u32,
-    pub version: u16,
-    pub size: u16,
-    pub flags: u64,
-    pub entry_point: u64,
-    pub fb: FramebufferInfo,
-    pub mmap: MemoryMap,
-    pub acpi: AcpiInfo,
-    pub smbios: SmbiosInfo,
-    pub modules: Modules,
-    pub timing: Timing,
-    pub meas: Measurements,
-    pub rng: RngSeed,
-    pub cmdline_ptr: u64,
-    // reserved0 is available for bootloader to surface auxiliary info (e.g. bootinfo phys)
-    pub reserved0: u64,
+fn pages_for(bytes: usize) -> usize {
+    (bytes + 0xFFF) / 0x1000
 }
 
-impl BootHandoffV1 {
-    #[inline]
-    pub fn is_valid(&self) -> bool {
-        self.magic == HANDOFF_MAGIC
-            && self.version == HANDOFF_VERSION
-            && self.size as usize == size_of::<Self>()
-    }
+/// Allocate LOADER_DATA pages while boot services are still available.
+fn alloc_loader_pages(bs: &uefi::table::boot::BootServices, pages: usize, what: &'static str) -> Result<u64, LoaderError> {
+    bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages).map_err(|e| {
+        log_error("handoff", &format!("{} allocation failed: {:?}", what, e.status()));
+        LoaderError::UefiError { desc: what, status: e.status() }
+    })
 }
 
-pub type KernelEntry = extern "C" fn(u64) -> !;
-
 /// ExitBootServices and transfer to the kernel.
-/// This preserves the existing BootHandoffV1 ABI. When compiled with "bootinfo"
-/// the loader also publishes a compact signed BootInfo page and stores its
-/// physical address into BootHandoffV1.reserved0 so updated kernels can find it.
+///
+/// Every page the kernel receives (handoff and its tag list, cmdline, stack,
+/// raw and NONOS memory maps, boot log, optional BootInfo) is allocated up front. Then
+/// GetMemoryMap + ExitBootServices are retried until the map key sticks,
+/// and the final firmware map is normalized into `memmap::MemoryRegion`s
+/// (see `memmap.rs`). No boot service is touched after a successful exit;
+/// the one runtime call is SetVirtualAddressMap, when the kernel asked for
+/// a virtual runtime layout (see `runtime.rs`).
+///
+/// Errors are only returned while boot services are intact. If
+/// ExitBootServices was called and never succeeded, the machine is reset
+/// through runtime services instead.
+///
+/// When compiled with "bootinfo" the loader also publishes a BootInfo page
+/// signed with a per-boot key (see `bootinfo.rs`) and stores its physical
+/// address in BootHandoffV1.reserved0.
 pub fn exit_and_jump(
     image_handle: Handle,
     st: &mut SystemTable<Boot>,
     kernel: &KernelImage,
-    cmdline: Option<&str>,
-    signing_key: Option<&'static [u8; 64]>, // optional: provider must secure keys externally
-) -> Result<! , LoaderError> {
-    log_info(st, "handoff", "Preparing memory map and ExitBootServices.");
+    params: &HandoffParams,
+) -> Result<Infallible, LoaderError> {
+    log_info("handoff", "Preparing memory map and ExitBootServices.");
+
+    // GOP, config tables and the RNG are gone after ExitBootServices; gather them now.
+    // Availability of a required framebuffer was already enforced by the loader.
+    let fb = match (params.framebuffer, kernel.requirements.framebuffer) {
+        (Some(fb), _) => Some(fb),
+        (None, fb_request::NONE) => None,
+        (None, _) => query_framebuffer(st),
+    };
+    let rsdp = discover_acpi_rsdp(st).unwrap_or(0);
+    let smbios = smbios::find_entry(st);
+    let epoch_ms = unix_epoch_ms(st.runtime_services());
+
+    let hardening = harden::prepare(st, kernel, &params.cpu);
+    let mut boot_tags = BootTags::collect(st, image_handle, kernel, params, fb, rsdp, smbios);
+    boot_tags.cpu.shadow_stack = hardening.shadow_stack;
+    boot_tags.cpu.shadow_stack_size = hardening.shadow_stack_size;
+    // APs are woken after ExitBootServices; everything they touch is reserved now
+    let mut ap_startup = match kernel.requirements.smp {
+        smp_request::PARK if rsdp != 0 => smp::prepare(st, rsdp),
+        _ => None,
+    };
+    #[cfg(feature = "bootinfo")]
+    let pending_bootinfo = bootinfo::prepare(st, kernel, epoch_ms, params.measured_boot);
 
+    let mut timeline = params.timeline;
+    if timeline.tsc.hz == 0 {
+        timeline.tsc = calibrate(st);
+    }
+    let tsc_hz = timeline.tsc.hz;
     let bs = st.boot_services();
-    let mut pages_for_map: usize = 8;
-    let map_len = |p: usize| p * 0x1000usize;
-    let mut map_addr: uefi::table::boot::PhysicalAddress = 0;
-    let memory_map_key: MemoryMapKey;
-
-    // grow memmap buffer until firmware fits
-    loop {
-        if let Err(e) = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages_for_map, &mut map_addr) {
-            log_error(st, "handoff", &format!("alloc memmap buffer failed: {:?}", e.status()));
-            return Err(LoaderError::UefiError { desc: "alloc memory map failed", status: e.status() });
-        }
-
-        // safe: map_addr is newly allocated BootServices memory
-        let map_slice = unsafe { core::slice::from_raw_parts_mut(map_addr as *mut u8, map_len(pages_for_map)) };
-
-        match bs.memory_map(map_slice) {
-            Ok((key, _iter)) => {
-                memory_map_key = key;
-                break;
-            }
-            Err(_) => {
-                let _ = bs.free_pages(map_addr, pages_for_map);
-                pages_for_map = (pages_for_map * 2).min(256);
-                continue;
-            }
+    let mut entropy = collect_boot_entropy(bs);
+    let seed32: [u8; 32] = Sha256::digest(&entropy).into();
+    entropy.iter_mut().for_each(|b| *b = 0);
+
+    // 1. Pre-allocate everything the kernel will be handed.
+    // the tag list shares the handoff allocation, starting right after the header
+    let smp_tag_bytes = ap_startup.as_ref().map_or(0, |a| tag_bytes(a.cpu_count() * size_of::<SmpCpu>()));
+    let bh_pages = pages_for(HANDOFF_SIZE + boot_tags.area_bytes() + smp_tag_bytes);
+    let bh_addr = alloc_loader_pages(bs, bh_pages, "BootHandoff alloc failed")?;
+    let stack_pages = pages_for(kernel.requirements.stack_size as usize);
+    let stack_addr = alloc_loader_pages(bs, stack_pages, "stack alloc failed")?;
+    let stack_top = (stack_addr as usize + stack_pages * 0x1000) & !0xF;
+
+    let mut cmdline_ptr = 0u64;
+    if let Some(s) = params.cmdline {
+        match alloc_loader_pages(bs, pages_for(s.len() + 1), "cmdline alloc failed") {
+            // safe: fresh pages hold len + 1 bytes
+            Ok(addr) => unsafe {
+                let ptr = addr as *mut u8;
+                core::ptr::copy_nonoverlapping(s.as_ptr(), ptr, s.len());
+                core::ptr::write_volatile(ptr.add(s.len()), 0u8);
+                cmdline_ptr = addr;
+            },
+            Err(_) => log_warn("handoff", "cmdline allocation failed; proceeding without cmdline"),
         }
     }
 
-    // after fitting memmap buffer, call ExitBootServices
-    let map_slice = unsafe { core::slice::from_raw_parts_mut(map_addr as *mut u8, map_len(pages_for_map)) };
-    if let Err(status) = st.exit_boot_services(image_handle, memory_map_key) {
-        let _ = bs.free_pages(map_addr, pages_for_map);
-        log_error(st, "handoff", &format!("ExitBootServices failed: {:?}", status));
-        return Err(LoaderError::UefiError { desc: "ExitBootServices failed", status });
-    }
-
-    // allocate BootHandoffV1 page
-    let mut bh_addr: uefi::table::boot::PhysicalAddress = 0;
-    if let Err(e) = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, 1, &mut bh_addr) {
-        log_error(st, "handoff", &format!("BootHandoff alloc failed: {:?}", e.status()));
-        return Err(LoaderError::UefiError { desc: "BootHandoff alloc failed", status: e.status() });
-    }
-
     let bh_ptr = bh_addr as *mut BootHandoffV1;
+    // safe: bh_addr is bh_pages fresh pages, large enough for BootHandoffV1 and the tags
     unsafe {
-        core::ptr::write_bytes(bh_ptr as *mut u8, 0, size_of::<BootHandoffV1>());
+        core::ptr::write_bytes(bh_ptr as *mut u8, 0, bh_pages * 0x1000);
 
         (*bh_ptr).magic = HANDOFF_MAGIC;
         (*bh_ptr).version = HANDOFF_VERSION;
@@ -198,136 +178,245 @@ pub fn exit_and_jump(
         (*bh_ptr).flags = 0;
         (*bh_ptr).entry_point = kernel.entry_point as u64;
 
-        (*bh_ptr).fb = FramebufferInfo { ptr: 0, size: 0, width: 0, height: 0, stride: 0, pixel_format: 0 };
-
-        let entry_size = size_of::<MemoryDescriptor>() as u32;
-        let entry_count = ((pages_for_map * 0x1000) / (entry_size as usize)) as u32;
-        (*bh_ptr).mmap.ptr = map_addr as u64;
-        (*bh_ptr).mmap.entry_size = entry_size;
-        (*bh_ptr).mmap.entry_count = entry_count;
-        (*bh_ptr).mmap.desc_version = 0;
-
-        (*bh_ptr).acpi.rsdp = 0;
-        (*bh_ptr).smbios.entry = 0;
-        (*bh_ptr).modules.ptr = 0;
-        (*bh_ptr).modules.count = 0;
-        (*bh_ptr).timing.tsc_hz = 0;
-        (*bh_ptr).timing.unix_epoch_ms = 0;
-        (*bh_ptr).meas = Measurements { kernel_sha256: [0u8;32], kernel_sig_ok: 0, secure_boot: 0, reserved: [0u8;6] };
-        (*bh_ptr).rng = RngSeed { seed32: [0u8;32] };
-
-        (*bh_ptr).cmdline_ptr = 0;
-        (*bh_ptr).reserved0 = 0;
+        (*bh_ptr).fb = fb.unwrap_or_default();
+
+        (*bh_ptr).acpi.rsdp = rsdp;
+        (*bh_ptr).smbios.entry = smbios;
+        // table and module pages were allocated as LOADER_DATA by loader::load_modules
+        (*bh_ptr).modules = params.modules;
+        (*bh_ptr).timing = Timing { tsc_hz, unix_epoch_ms: epoch_ms };
+        // image hash covers the exported symbol region as well
+        (*bh_ptr).meas = Measurements {
+            kernel_sha256: kernel.image_sha256,
+            kernel_sig_ok: params.kernel_sig_ok as u8,
+            secure_boot: params.secure_boot as u8,
+            reserved: [0u8; 6],
+        };
+        (*bh_ptr).rng = RngSeed { seed32 };
+        (*bh_ptr).cmdline_ptr = cmdline_ptr;
+        #[cfg(feature = "bootinfo")]
+        {
+            (*bh_ptr).reserved0 = pending_bootinfo.as_ref().map_or(0, |p| p.phys);
+        }
+
+        (*bh_ptr).symbols = match kernel.symbols {
+            Some(r) => SymbolTable {
+                symtab_ptr: r.base,
+                symtab_size: r.symtab_size,
+                symtab_entsize: r.symtab_entsize,
+                strtab_ptr: r.base + r.strtab_offset,
+                strtab_size: r.strtab_size,
+            },
+            None => SymbolTable::default(),
+        };
     }
 
-    // optional cmdline buffer
-    if let Some(s) = cmdline {
-        let cmd_bytes = s.as_bytes();
-        let cmd_len = cmd_bytes.len() + 1;
-        let cmd_pages = (cmd_len + 0xFFF) / 0x1000;
-        let mut cmd_addr: uefi::table::boot::PhysicalAddress = 0;
-        if bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, cmd_pages, &mut cmd_addr).is_ok() {
-            unsafe {
-                let ptr = cmd_addr as *mut u8;
-                core::ptr::copy_nonoverlapping(cmd_bytes.as_ptr(), ptr, cmd_bytes.len());
-                core::ptr::write_volatile(ptr.add(cmd_bytes.len()), 0u8);
-                (*bh_ptr).cmdline_ptr = cmd_addr as u64;
-            }
-        } else {
-            log_warn(st, "handoff", "cmdline allocation failed; proceeding without cmdline");
+    // safe: the bytes after the header belong to the same allocation and nothing else aliases them
+    let tag_area = unsafe {
+        core::slice::from_raw_parts_mut((bh_addr as usize + HANDOFF_SIZE) as *mut u8, bh_pages * 0x1000 - HANDOFF_SIZE)
+    };
+    let tag_writer = match TagWriter::new(tag_area).and_then(|mut w| boot_tags.write(&mut w).map(|_| w)) {
+        Ok(w) => Some(w),
+        Err(e) => {
+            log_warn("handoff", &format!("Tag list dropped: {}", e));
+            None
         }
+    };
+
+    // Sized after the allocations above so they are already part of the map.
+    let sizes = bs.memory_map_size();
+    let desc_size = sizes.entry_size.max(size_of::<efi::MemoryDescriptor>());
+    let max_entries = sizes.map_size / desc_size + MMAP_SLACK_ENTRIES;
+    let raw_pages = pages_for(max_entries * desc_size);
+    let raw_addr = alloc_loader_pages(bs, raw_pages, "alloc memory map failed")?;
+    let region_pages = pages_for(max_entries * size_of::<MemoryRegion>());
+    let region_addr = alloc_loader_pages(bs, region_pages, "alloc NONOS memory map failed")?;
+    let mut rt_ranges = alloc::vec![EfiRuntimeRange::default(); boot_tags.efi_runtime_ranges];
+
+    // last runtime variable write we can make as a volatile variable
+    if !set_loader_time_var(st.runtime_services(), LOADER_TIME_EXEC, now_us()) {
+        log_warn("handoff", "Could not set LoaderTimeExecUSec");
     }
 
-    // small stack for kernel
-    let stack_pages: usize = 8;
-    let mut stack_addr: uefi::table::boot::PhysicalAddress = 0;
-    if let Err(e) = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, stack_pages, &mut stack_addr) {
-        log_error(st, "handoff", &format!("stack alloc failed: {:?}", e.status()));
-        return Err(LoaderError::UefiError { desc: "stack alloc failed", status: e.status() });
+    // 2. GetMemoryMap + ExitBootServices, retrying while the map key moves.
+    timeline.begin(boot_phase::EXIT_BOOT_SERVICES);
+    // safe: SystemTable<Boot> wraps a valid firmware system table
+    let raw_bs = unsafe { (*(st.as_ptr() as *const efi::SystemTable)).boot_services };
+    let raw_buf = raw_addr as *mut efi::MemoryDescriptor;
+    let mut map_size = 0usize;
+    let mut got_desc_size = 0usize;
+    let mut desc_version = 0u32;
+    let mut exited = false;
+    let mut called_exit = false;
+    // Once ExitBootServices has been called, even unsuccessfully, the pool
+    // may be gone: only static strings are logged from here on.
+    for _ in 0..EBS_ATTEMPTS {
+        map_size = raw_pages * 0x1000;
+        let mut key = 0usize;
+        // safe: raw_buf spans raw_pages pages owned by us; out-params are locals
+        let status = unsafe {
+            ((*raw_bs).get_memory_map)(&mut map_size, raw_buf, &mut key, &mut got_desc_size, &mut desc_version)
+        };
+        if status.is_error() {
+            log_error("handoff", "GetMemoryMap failed");
+            break;
+        }
+        // safe: image_handle is our own image; key comes from the map just read
+        let status = unsafe { ((*raw_bs).exit_boot_services)(image_handle.as_ptr(), key) };
+        called_exit = true;
+        if status == efi::Status::SUCCESS {
+            exited = true;
+            break;
+        }
+        if status != efi::Status::INVALID_PARAMETER {
+            log_error("handoff", "ExitBootServices failed");
+            break;
+        }
+        // map key changed under us: only GetMemoryMap is legal now, loop
+    }
+    if !exited && !called_exit {
+        // boot services are untouched; the caller can still report and reset
+        return Err(LoaderError::UefiError { desc: "GetMemoryMap failed", status: Status::ABORTED });
+    }
+    if !exited {
+        // boot services may be half torn down; runtime services survive
+        log_error("handoff", "ExitBootServices did not succeed, resetting");
+        st.runtime_services().reset(ResetType::COLD, Status::ABORTED, None);
+    }
+    timeline.end(boot_phase::EXIT_BOOT_SERVICES);
+
+    // 3. Boot services are gone: normalize the map into the pre-allocated region buffer.
+    // safe: firmware filled map_size bytes of raw_buf; region buffer holds max_entries entries
+    let (raw, regions) = unsafe {
+        (
+            core::slice::from_raw_parts(raw_addr as *const u8, map_size),
+            core::slice::from_raw_parts_mut(region_addr as *mut MemoryRegion, max_entries),
+        )
+    };
+    let count = map_size / got_desc_size.max(1);
+    let raw_descs = || (0..count).filter_map(|i| memmap::raw_descriptor(raw, got_desc_size, i));
+    let region_count = match memmap::normalize(raw_descs(), regions) {
+        Some(n) => n,
+        // cannot report anything anymore; a halted CPU is the honest outcome
+        None => loop {
+            // safe: no firmware left to return to
+            unsafe { core::arch::asm!("cli; hlt") };
+        },
+    };
+    // safe: bh_ptr initialised above and still owned by us
+    unsafe {
+        (*bh_ptr).mmap = MemoryMap {
+            ptr: region_addr,
+            entry_size: size_of::<MemoryRegion>() as u32,
+            entry_count: region_count as u32,
+            desc_version: MMAP_FORMAT_NONOS_V1,
+            reserved: 0,
+        };
     }
-    let stack_top = (stack_addr as usize).checked_add(stack_pages * 0x1000).expect("stack overflow");
 
-    // Dual-handoff: publish BootInfo page alongside BootHandoffV1
-    #[cfg(feature = "bootinfo")]
-    {
-        let mut bi = BootInfoV1::empty();
-        bi.kernel_phys = kernel.address as u64;
-        bi.kernel_size = kernel.size as u64;
-        bi.kernel_entry = kernel.entry_point as u64;
-        bi.capsule_payload_hash = kernel.metadata.payload_hash;
-        bi.timestamp = 0;
-
-        #[cfg(all(feature = "ed25519", feature = "bootinfo"))]
-        {
-            if let Some(kbytes) = signing_key {
-                use ed25519_dalek::{SecretKey, PublicKey};
-                if kbytes.len() == 64 {
-                    let mut sk_bytes = [0u8;32];
-                    sk_bytes.copy_from_slice(&kbytes[0..32]);
-                    let mut pk_bytes = [0u8;32];
-                    pk_bytes.copy_from_slice(&kbytes[32..64]);
-                    if let (Ok(sk), Ok(pk)) = (SecretKey::from_bytes(&sk_bytes), PublicKey::from_bytes(&pk_bytes)) {
-                        let kp = Keypair { secret: sk, public: pk };
-                        match bootinfo_mod::publish_bootinfo_page(bs, &mut bi, Some(&kp)) {
-                            Ok(bootinfo_phys) => {
-                                if let Ok(sig_arr) = bootinfo_mod::sign_bootinfo_hash(&kp, &bi) {
-                                    let _ = bootinfo_mod::write_signed_bootinfo_page(bs, bootinfo_phys, &sig_arr);
-                                }
-                                unsafe { (*bh_ptr).reserved0 = bootinfo_phys; }
-                                log_info(st, "handoff", &format!("Published BootInfo @ 0x{:x}", bootinfo_phys));
-                            }
-                            Err(_) => {
-                                log_warn(st, "handoff", "publish_bootinfo_page failed; continuing without BootInfo");
-                            }
-                        }
-                    } else {
-                        // invalid key material: publish unsigned BootInfo
-                        if let Ok(bootinfo_phys) = bootinfo_mod::publish_bootinfo_page(bs, &mut bi, None) {
-                            unsafe { (*bh_ptr).reserved0 = bootinfo_phys; }
-                            log_info(st, "handoff", &format!("Published unsigned BootInfo @ 0x{:x}", bootinfo_phys));
-                        }
-                    }
-                } else {
-                    if let Ok(bootinfo_phys) = bootinfo_mod::publish_bootinfo_page(bs, &mut bi, None) {
-                        unsafe { (*bh_ptr).reserved0 = bootinfo_phys; }
-                        log_info(st, "handoff", &format!("Published unsigned BootInfo @ 0x{:x}", bootinfo_phys));
-                    }
-                }
+    // 4. Runtime services: list the runtime ranges and, if the kernel asked for
+    // a virtual layout, move firmware onto it. SetVirtualAddressMap rewrites
+    // the raw map buffer, so this is its last reader.
+    let st_raw = st.as_ptr() as *mut efi::SystemTable;
+    // safe: the system table and the runtime services table outlive ExitBootServices
+    let rt_phys = unsafe { (*st_raw).runtime_services } as u64;
+    let mut efi_runtime = EfiRuntimeTag { system_table: st_raw as u64, runtime_services: rt_phys, ..Default::default() };
+    let offset = runtime::virt_offset(&kernel.requirements);
+    let planned = offset.and_then(|off| runtime::plan(raw_descs(), off, &mut rt_ranges).map(|n| (off, n)));
+    let range_count = match planned {
+        Some((off, n)) => {
+            // safe: boot services are gone; raw_addr is our map buffer and raw is not read again
+            let status = unsafe {
+                runtime::set_virtual_map(st_raw, raw_addr as *mut u8, count, got_desc_size, desc_version, off)
+            };
+            efi_runtime.status = status.as_usize() as u64;
+            if status == efi::Status::SUCCESS {
+                efi_runtime.flags = efi_runtime_flags::VIRTUAL;
+                efi_runtime.virt_offset = off;
+                efi_runtime.runtime_services = rt_phys.wrapping_add(off);
             } else {
-                if let Ok(bootinfo_phys) = bootinfo_mod::publish_bootinfo_page(bs, &mut bi, None) {
-                    unsafe { (*bh_ptr).reserved0 = bootinfo_phys; }
-                    log_info(st, "handoff", &format!("Published unsigned BootInfo @ 0x{:x}", bootinfo_phys));
-                }
+                log_warn("handoff", "SetVirtualAddressMap failed; runtime services stay physical");
+                rt_ranges[..n].iter_mut().for_each(|r| r.virt = r.phys);
             }
+            n
         }
-
-        #[cfg(all(not(feature = "ed25519"), feature = "bootinfo"))]
-        {
-            if let Ok(bootinfo_phys) = bootinfo_mod::publish_bootinfo_page(bs, &mut bi, None) {
-                unsafe { (*bh_ptr).reserved0 = bootinfo_phys; }
-                log_info(st, "handoff", &format!("Published unsigned BootInfo @ 0x{:x}", bootinfo_phys));
-            } else {
-                log_warn(st, "handoff", "Failed to publish BootInfo (unsigned)");
+        None => {
+            if offset.is_some() {
+                log_warn("handoff", "Runtime layout not usable; SetVirtualAddressMap skipped");
             }
+            runtime::plan(raw_descs(), 0, &mut rt_ranges).unwrap_or(0)
         }
+    };
+    efi_runtime.range_count = range_count as u32;
+
+    let smp_cpus = ap_startup.as_mut().map_or(&[][..], |a| a.start(tsc_hz));
+
+    // nothing is logged past this point; an empty region reads as a missing log
+    if let Some(log) = boot_tags.boot_log {
+        ring::publish(log.ptr, log.size as usize, tsc_hz);
     }
 
-    let boothandoff_ptr = bh_addr as u64;
-    log_info(st, "handoff", &format!("Transferring control to kernel 0x{:x} with handoff @ 0x{:x}", kernel.entry_point, bh_addr));
+    // memory map, timing, TSC, SMP and EFI runtime tags last: they needed the final map and EBS;
+    // the budget in BootTags (plus smp_tag_bytes) covers them
+    let mut handoff_len = HANDOFF_SIZE;
+    if let Some(mut w) = tag_writer {
+        // safe: bh_ptr initialised above and still owned by us
+        let map = unsafe { (*bh_ptr).mmap };
+        timeline.end(boot_phase::LOADER);
+        let mut phases = [PhaseTiming::default(); boot_phase::COUNT];
+        let phase_count = timeline.export(&mut phases);
+        let mut pushed = w
+            .push_struct(tag::MEMORY_MAP, 1, &map)
+            .and_then(|_| w.push_slice(tag::TIMING, 1, &phases[..phase_count]))
+            .and_then(|_| w.push_struct(tag::TSC, 1, &timeline.tsc));
+        if !smp_cpus.is_empty() {
+            pushed = pushed.and_then(|_| w.push_slice(tag::SMP, 1, smp_cpus));
+        }
+        pushed = pushed.and_then(|_| w.push_struct_slice(tag::EFI_RUNTIME, 1, &efi_runtime, &rt_ranges[..range_count]));
+        if let Ok(tags_len) = pushed.and_then(|_| w.finish()) {
+            // safe: as above
+            unsafe { (*bh_ptr).flags |= flags::TAGS };
+            handoff_len += tags_len;
+        }
+    }
+
+    // hardening bits go into the header, so they come before the BootInfo signature
+    let applied = harden::apply(&params.cpu, kernel, &hardening, stack_top as u64, bh_addr);
+    // safe: bh_ptr initialised above and still owned by us
+    unsafe { (*bh_ptr).flags |= applied.flags };
 
+    // last write to anything the kernel receives: sign over the final state
+    #[cfg(feature = "bootinfo")]
+    if let Some(p) = pending_bootinfo {
+        // safe: header and tag list are handoff_len bytes of our bh_pages allocation
+        let handoff = unsafe { core::slice::from_raw_parts(bh_addr as *const u8, handoff_len) };
+        p.seal(bh_addr, handoff, &regions[..region_count]);
+    }
+    #[cfg(not(feature = "bootinfo"))]
+    let _ = handoff_len;
+
+    let boothandoff_ptr = bh_addr;
+
+    // safe: entry point validated by the loader; stack and handoff pages are ours.
+    // CR4.CET and the shadow stack go live here: no return or indirect branch
+    // follows except the jump, which lands on ENDBR64 whenever IBT is on.
     unsafe {
         let kernel_fn: KernelEntry = core::mem::transmute(kernel.entry_point as usize);
         core::arch::asm!(
-            "mov rdi, {0}",
-            "mov rsp, {1}",
+            "mov cr4, {cr4}",
+            "test {ssbsy:e}, {ssbsy:e}",
+            "jz 2f",
+            "setssbsy",
+            "2:",
+            "mov rsp, {stack}",
             "xor rbp, rbp",
-            "jmp {2}",
-            in(reg) boothandoff_ptr as usize,
-            in(reg) stack_top,
-            in(reg) kernel_fn as usize,
+            "jmp {entry}",
+            cr4 = in(reg) applied.cr4,
+            ssbsy = in(reg) applied.setssbsy as u32,
+            stack = in(reg) stack_top,
+            entry = in(reg) kernel_fn as usize,
+            in("rdi") boothandoff_ptr as usize,
             options(noreturn)
         );
     }
-
-    core::hint::unreachable_unchecked()
 }
@@ -0,0 +1,439 @@
+//! CPU hardening on the BSP right before the jump to the kernel.
+//!
+//! `prepare` runs while boot services are up and reserves what CET needs:
+//! the shadow stack and spare page-table pages for splitting the large page
+//! that maps it. `apply` runs after the last log line, with the APs already
+//! parked. It turns on EFER.NXE, CR0.WP and CR4.SMEP/SMAP/UMIP where CPUID
+//! reports them, then the CET and SYSCALL MSRs when the loader is built with
+//! `nonos-cet` / `nonos-syscall-msr` and the kernel asked for them through
+//! its notes. The `flags` bits it returns come from reading the registers
+//! back, not from what was attempted.
+//!
+//! CR4.CET and SETSSBSY are left to the jump itself: once they are live, the
+//! loader's own returns and indirect branches would fault. SMEP and SMAP stay
+//! off when firmware maps the kernel entry, the stack or the handoff as user
+//! pages, since the kernel would fault on its first instruction.
+
+#![allow(dead_code)]
+
+use crate::hardware::CpuFeatures;
+use crate::loader::notes::cet_request;
+use crate::loader::KernelImage;
+use crate::log::logger::{log_info, log_warn};
+use crate::smp::{rdmsr, wrmsr};
+use alloc::format;
+use nonos_handoff::{cpu_feature, flags};
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+const CR0_WP: u64 = 1 << 16;
+const CR4_UMIP: u64 = 1 << 11;
+const CR4_LA57: u64 = 1 << 12;
+const CR4_SMEP: u64 = 1 << 20;
+const CR4_SMAP: u64 = 1 << 21;
+const CR4_CET: u64 = 1 << 23;
+
+const IA32_EFER: u32 = 0xC000_0080;
+const EFER_SCE: u64 = 1 << 0;
+const EFER_NXE: u64 = 1 << 11;
+const IA32_STAR: u32 = 0xC000_0081;
+const IA32_LSTAR: u32 = 0xC000_0082;
+const IA32_FMASK: u32 = 0xC000_0084;
+const IA32_S_CET: u32 = 0x6A2;
+const IA32_PL0_SSP: u32 = 0x6A4;
+const S_CET_SH_STK_EN: u64 = 1 << 0;
+const S_CET_ENDBR_EN: u64 = 1 << 2;
+
+const ENDBR64: [u8; 4] = [0xF3, 0x0F, 0x1E, 0xFA];
+/// Supervisor shadow stack handed to the kernel (8 bytes per call frame)
+pub const SHADOW_STACK_PAGES: usize = 4;
+/// A 1 GiB page split down to 4 KiB needs a PD and a PT
+const SPARE_TABLES: usize = 2;
+
+const PTE_P: u64 = 1 << 0;
+const PTE_RW: u64 = 1 << 1;
+const PTE_US: u64 = 1 << 2;
+const PTE_A: u64 = 1 << 5;
+const PTE_D: u64 = 1 << 6;
+const PTE_PS: u64 = 1 << 7;
+const PTE_PAT_4K: u64 = 1 << 7;
+const PTE_PAT_LARGE: u64 = 1 << 12;
+const PTE_ADDR: u64 = 0x000F_FFFF_FFFF_F000;
+/// XD, protection key and the ignored bits above the address
+const PTE_HIGH: u64 = 0xFFF0_0000_0000_0000;
+
+/// Pages reserved before ExitBootServices; zero when CET shadow stacks are not wanted
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Prepared {
+    pub shadow_stack: u64,
+    pub shadow_stack_size: u64,
+    spare_tables: u64,
+}
+
+/// What `apply` switched on, and what is left for the jump
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Applied {
+    /// `BootHandoffV1.flags` hardening bits
+    pub flags: u64,
+    /// CR4 to load right before the jump (adds CR4.CET when CET is on)
+    pub cr4: u64,
+    /// SETSSBSY after loading `cr4`, switching to the shadow stack in IA32_PL0_SSP
+    pub setssbsy: bool,
+}
+
+/// Reserve the shadow stack and spare tables if the kernel wants CET shadow stacks
+pub fn prepare(st: &SystemTable<Boot>, kernel: &KernelImage, cpu: &CpuFeatures) -> Prepared {
+    let req = &kernel.requirements;
+    if cfg!(not(feature = "nonos-cet")) && req.cet != 0 {
+        log_warn("harden", "Kernel asks for CET; loader built without nonos-cet");
+    }
+    if cfg!(not(feature = "nonos-syscall-msr")) && req.syscall.is_some() {
+        log_warn("harden", "Kernel carries a syscall note; loader built without nonos-syscall-msr");
+    }
+    if !cfg!(feature = "nonos-cet") || req.cet & cet_request::SHADOW_STACK == 0 {
+        return Prepared::default();
+    }
+    if !cpu.has(cpu_feature::CET_SS) {
+        log_info("harden", "CPU has no CET shadow stacks; kernel runs without one");
+        return Prepared::default();
+    }
+    let bs = st.boot_services();
+    match bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, SHADOW_STACK_PAGES + SPARE_TABLES) {
+        Ok(base) => {
+            // safe: fresh allocation of SHADOW_STACK_PAGES + SPARE_TABLES pages
+            unsafe { core::ptr::write_bytes(base as *mut u8, 0, (SHADOW_STACK_PAGES + SPARE_TABLES) * 0x1000) };
+            Prepared {
+                shadow_stack: base,
+                shadow_stack_size: (SHADOW_STACK_PAGES * 0x1000) as u64,
+                spare_tables: base + (SHADOW_STACK_PAGES * 0x1000) as u64,
+            }
+        }
+        Err(e) => {
+            log_warn("harden", &format!("Shadow stack allocation failed: {:?}", e.status()));
+            Prepared::default()
+        }
+    }
+}
+
+/// Program the hardening bits on the BSP. Runs after ExitBootServices and
+/// the last log line: no boot services, no logging, no heap.
+pub fn apply(cpu: &CpuFeatures, kernel: &KernelImage, prepared: &Prepared, stack_top: u64, handoff: u64) -> Applied {
+    let entry = kernel.entry_point as u64;
+    let (cr0, cr3, cr4) = (read_cr0(), read_cr3(), read_cr4());
+    let levels = if cr4 & CR4_LA57 != 0 { 5 } else { 4 };
+    let mut pt = Identity;
+
+    if cpu.has(cpu_feature::NXE) {
+        // safe: EFER exists on every x86_64 CPU; NXE is supported per CPUID
+        unsafe { wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_NXE) };
+    }
+
+    // firmware may keep its page tables read-only; WP stays off until the shadow stack is mapped
+    let shadow_stack = cfg!(feature = "nonos-cet") && prepared.shadow_stack != 0 && {
+        write_cr0(cr0 & !CR0_WP);
+        let token = prepared.shadow_stack + prepared.shadow_stack_size - 8;
+        // safe: the token slot is in our shadow stack pages, still ordinary writable memory
+        unsafe { core::ptr::write_volatile(token as *mut u64, token) };
+        let mut spare = (0..SPARE_TABLES as u64).map(|i| prepared.spare_tables + i * 0x1000);
+        let marked = mark_shadow_stack(&mut pt, cr3, levels, prepared.shadow_stack, SHADOW_STACK_PAGES, &mut spare);
+        for i in 0..SHADOW_STACK_PAGES as u64 {
+            invlpg(prepared.shadow_stack + i * 0x1000);
+        }
+        marked
+    };
+    write_cr0(cr0 | CR0_WP);
+
+    let mut cr4_new = cr4;
+    if cpu.has(cpu_feature::UMIP) {
+        cr4_new |= CR4_UMIP;
+    }
+    if cpu.has(cpu_feature::SMEP) && !user_page(&pt, cr3, levels, entry) {
+        cr4_new |= CR4_SMEP;
+    }
+    if cpu.has(cpu_feature::SMAP) && !user_page(&pt, cr3, levels, stack_top - 8) && !user_page(&pt, cr3, levels, handoff) {
+        cr4_new |= CR4_SMAP;
+    }
+    write_cr4(cr4_new);
+
+    let mut jump_cr4 = read_cr4();
+    let mut s_cet = 0;
+    if cfg!(feature = "nonos-cet") {
+        if shadow_stack {
+            s_cet |= S_CET_SH_STK_EN;
+            // safe: CET_SS is supported per CPUID; the token sits at the top of the marked pages
+            unsafe { wrmsr(IA32_PL0_SSP, prepared.shadow_stack + prepared.shadow_stack_size - 8) };
+        }
+        if kernel.requirements.cet & cet_request::IBT != 0 && cpu.has(cpu_feature::CET_IBT) && starts_with_endbr64(entry) {
+            s_cet |= S_CET_ENDBR_EN;
+        }
+        if s_cet != 0 {
+            // safe: CET is supported per CPUID; nothing is enforced before CR4.CET is set at the jump
+            unsafe { wrmsr(IA32_S_CET, s_cet) };
+            jump_cr4 |= CR4_CET;
+        }
+    }
+
+    #[cfg(feature = "nonos-syscall-msr")]
+    let syscall = kernel.requirements.syscall.map(|sc| {
+        // safe: the SYSCALL MSRs exist on every x86_64 CPU; no user code runs before the kernel sets its GDT
+        unsafe {
+            wrmsr(IA32_STAR, (sc.user_base as u64) << 48 | (sc.kernel_cs as u64) << 32);
+            wrmsr(IA32_LSTAR, sc.entry);
+            wrmsr(IA32_FMASK, sc.rflags_mask);
+            wrmsr(IA32_EFER, rdmsr(IA32_EFER) | EFER_SCE);
+        }
+    });
+    #[cfg(not(feature = "nonos-syscall-msr"))]
+    let syscall: Option<()> = None;
+
+    // safe: EFER exists on every x86_64 CPU
+    let efer = unsafe { rdmsr(IA32_EFER) };
+    let (cr0, cr4) = (read_cr0(), read_cr4());
+    // safe: s_cet != 0 only on CPUs with CET, after the write above
+    let s_cet = if s_cet != 0 { unsafe { rdmsr(IA32_S_CET) } } else { 0 };
+    let mut out = 0;
+    for (set, flag) in [
+        (efer & EFER_NXE != 0, flags::NXE),
+        (cr0 & CR0_WP != 0, flags::WP),
+        (cr4 & CR4_SMEP != 0, flags::SMEP),
+        (cr4 & CR4_SMAP != 0, flags::SMAP),
+        (cr4 & CR4_UMIP != 0, flags::UMIP),
+        (s_cet & S_CET_SH_STK_EN != 0, flags::CET_SS),
+        (s_cet & S_CET_ENDBR_EN != 0, flags::CET_IBT),
+        (syscall.is_some() && efer & EFER_SCE != 0, flags::SYSCALL),
+    ] {
+        if set {
+            out |= flag;
+        }
+    }
+    Applied { flags: out, cr4: jump_cr4, setssbsy: s_cet & S_CET_SH_STK_EN != 0 }
+}
+
+fn starts_with_endbr64(entry: u64) -> bool {
+    // safe: the loader checked that the entry point lies inside the loaded image
+    unsafe { core::ptr::read_unaligned(entry as *const [u8; 4]) == ENDBR64 }
+}
+
+fn read_cr0() -> u64 {
+    let v;
+    // safe: reading control registers has no side effects
+    unsafe { core::arch::asm!("mov {}, cr0", out(reg) v, options(nomem, nostack, preserves_flags)) };
+    v
+}
+
+fn read_cr3() -> u64 {
+    let v;
+    // safe: as above
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) v, options(nomem, nostack, preserves_flags)) };
+    v
+}
+
+fn read_cr4() -> u64 {
+    let v;
+    // safe: as above
+    unsafe { core::arch::asm!("mov {}, cr4", out(reg) v, options(nomem, nostack, preserves_flags)) };
+    v
+}
+
+fn write_cr0(v: u64) {
+    // safe: only WP changes; the loader runs at CPL0 on supervisor pages
+    unsafe { core::arch::asm!("mov cr0, {}", in(reg) v, options(nostack, preserves_flags)) };
+}
+
+fn write_cr4(v: u64) {
+    // safe: only bits CPUID reports are added, and none that affect the loader's own mappings
+    unsafe { core::arch::asm!("mov cr4, {}", in(reg) v, options(nostack, preserves_flags)) };
+}
+
+fn invlpg(va: u64) {
+    // safe: dropping a TLB entry is always allowed
+    unsafe { core::arch::asm!("invlpg [{}]", in(reg) va, options(nostack, preserves_flags)) };
+}
+
+/// Paging structures, addressed physically
+pub(crate) trait PageTables {
+    fn read(&self, pa: u64) -> u64;
+    fn write(&mut self, pa: u64, value: u64);
+}
+
+/// The live tables: firmware identity-maps all of memory
+struct Identity;
+
+impl PageTables for Identity {
+    fn read(&self, pa: u64) -> u64 {
+        // safe: pa is an entry of a table reachable from CR3
+        unsafe { core::ptr::read_volatile(pa as *const u64) }
+    }
+
+    fn write(&mut self, pa: u64, value: u64) {
+        // safe: as above; CR0.WP is clear while firmware tables are written
+        unsafe { core::ptr::write_volatile(pa as *mut u64, value) }
+    }
+}
+
+fn slot(table: u64, va: u64, level: u32) -> u64 {
+    (table & PTE_ADDR) + ((va >> (12 + 9 * level)) & 0x1FF) * 8
+}
+
+/// True if `va` is mapped and every level allows user access
+pub(crate) fn user_page<T: PageTables>(pt: &T, cr3: u64, levels: u32, va: u64) -> bool {
+    let mut table = cr3;
+    for level in (0..levels).rev() {
+        let e = pt.read(slot(table, va, level));
+        if e & PTE_P == 0 || e & PTE_US == 0 {
+            return false;
+        }
+        if level == 0 || (level <= 2 && e & PTE_PS != 0) {
+            return true;
+        }
+        table = e;
+    }
+    false
+}
+
+/// Replace the large page `entry` at `level` (1: 2 MiB, 2: 1 GiB) by a table of
+/// 512 entries one level down with the same translation and attributes
+fn split<T: PageTables>(pt: &mut T, entry: u64, level: u32, table: u64) {
+    let step = 1u64 << (12 + 9 * (level - 1));
+    let base = entry & PTE_ADDR & !((step << 9) - 1);
+    let attrs = if level == 1 {
+        // PAT moves from bit 12 to bit 7 in a 4 KiB entry, where bit 7 no longer means PS
+        (entry & (0x17F | PTE_HIGH)) | if entry & PTE_PAT_LARGE != 0 { PTE_PAT_4K } else { 0 }
+    } else {
+        entry & (0x1FF | PTE_PAT_LARGE | PTE_HIGH)
+    };
+    for i in 0..512 {
+        pt.write(table + i * 8, (base + i * step) | attrs);
+    }
+}
+
+/// Slot of the 4 KiB entry mapping `va`, splitting large pages with tables from
+/// `spare`. `None` if unmapped, out of spares, or a level above denies writes.
+fn leaf_4k<T: PageTables>(pt: &mut T, cr3: u64, levels: u32, va: u64, spare: &mut impl Iterator<Item = u64>) -> Option<u64> {
+    let mut table = cr3;
+    for level in (0..levels).rev() {
+        let at = slot(table, va, level);
+        let mut e = pt.read(at);
+        if e & PTE_P == 0 {
+            return None;
+        }
+        if level == 0 {
+            return Some(at);
+        }
+        if level <= 2 && e & PTE_PS != 0 {
+            let new = spare.next()?;
+            split(pt, e, level, new);
+            // writes stay governed by the children, which inherited R/W
+            e = new | PTE_P | PTE_RW | PTE_A | (e & PTE_US);
+            pt.write(at, e);
+        }
+        // shadow stack accesses are writes: every level above the leaf must allow them
+        if e & PTE_RW == 0 {
+            return None;
+        }
+        table = e;
+    }
+    None
+}
+
+/// Map `pages` 4 KiB pages at `va` as supervisor shadow stack (R/W clear, dirty
+/// set). Nothing is marked unless every page can be; splits alone keep the
+/// translation, so a refusal leaves memory mapped as before.
+pub(crate) fn mark_shadow_stack<T: PageTables>(
+    pt: &mut T,
+    cr3: u64,
+    levels: u32,
+    va: u64,
+    pages: usize,
+    spare: &mut impl Iterator<Item = u64>,
+) -> bool {
+    let page = |i: usize| va + (i * 0x1000) as u64;
+    if !(0..pages).all(|i| leaf_4k(pt, cr3, levels, page(i), spare).is_some()) {
+        return false;
+    }
+    for i in 0..pages {
+        if let Some(at) = leaf_4k(pt, cr3, levels, page(i), spare) {
+            let e = pt.read(at);
+            pt.write(at, (e & !PTE_RW) | PTE_D | PTE_A);
+        }
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::collections::BTreeMap;
+
+    /// Sparse physical memory; unset words read as zero
+    #[derive(Default)]
+    struct Mem(BTreeMap<u64, u64>);
+
+    impl PageTables for Mem {
+        fn read(&self, pa: u64) -> u64 {
+            self.0.get(&pa).copied().unwrap_or(0)
+        }
+
+        fn write(&mut self, pa: u64, value: u64) {
+            self.0.insert(pa, value);
+        }
+    }
+
+    const PML4: u64 = 0x1000;
+    const PDPT: u64 = 0x2000;
+
+    /// 4-level tables mapping the first 1 GiB with one PDPT entry
+    fn tables(pdpt_entry: u64) -> Mem {
+        let mut m = Mem::default();
+        m.write(PML4, PDPT | PTE_P | PTE_RW);
+        m.write(PDPT, pdpt_entry);
+        m
+    }
+
+    #[test]
+    fn splits_a_gigabyte_page_and_marks_only_the_stack() {
+        let xd_pat = 1 << 63 | PTE_PAT_LARGE;
+        let mut m = tables(PTE_P | PTE_RW | PTE_PS | PTE_A | xd_pat);
+        let stack = 0x2345_6000;
+        let mut spare = [0x10_0000u64, 0x20_0000].into_iter();
+        assert!(mark_shadow_stack(&mut m, PML4, 4, stack, 4, &mut spare));
+        assert!(spare.next().is_none());
+
+        // PDPT -> new PD -> new PT; the 2 MiB siblings keep PS, PAT and XD
+        assert_eq!(m.read(PDPT) & PTE_ADDR, 0x10_0000);
+        assert_eq!(m.read(0x10_0000 + 8), 0x20_0000 | PTE_P | PTE_RW | PTE_PS | PTE_A | xd_pat);
+        let pd = m.read(0x10_0000 + (stack >> 21) * 8);
+        assert_eq!(pd & PTE_ADDR, 0x20_0000);
+        let pte = |va: u64| m.read(0x20_0000 + ((va >> 12) & 0x1FF) * 8);
+        for i in 0..4 {
+            let e = pte(stack + i * 0x1000);
+            assert_eq!(e & PTE_ADDR, stack + i * 0x1000);
+            assert_eq!(e & (PTE_RW | PTE_D | PTE_PAT_4K | 1 << 63), PTE_D | PTE_PAT_4K | 1 << 63);
+        }
+        assert_eq!(pte(stack + 0x4000), (stack + 0x4000) | PTE_P | PTE_RW | PTE_A | PTE_PAT_4K | 1 << 63);
+    }
+
+    #[test]
+    fn refuses_without_spares_or_write_access() {
+        let mut m = tables(PTE_P | PTE_RW | PTE_PS);
+        let mut none = core::iter::empty();
+        assert!(!mark_shadow_stack(&mut m, PML4, 4, 0x40_0000, 1, &mut none));
+        assert_eq!(m.read(PDPT), PTE_P | PTE_RW | PTE_PS);
+
+        let mut m = tables(0x3000 | PTE_P);
+        m.write(0x3000, 0x4000 | PTE_P | PTE_RW);
+        m.write(0x4000, PTE_P | PTE_RW);
+        assert!(!mark_shadow_stack(&mut m, PML4, 4, 0, 1, &mut none));
+        assert_eq!(m.read(0x4000), PTE_P | PTE_RW);
+    }
+
+    #[test]
+    fn user_page_needs_every_level() {
+        let mut m = tables(0x3000 | PTE_P | PTE_RW | PTE_US);
+        m.write(0x3000, PTE_P | PTE_RW | PTE_US | PTE_PS);
+        assert!(!user_page(&m, PML4, 4, 0x1000));
+        m.write(PML4, PDPT | PTE_P | PTE_RW | PTE_US);
+        assert!(user_page(&m, PML4, 4, 0x1000));
+        assert!(!user_page(&m, PML4, 4, 0x20_0000));
+    }
+}
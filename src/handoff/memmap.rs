@@ -0,0 +1,141 @@
+//! NONOS memory map handed to the kernel.
+//!
+//! `MemoryRegion` and `region_kind` are defined in `nonos_handoff::memmap`;
+//! this file builds the map. Firmware descriptors are classified into a
+//! handful of kinds, sorted by base and merged where contiguous with equal
+//! kind and attributes. The result is written into a caller-provided buffer
+//! so it can run after ExitBootServices (no allocation).
+//! `BootHandoffV1.mmap.desc_version` is set to `MMAP_FORMAT_NONOS_V1` to tell
+//! the kernel it is not a raw UEFI map.
+
+#![allow(dead_code)]
+
+use r_efi::efi;
+
+pub use nonos_handoff::memmap::{region_kind, MemoryRegion, MMAP_FORMAT_NONOS_V1};
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Map a UEFI memory type onto a `region_kind`
+pub fn classify(ty: u32) -> u32 {
+    match ty {
+        efi::CONVENTIONAL_MEMORY | efi::BOOT_SERVICES_CODE | efi::BOOT_SERVICES_DATA => region_kind::USABLE,
+        efi::LOADER_CODE | efi::LOADER_DATA => region_kind::LOADER_RECLAIMABLE,
+        efi::ACPI_RECLAIM_MEMORY => region_kind::ACPI_RECLAIMABLE,
+        efi::ACPI_MEMORY_NVS => region_kind::ACPI_NVS,
+        efi::MEMORY_MAPPED_IO | efi::MEMORY_MAPPED_IO_PORT_SPACE => region_kind::MMIO,
+        efi::RUNTIME_SERVICES_CODE | efi::RUNTIME_SERVICES_DATA => region_kind::RUNTIME,
+        efi::UNUSABLE_MEMORY => region_kind::BAD,
+        efi::PERSISTENT_MEMORY => region_kind::PERSISTENT,
+        _ => region_kind::RESERVED,
+    }
+}
+
+/// Read descriptor `i` from a raw GetMemoryMap buffer with stride `desc_size`.
+pub fn raw_descriptor(buf: &[u8], desc_size: usize, i: usize) -> Option<efi::MemoryDescriptor> {
+    let off = i.checked_mul(desc_size)?;
+    if desc_size < core::mem::size_of::<efi::MemoryDescriptor>() || off + desc_size > buf.len() {
+        return None;
+    }
+    // safe: bounds checked above; descriptors may be unaligned within the buffer
+    Some(unsafe { core::ptr::read_unaligned(buf.as_ptr().add(off) as *const efi::MemoryDescriptor) })
+}
+
+/// Classify, sort and merge `descs` into `out`. Returns the region count, or
+/// `None` if `out` is too small.
+pub fn normalize<I>(descs: I, out: &mut [MemoryRegion]) -> Option<usize>
+where
+    I: IntoIterator<Item = efi::MemoryDescriptor>,
+{
+    let mut n = 0;
+    for d in descs {
+        if d.number_of_pages == 0 {
+            continue;
+        }
+        let region = MemoryRegion {
+            base: d.physical_start,
+            length: d.number_of_pages.checked_mul(PAGE_SIZE)?,
+            kind: classify(d.r#type),
+            reserved: 0,
+            attributes: d.attribute,
+        };
+        // insertion sort by base: firmware maps are nearly sorted already
+        let slot = out.get_mut(n)?;
+        *slot = region;
+        let mut j = n;
+        while j > 0 && out[j - 1].base > out[j].base {
+            out.swap(j - 1, j);
+            j -= 1;
+        }
+        n += 1;
+    }
+
+    let mut merged = 0;
+    for i in 0..n {
+        let r = out[i];
+        if merged > 0 {
+            let prev = &mut out[merged - 1];
+            if prev.kind == r.kind && prev.attributes == r.attributes && prev.base + prev.length == r.base {
+                prev.length += r.length;
+                continue;
+            }
+        }
+        out[merged] = r;
+        merged += 1;
+    }
+    Some(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(ty: u32, base: u64, pages: u64) -> efi::MemoryDescriptor {
+        efi::MemoryDescriptor {
+            r#type: ty,
+            physical_start: base,
+            virtual_start: 0,
+            number_of_pages: pages,
+            attribute: 0xF,
+        }
+    }
+
+    #[test]
+    fn sorts_merges_and_classifies() {
+        let descs = [
+            desc(efi::BOOT_SERVICES_DATA, 0x2000, 1),
+            desc(efi::CONVENTIONAL_MEMORY, 0x0, 2),
+            desc(efi::LOADER_DATA, 0x3000, 1),
+            desc(efi::ACPI_RECLAIM_MEMORY, 0x10000, 4),
+            desc(efi::CONVENTIONAL_MEMORY, 0x8000, 0),
+        ];
+        let mut out = [MemoryRegion::default(); 8];
+        let n = normalize(descs, &mut out).unwrap();
+        assert_eq!(n, 3);
+        assert_eq!(out[0], MemoryRegion { base: 0, length: 0x3000, kind: region_kind::USABLE, reserved: 0, attributes: 0xF });
+        assert_eq!(out[1].kind, region_kind::LOADER_RECLAIMABLE);
+        assert_eq!(out[2].kind, region_kind::ACPI_RECLAIMABLE);
+    }
+
+    #[test]
+    fn does_not_merge_across_holes_and_reports_overflow() {
+        let descs = [desc(efi::CONVENTIONAL_MEMORY, 0, 1), desc(efi::CONVENTIONAL_MEMORY, 0x2000, 1)];
+        let mut out = [MemoryRegion::default(); 2];
+        assert_eq!(normalize(descs, &mut out), Some(2));
+        let mut small = [MemoryRegion::default(); 1];
+        assert_eq!(normalize(descs, &mut small), None);
+    }
+
+    #[test]
+    fn raw_descriptor_honours_stride() {
+        let stride = core::mem::size_of::<efi::MemoryDescriptor>() + 8;
+        let mut buf = [0u8; 128];
+        let d = desc(efi::MEMORY_MAPPED_IO, 0xFEC0_0000, 1);
+        // safe: buf holds two strides
+        unsafe { core::ptr::write_unaligned(buf.as_mut_ptr().add(stride) as *mut efi::MemoryDescriptor, d) };
+        let back = raw_descriptor(&buf, stride, 1).unwrap();
+        assert_eq!(back.physical_start, 0xFEC0_0000);
+        assert_eq!(classify(back.r#type), region_kind::MMIO);
+        assert!(raw_descriptor(&buf, stride, 3).is_none());
+    }
+}
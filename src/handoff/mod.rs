@@ -1,2 +1,9 @@
+#[cfg(feature = "bootinfo")]
+pub mod bootinfo;
 pub mod handoff;
+pub mod harden;
+pub mod memmap;
+pub mod runtime;
+pub mod tags;
 pub use handoff::exit_and_jump;
+pub use nonos_handoff::{build_bootinfo, BootInfoParams, BootInfoV1, BootModeFlags, ZeroStateBootInfo};
@@ -0,0 +1,153 @@
+//! UEFI runtime services for the kernel (layout: `nonos_handoff::efi_runtime`).
+//!
+//! A kernel that wants runtime services in its own address space asks for a
+//! virtual layout with an `NT_NONOS_EFI_RUNTIME` note: either the loader's
+//! default offset or one of its own. Right after ExitBootServices `plan`
+//! places every `EFI_MEMORY_RUNTIME` descriptor at `phys + offset` and
+//! `set_virtual_map` hands that layout to SetVirtualAddressMap. The call
+//! happens at most once and cannot be undone; if it is skipped or fails the
+//! tag still goes out with physical addresses and the firmware status, and
+//! the kernel can keep calling runtime services in physical mode.
+//!
+//! A single offset for all ranges keeps code and data of each firmware
+//! image at their original distance, which some firmware relies on.
+
+#![allow(dead_code)]
+
+use crate::loader::notes::{efi_runtime_request, KernelRequirements};
+use core::mem::size_of;
+use nonos_handoff::efi_runtime::{EfiRuntimeRange, EFI_MEMORY_RUNTIME};
+use r_efi::efi;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+/// Loader's choice: PML4 slot 508, `phys + offset` stays canonical below 2 TiB
+pub const DEFAULT_VIRT_OFFSET: u64 = 0xFFFF_FE00_0000_0000;
+/// Runtime descriptors firmware may add between sizing the tag and ExitBootServices
+const RANGE_SLACK: usize = 8;
+const PAGE_SIZE: u64 = 0x1000;
+
+/// Offset the kernel asked for, `None` to leave runtime services in physical mode
+pub fn virt_offset(req: &KernelRequirements) -> Option<u64> {
+    match req.efi_runtime {
+        efi_runtime_request::LOADER => Some(DEFAULT_VIRT_OFFSET),
+        efi_runtime_request::OFFSET => Some(req.efi_runtime_offset),
+        _ => None,
+    }
+}
+
+fn canonical(addr: u64) -> bool {
+    (((addr << 16) as i64) >> 16) as u64 == addr
+}
+
+/// Runtime descriptors of `descs` at `phys + offset`, in firmware order.
+/// `None` if `out` is too small or a range would wrap or leave canonical space.
+pub fn plan<I>(descs: I, offset: u64, out: &mut [EfiRuntimeRange]) -> Option<usize>
+where
+    I: IntoIterator<Item = efi::MemoryDescriptor>,
+{
+    let mut n = 0;
+    for d in descs {
+        if d.attribute & EFI_MEMORY_RUNTIME == 0 || d.number_of_pages == 0 {
+            continue;
+        }
+        let virt = d.physical_start.wrapping_add(offset);
+        let last = virt.checked_add(d.number_of_pages.checked_mul(PAGE_SIZE)? - 1)?;
+        if !canonical(virt) || !canonical(last) {
+            return None;
+        }
+        *out.get_mut(n)? = EfiRuntimeRange {
+            phys: d.physical_start,
+            virt,
+            pages: d.number_of_pages,
+            attribute: d.attribute,
+            efi_type: d.r#type,
+            reserved: 0,
+        };
+        n += 1;
+    }
+    Some(n)
+}
+
+/// Upper bound on runtime descriptors at ExitBootServices, read from the current map
+pub fn range_bound(st: &SystemTable<Boot>) -> usize {
+    let bs = st.boot_services();
+    let sizes = bs.memory_map_size();
+    let buf_size = sizes.map_size + sizes.entry_size * 8;
+    let pages = buf_size.div_ceil(0x1000);
+    let Ok(ptr) = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages) else {
+        return sizes.map_size / sizes.entry_size.max(1) + RANGE_SLACK;
+    };
+    // safe: fresh allocation of `pages` pages
+    let buf = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, buf_size) };
+    let runtime = match bs.memory_map(buf) {
+        Ok(map) => map.entries().filter(|d| d.att.bits() & EFI_MEMORY_RUNTIME != 0).count(),
+        Err(_) => sizes.map_size / sizes.entry_size.max(1),
+    };
+    let _ = bs.free_pages(ptr, pages);
+    runtime + RANGE_SLACK
+}
+
+/// Rewrite the raw map at `raw` (`count` descriptors of `desc_size`) to hold
+/// only the runtime descriptors, each with `VirtualStart = phys + offset`,
+/// and call SetVirtualAddressMap with it.
+///
+/// # Safety
+/// Boot services must be gone, `st` must be the firmware system table and
+/// `raw` a map buffer owned by the caller. Nothing may read the raw map as a
+/// full map afterwards.
+pub unsafe fn set_virtual_map(
+    st: *mut efi::SystemTable,
+    raw: *mut u8,
+    count: usize,
+    desc_size: usize,
+    desc_version: u32,
+    offset: u64,
+) -> efi::Status {
+    if desc_size < size_of::<efi::MemoryDescriptor>() {
+        return efi::Status::INVALID_PARAMETER;
+    }
+    let mut kept = 0;
+    for i in 0..count {
+        let d = core::ptr::read_unaligned(raw.add(i * desc_size) as *const efi::MemoryDescriptor);
+        if d.attribute & EFI_MEMORY_RUNTIME == 0 {
+            continue;
+        }
+        let d = efi::MemoryDescriptor { virtual_start: d.physical_start.wrapping_add(offset), ..d };
+        // kept <= i: compaction only ever moves descriptors towards the front
+        core::ptr::write_unaligned(raw.add(kept * desc_size) as *mut efi::MemoryDescriptor, d);
+        kept += 1;
+    }
+    let rt = (*st).runtime_services;
+    ((*rt).set_virtual_address_map)(kept * desc_size, desc_size, desc_version, raw as *mut efi::MemoryDescriptor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn desc(ty: u32, base: u64, pages: u64, attribute: u64) -> efi::MemoryDescriptor {
+        efi::MemoryDescriptor { r#type: ty, physical_start: base, virtual_start: 0, number_of_pages: pages, attribute }
+    }
+
+    #[test]
+    fn plans_runtime_ranges_at_one_offset() {
+        let descs = [
+            desc(efi::CONVENTIONAL_MEMORY, 0, 0x9F, 0xF),
+            desc(efi::RUNTIME_SERVICES_CODE, 0x7F00_0000, 0x10, EFI_MEMORY_RUNTIME | 0xF),
+            desc(efi::RUNTIME_SERVICES_DATA, 0x7F01_0000, 0x20, EFI_MEMORY_RUNTIME | 0xF),
+            desc(efi::MEMORY_MAPPED_IO, 0xFED0_0000, 1, EFI_MEMORY_RUNTIME | 1),
+        ];
+        let mut out = [EfiRuntimeRange::default(); 4];
+        assert_eq!(plan(descs, DEFAULT_VIRT_OFFSET, &mut out), Some(3));
+        assert_eq!(out[0].virt, 0xFFFF_FE00_7F00_0000);
+        assert_eq!(out[1].virt - out[0].virt, out[1].phys - out[0].phys);
+        assert_eq!(out[2].efi_type, efi::MEMORY_MAPPED_IO);
+
+        // identity keeps virt == phys; an offset into the hole is refused
+        assert_eq!(plan(descs, 0, &mut out), Some(3));
+        assert_eq!(out[2].virt, out[2].phys);
+        assert_eq!(plan(descs, 0x0000_8000_0000_0000, &mut out), None);
+        assert_eq!(plan(descs, DEFAULT_VIRT_OFFSET, &mut out[..2]), None);
+    }
+}
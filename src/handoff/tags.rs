@@ -0,0 +1,265 @@
+//! Tag list appended after `BootHandoffV1` (layout: `nonos_handoff::tags`).
+//!
+//! Everything here is gathered while boot services are up; `exit_and_jump`
+//! writes it into the handoff allocation before ExitBootServices and adds
+//! the tags that need the final map (memory map, timing, TSC, EFI runtime) after it.
+
+#![allow(dead_code)]
+
+use crate::acpi::{self, TableRef};
+use crate::eventlog::copy_event_log;
+use crate::hardware::query_framebuffers;
+use crate::loader::modules::PCR_MODULES;
+use crate::loader::KernelImage;
+use crate::memtest;
+use crate::log::logger::log_warn;
+use crate::log::ring;
+use crate::smbios::Inventory;
+use alloc::format;
+use nonos_handoff::tags::{
+    attestation_flags, boot_phase, AcpiTableEntry, AcpiTag, AttestationTag, EventLogTag, PhaseTiming, SmbiosTag, TagError, TagHeader,
+    TagListHeader, TscCalibration,
+};
+use nonos_handoff::{
+    tag, BadPage, BadPagesTag, BlockDevice, BootLogTag, CpuTag, EfiRuntimeRange, EfiRuntimeTag, FramebufferInfo, GptPartition,
+    MemoryMap, Module, PciFunction, SmbiosIdentity, SmbiosMemoryDevice, TagWriter,
+};
+use core::mem::size_of;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+use super::handoff::HandoffParams;
+use super::runtime;
+
+pub const MAX_FRAMEBUFFERS: usize = 4;
+/// PCR the kernel capsule is measured into (see main's measurement phase)
+const KERNEL_PCR: u32 = 4;
+
+pub(crate) const fn tag_bytes(payload: usize) -> usize {
+    size_of::<TagHeader>() + ((payload + 7) & !7)
+}
+
+/// Tag payloads collected before ExitBootServices
+pub struct BootTags<'a> {
+    pub cmdline: Option<&'a str>,
+    pub framebuffers: [FramebufferInfo; MAX_FRAMEBUFFERS],
+    pub framebuffer_count: usize,
+    pub modules: &'a [Module],
+    pub acpi: Option<AcpiTag>,
+    pub acpi_tables: [AcpiTableEntry; acpi::MAX_TABLES],
+    pub acpi_table_count: usize,
+    pub smbios: Option<SmbiosTag>,
+    pub smbios_info: Option<&'a Inventory>,
+    pub pci: &'a [PciFunction],
+    pub block_devices: &'a [BlockDevice],
+    pub partitions: &'a [GptPartition],
+    pub bad_pages: Option<&'a memtest::Report>,
+    pub event_log: Option<EventLogTag>,
+    /// Region reserved for the loader log; filled by `ring::publish` just before the jump
+    pub boot_log: Option<BootLogTag>,
+    pub attestation: AttestationTag,
+    pub cpu: CpuTag,
+    /// Most runtime ranges the EFI_RUNTIME tag may carry
+    pub efi_runtime_ranges: usize,
+}
+
+impl<'a> BootTags<'a> {
+    pub fn collect(
+        st: &SystemTable<Boot>,
+        image_handle: Handle,
+        kernel: &KernelImage,
+        params: &HandoffParams<'a>,
+        primary_fb: Option<FramebufferInfo>,
+        rsdp: u64,
+        smbios_entry: u64,
+    ) -> Self {
+        let mut framebuffers = [FramebufferInfo::default(); MAX_FRAMEBUFFERS];
+        let framebuffer_count = query_framebuffers(st, image_handle, primary_fb, &mut framebuffers);
+
+        let modules = if params.modules.ptr == 0 {
+            &[][..]
+        } else {
+            // safe: loader::load_modules built this table in LOADER_DATA; it outlives the handoff
+            unsafe { core::slice::from_raw_parts(params.modules.ptr as *const Module, params.modules.count as usize) }
+        };
+
+        let mut acpi_tables = [AcpiTableEntry::default(); acpi::MAX_TABLES];
+        let acpi_table_count = if rsdp != 0 { acpi_directory(rsdp, &mut acpi_tables) } else { 0 };
+
+        let mut flags = 0;
+        if params.kernel_sig_ok {
+            flags |= attestation_flags::KERNEL_SIG_OK;
+        }
+        if params.secure_boot {
+            flags |= attestation_flags::SECURE_BOOT;
+        }
+        if params.measured_boot {
+            flags |= attestation_flags::MEASURED_BOOT;
+        }
+
+        BootTags {
+            cmdline: params.cmdline,
+            framebuffers,
+            framebuffer_count,
+            modules,
+            acpi: (rsdp != 0).then(|| acpi_tag(rsdp)),
+            acpi_tables,
+            acpi_table_count,
+            smbios: smbios_tag(smbios_entry),
+            smbios_info: params.smbios,
+            pci: params.pci,
+            block_devices: params.block_devices,
+            partitions: params.partitions,
+            bad_pages: params.bad_pages,
+            event_log: if params.measured_boot { copy_event_log(st) } else { None },
+            boot_log: reserve_boot_log(st),
+            attestation: AttestationTag {
+                kernel_sha256: kernel.image_sha256,
+                flags,
+                kernel_pcr: KERNEL_PCR,
+                modules_pcr: PCR_MODULES,
+                reserved: 0,
+            },
+            efi_runtime_ranges: runtime::range_bound(st),
+            cpu: CpuTag {
+                available: params.cpu.bits,
+                required: kernel.requirements.cpu_features | params.required_cpu_features,
+                signature: params.cpu.signature,
+                phys_bits: params.cpu.phys_bits,
+                virt_bits: params.cpu.virt_bits,
+                reserved: 0,
+                vendor: params.cpu.vendor,
+                hypervisor: params.cpu.hypervisor,
+                // filled in by exit_and_jump from `harden::prepare`
+                shadow_stack: 0,
+                shadow_stack_size: 0,
+            },
+        }
+    }
+
+    /// Upper bound of the finished list, memory map, timing, TSC, EFI runtime and END tags included
+    pub fn area_bytes(&self) -> usize {
+        size_of::<TagListHeader>()
+            + self.cmdline.map_or(0, |c| tag_bytes(c.len() + 1))
+            + tag_bytes(size_of::<MemoryMap>())
+            + tag_bytes(boot_phase::COUNT * size_of::<PhaseTiming>())
+            + tag_bytes(size_of::<TscCalibration>())
+            + tag_bytes(size_of::<EfiRuntimeTag>() + self.efi_runtime_ranges * size_of::<EfiRuntimeRange>())
+            + tag_bytes(self.framebuffer_count * size_of::<FramebufferInfo>())
+            + tag_bytes(self.modules.len() * size_of::<Module>())
+            + tag_bytes(size_of::<AcpiTag>())
+            + tag_bytes(self.acpi_table_count * size_of::<AcpiTableEntry>())
+            + tag_bytes(size_of::<SmbiosTag>())
+            + self.smbios_info.map_or(0, |i| {
+                tag_bytes(size_of::<SmbiosIdentity>() + i.memory.len() * size_of::<SmbiosMemoryDevice>())
+            })
+            + tag_bytes(self.pci.len() * size_of::<PciFunction>())
+            + tag_bytes(self.block_devices.len() * size_of::<BlockDevice>())
+            + tag_bytes(self.partitions.len() * size_of::<GptPartition>())
+            + self.bad_pages.map_or(0, |r| tag_bytes(size_of::<BadPagesTag>() + r.pages.len() * size_of::<BadPage>()))
+            + tag_bytes(size_of::<EventLogTag>())
+            + tag_bytes(size_of::<BootLogTag>())
+            + tag_bytes(size_of::<AttestationTag>())
+            + tag_bytes(size_of::<CpuTag>())
+            + tag_bytes(0)
+    }
+
+    /// Write every tag except the ones `exit_and_jump` adds after ExitBootServices
+    pub fn write(&self, w: &mut TagWriter) -> Result<(), TagError> {
+        if let Some(c) = self.cmdline {
+            w.push_str(tag::CMDLINE, 1, c)?;
+        }
+        if self.framebuffer_count > 0 {
+            w.push_slice(tag::FRAMEBUFFERS, 1, &self.framebuffers[..self.framebuffer_count])?;
+        }
+        if !self.modules.is_empty() {
+            w.push_slice(tag::MODULES, 1, self.modules)?;
+        }
+        if let Some(acpi) = &self.acpi {
+            w.push_struct(tag::ACPI, 1, acpi)?;
+        }
+        if self.acpi_table_count > 0 {
+            w.push_slice(tag::ACPI_TABLES, 1, &self.acpi_tables[..self.acpi_table_count])?;
+        }
+        if let Some(smbios) = &self.smbios {
+            w.push_struct(tag::SMBIOS, 1, smbios)?;
+        }
+        if let Some(info) = self.smbios_info {
+            w.push_struct_slice(tag::SMBIOS_INFO, 1, &info.identity, &info.memory)?;
+        }
+        if !self.pci.is_empty() {
+            w.push_slice(tag::PCI, 1, self.pci)?;
+        }
+        if !self.block_devices.is_empty() {
+            w.push_slice(tag::BLOCK_DEVICES, 1, self.block_devices)?;
+        }
+        if !self.partitions.is_empty() {
+            w.push_slice(tag::PARTITIONS, 1, self.partitions)?;
+        }
+        if let Some(r) = self.bad_pages {
+            w.push_struct_slice(tag::BAD_PAGES, 1, &r.summary, &r.pages)?;
+        }
+        if let Some(log) = &self.event_log {
+            w.push_struct(tag::EVENT_LOG, 1, log)?;
+        }
+        if let Some(log) = &self.boot_log {
+            w.push_struct(tag::BOOT_LOG, 1, log)?;
+        }
+        w.push_struct(tag::ATTESTATION, 1, &self.attestation)?;
+        w.push_struct(tag::CPU, 1, &self.cpu)?;
+        Ok(())
+    }
+}
+
+/// LOADER_DATA pages large enough for a full log ring
+fn reserve_boot_log(st: &SystemTable<Boot>) -> Option<BootLogTag> {
+    let pages = ring::REGION_BYTES.div_ceil(0x1000);
+    match st.boot_services().allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages) {
+        Ok(ptr) => Some(BootLogTag { ptr, size: (pages * 0x1000) as u64 }),
+        Err(e) => {
+            log_warn("handoff", &format!("Boot log region allocation failed: {:?}", e.status()));
+            None
+        }
+    }
+}
+
+/// RSDP revision byte: 0 for ACPI 1.0, 2 when an XSDT is present
+fn acpi_tag(rsdp: u64) -> AcpiTag {
+    // safe: discover_acpi_rsdp validated the RSDP signature and checksum at this address
+    let revision = unsafe { core::ptr::read_volatile((rsdp + 15) as *const u8) };
+    AcpiTag { rsdp, revision, reserved: [0; 7] }
+}
+
+/// Tables reachable from the RSDP that passed their checksum; the rest are logged and left out
+fn acpi_directory(rsdp: u64, out: &mut [AcpiTableEntry]) -> usize {
+    let mut found = [TableRef::default(); acpi::MAX_TABLES];
+    let count = acpi::walk(rsdp, &mut found);
+    let mut n = 0;
+    for t in &found[..count] {
+        if !t.checksum_ok {
+            log_warn("acpi", &format!("{} at {:#x} fails its checksum; not handed over", t.name(), t.phys));
+            continue;
+        }
+        let Some(slot) = out.get_mut(n) else { break };
+        *slot = AcpiTableEntry { signature: t.signature, length: t.length, phys: t.phys, revision: t.revision, reserved: [0; 7] };
+        n += 1;
+    }
+    n
+}
+
+/// Version of the SMBIOS entry point at `entry`; `None` if absent or unrecognised
+fn smbios_tag(entry: u64) -> Option<SmbiosTag> {
+    if entry == 0 {
+        return None;
+    }
+    // safe: firmware config table entry; both anchors fit in the first 32 bytes
+    let head = unsafe { core::slice::from_raw_parts(entry as *const u8, 32) };
+    let (entry_kind, major, minor) = if head.starts_with(b"_SM3_") {
+        (3, head[7], head[8])
+    } else if head.starts_with(b"_SM_") {
+        (2, head[6], head[7])
+    } else {
+        return None;
+    };
+    Some(SmbiosTag { entry, major, minor, entry_kind, reserved: [0; 5] })
+}
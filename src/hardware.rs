@@ -2,9 +2,21 @@
 
 #![allow(dead_code)]
 
+use crate::acpi;
+use crate::handoff::handoff::{pixel_format, FramebufferInfo};
 use crate::log::logger::{log_debug, log_info, log_warn};
+use crate::pci;
+use crate::smbios::{self, Inventory};
+use crate::storage;
+use alloc::string::String;
+use alloc::vec::Vec;
+use nonos_handoff::cpu::cpu_feature;
+use nonos_handoff::pci::PciFunction;
+use nonos_handoff::smbios::text;
 use uefi::cstr16;
 use uefi::prelude::*;
+use uefi::proto::console::gop::{GraphicsOutput, PixelFormat};
+use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams};
 
 /// ACPI Root System Description Pointer structure
 #[repr(C, packed)]
@@ -27,12 +39,39 @@ pub struct RsdpDescriptor {
 pub struct HardwareInfo {
     pub acpi_available: bool,
     pub rsdp_address: Option<u64>,
+    pub acpi_revision: u8,
+    /// Tables reachable from the RSDP, and how many of them failed their checksum
+    pub acpi_tables: usize,
+    pub acpi_bad_tables: usize,
     pub cpu_count: usize,
+    pub ioapic_count: usize,
+    /// PCIe ECAM windows from the MCFG
+    pub ecam_regions: usize,
+    pub hpet_address: Option<u64>,
+    /// ACPI PM timer block (I/O port or MMIO address)
+    pub pm_timer: Option<u64>,
+    /// FADT reset register is usable
+    pub acpi_reset: bool,
     pub memory_size: u64,
+    /// System identity and memory devices from SMBIOS (handoff `tag::SMBIOS_INFO`)
+    pub smbios: Option<Inventory>,
     pub pci_devices: usize,
+    /// Every PCI function found, in scan order (handoff `tag::PCI`)
+    pub pci: Vec<PciFunction>,
     pub storage_devices: usize,
+    /// Whole block devices and GPT partitions (handoff `tag::BLOCK_DEVICES`, `tag::PARTITIONS`)
+    pub storage: storage::Inventory,
     pub network_interfaces: usize,
     pub graphics_devices: usize,
+    /// Boot CPU features (handoff `tag::CPU`)
+    pub cpu: CpuFeatures,
+}
+
+impl HardwareInfo {
+    /// SMBIOS system UUID: stable across boots and firmware updates, unlike handles or MACs
+    pub fn machine_uuid(&self) -> Option<[u8; 16]> {
+        self.smbios.as_ref()?.identity.uuid()
+    }
 }
 
 pub fn discover_system_hardware(system_table: &mut SystemTable<Boot>) -> HardwareInfo {
@@ -52,55 +91,188 @@ pub fn discover_system_hardware(system_table: &mut SystemTable<Boot>) -> Hardwar
     hardware.memory_size = discover_memory_size(system_table);
     log_info("memory", &format!("Total RAM: {} MiB", hardware.memory_size / (1024*1024)));
 
-    // CPU count (MADT parsing if ACPI available, fallback otherwise)
-    hardware.cpu_count = if let Some(rsdp) = hardware.rsdp_address {
-        get_cpu_count_from_acpi(rsdp)
-    } else { 1 };
+    // CPUs, interrupt controllers, timers and ECAM from the ACPI tables
+    hardware.cpu_count = 1;
+    let acpi_info = hardware.rsdp_address.map(acpi::discover);
+    if let Some(info) = &acpi_info {
+        apply_acpi(&mut hardware, info);
+    }
+
+    // System identity and DIMMs
+    hardware.smbios = smbios::discover(smbios::find_entry(system_table));
+    match &hardware.smbios {
+        Some(inv) => log_smbios(inv),
+        None => log_warn("smbios", "No valid SMBIOS entry point"),
+    }
 
     // Device enumeration
-    hardware.storage_devices = enumerate_storage(system_table);
+    hardware.storage = storage::enumerate(system_table.boot_services());
+    hardware.storage_devices = hardware.storage.devices.len();
+    storage::log_inventory(&hardware.storage);
     hardware.network_interfaces = enumerate_network(system_table);
     hardware.graphics_devices = enumerate_graphics(system_table);
-    hardware.pci_devices = enumerate_pci(system_table);
+    hardware.pci = pci::enumerate(system_table, acpi_info.as_ref().map_or(&[], |i| i.ecam()));
+    hardware.pci_devices = hardware.pci.len();
+    pci::log_inventory(&hardware.pci);
 
-    // CPU features (NXE, SMEP, SMAP, UMIP)
-    let cpu_flags = detect_cpu_features();
-
-    log_info("cpu", &format!("CPU features: NXE={} SMEP={} SMAP={} UMIP={}",
-        cpu_flags.nxe, cpu_flags.smep, cpu_flags.smap, cpu_flags.umip));
+    // Boot CPU identity and features
+    hardware.cpu = detect_cpu_features();
+    log_cpu(&hardware.cpu);
 
     display_hardware_summary(&hardware, system_table);
 
     hardware
 }
 
-fn discover_acpi_rsdp(system_table: &mut SystemTable<Boot>) -> Option<u64> {
-    for entry in system_table.config_table() {
-        if entry.guid == uefi::table::cfg::ACPI2_GUID || entry.guid == uefi::table::cfg::ACPI_GUID {
-            let rsdp_ptr = entry.address as u64;
-            if validate_rsdp(rsdp_ptr) {
-                return Some(rsdp_ptr);
-            }
+/// RSDP from the config table, the ACPI 2.0 entry preferred
+pub fn discover_acpi_rsdp(system_table: &mut SystemTable<Boot>) -> Option<u64> {
+    let table = system_table.config_table();
+    [uefi::table::cfg::ACPI2_GUID, uefi::table::cfg::ACPI_GUID].iter().find_map(|guid| {
+        table.iter().filter(|e| e.guid == *guid).map(|e| e.address as u64).find(|&rsdp| validate_rsdp(rsdp))
+    })
+}
+
+fn apply_acpi(hardware: &mut HardwareInfo, info: &acpi::AcpiInfo) {
+    hardware.acpi_revision = info.revision;
+    hardware.acpi_tables = info.table_count;
+    hardware.acpi_bad_tables = info.bad_tables();
+    for t in info.tables() {
+        log_debug("acpi", &format!("{} rev {} at {:#x}, {} bytes{}", t.name(), t.revision, t.phys, t.length,
+            if t.checksum_ok { "" } else { " (bad checksum)" }));
+    }
+    log_info("acpi", &format!("ACPI rev {}: {} tables, {} failed checksum",
+        info.revision, hardware.acpi_tables, hardware.acpi_bad_tables));
+
+    match &info.madt {
+        Some(m) => {
+            hardware.cpu_count = m.enabled_cpus.max(1);
+            hardware.ioapic_count = m.ioapic_count;
+            log_info("acpi", &format!("MADT: {} CPUs ({} hot-pluggable), {} IOAPICs, {} overrides",
+                m.enabled_cpus, m.online_capable_cpus, m.ioapic_count, m.override_count));
         }
+        None => log_debug("acpi", "No usable MADT; assuming a single CPU"),
+    }
+    if let Some(f) = &info.fadt {
+        hardware.pm_timer = f.pm_timer.map(|g| g.address);
+        hardware.acpi_reset = f.reset.is_some();
     }
-    None
+    hardware.ecam_regions = info.ecam_count;
+    for e in info.ecam() {
+        log_info("acpi", &format!("ECAM segment {} buses {}-{} at {:#x}", e.segment, e.start_bus, e.end_bus, e.base));
+    }
+    hardware.hpet_address = info.hpet.map(|h| h.base.address);
 }
 
-fn validate_rsdp(rsdp_address: u64) -> bool {
-    unsafe {
-        let rsdp = &*(rsdp_address as *const RsdpDescriptor);
-        if &rsdp.signature != b"RSD PTR " { return false; }
-        let bytes = core::slice::from_raw_parts(rsdp_address as *const u8, 20);
-        let sum: u8 = bytes.iter().fold(0, |acc, &b| acc.wrapping_add(b));
-        sum == 0
+fn log_cpu(cpu: &CpuFeatures) {
+    let mut names = String::new();
+    for n in cpu_feature::names(cpu.bits) {
+        names.push(' ');
+        names.push_str(n);
+    }
+    log_info("cpu", &format!("{} signature {:#x}, {}-bit physical / {}-bit virtual, features:{}",
+        text(&cpu.vendor), cpu.signature, cpu.phys_bits, cpu.virt_bits, names));
+    if cpu.has(cpu_feature::HYPERVISOR) {
+        log_info("cpu", &format!("Hypervisor: {}", text(&cpu.hypervisor)));
+    }
+}
+
+fn log_smbios(inv: &Inventory) {
+    let id = &inv.identity;
+    log_info("smbios", &format!("SMBIOS {}.{}: {} {}, serial '{}', BIOS {} {}", id.major, id.minor,
+        text(&id.manufacturer), text(&id.product), text(&id.serial), text(&id.bios_vendor), text(&id.bios_version)));
+    match id.uuid() {
+        Some(uuid) => log_info("smbios", &format!("Machine UUID {}", smbios::format_uuid(&uuid))),
+        None => log_warn("smbios", "System UUID not set by firmware"),
+    }
+    for d in inv.memory.iter().filter(|d| d.size != 0) {
+        log_debug("smbios", &format!("{}: {} MiB {} MT/s {} {}", text(&d.locator), d.size >> 20, d.speed_mts,
+            text(&d.manufacturer), text(&d.part_number)));
+    }
+    log_info("smbios", &format!("{} memory devices, {} MiB installed", inv.memory.len(), inv.installed_memory() >> 20));
+}
+
+/// Describe the current GOP mode and framebuffer, if any.
+pub fn query_framebuffer(system_table: &mut SystemTable<Boot>) -> Option<FramebufferInfo> {
+    let bs = system_table.boot_services();
+    let handle = bs.get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    let mut gop = bs.open_protocol_exclusive::<GraphicsOutput>(handle).ok()?;
+    Some(describe_gop(&mut gop))
+}
+
+/// Red, green, blue and reserved masks of the active GOP mode, if it is `PixelFormat::Bitmask`
+pub fn query_pixel_bitmask(system_table: &mut SystemTable<Boot>) -> Option<[u32; 4]> {
+    let bs = system_table.boot_services();
+    let handle = bs.get_handle_for_protocol::<GraphicsOutput>().ok()?;
+    let gop = bs.open_protocol_exclusive::<GraphicsOutput>(handle).ok()?;
+    let m = gop.current_mode_info().pixel_bitmask()?;
+    Some([m.red, m.green, m.blue, m.reserved])
+}
+
+/// Every GOP framebuffer, deduplicated by address; `primary` (if any) goes first.
+/// Uses GetProtocol opens so the console keeps its own GOP binding.
+pub fn query_framebuffers(
+    system_table: &SystemTable<Boot>,
+    image_handle: Handle,
+    primary: Option<FramebufferInfo>,
+    out: &mut [FramebufferInfo],
+) -> usize {
+    let mut n = 0;
+    let mut add = |fb: FramebufferInfo, n: &mut usize| {
+        if fb.ptr != 0 && *n < out.len() && !out[..*n].iter().any(|f| f.ptr == fb.ptr) {
+            out[*n] = fb;
+            *n += 1;
+        }
+    };
+    if let Some(fb) = primary {
+        add(fb, &mut n);
+    }
+    let bs = system_table.boot_services();
+    let Ok(handles) = bs.find_handles::<GraphicsOutput>() else { return n };
+    for handle in handles {
+        let params = OpenProtocolParams { handle, agent: image_handle, controller: None };
+        // safe: GetProtocol does not take ownership; we only read mode info
+        if let Ok(mut gop) = unsafe { bs.open_protocol::<GraphicsOutput>(params, OpenProtocolAttributes::GetProtocol) } {
+            add(describe_gop(&mut gop), &mut n);
+        }
+    }
+    n
+}
+
+fn describe_gop(gop: &mut GraphicsOutput) -> FramebufferInfo {
+    let info = gop.current_mode_info();
+    let (width, height) = info.resolution();
+    let format = match info.pixel_format() {
+        PixelFormat::Rgb => pixel_format::RGBX8,
+        PixelFormat::Bgr => pixel_format::BGRX8,
+        PixelFormat::Bitmask => pixel_format::BITMASK,
+        PixelFormat::BltOnly => pixel_format::BLT_ONLY,
+    };
+    let stride = info.stride() as u32;
+    let mut fb = gop.frame_buffer();
+    FramebufferInfo {
+        ptr: fb.as_mut_ptr() as u64,
+        size: fb.size() as u64,
+        width: width as u32,
+        height: height as u32,
+        stride,
+        pixel_format: format,
     }
 }
 
-// Kernel should parse MADT table for true count; here we fallback to 1 for safety.
-fn get_cpu_count_from_acpi(_rsdp_address: u64) -> usize {
-    // Next development: parse MADT table for APIC count
-    log_debug("acpi", "MADT parsing for CPU count not implemented; defaulting to 1");
-    1
+fn validate_rsdp(rsdp_address: u64) -> bool {
+    if rsdp_address == 0 {
+        return false;
+    }
+    // safe: config table entry; rsdp_at reads at most the 36-byte v2 structure
+    let head = unsafe { acpi::rsdp_at(rsdp_address) };
+    // a v2 RSDP declares its own length; anything odd is caught by rsdp_valid
+    let len = match head.get(20..24) {
+        Some(l) => u32::from_le_bytes([l[0], l[1], l[2], l[3]]).clamp(36, 4096) as usize,
+        None => head.len(),
+    };
+    // safe: as above, bounded to one page
+    let bytes = unsafe { core::slice::from_raw_parts(rsdp_address as *const u8, len) };
+    acpi::rsdp_valid(bytes)
 }
 
 fn discover_memory_size(system_table: &mut SystemTable<Boot>) -> u64 {
@@ -123,14 +295,6 @@ fn discover_memory_size(system_table: &mut SystemTable<Boot>) -> u64 {
     0
 }
 
-// Storage: BlockIO
-fn enumerate_storage(system_table: &mut SystemTable<Boot>) -> usize {
-    let bs = system_table.boot_services();
-    let count = bs.find_handles::<uefi::proto::media::block::BlockIO>().map(|h| h.len()).unwrap_or(0);
-    log_info("storage", &format!("Storage devices: {}", count));
-    count
-}
-
 // Network: SNP
 fn enumerate_network(system_table: &mut SystemTable<Boot>) -> usize {
     let bs = system_table.boot_services();
@@ -147,51 +311,88 @@ fn enumerate_graphics(system_table: &mut SystemTable<Boot>) -> usize {
     count
 }
 
-// PCI: DevicePath but parsing is deferred to kernel
-fn enumerate_pci(system_table: &mut SystemTable<Boot>) -> usize {
-    let bs = system_table.boot_services();
-    let count = bs.find_handles::<uefi::proto::device_path::DevicePath>().map(|h| h.len()).unwrap_or(0);
-    log_info("pci", &format!("PCI devices: {}", count));
-    count
+/// Boot CPU identity and `cpu_feature` bits as CPUID reports them
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuFeatures {
+    pub bits: u64,
+    pub signature: u32,
+    pub vendor: [u8; 12],
+    /// Hypervisor vendor from leaf 0x40000000, zero on bare metal
+    pub hypervisor: [u8; 12],
+    pub phys_bits: u8,
+    pub virt_bits: u8,
 }
 
-// CPU features: NXE, SMEP, SMAP, UMIP
-#[derive(Default)]
-pub struct CpuFeatureFlags { pub nxe: bool, pub smep: bool, pub smap: bool, pub umip: bool }
+impl CpuFeatures {
+    pub fn has(&self, bits: u64) -> bool {
+        self.bits & bits == bits
+    }
 
-pub fn detect_cpu_features() -> CpuFeatureFlags {
-    #[cfg(target_arch = "x86_64")]
-    unsafe {
-        let mut flags = CpuFeatureFlags::default();
-        // CPUID: leaf 0x80000001, EDX[20] = NX
-        let (_, _, _, edx) = cpuid(0x80000001);
-        flags.nxe = (edx & (1 << 20)) != 0;
-        // CPUID: leaf 7, EBX[7]=SMEP EBX[20]=SMAP EBX[2]=UMIP
-        let (_, ebx, _, _) = cpuid(7);
-        flags.smep = (ebx & (1 << 7)) != 0;
-        flags.smap = (ebx & (1 << 20)) != 0;
-        flags.umip = (ebx & (1 << 2)) != 0;
-        flags
+    /// Required bits this CPU lacks
+    pub fn missing(&self, required: u64) -> u64 {
+        required & !self.bits
     }
-    #[cfg(not(target_arch = "x86_64"))]
-    { CpuFeatureFlags::default() }
 }
 
+/// CPUID leaf/subleaf as (eax, ebx, ecx, edx)
 #[cfg(target_arch = "x86_64")]
-unsafe fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
-    let mut eax: u32;
-    let mut ebx: u32;
-    let mut ecx: u32;
-    let mut edx: u32;
-    core::arch::asm!(
-        "cpuid",
-        inout("eax") leaf => eax,
-        lateout("ebx") ebx,
-        lateout("ecx") ecx,
-        lateout("edx") edx,
-        options(nostack, preserves_flags)
-    );
-    (eax, ebx, ecx, edx)
+pub(crate) fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let r = core::arch::x86_64::__cpuid_count(leaf, subleaf);
+    (r.eax, r.ebx, r.ecx, r.edx)
+}
+
+fn vendor_of(b: u32, c: u32, d: u32) -> [u8; 12] {
+    let mut v = [0u8; 12];
+    v[..4].copy_from_slice(&b.to_le_bytes());
+    v[4..8].copy_from_slice(&c.to_le_bytes());
+    v[8..].copy_from_slice(&d.to_le_bytes());
+    v
+}
+
+pub fn detect_cpu_features() -> CpuFeatures {
+    #[cfg(target_arch = "x86_64")]
+    {
+        let set = |reg: u32, n: u32, feature: u64| if reg & (1 << n) != 0 { feature } else { 0 };
+        let mut cpu = CpuFeatures::default();
+        let (max_basic, b, c, d) = cpuid(0, 0);
+        cpu.vendor = vendor_of(b, d, c);
+
+        let (signature, _, ecx1, _) = cpuid(1, 0);
+        cpu.signature = signature;
+        cpu.bits |= set(ecx1, 25, cpu_feature::AES_NI) | set(ecx1, 30, cpu_feature::RDRAND);
+        cpu.bits |= set(ecx1, 31, cpu_feature::HYPERVISOR);
+
+        if max_basic >= 7 {
+            let (_, ebx, ecx, edx) = cpuid(7, 0);
+            cpu.bits |= set(ebx, 5, cpu_feature::AVX2) | set(ebx, 7, cpu_feature::SMEP) | set(ebx, 18, cpu_feature::RDSEED);
+            cpu.bits |= set(ebx, 20, cpu_feature::SMAP) | set(ebx, 29, cpu_feature::SHA);
+            cpu.bits |= set(ecx, 2, cpu_feature::UMIP) | set(ecx, 3, cpu_feature::PKU) | set(ecx, 7, cpu_feature::CET_SS);
+            cpu.bits |= set(ecx, 13, cpu_feature::TME) | set(ecx, 16, cpu_feature::LA57);
+            cpu.bits |= set(edx, 20, cpu_feature::CET_IBT);
+        }
+
+        let (max_ext, _, _, _) = cpuid(0x8000_0000, 0);
+        if max_ext >= 0x8000_0001 {
+            cpu.bits |= set(cpuid(0x8000_0001, 0).3, 20, cpu_feature::NXE);
+        }
+        if max_ext >= 0x8000_0008 {
+            let (eax, _, _, _) = cpuid(0x8000_0008, 0);
+            cpu.phys_bits = eax as u8;
+            cpu.virt_bits = (eax >> 8) as u8;
+        }
+        // leaf 0x8000001F is AMD's memory encryption leaf; Intel reports nothing there
+        if max_ext >= 0x8000_001F && &cpu.vendor == b"AuthenticAMD" {
+            cpu.bits |= set(cpuid(0x8000_001F, 0).0, 0, cpu_feature::SME);
+        }
+
+        if cpu.has(cpu_feature::HYPERVISOR) {
+            let (_, b, c, d) = cpuid(0x4000_0000, 0);
+            cpu.hypervisor = vendor_of(b, c, d);
+        }
+        cpu
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    { CpuFeatures::default() }
 }
 
 fn display_hardware_summary(h: &HardwareInfo, system_table: &mut SystemTable<Boot>) {
@@ -3,16 +3,30 @@
 
 extern crate alloc;
 
+pub mod acpi;
+pub mod bytes;
 pub mod capsule;
+pub mod chainload;
 pub mod config;
 pub mod entropy;
+pub mod eventlog;
+pub mod gpt;
 pub mod handoff;
 pub mod hardware;
+pub mod linux;
 pub mod loader;
+pub mod memtest;
 pub mod multiboot;
+pub mod multiboot2;
 pub mod network;
+pub mod pci;
 pub mod security;
+pub mod slots;
+pub mod smbios;
+pub mod smp;
+pub mod storage;
 pub mod testing;
+pub mod timing;
 pub mod ui;
 pub mod verify;
 pub mod zkmeta;
@@ -31,4 +45,5 @@ pub mod zk {
 // Logging modules
 pub mod log {
     pub mod logger;
+    pub mod ring;
 }
@@ -0,0 +1,553 @@
+//! Linux x86 boot protocol for signed recovery kernels.
+//!
+//! Handles `BootEntryType::LinuxKernel`. The bzImage (and optional initrd,
+//! named by an `initrd=<path>` token in the entry command line) are verified
+//! against the NONOS keyring through the same rules as chainloaded images,
+//! measured, and placed per Documentation/arch/x86/boot.rst:
+//!
+//! - `boot_params` zero page: setup header copied from the image,
+//!   `type_of_loader = 0xFF`, cmdline/initrd pointers, EFI info block and
+//!   an e820 table derived from the UEFI memory map.
+//! - Entry: the 64-bit EFI handover entry when `XLF_EFI_HANDOVER_64` is set
+//!   (the kernel's stub calls ExitBootServices), otherwise we exit boot
+//!   services ourselves and jump to the 64-bit entry at `load + 0x200`.
+//!
+//! Measurements: kernel → PCR 4, command line → PCR 8, initrd → PCR 9.
+
+#![allow(dead_code)]
+
+use crate::bytes::{le16, le32, le64};
+use crate::chainload::{read_verified, ChainloadError};
+use crate::loader::{FileBuffer, LoaderError};
+use crate::log::logger::{log_info, log_warn};
+use crate::multiboot::{BootEntry, BootEntryType};
+use crate::security::extend_pcr_measurement_with_event;
+use alloc::format;
+use core::convert::Infallible;
+use core::fmt;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, BootServices, MemoryDescriptor, MemoryType, PhysicalAddress};
+
+const PAGE_SIZE: usize = 0x1000;
+
+/* ---- boot_params / setup_header offsets (zero page relative) ---- */
+const BP_EXT_RAMDISK_IMAGE: usize = 0x0C0;
+const BP_EXT_RAMDISK_SIZE: usize = 0x0C4;
+const BP_EXT_CMD_LINE_PTR: usize = 0x0C8;
+const BP_EFI_INFO: usize = 0x1C0;
+const BP_E820_ENTRIES: usize = 0x1E8;
+const BP_E820_TABLE: usize = 0x2D0;
+const E820_MAX_ENTRIES: usize = 128;
+
+const HDR_START: usize = 0x1F1;
+const HDR_SETUP_SECTS: usize = 0x1F1;
+const HDR_JUMP_LEN: usize = 0x201;
+const HDR_MAGIC: usize = 0x202;
+const HDR_VERSION: usize = 0x206;
+const HDR_TYPE_OF_LOADER: usize = 0x210;
+const HDR_LOADFLAGS: usize = 0x211;
+const HDR_CODE32_START: usize = 0x214;
+const HDR_RAMDISK_IMAGE: usize = 0x218;
+const HDR_RAMDISK_SIZE: usize = 0x21C;
+const HDR_HEAP_END_PTR: usize = 0x224;
+const HDR_CMD_LINE_PTR: usize = 0x228;
+const HDR_INITRD_ADDR_MAX: usize = 0x22C;
+const HDR_KERNEL_ALIGNMENT: usize = 0x230;
+const HDR_RELOCATABLE: usize = 0x234;
+const HDR_XLOADFLAGS: usize = 0x236;
+const HDR_CMDLINE_SIZE: usize = 0x238;
+const HDR_PREF_ADDRESS: usize = 0x258;
+const HDR_INIT_SIZE: usize = 0x260;
+const HDR_HANDOVER_OFFSET: usize = 0x264;
+
+const HDRS_MAGIC: &[u8; 4] = b"HdrS";
+const MIN_PROTOCOL: u16 = 0x020C;
+
+const LOADED_HIGH: u8 = 1 << 0;
+const CAN_USE_HEAP: u8 = 1 << 7;
+const XLF_KERNEL_64: u16 = 1 << 0;
+const XLF_CAN_BE_LOADED_ABOVE_4G: u16 = 1 << 1;
+const XLF_EFI_HANDOVER_64: u16 = 1 << 3;
+
+const E820_RAM: u32 = 1;
+const E820_RESERVED: u32 = 2;
+const E820_ACPI: u32 = 3;
+const E820_NVS: u32 = 4;
+const E820_UNUSABLE: u32 = 5;
+const E820_PMEM: u32 = 7;
+
+const PCR_KERNEL: u32 = 4;
+const PCR_CMDLINE: u32 = 8;
+const PCR_INITRD: u32 = 9;
+
+#[derive(Debug)]
+pub enum LinuxError {
+    WrongEntryType,
+    File(LoaderError),
+    Verify(ChainloadError),
+    BadImage(&'static str),
+    Allocation(Status),
+}
+
+impl fmt::Display for LinuxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinuxError::WrongEntryType => write!(f, "entry is not a Linux kernel"),
+            LinuxError::File(e) => write!(f, "read failed: {}", e),
+            LinuxError::Verify(e) => write!(f, "verification failed: {}", e),
+            LinuxError::BadImage(s) => write!(f, "bad bzImage: {}", s),
+            LinuxError::Allocation(s) => write!(f, "allocation failed: {:?}", s),
+        }
+    }
+}
+
+impl From<ChainloadError> for LinuxError {
+    fn from(e: ChainloadError) -> Self {
+        match e {
+            ChainloadError::File(e) => LinuxError::File(e),
+            e => LinuxError::Verify(e),
+        }
+    }
+}
+
+/// Fields of the bzImage setup header the loader acts on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SetupHeader {
+    pub setup_sects: u8,
+    pub version: u16,
+    pub relocatable: bool,
+    pub kernel_alignment: u32,
+    pub xloadflags: u16,
+    pub cmdline_size: u32,
+    pub initrd_addr_max: u32,
+    pub pref_address: u64,
+    pub init_size: u32,
+    pub handover_offset: u32,
+    /// End of the setup header within the file (0x202 + jump length)
+    pub header_end: usize,
+}
+
+impl SetupHeader {
+    /// Byte offset of the protected-mode kernel within the bzImage.
+    pub fn kernel_offset(&self) -> usize {
+        (self.setup_sects as usize + 1) * 512
+    }
+}
+
+/// Parse and sanity-check the setup header of a 64-bit capable bzImage.
+pub fn parse_setup_header(image: &[u8]) -> Result<SetupHeader, &'static str> {
+    if image.len() < HDR_HANDOVER_OFFSET + 4 {
+        return Err("image too small");
+    }
+    if &image[HDR_MAGIC..HDR_MAGIC + 4] != HDRS_MAGIC {
+        return Err("missing HdrS magic");
+    }
+    let version = le16(image, HDR_VERSION);
+    if version < MIN_PROTOCOL {
+        return Err("boot protocol older than 2.12");
+    }
+    let xloadflags = le16(image, HDR_XLOADFLAGS);
+    if xloadflags & XLF_KERNEL_64 == 0 {
+        return Err("kernel has no 64-bit entry");
+    }
+
+    let mut setup_sects = image[HDR_SETUP_SECTS];
+    if setup_sects == 0 {
+        setup_sects = 4;
+    }
+    let hdr = SetupHeader {
+        setup_sects,
+        version,
+        relocatable: image[HDR_RELOCATABLE] != 0,
+        kernel_alignment: le32(image, HDR_KERNEL_ALIGNMENT),
+        xloadflags,
+        cmdline_size: le32(image, HDR_CMDLINE_SIZE),
+        initrd_addr_max: le32(image, HDR_INITRD_ADDR_MAX),
+        pref_address: le64(image, HDR_PREF_ADDRESS),
+        init_size: le32(image, HDR_INIT_SIZE),
+        handover_offset: le32(image, HDR_HANDOVER_OFFSET),
+        header_end: HDR_MAGIC + image[HDR_JUMP_LEN] as usize,
+    };
+    if hdr.kernel_offset() >= image.len() {
+        return Err("setup sectors exceed image");
+    }
+    if hdr.header_end > PAGE_SIZE || hdr.header_end > image.len() {
+        return Err("setup header too long");
+    }
+    Ok(hdr)
+}
+
+/// Map a UEFI memory type to its e820 equivalent (post-ExitBootServices view).
+pub fn e820_type(ty: MemoryType) -> u32 {
+    match ty {
+        MemoryType::CONVENTIONAL
+        | MemoryType::LOADER_CODE
+        | MemoryType::LOADER_DATA
+        | MemoryType::BOOT_SERVICES_CODE
+        | MemoryType::BOOT_SERVICES_DATA => E820_RAM,
+        MemoryType::ACPI_RECLAIM => E820_ACPI,
+        MemoryType::ACPI_NON_VOLATILE => E820_NVS,
+        MemoryType::UNUSABLE => E820_UNUSABLE,
+        MemoryType::PERSISTENT_MEMORY => E820_PMEM,
+        _ => E820_RESERVED,
+    }
+}
+
+/// Append an e820 range, merging with the previous one when contiguous.
+/// Returns false if the table is full.
+pub fn push_e820(table: &mut [(u64, u64, u32)], count: &mut usize, addr: u64, size: u64, ty: u32) -> bool {
+    if *count > 0 {
+        let (pa, ps, pt) = table[*count - 1];
+        if pt == ty && pa + ps == addr {
+            table[*count - 1].1 = ps + size;
+            return true;
+        }
+    }
+    if *count >= table.len() {
+        return false;
+    }
+    table[*count] = (addr, size, ty);
+    *count += 1;
+    true
+}
+
+/// Pull `initrd=<path>` out of the command line; the rest is passed through.
+fn split_initrd<'a>(cmdline: &'a str, rest: &mut [u8; 256]) -> (Option<&'a str>, usize) {
+    let mut initrd = None;
+    let mut n = 0;
+    for tok in cmdline.split_ascii_whitespace() {
+        if let Some(p) = tok.strip_prefix("initrd=") {
+            initrd = Some(p);
+            continue;
+        }
+        if n + tok.len() + 1 >= rest.len() {
+            break;
+        }
+        if n > 0 {
+            rest[n] = b' ';
+            n += 1;
+        }
+        rest[n..n + tok.len()].copy_from_slice(tok.as_bytes());
+        n += tok.len();
+    }
+    (initrd, n)
+}
+
+/// Pages and files `boot_linux` holds; all of it is released if the boot
+/// fails before the jump.
+struct Allocations<'a> {
+    bs: &'a BootServices,
+    pages: [(PhysicalAddress, usize); 4],
+    count: usize,
+    files: [Option<FileBuffer>; 2],
+}
+
+impl<'a> Allocations<'a> {
+    fn new(bs: &'a BootServices, files: [Option<FileBuffer>; 2]) -> Self {
+        Allocations { bs, pages: [(0, 0); 4], count: 0, files }
+    }
+
+    fn allocate(&mut self, ty: AllocateType, mem: MemoryType, pages: usize) -> Result<PhysicalAddress, LinuxError> {
+        let addr = self.bs.allocate_pages(ty, mem, pages).map_err(|e| LinuxError::Allocation(e.status()))?;
+        self.pages[self.count] = (addr, pages);
+        self.count += 1;
+        Ok(addr)
+    }
+
+    fn alloc_below(&mut self, max: u64, bytes: usize) -> Result<PhysicalAddress, LinuxError> {
+        self.allocate(AllocateType::MaxAddress(max), MemoryType::LOADER_DATA, (bytes + PAGE_SIZE - 1) / PAGE_SIZE)
+    }
+
+    fn free_file(&mut self, slot: usize) {
+        if let Some(f) = self.files[slot].take() {
+            f.free(self.bs);
+        }
+    }
+
+    /// The placed pages now belong to the kernel; only the file buffers go
+    fn keep_pages(mut self) {
+        self.free_file(0);
+        self.free_file(1);
+        self.count = 0;
+    }
+}
+
+impl Drop for Allocations<'_> {
+    fn drop(&mut self) {
+        self.free_file(0);
+        self.free_file(1);
+        for &(addr, pages) in &self.pages[..self.count] {
+            let _ = self.bs.free_pages(addr, pages);
+        }
+    }
+}
+
+/// GDT with the selectors the 64-bit boot protocol requires (__BOOT_CS/__BOOT_DS).
+#[repr(C, align(16))]
+struct BootGdt([u64; 4]);
+
+static LINUX_GDT: BootGdt = BootGdt([
+    0,
+    0,
+    0x00AF_9A00_0000_FFFF, // 0x10: 64-bit code
+    0x00CF_9200_0000_FFFF, // 0x18: data
+]);
+
+#[repr(C, packed)]
+struct GdtPtr {
+    limit: u16,
+    base: u64,
+}
+
+type HandoverEntry = extern "sysv64" fn(*mut core::ffi::c_void, *mut core::ffi::c_void, *mut u8) -> !;
+
+/// Verify, load and boot the Linux kernel referenced by `entry`.
+/// Only returns on failure; boot services remain usable in that case.
+pub fn boot_linux(
+    image_handle: Handle,
+    st: &mut SystemTable<Boot>,
+    entry: &BootEntry,
+    measured_boot: bool,
+) -> Result<Infallible, LinuxError> {
+    if entry.entry_type != BootEntryType::LinuxKernel {
+        return Err(LinuxError::WrongEntryType);
+    }
+
+    let mut cmdline = [0u8; 256];
+    let (initrd_path, cmd_len) = split_initrd(entry.command_line_str(), &mut cmdline);
+
+    log_info("linux", &format!("Loading Linux kernel {}", entry.path_str()));
+    let kernel_file = read_verified(st, image_handle, entry.path_str(), PCR_KERNEL, b"NONOS linux kernel", measured_boot)?;
+    let image = kernel_file.as_slice();
+    let hdr = match parse_setup_header(image) {
+        Ok(h) => h,
+        Err(e) => {
+            kernel_file.free(st.boot_services());
+            return Err(LinuxError::BadImage(e));
+        }
+    };
+    log_info("linux", &format!("Boot protocol {}.{:02}", hdr.version >> 8, hdr.version & 0xFF));
+
+    if measured_boot && !extend_pcr_measurement_with_event(st, PCR_CMDLINE, &cmdline[..cmd_len], b"NONOS linux cmdline") {
+        log_warn("linux", "PCR measurement of command line failed");
+    }
+
+    let initrd = match initrd_path {
+        Some(p) => match read_verified(st, image_handle, p, PCR_INITRD, b"NONOS linux initrd", measured_boot) {
+            Ok(f) => Some(f),
+            Err(e) => {
+                kernel_file.free(st.boot_services());
+                return Err(e.into());
+            }
+        },
+        None => None,
+    };
+
+    let bs = st.boot_services();
+    let mut allocs = Allocations::new(bs, [Some(kernel_file), initrd]);
+
+    // Protected-mode kernel: prefer pref_address, relocate if allowed
+    let payload = &image[hdr.kernel_offset()..];
+    let load_bytes = (hdr.init_size as usize).max(payload.len());
+    let load_pages = (load_bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+    let kernel_addr = match allocs.allocate(AllocateType::Address(hdr.pref_address), MemoryType::LOADER_CODE, load_pages) {
+        Ok(a) => a,
+        Err(_) if hdr.relocatable => {
+            // over-allocate to honour kernel_alignment
+            let align = (hdr.kernel_alignment as u64).max(PAGE_SIZE as u64);
+            let extra = (align as usize) / PAGE_SIZE;
+            let max = if hdr.xloadflags & XLF_CAN_BE_LOADED_ABOVE_4G != 0 { u64::MAX } else { 0xFFFF_FFFF };
+            let raw = allocs.allocate(AllocateType::MaxAddress(max), MemoryType::LOADER_CODE, load_pages + extra)?;
+            (raw + align - 1) & !(align - 1)
+        }
+        Err(e) => return Err(e),
+    };
+    // safe: kernel_addr..+load_bytes was allocated above
+    unsafe {
+        core::ptr::copy_nonoverlapping(payload.as_ptr(), kernel_addr as *mut u8, payload.len());
+        core::ptr::write_bytes((kernel_addr as usize + payload.len()) as *mut u8, 0, load_bytes - payload.len());
+    }
+    log_info("linux", &format!("Kernel placed at 0x{:x} ({} bytes)", kernel_addr, load_bytes));
+
+    // boot_params zero page
+    let bp_addr = allocs.alloc_below(0xFFFF_FFFF, PAGE_SIZE)?;
+    // safe: one freshly allocated page
+    let bp = unsafe { core::slice::from_raw_parts_mut(bp_addr as *mut u8, PAGE_SIZE) };
+    bp.fill(0);
+    bp[HDR_START..hdr.header_end].copy_from_slice(&image[HDR_START..hdr.header_end]);
+    bp[HDR_TYPE_OF_LOADER] = 0xFF;
+    bp[HDR_LOADFLAGS] |= LOADED_HIGH | CAN_USE_HEAP;
+    bp[HDR_HEAP_END_PTR..HDR_HEAP_END_PTR + 2].copy_from_slice(&(0xFE00u16 - 0x200).to_le_bytes());
+    bp[HDR_CODE32_START..HDR_CODE32_START + 4].copy_from_slice(&(kernel_addr as u32).to_le_bytes());
+
+    // command line (NUL terminated, clipped to cmdline_size)
+    let cmd_len = cmd_len.min(hdr.cmdline_size as usize);
+    let cmd_addr = allocs.alloc_below(0xFFFF_FFFF, cmd_len + 1)?;
+    // safe: cmd_addr spans at least cmd_len + 1 bytes
+    unsafe {
+        core::ptr::copy_nonoverlapping(cmdline.as_ptr(), cmd_addr as *mut u8, cmd_len);
+        *((cmd_addr as usize + cmd_len) as *mut u8) = 0;
+    }
+    bp[HDR_CMD_LINE_PTR..HDR_CMD_LINE_PTR + 4].copy_from_slice(&(cmd_addr as u32).to_le_bytes());
+    bp[BP_EXT_CMD_LINE_PTR..BP_EXT_CMD_LINE_PTR + 4].copy_from_slice(&((cmd_addr >> 32) as u32).to_le_bytes());
+
+    // initrd, relocated below initrd_addr_max
+    if let Some(rd) = initrd {
+        let rd_addr = allocs.alloc_below(hdr.initrd_addr_max as u64, rd.len)?;
+        // safe: rd_addr spans rd.len bytes; source is our file buffer
+        unsafe { core::ptr::copy_nonoverlapping(rd.addr as *const u8, rd_addr as *mut u8, rd.len) };
+        bp[HDR_RAMDISK_IMAGE..HDR_RAMDISK_IMAGE + 4].copy_from_slice(&(rd_addr as u32).to_le_bytes());
+        bp[HDR_RAMDISK_SIZE..HDR_RAMDISK_SIZE + 4].copy_from_slice(&(rd.len as u32).to_le_bytes());
+        bp[BP_EXT_RAMDISK_IMAGE..BP_EXT_RAMDISK_IMAGE + 4].copy_from_slice(&((rd_addr >> 32) as u32).to_le_bytes());
+        bp[BP_EXT_RAMDISK_SIZE..BP_EXT_RAMDISK_SIZE + 4].copy_from_slice(&((rd.len as u64 >> 32) as u32).to_le_bytes());
+        allocs.free_file(1);
+        log_info("linux", &format!("initrd at 0x{:x} ({} bytes)", rd_addr, rd.len));
+    }
+
+    // EFI handover: the kernel stub owns ExitBootServices and the memory map
+    if hdr.xloadflags & XLF_EFI_HANDOVER_64 != 0 && hdr.handover_offset != 0 {
+        let entry_addr = kernel_addr as usize + 0x200 + hdr.handover_offset as usize;
+        allocs.keep_pages();
+        log_info("linux", &format!("EFI handover entry 0x{:x}", entry_addr));
+        // safe: entry lies inside the verified kernel we just placed
+        let handover: HandoverEntry = unsafe { core::mem::transmute(entry_addr) };
+        handover(image_handle.as_ptr(), st.as_ptr() as *mut _, bp_addr as *mut u8);
+    }
+
+    // 64-bit entry: we own ExitBootServices and provide e820 + EFI info
+    allocs.keep_pages();
+    let entry64 = kernel_addr + 0x200;
+    log_info("linux", &format!("64-bit entry 0x{:x}", entry64));
+
+    // safe: boot services are not touched through `st` after this point
+    let (rt, mut mmap) = unsafe { st.unsafe_clone() }.exit_boot_services();
+    mmap.sort();
+
+    let mut e820 = [(0u64, 0u64, 0u32); E820_MAX_ENTRIES];
+    let mut count = 0usize;
+    for d in mmap.entries() {
+        if !push_e820(&mut e820, &mut count, d.phys_start, d.page_count * PAGE_SIZE as u64, e820_type(d.ty)) {
+            break;
+        }
+    }
+    for (i, (addr, size, ty)) in e820[..count].iter().enumerate() {
+        let off = BP_E820_TABLE + i * 20;
+        bp[off..off + 8].copy_from_slice(&addr.to_le_bytes());
+        bp[off + 8..off + 16].copy_from_slice(&size.to_le_bytes());
+        bp[off + 16..off + 20].copy_from_slice(&ty.to_le_bytes());
+    }
+    bp[BP_E820_ENTRIES] = count as u8;
+
+    // efi_info: "EL64", system table, memory map (as left by firmware)
+    let systab = rt.as_ptr() as u64;
+    let (map_ptr, map_size, desc_size) = mmap_raw_parts(&mmap);
+    let efi = BP_EFI_INFO;
+    bp[efi..efi + 4].copy_from_slice(b"EL64");
+    bp[efi + 4..efi + 8].copy_from_slice(&(systab as u32).to_le_bytes());
+    bp[efi + 8..efi + 12].copy_from_slice(&(desc_size as u32).to_le_bytes());
+    bp[efi + 12..efi + 16].copy_from_slice(&1u32.to_le_bytes());
+    bp[efi + 16..efi + 20].copy_from_slice(&(map_ptr as u32).to_le_bytes());
+    bp[efi + 20..efi + 24].copy_from_slice(&(map_size as u32).to_le_bytes());
+    bp[efi + 24..efi + 28].copy_from_slice(&((systab >> 32) as u32).to_le_bytes());
+    bp[efi + 28..efi + 32].copy_from_slice(&((map_ptr >> 32) as u32).to_le_bytes());
+
+    let gdt = GdtPtr { limit: (core::mem::size_of::<BootGdt>() - 1) as u16, base: &LINUX_GDT as *const _ as u64 };
+    // safe: identity-mapped, interrupts off, selectors match the GDT above;
+    // RSI = boot_params as required by the 64-bit boot protocol
+    unsafe {
+        core::arch::asm!(
+            "cli",
+            "lgdt [{gdt}]",
+            "mov ax, 0x18",
+            "mov ds, ax",
+            "mov es, ax",
+            "mov ss, ax",
+            "mov fs, ax",
+            "mov gs, ax",
+            "push 0x10",
+            "lea rax, [rip + 2f]",
+            "push rax",
+            "retfq",
+            "2:",
+            "xor rbp, rbp",
+            "jmp {entry}",
+            gdt = in(reg) &gdt,
+            entry = in(reg) entry64,
+            in("rsi") bp_addr,
+            out("rax") _,
+            options(noreturn)
+        );
+    }
+}
+
+/// Raw (pointer, size, descriptor size) of the final firmware memory map.
+/// The descriptor stride is taken from the buffer itself since firmware may
+/// use a larger descriptor than `MemoryDescriptor`.
+fn mmap_raw_parts(mmap: &uefi::table::boot::MemoryMap<'static>) -> (u64, usize, usize) {
+    let mut it = mmap.entries();
+    let first = it.next().map(|d| d as *const _ as u64).unwrap_or(0);
+    let stride = it
+        .next()
+        .map(|d| (d as *const _ as u64 - first) as usize)
+        .unwrap_or(core::mem::size_of::<MemoryDescriptor>());
+    (first, mmap.entries().count() * stride, stride)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn bzimage() -> alloc::vec::Vec<u8> {
+        let mut img = vec![0u8; 0x2000];
+        img[HDR_SETUP_SECTS] = 3;
+        img[HDR_JUMP_LEN] = 0x66;
+        img[HDR_MAGIC..HDR_MAGIC + 4].copy_from_slice(HDRS_MAGIC);
+        img[HDR_VERSION..HDR_VERSION + 2].copy_from_slice(&0x020Fu16.to_le_bytes());
+        img[HDR_RELOCATABLE] = 1;
+        img[HDR_XLOADFLAGS..HDR_XLOADFLAGS + 2].copy_from_slice(&(XLF_KERNEL_64 | XLF_EFI_HANDOVER_64).to_le_bytes());
+        img[HDR_PREF_ADDRESS..HDR_PREF_ADDRESS + 8].copy_from_slice(&0x100_0000u64.to_le_bytes());
+        img
+    }
+
+    #[test]
+    fn parses_setup_header() {
+        let hdr = parse_setup_header(&bzimage()).unwrap();
+        assert_eq!(hdr.kernel_offset(), 4 * 512);
+        assert_eq!(hdr.header_end, 0x268);
+        assert_eq!(hdr.pref_address, 0x100_0000);
+        assert!(hdr.relocatable);
+    }
+
+    #[test]
+    fn rejects_old_or_32bit_kernels() {
+        let mut img = bzimage();
+        img[HDR_VERSION..HDR_VERSION + 2].copy_from_slice(&0x020Au16.to_le_bytes());
+        assert!(parse_setup_header(&img).is_err());
+
+        let mut img = bzimage();
+        img[HDR_XLOADFLAGS..HDR_XLOADFLAGS + 2].copy_from_slice(&0u16.to_le_bytes());
+        assert!(parse_setup_header(&img).is_err());
+    }
+
+    #[test]
+    fn e820_merges_contiguous_ranges() {
+        let mut t = [(0u64, 0u64, 0u32); 4];
+        let mut n = 0;
+        assert!(push_e820(&mut t, &mut n, 0, 0x1000, E820_RAM));
+        assert!(push_e820(&mut t, &mut n, 0x1000, 0x1000, E820_RAM));
+        assert!(push_e820(&mut t, &mut n, 0x2000, 0x1000, E820_ACPI));
+        assert_eq!(n, 2);
+        assert_eq!(t[0], (0, 0x2000, E820_RAM));
+        assert_eq!(e820_type(MemoryType::BOOT_SERVICES_DATA), E820_RAM);
+        assert_eq!(e820_type(MemoryType::RUNTIME_SERVICES_CODE), E820_RESERVED);
+    }
+
+    #[test]
+    fn initrd_token_is_stripped() {
+        let mut rest = [0u8; 256];
+        let (rd, n) = split_initrd("console=ttyS0 initrd=/EFI/nonos/rd.img quiet", &mut rest);
+        assert_eq!(rd, Some("/EFI/nonos/rd.img"));
+        assert_eq!(&rest[..n], b"console=ttyS0 quiet");
+    }
+}
@@ -0,0 +1,123 @@
+//! ESP file reader for the loader.
+//!
+//! Reads whole files from the volume the bootloader itself was loaded from
+//! into LOADER_DATA pages, or from a GPT partition named by a `PARTUUID=`
+//! or `PARTLABEL=` prefix (see `storage.rs`). No heap: the caller owns the
+//! returned pages and must `free` them on error paths.
+
+use crate::log::logger::{log_error, log_info};
+use crate::loader::{LoaderError, LoaderResult};
+use crate::storage;
+use alloc::format;
+use uefi::prelude::*;
+use uefi::proto::media::file::{File, FileAttribute, FileInfo, FileMode};
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType, PhysicalAddress};
+use uefi::CStr16;
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Upper bound for any single file we read from the ESP (128 MiB).
+pub const MAX_FILE_SIZE: usize = 128 * 1024 * 1024;
+
+/// A file image held in firmware-allocated pages.
+#[derive(Debug, Clone, Copy)]
+pub struct FileBuffer {
+    pub addr: PhysicalAddress,
+    pub len: usize,
+    pub pages: usize,
+}
+
+impl FileBuffer {
+    pub fn as_slice(&self) -> &[u8] {
+        // safe: addr..addr+len was allocated by us and filled by read()
+        unsafe { core::slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+
+    pub fn free(self, bs: &BootServices) {
+        let _ = bs.free_pages(self.addr, self.pages);
+    }
+}
+
+/// Read `path` (either `/` or `\` separated) from the boot volume or the
+/// partition its prefix names.
+pub fn read_file(bs: &BootServices, image_handle: Handle, path: &str) -> LoaderResult<FileBuffer> {
+    let (partition, path) = storage::split_partition_path(path).map_err(|e| {
+        log_error("loader", &format!("{}: {}", path, e));
+        LoaderError::UefiError { desc: "bad partition reference", status: Status::INVALID_PARAMETER }
+    })?;
+    let mut path_buf = [0u16; 256];
+    let mut fixed = [0u8; 255];
+    let n = path.len().min(fixed.len());
+    for (d, s) in fixed.iter_mut().zip(path.as_bytes()[..n].iter()) {
+        *d = if *s == b'/' { b'\\' } else { *s };
+    }
+    let fixed = core::str::from_utf8(&fixed[..n]).map_err(|_| LoaderError::UefiError {
+        desc: "path not UTF-8",
+        status: Status::INVALID_PARAMETER,
+    })?;
+    let cpath = CStr16::from_str_with_buf(fixed, &mut path_buf).map_err(|_| LoaderError::UefiError {
+        desc: "path not representable in UCS-2",
+        status: Status::INVALID_PARAMETER,
+    })?;
+
+    let mut fs = match partition {
+        None => bs.get_image_file_system(image_handle),
+        Some(r) => {
+            let handle = storage::resolve_volume(bs, &r).map_err(|e| {
+                log_error("loader", &format!("partition for {}: {}", path, e));
+                LoaderError::UefiError { desc: "partition not accessible", status: Status::NOT_FOUND }
+            })?;
+            bs.open_protocol_exclusive::<SimpleFileSystem>(handle)
+        }
+    }
+    .map_err(|e| LoaderError::UefiError { desc: "boot volume not accessible", status: e.status() })?;
+    let mut root = fs.open_volume().map_err(|e| LoaderError::UefiError {
+        desc: "open_volume failed",
+        status: e.status(),
+    })?;
+    let mut file = root
+        .open(cpath, FileMode::Read, FileAttribute::empty())
+        .map_err(|e| {
+            log_error("loader", &format!("open {} failed: {:?}", path, e.status()));
+            LoaderError::UefiError { desc: "file not found", status: e.status() }
+        })?
+        .into_regular_file()
+        .ok_or(LoaderError::UefiError { desc: "not a regular file", status: Status::INVALID_PARAMETER })?;
+
+    let mut info_buf = [0u8; 512];
+    let size = file
+        .get_info::<FileInfo>(&mut info_buf)
+        .map_err(|e| LoaderError::UefiError { desc: "get_info failed", status: e.status() })?
+        .file_size() as usize;
+    if size == 0 || size > MAX_FILE_SIZE {
+        return Err(LoaderError::FileTooLarge { size, max: MAX_FILE_SIZE });
+    }
+
+    let pages = (size + PAGE_SIZE - 1) / PAGE_SIZE;
+    let addr = bs
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages)
+        .map_err(|e| LoaderError::AllocationFailed { addr: 0, pages, status: e.status() })?;
+    let buf = FileBuffer { addr, len: size, pages };
+
+    // safe: freshly allocated pages cover `size` bytes
+    let dst = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+    let mut done = 0usize;
+    while done < size {
+        match file.read(&mut dst[done..]) {
+            Ok(0) => break,
+            Ok(n) => done += n,
+            Err(e) => {
+                buf.free(bs);
+                return Err(LoaderError::UefiError { desc: "file read failed", status: e.status() });
+            }
+        }
+    }
+    if done != size {
+        buf.free(bs);
+        return Err(LoaderError::UefiError { desc: "short read", status: Status::END_OF_FILE });
+    }
+
+    log_info("loader", &format!("Read {} ({} bytes) into 0x{:x}", path, size, addr));
+    Ok(buf)
+}
@@ -4,12 +4,17 @@
 #![no_std]
 
 // Logger functions.
+use crate::handoff::handoff::HANDOFF_VERSION;
+use crate::hardware::{detect_cpu_features, query_framebuffer};
+use crate::capsule::manifest;
+use crate::loader::notes::{cpu_feature, fb_request, parse_notes, KernelRequirements};
 use crate::log::logger::{log_error, log_info};
 use crate::verify::{load_validated_capsule, CapsuleMetadata};
 use core::fmt;
-use goblin::elf::{header, program_header, Elf};
+use goblin::elf::{header, program_header, section_header, Elf};
+use sha2::{Digest, Sha256};
 use uefi::prelude::*;
-use uefi::table::boot::{AllocateType, MemoryType, PhysicalAddress};
+use uefi::table::boot::{AllocateType, MemoryType};
 
 const PAGE_SIZE: usize = 0x1000;
 const MAX_LOADS: usize = 32;
@@ -27,6 +32,13 @@ pub enum LoaderError {
     NoLoadableSegments,
     EntryNotInRange,
     AllocationTableFull,
+    FileTooLarge { size: usize, max: usize },
+    NoteMalformed(&'static str),
+    AbiMismatch { wanted: u32, supported: u32 },
+    CpuFeaturesMissing(u64),
+    ManifestMalformed(&'static str),
+    FramebufferUnavailable,
+    PlacementViolation { base: u64, size: u64 },
 }
 
 impl fmt::Display for LoaderError {
@@ -43,6 +55,22 @@ impl fmt::Display for LoaderError {
             LoaderError::NoLoadableSegments => write!(f, "no PT_LOAD segments found"),
             LoaderError::EntryNotInRange => write!(f, "ELF entry not inside loaded image range"),
             LoaderError::AllocationTableFull => write!(f, "allocation bookkeeping table full"),
+            LoaderError::FileTooLarge { size, max } =>
+                write!(f, "file size {} outside accepted range (max {})", size, max),
+            LoaderError::NoteMalformed(s) => write!(f, "malformed NONOS note: {}", s),
+            LoaderError::AbiMismatch { wanted, supported } =>
+                write!(f, "kernel wants handoff ABI v{}, loader provides v{}", wanted, supported),
+            LoaderError::CpuFeaturesMissing(bits) => {
+                write!(f, "CPU lacks features required by kernel:")?;
+                for name in cpu_feature::names(*bits) {
+                    write!(f, " {}", name)?;
+                }
+                write!(f, " (mask 0x{:x})", bits)
+            }
+            LoaderError::ManifestMalformed(s) => write!(f, "malformed capsule manifest: {}", s),
+            LoaderError::FramebufferUnavailable => write!(f, "kernel requires a framebuffer but none is available"),
+            LoaderError::PlacementViolation { base, size } =>
+                write!(f, "image 0x{:x}+0x{:x} violates kernel placement note", base, size),
         }
     }
 }
@@ -56,6 +84,31 @@ pub struct KernelImage {
     pub metadata: CapsuleMetadata,
     pub allocations: [(u64, usize); MAX_ALLOCS],
     pub alloc_count: usize,
+    /// Boot requirements from the kernel's NONOS notes
+    pub requirements: KernelRequirements,
+    /// `.symtab`/`.strtab` copy for early symbolization (manifest `symbols on`)
+    pub symbols: Option<SymbolRegion>,
+    /// SHA-256 over the ELF payload followed by the symbol region, if any
+    pub image_sha256: [u8; 32],
+}
+
+/// LOADER_DATA copy of the ELF symbol table: `.symtab` at `base`,
+/// its string table at `base + strtab_offset`.
+#[derive(Debug, Clone, Copy)]
+pub struct SymbolRegion {
+    pub base: u64,
+    pub pages: usize,
+    pub symtab_size: u64,
+    pub symtab_entsize: u64,
+    pub strtab_offset: u64,
+    pub strtab_size: u64,
+}
+
+impl SymbolRegion {
+    pub fn as_slice(&self) -> &[u8] {
+        // safe: region was allocated and filled by export_symbols
+        unsafe { core::slice::from_raw_parts(self.base as *const u8, (self.strtab_offset + self.strtab_size) as usize) }
+    }
 }
 
 pub type LoaderResult<T> = core::result::Result<T, LoaderError>;
@@ -70,42 +123,173 @@ fn record_alloc(table: &mut [(u64, usize); MAX_ALLOCS], count: &mut usize, addr:
     Ok(())
 }
 
-// Now accepts system table boot for logging so we can emit audit logs.
-fn free_all(st: &mut SystemTable<Boot>, bs: &uefi::table::boot::BootServices, table: &[(u64, usize); MAX_ALLOCS], count: usize) {
+// Free every recorded allocation, logging each one for the audit trail.
+fn free_all(bs: &uefi::table::boot::BootServices, table: &[(u64, usize); MAX_ALLOCS], count: usize) {
     for i in 0..count {
         let (addr, pages) = table[i];
         if addr == 0 || pages == 0 { continue; }
         match bs.free_pages(addr, pages) {
-            Ok(_) => log_info(st, "loader", &format!("Freed pages at 0x{:x} ({} pages)", addr, pages)),
-            Err(e) => log_error(st, "loader", &format!("free_pages failed for 0x{:x} ({}): {:?}", addr, pages, e.status())),
+            Ok(_) => log_info("loader", &format!("Freed pages at 0x{:x} ({} pages)", addr, pages)),
+            Err(e) => log_error("loader", &format!("free_pages failed for 0x{:x} ({}): {:?}", addr, pages, e.status())),
         }
     }
 }
 
+/// Refuse kernels whose NONOS notes ask for something this loader or CPU cannot give.
+fn check_requirements(system_table: &mut SystemTable<Boot>, req: &KernelRequirements) -> LoaderResult<()> {
+    if let Some(wanted) = req.abi_version {
+        if wanted != HANDOFF_VERSION as u32 {
+            log_error("loader", &format!("Kernel expects handoff ABI v{}, we provide v{}", wanted, HANDOFF_VERSION));
+            return Err(LoaderError::AbiMismatch { wanted, supported: HANDOFF_VERSION as u32 });
+        }
+    }
+
+    // unknown bits always count as missing
+    let missing = req.cpu_features & !(detect_cpu_features().bits & cpu_feature::KNOWN);
+    if missing != 0 {
+        let err = LoaderError::CpuFeaturesMissing(missing);
+        log_error("loader", &format!("{}", err));
+        return Err(err);
+    }
+
+    if req.framebuffer == fb_request::REQUIRED && query_framebuffer(system_table).is_none() {
+        log_error("loader", "Kernel requires a framebuffer; GOP unavailable.");
+        return Err(LoaderError::FramebufferUnavailable);
+    }
+
+    log_info("loader", &format!(
+        "Kernel notes: abi={:?} stack=0x{:x} cpu=0x{:x} fb={} placement={}",
+        req.abi_version, req.stack_size, req.cpu_features, req.framebuffer, req.placement.is_some()
+    ));
+    Ok(())
+}
+
+/// Relocate the NT_NONOS_SYSCALL entry by `delta`, like `e_entry`, and keep it inside the image.
+fn relocate_syscall(req: &mut KernelRequirements, delta: u64, base: u64, size: u64) -> LoaderResult<()> {
+    let Some(sc) = req.syscall.as_mut() else { return Ok(()) };
+    sc.entry = sc.entry.wrapping_add(delta);
+    if sc.entry < base || sc.entry - base >= size {
+        log_error("loader", "Syscall entry not contained within loaded segments.");
+        return Err(LoaderError::NoteMalformed("syscall entry outside image"));
+    }
+    Ok(())
+}
+
+/// The capsule's `.nonos.manifest` section, if present and inside the payload.
+fn manifest_section<'a>(elf: &Elf, payload: &'a [u8]) -> Option<&'a [u8]> {
+    let sh = elf.section_headers.iter().find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(".nonos.manifest"))?;
+    let (off, len) = (sh.sh_offset as usize, sh.sh_size as usize);
+    off.checked_add(len).and_then(|end| payload.get(off..end))
+}
+
+/// True when the capsule's `.nonos.manifest` carries `symbols on`.
+fn manifest_wants_symbols(elf: &Elf, payload: &[u8]) -> bool {
+    manifest_section(elf, payload)
+        .is_some_and(|data| manifest::entries(data).any(|(k, v)| k == "symbols" && (v == "on" || v == "1")))
+}
+
+/// Copy `.symtab` and its linked string table into one LOADER_DATA region.
+fn export_symbols(
+    bs: &uefi::table::boot::BootServices,
+    elf: &Elf,
+    payload: &[u8],
+    allocations: &mut [(u64, usize); MAX_ALLOCS],
+    alloc_count: &mut usize,
+) -> LoaderResult<Option<SymbolRegion>> {
+    let Some(symtab) = elf.section_headers.iter().find(|sh| sh.sh_type == section_header::SHT_SYMTAB) else {
+        log_info("loader", "Manifest requests symbols but payload has no .symtab.");
+        return Ok(None);
+    };
+    let strtab = elf.section_headers.get(symtab.sh_link as usize).ok_or(LoaderError::ElfParseError("symtab sh_link"))?;
+    let section = |off: u64, size: u64| {
+        (off as usize).checked_add(size as usize).and_then(|end| payload.get(off as usize..end)).ok_or(LoaderError::SegmentOutOfBounds)
+    };
+    let sym = section(symtab.sh_offset, symtab.sh_size)?;
+    let strs = section(strtab.sh_offset, strtab.sh_size)?;
+
+    let strtab_offset = (sym.len() + 7) & !7;
+    let total = strtab_offset + strs.len();
+    let pages = (total + PAGE_SIZE - 1) / PAGE_SIZE;
+    let base = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages).map_err(|e| {
+        log_error("loader", &format!("Symbol region allocation failed: {:?}", e.status()));
+        LoaderError::AllocationFailed { addr: 0, pages, status: e.status() }
+    })?;
+    if let Err(e) = record_alloc(allocations, alloc_count, base, pages) {
+        // not in the table, so the caller's cleanup would miss it
+        let _ = bs.free_pages(base, pages);
+        return Err(e);
+    }
+
+    // safe: `pages` fresh pages cover `total` bytes; sources are bounds-checked payload slices
+    unsafe {
+        let dst = base as *mut u8;
+        core::ptr::write_bytes(dst, 0, pages * PAGE_SIZE);
+        core::ptr::copy_nonoverlapping(sym.as_ptr(), dst, sym.len());
+        core::ptr::copy_nonoverlapping(strs.as_ptr(), dst.add(strtab_offset), strs.len());
+    }
+    log_info("loader", &format!("Exported {} symbols ({} bytes) at 0x{:x}", sym.len() / symtab.sh_entsize.max(1) as usize, total, base));
+
+    Ok(Some(SymbolRegion {
+        base,
+        pages,
+        symtab_size: sym.len() as u64,
+        symtab_entsize: symtab.sh_entsize,
+        strtab_offset: strtab_offset as u64,
+        strtab_size: strs.len() as u64,
+    }))
+}
+
+/// Export symbols if the manifest asks for it and hash payload + symbol region.
+/// Runs after the kernel's own pages are claimed so AnyPages cannot take them.
+fn symbols_and_hash(
+    bs: &uefi::table::boot::BootServices,
+    elf: &Elf,
+    payload: &[u8],
+    allocations: &mut [(u64, usize); MAX_ALLOCS],
+    alloc_count: &mut usize,
+) -> LoaderResult<(Option<SymbolRegion>, [u8; 32])> {
+    let symbols = if manifest_wants_symbols(elf, payload) {
+        export_symbols(bs, elf, payload, allocations, alloc_count)?
+    } else {
+        None
+    };
+    let mut hasher = Sha256::new();
+    hasher.update(payload);
+    if let Some(region) = &symbols {
+        hasher.update(region.as_slice());
+    }
+    Ok((symbols, hasher.finalize().into()))
+}
+
 // load_kernel: main loader entry point.
 pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -> LoaderResult<KernelImage> {
     // 1. Log start.
-    log_info(system_table, "loader", "Starting kernel load operation.");
+    log_info("loader", "Starting kernel load operation.");
 
     // 2. Validate the capsule with payload slice.
     let payload = load_validated_capsule(capsule_bytes).ok_or_else(|| {
-        log_error(system_table, "loader", "Capsule validation failed.");
+        log_error("loader", "Capsule validation failed.");
         LoaderError::CapsuleInvalid
     })?;
 
+    load_kernel_elf(system_table, &payload)
+}
+
+/// Load an already verified ELF kernel (e.g. from `capsule::Capsule`) into memory.
+pub fn load_kernel_elf(system_table: &mut SystemTable<Boot>, payload: &[u8]) -> LoaderResult<KernelImage> {
     // 3. Parse the ELF using goblin.
-    let elf = Elf::parse(&payload).map_err(|e| {
-        log_error(system_table, "loader", &format!("ELF parse failed: {:?}", e));
+    let elf = Elf::parse(payload).map_err(|e| {
+        log_error("loader", &format!("ELF parse failed: {:?}", e));
         LoaderError::ElfParseError("goblin parse error")
     })?;
 
     // 4. Header check sanity.
     if !elf.is_64 {
-        log_error(system_table, "loader", "ELF is not 64-bit.");
+        log_error("loader", "ELF is not 64-bit.");
         return Err(LoaderError::UnsupportedElf("not 64-bit"));
     }
     if elf.header.e_machine != header::EM_X86_64 {
-        log_error(system_table, "loader", "ELF machine is not x86_64.");
+        log_error("loader", "ELF machine is not x86_64.");
         return Err(LoaderError::UnsupportedElf("non-x86_64"));
     }
 
@@ -114,11 +298,28 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
     let is_exec = elf.header.e_type == header::ET_EXEC;
     let is_dyn = elf.header.e_type == header::ET_DYN;
     if !is_exec && !is_dyn {
-        log_error(system_table, "loader", "Unsupported ELF type.");
+        log_error("loader", "Unsupported ELF type.");
         return Err(LoaderError::UnsupportedElf("unsupported e_type"));
     }
 
-    // 6. BootService.
+    // 6. NONOS notes: refuse kernels we cannot satisfy before allocating anything.
+    let mut requirements = KernelRequirements::default();
+    for ph in &elf.program_headers {
+        if ph.p_type != program_header::PT_NOTE { continue; }
+        let (off, len) = (ph.p_offset as usize, ph.p_filesz as usize);
+        let data = off.checked_add(len).and_then(|end| payload.get(off..end)).ok_or(LoaderError::SegmentOutOfBounds)?;
+        parse_notes(data, &mut requirements)?;
+    }
+    // the signed manifest can pin CPU features on top of what the notes ask for
+    if let Some(data) = manifest_section(&elf, payload) {
+        requirements.cpu_features |= manifest::required_cpu_features(data).map_err(|name| {
+            log_error("loader", &format!("Manifest requires unknown CPU feature '{}'", name));
+            LoaderError::ManifestMalformed("unknown require-cpu feature")
+        })?;
+    }
+    check_requirements(system_table, &requirements)?;
+
+    // BootService.
     let bs = system_table.boot_services();
 
     // 7. Fixed size load table.
@@ -133,7 +334,7 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
     for ph in &elf.program_headers {
         if ph.p_type != program_header::PT_LOAD { continue; }
         if load_count >= MAX_LOADS {
-            log_error(system_table, "loader", "too many PT_LOADs for fixed table");
+            log_error("loader", "too many PT_LOADs for fixed table");
             return Err(LoaderError::AllocationTableFull);
         }
 
@@ -144,14 +345,14 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
 
         // Bounds checks.
         if p_offset.checked_add(p_filesz).map_or(true, |end| end > payload.len()) {
-            log_error(system_table, "loader", "ELF program header indicates file data outside payload bounds.");
+            log_error("loader", "ELF program header indicates file data outside payload bounds.");
             return Err(LoaderError::SegmentOutOfBounds);
         }
 
         // Physical address target: prefer p_paddr else p_vaddr
         let target = if ph.p_paddr != 0 { ph.p_paddr } else { ph.p_vaddr } as u64;
         if target == 0 {
-            log_error(system_table, "loader", "PT_LOAD has no placement address.");
+            log_error("loader", "PT_LOAD has no placement address.");
             return Err(LoaderError::UnsupportedElf("no placement address"));
         }
 
@@ -170,7 +371,7 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
     }
 
     if load_count == 0 {
-        log_error(system_table, "loader", "No PT_LOAD segments found in ELF payload.");
+        log_error("loader", "No PT_LOAD segments found in ELF payload.");
         return Err(LoaderError::NoLoadableSegments);
     }
 
@@ -185,38 +386,55 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
 
     //------------------ET_EXEC: allocate at linked base------------------
     if is_exec {
-        let mut alloc_addr: PhysicalAddress = base;
-        match bs.allocate_pages(AllocateType::Address, MemoryType::LOADER_DATA, pages_needed, &mut alloc_addr) {
-            Ok(_) => {
+        if let Some(p) = requirements.placement {
+            if !p.allows(base, total_bytes as u64) {
+                log_error("loader", "Linked address violates kernel placement note.");
+                return Err(LoaderError::PlacementViolation { base, size: total_bytes as u64 });
+            }
+        }
+        match bs.allocate_pages(AllocateType::Address(base), MemoryType::LOADER_DATA, pages_needed) {
+            Ok(alloc_addr) => {
                 record_alloc(&mut allocations, &mut alloc_count, alloc_addr, pages_needed)?;
-                log_info(system_table, "loader", &format!("Allocated {} pages at 0x{:x} for kernel (ET_EXEC)", pages_needed, alloc_addr));
+                log_info("loader", &format!("Allocated {} pages at 0x{:x} for kernel (ET_EXEC)", pages_needed, alloc_addr));
             }
             Err(e) => {
-                log_error(system_table, "loader", &format!("Failed to allocate {} pages at 0x{:x}: {:?}", pages_needed, base, e.status()));
+                log_error("loader", &format!("Failed to allocate {} pages at 0x{:x}: {:?}", pages_needed, base, e.status()));
                 return Err(LoaderError::AllocationFailed { addr: base, pages: pages_needed, status: e.status() });
             }
         }
 
+        let (symbols, image_sha256) = match symbols_and_hash(bs, &elf, payload, &mut allocations, &mut alloc_count) {
+            Ok(r) => r,
+            Err(e) => {
+                free_all(bs, &allocations, alloc_count);
+                return Err(e);
+            }
+        };
+
         for i in 0..load_count {
             let (p_offset, p_filesz, p_memsz, target, _align, _flags) = loads[i];
             let dst_phys = target as usize;
             if p_filesz > 0 {
                 unsafe { core::ptr::copy_nonoverlapping(payload.as_ptr().add(p_offset), dst_phys as *mut u8, p_filesz); }
-                log_info(system_table, "loader", &format!("Copied {} bytes to 0x{:x}", p_filesz, dst_phys));
+                log_info("loader", &format!("Copied {} bytes to 0x{:x}", p_filesz, dst_phys));
             }
 
             if p_memsz > p_filesz {
                 unsafe { core::ptr::write_bytes((dst_phys + p_filesz) as *mut u8, 0, p_memsz - p_filesz); }
-                log_info(system_table, "loader", &format!("Zeroed {} bytes at 0x{:x}", p_memsz - p_filesz, dst_phys + p_filesz));
+                log_info("loader", &format!("Zeroed {} bytes at 0x{:x}", p_memsz - p_filesz, dst_phys + p_filesz));
             }
         }
 
         let entry = elf.header.e_entry as usize;
         if !(entry >= base as usize && entry < base as usize + total_bytes) {
-            free_all(system_table, bs, &allocations, alloc_count);
-            log_error(system_table, "loader", "ELF entry not contained within loaded segments.");
+            free_all(bs, &allocations, alloc_count);
+            log_error("loader", "ELF entry not contained within loaded segments.");
             return Err(LoaderError::EntryNotInRange);
         }
+        if let Err(e) = relocate_syscall(&mut requirements, 0, base, total_bytes as u64) {
+            free_all(bs, &allocations, alloc_count);
+            return Err(e);
+        }
 
         // Build KernelImage
         let k = KernelImage {
@@ -226,26 +444,57 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
             metadata: CapsuleMetadata { offset_sig: 0, len_sig: 0, offset_payload: 0, len_payload: payload.len() },
             allocations,
             alloc_count,
+            requirements,
+            symbols,
+            image_sha256,
         };
-        log_info(system_table, "loader", &format!("Kernel loaded: base=0x{:x} size=0x{:x} entry=0x{:x}", k.address, k.size, k.entry_point));
+        log_info("loader", &format!("Kernel loaded: base=0x{:x} size=0x{:x} entry=0x{:x}", k.address, k.size, k.entry_point));
         return Ok(k);
     }
 
     // -------------------ET_DYN path: allocate AnyPages, relocate segments---------------------
     {
-        let mut alloc_addr: PhysicalAddress = 0;
-        match bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages_needed, &mut alloc_addr) {
-            Ok(_) => {
-                record_alloc(&mut allocations, &mut alloc_count, alloc_addr, pages_needed)?;
-                log_info(system_table, "loader", &format!("Allocated {} pages at 0x{:x} for ET_DYN image", pages_needed, alloc_addr));
+        // Placement note: cap the top via MaxAddress and over-allocate to reach the alignment.
+        let (alloc_type, slack_pages) = match requirements.placement {
+            Some(p) => (
+                AllocateType::MaxAddress(p.max_phys - 1),
+                (p.align as usize).saturating_sub(PAGE_SIZE) / PAGE_SIZE,
+            ),
+            None => (AllocateType::AnyPages, 0),
+        };
+        let alloc_pages = pages_needed + slack_pages;
+        let alloc_addr = match bs.allocate_pages(alloc_type, MemoryType::LOADER_DATA, alloc_pages) {
+            Ok(addr) => {
+                record_alloc(&mut allocations, &mut alloc_count, addr, alloc_pages)?;
+                log_info("loader", &format!("Allocated {} pages at 0x{:x} for ET_DYN image", alloc_pages, addr));
+                addr
             }
             Err(e) => {
-                log_error(system_table, "loader", &format!("ET_DYN allocation failed: {:?}", e.status()));
-                return Err(LoaderError::AllocationFailed { addr: 0, pages: pages_needed, status: e.status() });
+                log_error("loader", &format!("ET_DYN allocation failed: {:?}", e.status()));
+                return Err(LoaderError::AllocationFailed { addr: 0, pages: alloc_pages, status: e.status() });
             }
-        }
+        };
 
-        let base_phys = alloc_addr as u64;
+        let (symbols, image_sha256) = match symbols_and_hash(bs, &elf, payload, &mut allocations, &mut alloc_count) {
+            Ok(r) => r,
+            Err(e) => {
+                free_all(bs, &allocations, alloc_count);
+                return Err(e);
+            }
+        };
+
+        let base_phys = match requirements.placement {
+            Some(p) => {
+                let aligned = (alloc_addr as u64 + p.align - 1) & !(p.align - 1);
+                if !p.allows(aligned, (pages_needed * PAGE_SIZE) as u64) {
+                    free_all(bs, &allocations, alloc_count);
+                    log_error("loader", "No memory satisfies the kernel placement note.");
+                    return Err(LoaderError::PlacementViolation { base: aligned, size: (pages_needed * PAGE_SIZE) as u64 });
+                }
+                aligned
+            }
+            None => alloc_addr as u64,
+        };
 
         for i in 0..load_count {
             let (p_offset, p_filesz, p_memsz, target, _align, _flags) = loads[i];
@@ -262,6 +511,10 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
         // End, Compute relocated entry.
         let entry_rel = elf.header.e_entry as u64;
         let entry_phys = (base_phys as usize).checked_add(entry_rel as usize).ok_or(LoaderError::UefiError { desc: "entry overflow", status: Status::OUT_OF_RESOURCES })?;
+        if let Err(e) = relocate_syscall(&mut requirements, base_phys, base_phys, (pages_needed * PAGE_SIZE) as u64) {
+            free_all(bs, &allocations, alloc_count);
+            return Err(e);
+        }
 
         let image = KernelImage {
             address: base_phys as usize,
@@ -270,8 +523,11 @@ pub fn load_kernel(system_table: &mut SystemTable<Boot>, capsule_bytes: &[u8]) -
             metadata: CapsuleMetadata { offset_sig: 0, len_sig: 0, offset_payload: 0, len_payload: payload.len() },
             allocations,
             alloc_count,
+            requirements,
+            symbols,
+            image_sha256,
         };
-        log_info(system_table, "loader", &format!("ET_DYN kernel loaded at 0x{:x} size=0x{:x} entry=0x{:x}", image.address, image.size, image.entry_point));
+        log_info("loader", &format!("ET_DYN kernel loaded at 0x{:x} size=0x{:x} entry=0x{:x}", image.address, image.size, image.entry_point));
         return Ok(image);
     }
 }
@@ -1,3 +1,10 @@
+pub mod file;
 pub mod loader;
+pub mod modules;
+pub mod notes;
+pub mod partition;
 
-pub use loader::{load_kernel, KernelImage, LoaderError, LoaderResult};
+pub use file::{read_file, FileBuffer};
+pub use loader::{load_kernel, load_kernel_elf, KernelImage, LoaderError, LoaderResult};
+pub use modules::{load_modules, ModuleError, ModuleList, ModuleSpec};
+pub use partition::{load_partition_capsule, read_partition_image};
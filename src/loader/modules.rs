@@ -0,0 +1,210 @@
+//! Boot modules (initrd, drivers, config and policy blobs) for the NONOS kernel.
+//!
+//! Modules come from two places:
+//! - the signed capsule manifest: `module <kind> <path> <sha256>`; the hash
+//!   is mandatory and pinned by the capsule signature,
+//! - the selected boot entry command line: `module=<kind>:<path>`.
+//!
+//! Every module must also carry its own keyring signature (embedded for PE,
+//! detached `<path>.sig` otherwise). Each file is loaded into page aligned
+//! LOADER_DATA pages, measured into PCR 9 and described by a `Module` entry
+//! in a table published through `BootHandoffV1.modules`.
+
+#![allow(dead_code)]
+
+use crate::capsule::manifest;
+use crate::chainload::{verify_image, ChainloadError};
+use crate::handoff::handoff::{Module, ModuleKind, Modules};
+use crate::loader::{read_file, FileBuffer, LoaderError};
+use crate::log::logger::{log_error, log_info, log_warn};
+use crate::security::extend_pcr_measurement_with_event;
+use alloc::format;
+use core::fmt;
+use core::mem::size_of;
+use heapless::Vec as FixedVec;
+use sha2::{Digest, Sha256};
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType};
+
+const PAGE_SIZE: usize = 0x1000;
+
+/// Upper bound on modules per boot (table fits in one page).
+pub const MAX_MODULES: usize = 16;
+pub const PCR_MODULES: u32 = 9;
+
+#[derive(Debug)]
+pub enum ModuleError {
+    TooMany,
+    BadSpec,
+    File(LoaderError),
+    Verify(ChainloadError),
+    HashMismatch,
+    Allocation(Status),
+}
+
+impl fmt::Display for ModuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModuleError::TooMany => write!(f, "more than {} modules", MAX_MODULES),
+            ModuleError::BadSpec => write!(f, "malformed module specification"),
+            ModuleError::File(e) => write!(f, "read failed: {}", e),
+            ModuleError::Verify(e) => write!(f, "signature check failed: {}", e),
+            ModuleError::HashMismatch => write!(f, "SHA-256 does not match manifest"),
+            ModuleError::Allocation(s) => write!(f, "module table allocation failed: {:?}", s),
+        }
+    }
+}
+
+/// One requested module
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleSpec<'a> {
+    pub kind: ModuleKind,
+    pub path: &'a str,
+    pub sha256: Option<[u8; 32]>,
+}
+
+pub type ModuleList<'a> = FixedVec<ModuleSpec<'a>, MAX_MODULES>;
+
+/// Append `module` lines from a signed capsule manifest.
+pub fn specs_from_manifest<'a>(data: &'a [u8], out: &mut ModuleList<'a>) -> Result<(), ModuleError> {
+    for (key, rest) in manifest::entries(data) {
+        if key != "module" {
+            continue;
+        }
+        let mut it = rest.split_ascii_whitespace();
+        let kind = it.next().and_then(ModuleKind::from_name).ok_or(ModuleError::BadSpec)?;
+        let path = it.next().ok_or(ModuleError::BadSpec)?;
+        let hash = it.next().and_then(manifest::parse_sha256).ok_or(ModuleError::BadSpec)?;
+        out.push(ModuleSpec { kind, path, sha256: Some(hash) }).map_err(|_| ModuleError::TooMany)?;
+    }
+    Ok(())
+}
+
+/// Append `module=<kind>:<path>` tokens from a boot entry command line.
+pub fn specs_from_cmdline<'a>(cmdline: &'a str, out: &mut ModuleList<'a>) -> Result<(), ModuleError> {
+    for tok in cmdline.split_ascii_whitespace() {
+        let Some(spec) = tok.strip_prefix("module=") else { continue };
+        let (kind, path) = spec.split_once(':').ok_or(ModuleError::BadSpec)?;
+        let kind = ModuleKind::from_name(kind).ok_or(ModuleError::BadSpec)?;
+        if path.is_empty() {
+            return Err(ModuleError::BadSpec);
+        }
+        out.push(ModuleSpec { kind, path, sha256: None }).map_err(|_| ModuleError::TooMany)?;
+    }
+    Ok(())
+}
+
+fn free_loaded(bs: &BootServices, loaded: &[FileBuffer]) {
+    for f in loaded {
+        f.free(bs);
+    }
+}
+
+/// Load, verify and measure every module and build the handoff table.
+/// Nothing stays allocated on error.
+pub fn load_modules(
+    st: &mut SystemTable<Boot>,
+    image_handle: Handle,
+    specs: &[ModuleSpec],
+    measured_boot: bool,
+) -> Result<Modules, ModuleError> {
+    if specs.is_empty() {
+        return Ok(Modules { ptr: 0, count: 0, reserved: 0 });
+    }
+    if specs.len() > MAX_MODULES {
+        return Err(ModuleError::TooMany);
+    }
+
+    let mut loaded: FixedVec<FileBuffer, MAX_MODULES> = FixedVec::new();
+    for spec in specs {
+        let file = match read_file(st.boot_services(), image_handle, spec.path) {
+            Ok(f) => f,
+            Err(e) => {
+                free_loaded(st.boot_services(), &loaded);
+                return Err(ModuleError::File(e));
+            }
+        };
+        let check = match verify_image(st.boot_services(), image_handle, spec.path, file.as_slice()) {
+            Err(e) => Err(ModuleError::Verify(e)),
+            Ok(()) => match spec.sha256 {
+                Some(want) if Sha256::digest(file.as_slice()).as_slice() != want => Err(ModuleError::HashMismatch),
+                _ => Ok(()),
+            },
+        };
+        if let Err(e) = check {
+            log_error("modules", &format!("{} ({}): {}", spec.path, spec.kind.name(), e));
+            file.free(st.boot_services());
+            free_loaded(st.boot_services(), &loaded);
+            return Err(e);
+        }
+
+        if measured_boot {
+            let event = format!("NONOS module {} {}", spec.kind.name(), spec.path);
+            if !extend_pcr_measurement_with_event(st, PCR_MODULES, file.as_slice(), event.as_bytes()) {
+                log_warn("modules", &format!("PCR {} measurement failed for {}", PCR_MODULES, spec.path));
+            }
+        }
+        // capacity checked against specs.len() above
+        let _ = loaded.push(file);
+    }
+
+    let bs = st.boot_services();
+    let table_bytes = specs.len() * size_of::<Module>();
+    let table_pages = (table_bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+    let table = match bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, table_pages) {
+        Ok(a) => a,
+        Err(e) => {
+            free_loaded(bs, &loaded);
+            return Err(ModuleError::Allocation(e.status()));
+        }
+    };
+
+    // safe: table spans table_pages fresh pages, enough for specs.len() entries
+    let entries = unsafe { core::slice::from_raw_parts_mut(table as *mut Module, specs.len()) };
+    for ((slot, spec), file) in entries.iter_mut().zip(specs).zip(loaded.iter()) {
+        *slot = Module { base: file.addr, size: file.len as u64, kind: spec.kind as u32, reserved: 0 };
+        log_info(
+            "modules",
+            &format!("{} {} @ 0x{:x} ({} bytes)", spec.kind.name(), spec.path, file.addr, file.len),
+        );
+    }
+
+    Ok(Modules { ptr: table, count: specs.len() as u32, reserved: 0 })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "00112233445566778899aabbccddeeff00112233445566778899AABBCCDDEEFF";
+
+    #[test]
+    fn manifest_modules_require_hash() {
+        let text = alloc::format!(
+            "# capsule\nversion 1\nmodule initrd /EFI/nonos/initrd.img {}\nmodule policy /EFI/nonos/policy.bin {}\n",
+            HASH, HASH
+        );
+        let mut list = ModuleList::new();
+        specs_from_manifest(text.as_bytes(), &mut list).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].kind, ModuleKind::Initrd);
+        assert_eq!(list[1].path, "/EFI/nonos/policy.bin");
+        assert_eq!(list[1].sha256.unwrap()[31], 0xFF);
+
+        let mut list = ModuleList::new();
+        assert!(specs_from_manifest(b"module initrd /initrd.img\n", &mut list).is_err());
+    }
+
+    #[test]
+    fn cmdline_modules() {
+        let mut list = ModuleList::new();
+        specs_from_cmdline("quiet module=initrd:/initrd.img module=config:/boot.cfg", &mut list).unwrap();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[1].kind, ModuleKind::Config);
+        assert_eq!(list[1].sha256, None);
+
+        let mut list = ModuleList::new();
+        assert!(specs_from_cmdline("module=firmware:/x.bin", &mut list).is_err());
+        assert!(specs_from_cmdline("module=initrd:", &mut list).is_err());
+    }
+}
@@ -0,0 +1,296 @@
+//! NONOS ELF notes: boot requirements the kernel declares to the loader.
+//!
+//! Notes live in a `PT_NOTE` segment (normally from a `.note.nonos` section)
+//! with owner name `"NONOS"`. Descriptors are little endian:
+//!
+//! | type | name        | desc                                             |
+//! |------|-------------|--------------------------------------------------|
+//! | 1    | ABI_VERSION | u32 `BootHandoffV1` version the kernel expects   |
+//! | 2    | STACK_SIZE  | u64 bytes of initial stack                       |
+//! | 3    | CPU_FEATURES| u64 bitmask of `cpu_feature` bits required       |
+//! | 4    | FRAMEBUFFER | u32 `fb_request` value                           |
+//! | 5    | PLACEMENT   | u64 min_phys, u64 max_phys (exclusive), u64 align|
+//! | 6    | SMP         | u32 `smp_request` value                          |
+//! | 7    | EFI_RUNTIME | u32 `efi_runtime_request`, u32 pad, u64 offset   |
+//! | 8    | CET         | u32 `cet_request` bits                           |
+//! | 9    | SYSCALL     | u64 LSTAR, u64 SFMASK, u16 CS, u16 user base     |
+//!
+//! Unknown note types are ignored; unknown CPU feature bits are refused since
+//! the loader cannot promise them.
+
+#![allow(dead_code)]
+
+use crate::bytes::{u32_at, u64_at};
+use crate::loader::LoaderError;
+
+pub const NOTE_OWNER: &[u8] = b"NONOS";
+
+pub const NT_NONOS_ABI_VERSION: u32 = 1;
+pub const NT_NONOS_STACK_SIZE: u32 = 2;
+pub const NT_NONOS_CPU_FEATURES: u32 = 3;
+pub const NT_NONOS_FRAMEBUFFER: u32 = 4;
+pub const NT_NONOS_PLACEMENT: u32 = 5;
+pub const NT_NONOS_SMP: u32 = 6;
+pub const NT_NONOS_EFI_RUNTIME: u32 = 7;
+pub const NT_NONOS_CET: u32 = 8;
+pub const NT_NONOS_SYSCALL: u32 = 9;
+
+/// CPU feature bits understood in NT_NONOS_CPU_FEATURES (shared with `tag::CPU`)
+pub use nonos_handoff::cpu::cpu_feature;
+
+/// NT_NONOS_FRAMEBUFFER values
+pub mod fb_request {
+    pub const NONE: u32 = 0;
+    pub const WANTED: u32 = 1;
+    pub const REQUIRED: u32 = 2;
+}
+
+/// NT_NONOS_SMP values
+pub mod smp_request {
+    pub const NONE: u32 = 0;
+    /// Start every AP and park it on a mailbox (handoff `tag::SMP`)
+    pub const PARK: u32 = 1;
+}
+
+/// NT_NONOS_EFI_RUNTIME values: where SetVirtualAddressMap puts runtime ranges
+pub mod efi_runtime_request {
+    /// Do not call SetVirtualAddressMap; runtime services stay physical
+    pub const NONE: u32 = 0;
+    /// Loader's default offset (`handoff::runtime::DEFAULT_VIRT_OFFSET`)
+    pub const LOADER: u32 = 1;
+    /// `virt = phys + offset` with the page-aligned offset from the note
+    pub const OFFSET: u32 = 2;
+}
+
+/// NT_NONOS_CET bits: CET modes the kernel is built for. The loader turns
+/// them on only with the `nonos-cet` feature and CPU support.
+pub mod cet_request {
+    /// Supervisor shadow stack, handed over in `CpuTag.shadow_stack`
+    pub const SHADOW_STACK: u32 = 1 << 0;
+    /// Indirect branch tracking; the entry point must start with ENDBR64
+    pub const IBT: u32 = 1 << 1;
+    pub const KNOWN: u32 = SHADOW_STACK | IBT;
+}
+
+/// Default handoff stack when the kernel does not ask (8 pages)
+pub const DEFAULT_STACK_SIZE: u64 = 8 * 0x1000;
+/// Largest stack we agree to allocate
+pub const MAX_STACK_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Physical placement constraint from NT_NONOS_PLACEMENT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Placement {
+    pub min_phys: u64,
+    pub max_phys: u64,
+    pub align: u64,
+}
+
+impl Placement {
+    pub fn allows(&self, base: u64, size: u64) -> bool {
+        base >= self.min_phys
+            && base.checked_add(size).map_or(false, |end| end <= self.max_phys)
+            && base % self.align == 0
+    }
+}
+
+/// SYSCALL setup from NT_NONOS_SYSCALL, programmed with the `nonos-syscall-msr` feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallEntry {
+    /// LSTAR; an ELF address, relocated with the image like `e_entry`
+    pub entry: u64,
+    /// SFMASK: RFLAGS bits cleared on entry
+    pub rflags_mask: u64,
+    /// STAR[47:32]; SS is `kernel_cs + 8`
+    pub kernel_cs: u16,
+    /// STAR[63:48]; SYSRET loads CS `user_base + 16` and SS `user_base + 8`
+    pub user_base: u16,
+}
+
+/// Requirements collected from the notes (defaults when absent)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KernelRequirements {
+    pub abi_version: Option<u32>,
+    pub stack_size: u64,
+    pub cpu_features: u64,
+    pub framebuffer: u32,
+    pub placement: Option<Placement>,
+    pub smp: u32,
+    pub efi_runtime: u32,
+    /// Only meaningful with `efi_runtime_request::OFFSET`
+    pub efi_runtime_offset: u64,
+    pub cet: u32,
+    pub syscall: Option<SyscallEntry>,
+}
+
+impl Default for KernelRequirements {
+    fn default() -> Self {
+        KernelRequirements {
+            abi_version: None,
+            stack_size: DEFAULT_STACK_SIZE,
+            cpu_features: 0,
+            framebuffer: fb_request::NONE,
+            placement: None,
+            smp: smp_request::NONE,
+            efi_runtime: efi_runtime_request::NONE,
+            efi_runtime_offset: 0,
+            cet: 0,
+            syscall: None,
+        }
+    }
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Fold the NONOS notes found in one note segment into `req`.
+pub fn parse_notes(data: &[u8], req: &mut KernelRequirements) -> Result<(), LoaderError> {
+    let bad = LoaderError::NoteMalformed;
+    let mut off = 0;
+    while off + 12 <= data.len() {
+        let namesz = u32_at(data, off).ok_or(bad("header"))? as usize;
+        let descsz = u32_at(data, off + 4).ok_or(bad("header"))? as usize;
+        let ty = u32_at(data, off + 8).ok_or(bad("header"))?;
+        let name_off = off + 12;
+        let desc_off = name_off.checked_add(align4(namesz)).ok_or(bad("name size"))?;
+        let next = desc_off.checked_add(align4(descsz)).ok_or(bad("desc size"))?;
+        if desc_off + descsz > data.len() {
+            return Err(bad("note exceeds segment"));
+        }
+        off = next;
+
+        let name = &data[name_off..name_off + namesz];
+        if name.strip_suffix(&[0]).unwrap_or(name) != NOTE_OWNER {
+            continue;
+        }
+        let desc = &data[desc_off..desc_off + descsz];
+        match ty {
+            NT_NONOS_ABI_VERSION => req.abi_version = Some(u32_at(desc, 0).ok_or(bad("ABI version"))?),
+            NT_NONOS_STACK_SIZE => req.stack_size = u64_at(desc, 0).ok_or(bad("stack size"))?,
+            NT_NONOS_CPU_FEATURES => req.cpu_features |= u64_at(desc, 0).ok_or(bad("CPU features"))?,
+            NT_NONOS_FRAMEBUFFER => req.framebuffer = u32_at(desc, 0).ok_or(bad("framebuffer"))?,
+            NT_NONOS_PLACEMENT => {
+                let p = Placement {
+                    min_phys: u64_at(desc, 0).ok_or(bad("placement"))?,
+                    max_phys: u64_at(desc, 8).ok_or(bad("placement"))?,
+                    align: u64_at(desc, 16).ok_or(bad("placement"))?.max(1),
+                };
+                if p.max_phys <= p.min_phys || !p.align.is_power_of_two() {
+                    return Err(bad("placement range"));
+                }
+                req.placement = Some(p);
+            }
+            NT_NONOS_SMP => req.smp = u32_at(desc, 0).ok_or(bad("SMP"))?,
+            NT_NONOS_EFI_RUNTIME => {
+                req.efi_runtime = u32_at(desc, 0).ok_or(bad("EFI runtime"))?;
+                if req.efi_runtime == efi_runtime_request::OFFSET {
+                    req.efi_runtime_offset = u64_at(desc, 8).ok_or(bad("EFI runtime offset"))?;
+                }
+            }
+            NT_NONOS_CET => req.cet = u32_at(desc, 0).ok_or(bad("CET"))?,
+            NT_NONOS_SYSCALL => {
+                let word = u32_at(desc, 16).ok_or(bad("syscall"))?;
+                req.syscall = Some(SyscallEntry {
+                    entry: u64_at(desc, 0).ok_or(bad("syscall"))?,
+                    rflags_mask: u64_at(desc, 8).ok_or(bad("syscall"))?,
+                    kernel_cs: word as u16,
+                    user_base: (word >> 16) as u16,
+                });
+            }
+            _ => {}
+        }
+    }
+    if req.stack_size == 0 || req.stack_size > MAX_STACK_SIZE {
+        return Err(bad("stack size out of range"));
+    }
+    if req.framebuffer > fb_request::REQUIRED {
+        return Err(bad("framebuffer request"));
+    }
+    if req.smp > smp_request::PARK {
+        return Err(bad("SMP request"));
+    }
+    if req.efi_runtime > efi_runtime_request::OFFSET || req.efi_runtime_offset & 0xFFF != 0 {
+        return Err(bad("EFI runtime request"));
+    }
+    if req.cet & !cet_request::KNOWN != 0 {
+        return Err(bad("CET request"));
+    }
+    if req.syscall.is_some_and(|s| s.entry == 0 || s.kernel_cs == 0 || s.kernel_cs & 7 != 0) {
+        return Err(bad("syscall entry"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn note(name: &[u8], ty: u32, desc: &[u8]) -> Vec<u8> {
+        let mut n = Vec::new();
+        n.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        n.extend_from_slice(&(desc.len() as u32).to_le_bytes());
+        n.extend_from_slice(&ty.to_le_bytes());
+        n.extend_from_slice(name);
+        n.resize(align4(n.len()), 0);
+        n.extend_from_slice(desc);
+        n.resize(align4(n.len()), 0);
+        n
+    }
+
+    #[test]
+    fn parses_nonos_notes_and_skips_others() {
+        let mut seg = note(b"GNU\0", 3, &[0xAA; 20]);
+        seg.extend(note(b"NONOS\0", NT_NONOS_ABI_VERSION, &1u32.to_le_bytes()));
+        seg.extend(note(b"NONOS\0", NT_NONOS_STACK_SIZE, &0x10000u64.to_le_bytes()));
+        seg.extend(note(b"NONOS\0", NT_NONOS_FRAMEBUFFER, &fb_request::REQUIRED.to_le_bytes()));
+        let mut place = Vec::new();
+        for v in [0x100000u64, 0x4000_0000, 0x200000] {
+            place.extend_from_slice(&v.to_le_bytes());
+        }
+        seg.extend(note(b"NONOS\0", NT_NONOS_PLACEMENT, &place));
+        seg.extend(note(b"NONOS\0", NT_NONOS_SMP, &smp_request::PARK.to_le_bytes()));
+        let mut rt = efi_runtime_request::OFFSET.to_le_bytes().to_vec();
+        rt.extend_from_slice(&[0; 4]);
+        rt.extend_from_slice(&0xFFFF_FF00_0000_0000u64.to_le_bytes());
+        seg.extend(note(b"NONOS\0", NT_NONOS_EFI_RUNTIME, &rt));
+        seg.extend(note(b"NONOS\0", NT_NONOS_CET, &cet_request::IBT.to_le_bytes()));
+        let mut sc = 0x20_1000u64.to_le_bytes().to_vec();
+        sc.extend_from_slice(&0x4_0700u64.to_le_bytes());
+        sc.extend_from_slice(&0x08u16.to_le_bytes());
+        sc.extend_from_slice(&0x1Bu16.to_le_bytes());
+        sc.extend_from_slice(&[0; 4]);
+        seg.extend(note(b"NONOS\0", NT_NONOS_SYSCALL, &sc));
+
+        let mut req = KernelRequirements::default();
+        parse_notes(&seg, &mut req).unwrap();
+        assert_eq!(req.abi_version, Some(1));
+        assert_eq!(req.stack_size, 0x10000);
+        assert_eq!(req.framebuffer, fb_request::REQUIRED);
+        assert_eq!(req.smp, smp_request::PARK);
+        assert_eq!((req.efi_runtime, req.efi_runtime_offset), (efi_runtime_request::OFFSET, 0xFFFF_FF00_0000_0000));
+        assert_eq!(req.cet, cet_request::IBT);
+        assert_eq!(
+            req.syscall,
+            Some(SyscallEntry { entry: 0x20_1000, rflags_mask: 0x4_0700, kernel_cs: 0x08, user_base: 0x1B })
+        );
+        let p = req.placement.unwrap();
+        assert!(p.allows(0x200000, 0x1000));
+        assert!(!p.allows(0x201000, 0x1000));
+        assert!(!p.allows(0x3FE0_0000, 0x40_0000));
+    }
+
+    #[test]
+    fn rejects_truncated_and_out_of_range() {
+        let mut seg = note(b"NONOS\0", NT_NONOS_STACK_SIZE, &0u64.to_le_bytes());
+        let mut req = KernelRequirements::default();
+        assert!(parse_notes(&seg, &mut req).is_err());
+
+        seg = note(b"NONOS\0", NT_NONOS_ABI_VERSION, &1u32.to_le_bytes());
+        seg.truncate(seg.len() - 2);
+        assert!(parse_notes(&seg, &mut KernelRequirements::default()).is_err());
+
+        seg = note(b"NONOS\0", NT_NONOS_CET, &4u32.to_le_bytes());
+        assert!(parse_notes(&seg, &mut KernelRequirements::default()).is_err());
+    }
+}
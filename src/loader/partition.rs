@@ -0,0 +1,225 @@
+//! Kernel capsules stored as raw GPT partitions.
+//!
+//! The capsule image starts at the partition's first LBA with no filesystem
+//! around it. Its length comes from the image itself: the ELF header gives
+//! the program and section header tables, and the image ends where the last
+//! table, segment or section does. Everything is read through the disk's
+//! BlockIO in `CHUNK`-sized pieces into LOADER_DATA pages, the same
+//! `FileBuffer` that `read_file` returns.
+
+use crate::bytes::{le16, le32, le64};
+use crate::capsule::Capsule;
+use crate::gpt::{format_guid, BlockRead};
+use crate::loader::file::MAX_FILE_SIZE;
+use crate::loader::{FileBuffer, LoaderError, LoaderResult};
+use crate::log::logger::{log_error, log_info};
+use crate::storage::{Inventory, Media, RawSource};
+use alloc::format;
+use alloc::vec;
+use nonos_handoff::storage::GptPartition;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType};
+
+const PAGE_SIZE: usize = 0x1000;
+/// Largest single BlockIO read
+pub const CHUNK: usize = 1024 * 1024;
+/// Upper bound for either ELF header table
+const MAX_TABLE_BYTES: u64 = 1024 * 1024;
+
+const EHDR_LEN: usize = 64;
+const PHDR_LEN: usize = 56;
+const SHDR_LEN: usize = 64;
+const SHT_NULL: u32 = 0;
+const SHT_NOBITS: u32 = 8;
+
+fn table_end(offset: u64, len: u64) -> LoaderResult<u64> {
+    offset.checked_add(len).ok_or(LoaderError::ElfParseError("table out of range"))
+}
+
+/// (offset, length) of the program and section header tables of an ELF64 LE header
+pub fn header_tables(ehdr: &[u8]) -> LoaderResult<[(u64, u64); 2]> {
+    if ehdr.len() < EHDR_LEN || ehdr[..4] != *b"\x7fELF" || ehdr[4] != 2 || ehdr[5] != 1 {
+        return Err(LoaderError::ElfParseError("partition holds no ELF64 image"));
+    }
+    let (phoff, phentsize, phnum) = (le64(ehdr, 32), le16(ehdr, 54) as u64, le16(ehdr, 56) as u64);
+    let (shoff, shentsize, shnum) = (le64(ehdr, 40), le16(ehdr, 58) as u64, le16(ehdr, 60) as u64);
+    if (phnum > 0 && phentsize < PHDR_LEN as u64) || (shnum > 0 && shentsize < SHDR_LEN as u64) {
+        return Err(LoaderError::ElfParseError("header table entries too small"));
+    }
+    if shnum == 0 && shoff != 0 {
+        return Err(LoaderError::ElfParseError("extended section numbering"));
+    }
+    let tables = [(phoff, phnum * phentsize), (shoff, shnum * shentsize)];
+    if tables.iter().any(|&(_, len)| len > MAX_TABLE_BYTES) {
+        return Err(LoaderError::ElfParseError("header table too large"));
+    }
+    Ok(tables)
+}
+
+/// Bytes from the start of the image to the end of its last table, segment or section
+pub fn image_extent(ehdr: &[u8], phdrs: &[u8], shdrs: &[u8]) -> LoaderResult<u64> {
+    let [(phoff, phlen), (shoff, shlen)] = header_tables(ehdr)?;
+    let mut end = (EHDR_LEN as u64).max(table_end(phoff, phlen)?).max(table_end(shoff, shlen)?);
+    let phentsize = le16(ehdr, 54) as usize;
+    for ph in phdrs.chunks_exact(phentsize.max(1)) {
+        end = end.max(table_end(le64(ph, 8), le64(ph, 32))?);
+    }
+    let shentsize = le16(ehdr, 58) as usize;
+    for sh in shdrs.chunks_exact(shentsize.max(1)) {
+        if matches!(le32(sh, 4), SHT_NULL | SHT_NOBITS) {
+            continue;
+        }
+        end = end.max(table_end(le64(sh, 24), le64(sh, 32))?);
+    }
+    Ok(end)
+}
+
+/// Byte-addressed reads within one partition
+struct PartitionReader<'a> {
+    disk: Media<'a>,
+    first_lba: u64,
+    bytes: u64,
+}
+
+impl PartitionReader<'_> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> LoaderResult<()> {
+        let block = self.disk.block_size() as u64;
+        let end = table_end(offset, buf.len() as u64)?;
+        if end > self.bytes {
+            return Err(LoaderError::ElfParseError("image extends past the partition end"));
+        }
+        let ok = if offset % block == 0 && buf.len() as u64 % block == 0 {
+            self.disk.read(self.first_lba + offset / block, buf)
+        } else {
+            let start = offset / block * block;
+            let mut covering = vec![0u8; (end.div_ceil(block) * block - start) as usize];
+            let ok = self.disk.read(self.first_lba + start / block, &mut covering);
+            let skip = (offset - start) as usize;
+            buf.copy_from_slice(&covering[skip..skip + buf.len()]);
+            ok
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(LoaderError::UefiError { desc: "partition read failed", status: Status::DEVICE_ERROR })
+        }
+    }
+}
+
+/// Read the capsule image stored in `part` into fresh pages
+pub fn read_partition_image(bs: &BootServices, inv: &Inventory, part: &GptPartition) -> LoaderResult<FileBuffer> {
+    let unreadable = || LoaderError::UefiError { desc: "partition device not accessible", status: Status::NOT_FOUND };
+    let handle = *inv.handles.get(part.device as usize).ok_or_else(unreadable)?;
+    let disk = Media::open(bs, handle).ok_or_else(unreadable)?;
+    let bytes = part.blocks().saturating_mul(disk.block_size() as u64);
+    let mut reader = PartitionReader { disk, first_lba: part.first_lba, bytes };
+
+    let mut ehdr = [0u8; EHDR_LEN];
+    reader.read_at(0, &mut ehdr)?;
+    let [(phoff, phlen), (shoff, shlen)] = header_tables(&ehdr)?;
+    let mut phdrs = vec![0u8; phlen as usize];
+    reader.read_at(phoff, &mut phdrs)?;
+    let mut shdrs = vec![0u8; shlen as usize];
+    reader.read_at(shoff, &mut shdrs)?;
+    let size = image_extent(&ehdr, &phdrs, &shdrs)?;
+    if size > bytes || size > MAX_FILE_SIZE as u64 {
+        return Err(LoaderError::FileTooLarge { size: size as usize, max: MAX_FILE_SIZE.min(bytes as usize) });
+    }
+    let size = size as usize;
+
+    let pages = size.div_ceil(PAGE_SIZE);
+    let addr = bs
+        .allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages)
+        .map_err(|e| LoaderError::AllocationFailed { addr: 0, pages, status: e.status() })?;
+    let buf = FileBuffer { addr, len: size, pages };
+    // safe: freshly allocated pages cover `size` bytes
+    let dst = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, size) };
+    for (i, chunk) in dst.chunks_mut(CHUNK).enumerate() {
+        if let Err(e) = reader.read_at((i * CHUNK) as u64, chunk) {
+            buf.free(bs);
+            return Err(e);
+        }
+    }
+
+    log_info(
+        "loader",
+        &format!("Read partition {} ({} bytes) into 0x{:x}", format_guid(&part.unique_guid), size, addr),
+    );
+    Ok(buf)
+}
+
+/// Load and verify the first capsule found on a partition matching `src`
+pub fn load_partition_capsule(st: &mut SystemTable<Boot>, inv: &Inventory, src: &RawSource) -> Result<Capsule, &'static str> {
+    let mut found = false;
+    for part in inv.raw_candidates(src) {
+        found = true;
+        let guid = format_guid(&part.unique_guid);
+        let capsule = read_partition_image(st.boot_services(), inv, part)
+            .map_err(|e| log_error("loader", &format!("Partition {}: {}", guid, e)))
+            .and_then(|file| {
+                Capsule::from_blob(file.as_slice()).map_err(|e| {
+                    log_error("loader", &format!("Partition {} rejected: {}", guid, e));
+                    file.free(st.boot_services());
+                })
+            });
+        if let Ok(c) = capsule {
+            log_info("loader", &format!("Booting capsule from partition {}", guid));
+            return Ok(c);
+        }
+    }
+    Err(if found { "no partition holds a valid capsule" } else { "no matching partition" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn ehdr(phoff: u64, phnum: u16, shoff: u64, shnum: u16) -> Vec<u8> {
+        let mut h = vec![0u8; EHDR_LEN];
+        h[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        h[32..40].copy_from_slice(&phoff.to_le_bytes());
+        h[40..48].copy_from_slice(&shoff.to_le_bytes());
+        h[54..56].copy_from_slice(&(PHDR_LEN as u16).to_le_bytes());
+        h[56..58].copy_from_slice(&phnum.to_le_bytes());
+        h[58..60].copy_from_slice(&(SHDR_LEN as u16).to_le_bytes());
+        h[60..62].copy_from_slice(&shnum.to_le_bytes());
+        h
+    }
+
+    fn section(ty: u32, offset: u64, size: u64) -> Vec<u8> {
+        let mut s = vec![0u8; SHDR_LEN];
+        s[4..8].copy_from_slice(&ty.to_le_bytes());
+        s[24..32].copy_from_slice(&offset.to_le_bytes());
+        s[32..40].copy_from_slice(&size.to_le_bytes());
+        s
+    }
+
+    #[test]
+    fn extent_covers_tables_segments_and_sections() {
+        let h = ehdr(64, 1, 0x2000, 3);
+        assert_eq!(header_tables(&h).unwrap(), [(64, 56), (0x2000, 192)]);
+
+        let mut ph = vec![0u8; PHDR_LEN];
+        ph[8..16].copy_from_slice(&0x1000u64.to_le_bytes());
+        ph[32..40].copy_from_slice(&0x800u64.to_le_bytes());
+        let mut sh = section(SHT_NULL, 0, 0);
+        sh.extend(section(SHT_NOBITS, 0x10000, 0x10000));
+        sh.extend(section(1, 0x1800, 0x80));
+        // section table ends last; .bss takes no file space
+        assert_eq!(image_extent(&h, &ph, &sh).unwrap(), 0x2000 + 192);
+
+        // a signature section appended after the section table
+        sh.extend(section(1, 0x2200, 0x40));
+        let h = ehdr(64, 1, 0x2000, 4);
+        assert_eq!(image_extent(&h, &ph, &sh).unwrap(), 0x2240);
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        assert!(header_tables(&[0u8; EHDR_LEN]).is_err());
+        assert!(header_tables(&ehdr(64, 0, 0x1000, 0)).is_err());
+        let h = ehdr(u64::MAX, 1, 0, 0);
+        assert!(image_extent(&h, &[0u8; PHDR_LEN], &[]).is_err());
+    }
+}
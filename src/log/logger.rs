@@ -1,28 +1,31 @@
 //! UEFI logging utilities for the bootloader
+//!
+//! Records go to the in-memory ring (`super::ring`), which the handoff
+//! passes to the kernel as the `BOOT_LOG` tag.
 
-/// Write a log message - simplified stub version for now
-/// In practice, this would need a way to access the mutable system table
-fn write_log(_level: &str, _category: &str, _message: &str) {
-    // Stub implementation - would need system table access to work properly
-    // For now, this just acts as a no-op to avoid compilation errors
+use super::ring;
+use nonos_handoff::bootlog::log_level;
+
+fn write_log(level: u8, category: &str, message: &str) {
+    ring::record(level, category, message);
 }
 
 pub fn log_info(category: &str, message: &str) {
-    write_log("INFO", category, message);
+    write_log(log_level::INFO, category, message);
 }
 
 pub fn log_warn(category: &str, message: &str) {
-    write_log("WARN", category, message);
+    write_log(log_level::WARN, category, message);
 }
 
 pub fn log_critical(category: &str, message: &str) {
-    write_log("CRIT", category, message);
+    write_log(log_level::CRITICAL, category, message);
 }
 
 pub fn log_debug(category: &str, message: &str) {
-    write_log("DEBUG", category, message);
+    write_log(log_level::DEBUG, category, message);
 }
 
 pub fn log_error(category: &str, message: &str) {
-    write_log("ERROR", category, message);
+    write_log(log_level::ERROR, category, message);
 }
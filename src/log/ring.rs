@@ -0,0 +1,204 @@
+//! Fixed-size in-memory ring holding every bootloader log record.
+//!
+//! Records use the `nonos_handoff::bootlog` layout from the start, so
+//! handing the log to the kernel is a linear copy of the ring into a
+//! LOADER_DATA region (`export`). When the ring is full the oldest records
+//! are evicted and counted in `dropped`.
+//!
+//! The loader runs single-threaded and never logs from interrupt context,
+//! which is what the unsynchronised global below relies on.
+
+#![allow(dead_code)]
+
+use crate::timing::rdtsc;
+use core::cell::UnsafeCell;
+use core::mem::size_of;
+use nonos_handoff::bootlog::{record_len, BootLogHeader, LogRecordHeader, BOOT_LOG_MAGIC, BOOT_LOG_VERSION};
+
+/// Record bytes kept for the kernel
+pub const RING_CAPACITY: usize = 64 * 1024;
+/// Size of an exported region: header plus a full ring
+pub const REGION_BYTES: usize = size_of::<BootLogHeader>() + RING_CAPACITY;
+
+pub struct LogRing<const N: usize> {
+    buf: [u8; N],
+    /// Offset of the oldest record
+    head: usize,
+    /// Bytes in use starting at `head`, wrapping at `N`
+    len: usize,
+    records: u32,
+    dropped: u32,
+}
+
+impl<const N: usize> LogRing<N> {
+    pub const fn new() -> Self {
+        LogRing { buf: [0; N], head: 0, len: 0, records: 0, dropped: 0 }
+    }
+
+    pub fn records(&self) -> u32 {
+        self.records
+    }
+
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Append a record, evicting the oldest ones until it fits. Text that
+    /// could never fit is cut at a character boundary.
+    pub fn push(&mut self, tsc: u64, level: u8, category: &str, message: &str) {
+        let category = truncate(category, u8::MAX as usize);
+        let room = N.saturating_sub(size_of::<LogRecordHeader>() + category.len());
+        let message = truncate(message, room.min(u16::MAX as usize));
+        let rec_len = record_len(category.len(), message.len());
+        if rec_len > N {
+            self.dropped = self.dropped.saturating_add(1);
+            return;
+        }
+
+        while self.len + rec_len > N && self.records > 0 {
+            self.evict_oldest();
+        }
+
+        let h = LogRecordHeader {
+            tsc,
+            level,
+            category_len: category.len() as u8,
+            message_len: message.len() as u16,
+            reserved: 0,
+        };
+        // safe: LogRecordHeader is repr(C) without implicit padding (asserted in nonos_handoff)
+        let hb = unsafe {
+            core::slice::from_raw_parts(&h as *const LogRecordHeader as *const u8, size_of::<LogRecordHeader>())
+        };
+        let mut at = (self.head + self.len) % N;
+        for part in [hb, category.as_bytes(), message.as_bytes()] {
+            self.write_at(at, part);
+            at = (at + part.len()) % N;
+        }
+        let pad = rec_len - hb.len() - category.len() - message.len();
+        self.write_at(at, &[0; 8][..pad]);
+
+        self.len += rec_len;
+        self.records += 1;
+    }
+
+    /// Records oldest first into `out`; returns the bytes written
+    pub fn copy_linear(&self, out: &mut [u8]) -> usize {
+        let n = self.len.min(out.len());
+        let first = n.min(N - self.head);
+        out[..first].copy_from_slice(&self.buf[self.head..self.head + first]);
+        out[first..n].copy_from_slice(&self.buf[..n - first]);
+        n
+    }
+
+    /// Write a `BootLogHeader` and the records into `region`; returns the
+    /// bytes used, 0 if `region` cannot hold them all
+    pub fn export(&self, region: &mut [u8], tsc_hz: u64) -> usize {
+        let hdr_len = size_of::<BootLogHeader>();
+        if region.len() < hdr_len + self.len {
+            return 0;
+        }
+        let data = self.copy_linear(&mut region[hdr_len..]);
+        let header = BootLogHeader {
+            magic: BOOT_LOG_MAGIC,
+            version: BOOT_LOG_VERSION,
+            header_size: hdr_len as u16,
+            records: self.records,
+            dropped: self.dropped,
+            data_size: data as u64,
+            tsc_hz,
+        };
+        // safe: region holds at least one header; write_unaligned has no alignment needs
+        unsafe { core::ptr::write_unaligned(region.as_mut_ptr() as *mut BootLogHeader, header) };
+        hdr_len + data
+    }
+
+    fn evict_oldest(&mut self) {
+        let mut raw = [0u8; size_of::<LogRecordHeader>()];
+        self.read_at(self.head, &mut raw);
+        // safe: raw is exactly one header long
+        let h = unsafe { core::ptr::read_unaligned(raw.as_ptr() as *const LogRecordHeader) };
+        let rec_len = h.record_len();
+        self.head = (self.head + rec_len) % N;
+        self.len -= rec_len;
+        self.records -= 1;
+        self.dropped = self.dropped.saturating_add(1);
+    }
+
+    fn write_at(&mut self, at: usize, bytes: &[u8]) {
+        let first = bytes.len().min(N - at);
+        self.buf[at..at + first].copy_from_slice(&bytes[..first]);
+        self.buf[..bytes.len() - first].copy_from_slice(&bytes[first..]);
+    }
+
+    fn read_at(&self, at: usize, out: &mut [u8]) {
+        let first = out.len().min(N - at);
+        out[..first].copy_from_slice(&self.buf[at..at + first]);
+        let rest = out.len() - first;
+        out[first..].copy_from_slice(&self.buf[..rest]);
+    }
+}
+
+impl<const N: usize> Default for LogRing<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Longest prefix of `s` no longer than `max` bytes that ends on a char boundary
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+struct GlobalRing(UnsafeCell<LogRing<RING_CAPACITY>>);
+
+// safe: see the module comment; the loader never touches the ring concurrently
+unsafe impl Sync for GlobalRing {}
+
+static RING: GlobalRing = GlobalRing(UnsafeCell::new(LogRing::new()));
+
+/// Append to the global ring, timestamped with the TSC
+pub fn record(level: u8, category: &str, message: &str) {
+    // safe: single-threaded loader, no reentrancy (module comment)
+    unsafe { (*RING.0.get()).push(rdtsc(), level, category, message) };
+}
+
+/// Export the global ring into the region at `phys`. Plain memory writes,
+/// valid after ExitBootServices.
+pub fn publish(phys: u64, size: usize, tsc_hz: u64) -> usize {
+    // safe: phys is a LOADER_DATA region of `size` bytes reserved for the log
+    let region = unsafe { core::slice::from_raw_parts_mut(phys as *mut u8, size) };
+    // safe: as in record
+    unsafe { (*RING.0.get()).export(region, tsc_hz) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nonos_handoff::bootlog::{log_level, BootLog};
+
+    #[test]
+    fn evicts_oldest_and_exports_in_order() {
+        let mut ring = LogRing::<128>::new();
+        // 16 + 8 each: five fit in 128
+        for i in 0..7u64 {
+            ring.push(i, log_level::INFO, "t", "msg");
+        }
+        assert_eq!((ring.records(), ring.dropped()), (5, 2));
+
+        let mut region = [0u8; size_of::<BootLogHeader>() + 128];
+        let used = ring.export(&mut region, 1_000);
+        assert_eq!(used, size_of::<BootLogHeader>() + 5 * 24);
+        let log = BootLog::parse(&region).unwrap();
+        assert_eq!(log.header.dropped, 2);
+        let tscs: alloc::vec::Vec<u64> = log.records().map(|r| r.tsc).collect();
+        assert_eq!(tscs, [2, 3, 4, 5, 6]);
+    }
+}
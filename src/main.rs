@@ -19,22 +19,44 @@ use uefi::proto::network::snp::SimpleNetwork;
 use uefi::table::runtime::ResetType;
 use uefi_services::init;
 
+use nonos_boot::chainload::chainload_entry;
 use nonos_boot::config::{apply_configuration, display_configuration, load_bootloader_config};
-use nonos_boot::handoff::ZeroStateBootInfo;
-use nonos_boot::hardware::discover_system_hardware;
-use nonos_boot::loader::load_kernel_capsule;
+use nonos_boot::handoff::handoff::{FramebufferInfo, HandoffParams};
+use nonos_boot::gpt::{format_guid, partition_name};
+use nonos_boot::handoff::exit_and_jump;
+use nonos_boot::hardware::{discover_system_hardware, query_framebuffer, HardwareInfo};
+use nonos_boot::linux::boot_linux;
+use nonos_boot::loader::modules::{specs_from_cmdline, specs_from_manifest};
+use nonos_boot::loader::{load_kernel_elf, load_modules, load_partition_capsule, ModuleList};
 use nonos_boot::log::logger::{log_critical, log_debug, log_error, log_info, log_warn};
-use nonos_boot::multiboot::MultiBootManager;
+use nonos_boot::memtest::{parse_options, reserve_bad_pages, run as run_memory_test, Report};
+use nonos_boot::multiboot::{BootEntryType, MultiBootManager};
+use nonos_boot::multiboot2::boot_multiboot2;
 use nonos_boot::network::{display_network_boot_menu, initialize_network_boot, NetworkBootOption};
+use nonos_boot::pci::{check_policy, class_name};
+use nonos_boot::smbios::format_uuid;
 use nonos_boot::security::initialize_security_subsystem;
+use nonos_boot::slots::load_slot_capsule;
+use nonos_boot::storage::{self, parse_raw_source};
 use nonos_boot::testing::TestingFramework;
-
-/// External capsule entry signature
-type KernelEntry = extern "C" fn(*const ZeroStateBootInfo) -> !;
+use nonos_boot::timing::{
+    calibrate, rdtsc, set_loader_time_var, spin_us, ticks_to_us, tsc_hz, tsc_method, BootTimeline,
+    LOADER_TIME_INIT,
+};
+use nonos_boot::ui::Ui;
+use nonos_handoff::cpu::cpu_feature;
+use nonos_handoff::pci::pci_bar_flags;
+use nonos_handoff::storage::{block_flags, gpt_flags};
+use nonos_handoff::smbios::text;
+use nonos_handoff::tags::boot_phase;
+use nonos_handoff::PciFunction;
 
 /// Entry point for UEFI firmware
 #[entry]
-fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
+fn efi_main(image_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
+    // before any firmware call: the LOADER phase and LoaderTimeInitUSec start here
+    let entry_tsc = rdtsc();
+
     // Initialize system and UI
     system_table.stdout().reset(false).unwrap_or(());
 
@@ -94,6 +116,15 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         "NØNOS Capsule Bootloader Activated - Advanced Professional Version",
     );
 
+    let mut timeline = BootTimeline::new(entry_tsc, calibrate(&mut system_table));
+    if !set_loader_time_var(
+        system_table.runtime_services(),
+        LOADER_TIME_INIT,
+        ticks_to_us(entry_tsc, timeline.tsc.hz),
+    ) {
+        log_warn("timing", "Could not set LoaderTimeInitUSec");
+    }
+
     // Professional bootloader banner
     system_table
         .stdout()
@@ -139,32 +170,47 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .stdout()
         .output_string(cstr16!("Phase 0: Configuration Loading\r\n"))
         .unwrap_or(());
+    timeline.begin(boot_phase::CONFIG);
     let bootloader_config = load_bootloader_config(&mut system_table);
     display_configuration(&bootloader_config, &mut system_table);
+    timeline.end(boot_phase::CONFIG);
 
     // Phase 1: Security Subsystem
     system_table
         .stdout()
         .output_string(cstr16!("Phase 1: Security Initialization\r\n"))
         .unwrap_or(());
+    timeline.begin(boot_phase::SECURITY);
     let security_context = initialize_security_subsystem(&mut system_table);
 
     // Assess and display security posture
     let security_score =
         nonos_boot::security::assess_security_posture(&security_context, &mut system_table);
+    timeline.end(boot_phase::SECURITY);
 
     // Phase 2: Hardware Discovery & ACPI
     system_table
         .stdout()
         .output_string(cstr16!("Phase 2: Hardware Discovery\r\n"))
         .unwrap_or(());
+    timeline.begin(boot_phase::HARDWARE);
     let hardware_info = discover_system_hardware(&mut system_table);
+    timeline.end(boot_phase::HARDWARE);
+
+    // Machine policy: CPU features the configuration insists on
+    let missing_cpu = hardware_info.cpu.missing(bootloader_config.required_cpu_features);
+    if missing_cpu != 0 {
+        let names: alloc::vec::Vec<&str> = cpu_feature::names(missing_cpu).collect();
+        log_critical("cpu", &alloc::format!("CPU lacks required features: {} (mask 0x{:x})", names.join(" "), missing_cpu));
+        fatal_reset(&mut system_table, "Required CPU features missing");
+    }
 
     // Phase 3: Network Subsystem
     system_table
         .stdout()
         .output_string(cstr16!("Phase 3: Network Subsystem\r\n"))
         .unwrap_or(());
+    timeline.begin(boot_phase::NETWORK);
     let network_context = initialize_network_boot(&mut system_table);
 
     // Perform network diagnostics and security assessment
@@ -172,6 +218,7 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         nonos_boot::network::perform_network_diagnostics(&mut system_table, &network_context);
     let network_security_score =
         nonos_boot::network::assess_network_security(&mut system_table, &network_context);
+    timeline.end(boot_phase::NETWORK);
 
     // Apply configuration to all subsystems
     system_table
@@ -186,6 +233,12 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         &hardware_info,
     );
 
+    // Device policy: under Maximum an unexpected bus master stops the boot here
+    if let Err(e) = check_policy(&hardware_info.pci, bootloader_config.security_policy) {
+        log_critical("pci", &alloc::format!("Refused by device policy: {}", e));
+        fatal_reset(&mut system_table, "PCI device policy violation");
+    }
+
     // Phase 3.7: Testing Framework (if diagnostic mode enabled)
     let testing_passed = if bootloader_config.diagnostic_output {
         system_table
@@ -212,7 +265,7 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .stdout()
         .output_string(cstr16!("Phase 4: Graphics & Memory\r\n"))
         .unwrap_or(());
-    initialize_graphics(&mut system_table);
+    let framebuffer = initialize_graphics(&mut system_table);
     setup_memory_management(&mut system_table);
 
     // Phase 5: Multi-Boot System & Boot Source Selection
@@ -222,7 +275,10 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .unwrap_or(());
 
     // Initialize multi-boot manager
-    let multiboot_manager = MultiBootManager::new(&mut system_table);
+    let multiboot_manager = MultiBootManager::new(&mut system_table, &bootloader_config);
+
+    // Partitions that PARTUUID=/PARTLABEL= entry paths can name
+    display_storage_inventory(&mut system_table, &hardware_info.storage);
 
     // Display multi-boot menu
     let selected_boot_entry =
@@ -237,17 +293,143 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .output_string(cstr16!("Phase 6: Kernel Loading\r\n"))
         .unwrap_or(());
 
-    // Handle multi-boot entry selection (minimal implementation)
+    // Handle multi-boot entry selection
     let entry_id = selected_boot_entry;
-    if let Some(_entry) = multiboot_manager.get_entry_info(entry_id) {
-        // In minimal implementation, just log selection
+    let mut memtest_report = None;
+    if let Some(entry) = multiboot_manager.get_entry_info(entry_id) {
         system_table
             .stdout()
             .output_string(cstr16!("   [INFO] Multi-boot entry selected\r\n"))
             .unwrap_or(());
         log_info("multiboot", "Boot entry processed successfully");
+
+        match entry.entry_type {
+            BootEntryType::UefiApplication | BootEntryType::ChainloadBootloader => {
+                system_table
+                    .stdout()
+                    .output_string(cstr16!("   [INFO] Chainloading signed UEFI image...\r\n"))
+                    .unwrap_or(());
+                match chainload_entry(
+                    image_handle,
+                    &mut system_table,
+                    entry,
+                    security_context.measured_boot_active,
+                ) {
+                    Ok(_) => {
+                        system_table
+                            .stdout()
+                            .output_string(cstr16!(
+                                "   [INFO] Chainloaded image returned, continuing boot\r\n"
+                            ))
+                            .unwrap_or(());
+                    }
+                    Err(_) => {
+                        system_table
+                            .stdout()
+                            .output_string(cstr16!(
+                                "   [ERROR] Chainload failed, continuing with NONOS kernel\r\n"
+                            ))
+                            .unwrap_or(());
+                        log_error("chainload", "Chainload of boot entry failed");
+                    }
+                }
+            }
+            BootEntryType::LinuxKernel => {
+                system_table
+                    .stdout()
+                    .output_string(cstr16!("   [INFO] Booting signed Linux kernel...\r\n"))
+                    .unwrap_or(());
+                // Only returns on failure
+                if let Err(_e) = boot_linux(
+                    image_handle,
+                    &mut system_table,
+                    entry,
+                    security_context.measured_boot_active,
+                ) {
+                    system_table
+                        .stdout()
+                        .output_string(cstr16!(
+                            "   [ERROR] Linux boot failed, continuing with NONOS kernel\r\n"
+                        ))
+                        .unwrap_or(());
+                    log_error("linux", "Linux kernel boot failed");
+                }
+            }
+            BootEntryType::MultibootKernel => {
+                system_table
+                    .stdout()
+                    .output_string(cstr16!("   [INFO] Booting signed Multiboot2 kernel...\r\n"))
+                    .unwrap_or(());
+                // Only returns on failure
+                if let Err(_e) = boot_multiboot2(
+                    image_handle,
+                    &mut system_table,
+                    entry,
+                    security_context.measured_boot_active,
+                ) {
+                    system_table
+                        .stdout()
+                        .output_string(cstr16!(
+                            "   [ERROR] Multiboot2 boot failed, continuing with NONOS kernel\r\n"
+                        ))
+                        .unwrap_or(());
+                    log_error("multiboot2", "Multiboot2 kernel boot failed");
+                }
+            }
+            BootEntryType::MemoryTest => {
+                system_table
+                    .stdout()
+                    .output_string(cstr16!("   [INFO] Running memory test...\r\n"))
+                    .unwrap_or(());
+                match parse_options(entry.command_line_str()) {
+                    Ok(opts) => {
+                        let mut report = run_memory_test(&mut system_table, &opts);
+                        display_memtest_report(&mut system_table, &report);
+                        if opts.halt {
+                            system_table
+                                .stdout()
+                                .output_string(cstr16!(
+                                    "   [INFO] Memory test finished, reset the machine to continue\r\n"
+                                ))
+                                .unwrap_or(());
+                            loop {
+                                spin_us(1_000_000, tsc_hz());
+                            }
+                        }
+                        if opts.handoff {
+                            let reserved = reserve_bad_pages(system_table.boot_services(), &mut report);
+                            log_info(
+                                "memtest",
+                                &alloc::format!("{} of {} bad pages reserved for the kernel", reserved, report.pages.len()),
+                            );
+                            memtest_report = Some(report);
+                        }
+                    }
+                    Err(e) => {
+                        log_error("memtest", &alloc::format!("{}", e));
+                        system_table
+                            .stdout()
+                            .output_string(cstr16!(
+                                "   [ERROR] Bad memory test options, continuing with NONOS kernel\r\n"
+                            ))
+                            .unwrap_or(());
+                    }
+                }
+            }
+            _ => {}
+        }
     }
 
+    // A NONOS entry may name a raw partition holding the capsule instead of the slots
+    let raw_source = match multiboot_manager.get_entry_info(entry_id) {
+        Some(e) if e.entry_type == BootEntryType::NonOsKernel => parse_raw_source(e.path_str()).unwrap_or_else(|err| {
+            log_error("storage", &alloc::format!("Boot entry path {}: {}", e.path_str(), err));
+            None
+        }),
+        _ => None,
+    };
+
+    timeline.begin(boot_phase::VERIFICATION);
     let kernel_capsule = match boot_option {
         NetworkBootOption::Pxe => {
             system_table
@@ -261,14 +443,14 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
                         .stdout()
                         .output_string(cstr16!("   [INFO] Falling back to local boot...\r\n"))
                         .unwrap_or(());
-                    load_kernel_capsule(&mut system_table)
+                    load_slot_capsule(&mut system_table, image_handle)
                 }
                 Err(_) => {
                     system_table
                         .stdout()
                         .output_string(cstr16!("   [WARN] PXE boot failed, trying local...\r\n"))
                         .unwrap_or(());
-                    load_kernel_capsule(&mut system_table)
+                    load_slot_capsule(&mut system_table, image_handle)
                 }
             }
         }
@@ -288,14 +470,14 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
                         .stdout()
                         .output_string(cstr16!("   [INFO] Falling back to local boot...\r\n"))
                         .unwrap_or(());
-                    load_kernel_capsule(&mut system_table)
+                    load_slot_capsule(&mut system_table, image_handle)
                 }
                 Err(_) => {
                     system_table
                         .stdout()
                         .output_string(cstr16!("   [WARN] HTTP boot failed, trying local...\r\n"))
                         .unwrap_or(());
-                    load_kernel_capsule(&mut system_table)
+                    load_slot_capsule(&mut system_table, image_handle)
                 }
             }
         }
@@ -306,7 +488,22 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
                     "   [INFO] Loading kernel from local storage...\r\n"
                 ))
                 .unwrap_or(());
-            load_kernel_capsule(&mut system_table)
+            match &raw_source {
+                Some(src) => match load_partition_capsule(&mut system_table, &hardware_info.storage, src) {
+                    Err(e) if bootloader_config.raw_partition_fallback => {
+                        let msg = alloc::format!("Raw partition boot failed: {}, using kernel slots", e);
+                        Ui::new(&mut system_table).warn(&msg).unwrap_or(());
+                        log_warn("loader", &msg);
+                        load_slot_capsule(&mut system_table, image_handle)
+                    }
+                    Err(e) => {
+                        log_error("loader", &alloc::format!("Raw partition boot failed: {}", e));
+                        Err(e)
+                    }
+                    ok => ok,
+                },
+                None => load_slot_capsule(&mut system_table, image_handle),
+            }
         }
     };
 
@@ -417,6 +614,50 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .stdout()
         .output_string(cstr16!("   [SUCCESS] Entry point validated\r\n"))
         .unwrap_or(());
+    timeline.end(boot_phase::VERIFICATION);
+
+    timeline.begin(boot_phase::LOAD);
+
+    // Boot modules from the signed manifest and the selected NONOS entry
+    let mut module_specs = ModuleList::new();
+    let mut module_specs_ok = match kernel_capsule.manifest() {
+        Some(m) => specs_from_manifest(m, &mut module_specs).is_ok(),
+        None => true,
+    };
+    if let Some(entry) = multiboot_manager.get_entry_info(entry_id) {
+        if entry.entry_type == BootEntryType::NonOsKernel {
+            module_specs_ok &= specs_from_cmdline(entry.command_line_str(), &mut module_specs).is_ok();
+        }
+    }
+    if !module_specs_ok {
+        log_error("modules", "Malformed module list");
+        fatal_reset(&mut system_table, "Malformed module list");
+    }
+    let boot_modules = match load_modules(
+        &mut system_table,
+        image_handle,
+        &module_specs,
+        security_context.measured_boot_active,
+    ) {
+        Ok(m) => m,
+        Err(_) => {
+            system_table
+                .stdout()
+                .output_string(cstr16!("   [ERROR] Boot module verification failed\r\n"))
+                .unwrap_or(());
+            fatal_reset(&mut system_table, "Boot module verification failed");
+        }
+    };
+    if boot_modules.count > 0 {
+        system_table
+            .stdout()
+            .output_string(cstr16!("   [SUCCESS] Boot modules loaded and measured\r\n"))
+            .unwrap_or(());
+        log_info(
+            "modules",
+            &alloc::format!("{} module(s) published to the handoff at 0x{:x}", boot_modules.count, boot_modules.ptr),
+        );
+    }
 
     // Phase 7: Final Handoff Preparation
     system_table
@@ -424,11 +665,39 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
         .output_string(cstr16!("Phase 7: Kernel Handoff\r\n"))
         .unwrap_or(());
 
-    // Convert to kernel function pointer
-    let kernel_entry: KernelEntry = unsafe { core::mem::transmute(kernel_capsule.entry_point) };
+    // Place the verified ELF's segments; the capsule itself was checked above
+    let kernel_image = match load_kernel_elf(&mut system_table, kernel_capsule.payload()) {
+        Ok(k) => k,
+        Err(_) => {
+            log_error("loader", "Kernel segment load failed");
+            fatal_reset(&mut system_table, "Kernel segment load failed");
+        }
+    };
+    timeline.end(boot_phase::LOAD);
 
-    // Prepare enhanced handoff information
-    let handoff_ptr = &kernel_capsule.handoff as *const _;
+    // Kernel command line: selected NONOS entry, else configuration default
+    let cmdline = match multiboot_manager.get_entry_info(entry_id) {
+        Some(e) if e.entry_type == BootEntryType::NonOsKernel && !e.command_line_str().is_empty() => {
+            e.command_line_str()
+        }
+        _ => bootloader_config.kernel_command_line.as_str(),
+    };
+    let handoff_params = HandoffParams {
+        cmdline: if cmdline.is_empty() { None } else { Some(cmdline) },
+        modules: boot_modules,
+        framebuffer,
+        kernel_sig_ok: kernel_capsule.signature_verified,
+        secure_boot: security_context.secure_boot_enabled,
+        measured_boot: security_context.measured_boot_active,
+        pci: &hardware_info.pci,
+        block_devices: &hardware_info.storage.devices,
+        partitions: &hardware_info.storage.partitions,
+        smbios: hardware_info.smbios.as_ref(),
+        cpu: hardware_info.cpu,
+        required_cpu_features: bootloader_config.required_cpu_features,
+        timeline,
+        bad_pages: memtest_report.as_ref(),
+    };
 
     // Save multi-boot preferences
     if multiboot_manager.save_boot_preferences(&mut system_table) {
@@ -523,6 +792,9 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
             .stdout()
             .output_string(cstr16!("   [INFO] Diagnostic output mode active\r\n"))
             .unwrap_or(());
+        display_boot_timing(&mut system_table, &timeline);
+        display_system_identity(&mut system_table, &hardware_info);
+        display_pci_inventory(&mut system_table, &hardware_info.pci);
         if testing_passed {
             system_table
                 .stdout()
@@ -596,76 +868,128 @@ fn efi_main(_handle: Handle, mut system_table: SystemTable<Boot>) -> Status {
 
     log_info("transition", "Transferring control to NØNOS kernel");
 
-    // Exit UEFI boot services
-    let (_runtime_system_table, _memory_map) = system_table.exit_boot_services();
-
-    // IMMEDIATE POST-UEFI SERIAL DEBUG - Raw port access
-    unsafe {
-        // Initialize COM1 directly
-        core::arch::asm!("out dx, al", in("dx") 0x3f9u16, in("al") 0x00u8); // Disable interrupts
-        core::arch::asm!("out dx, al", in("dx") 0x3fbu16, in("al") 0x80u8); // Enable DLAB
-        core::arch::asm!("out dx, al", in("dx") 0x3f8u16, in("al") 0x03u8); // Set divisor low
-        core::arch::asm!("out dx, al", in("dx") 0x3f9u16, in("al") 0x00u8); // Set divisor high
-        core::arch::asm!("out dx, al", in("dx") 0x3fbu16, in("al") 0x03u8); // 8N1
-        core::arch::asm!("out dx, al", in("dx") 0x3fau16, in("al") 0xC7u8); // Enable FIFO
-        core::arch::asm!("out dx, al", in("dx") 0x3fcu16, in("al") 0x0Bu8); // IRQs enabled
-
-        // Send debug message
-        let msg = b"POST-UEFI: About to call kernel!\r\n";
-        for &byte in msg {
-            // Wait for transmit ready
-            loop {
-                let mut status: u8;
-                core::arch::asm!("in al, dx", in("dx") 0x3fdu16, out("al") status);
-                if (status & 0x20) != 0 {
-                    break;
-                }
-            }
-            core::arch::asm!("out dx, al", in("dx") 0x3f8u16, in("al") byte);
-        }
+    // Only returns if the handoff failed before ExitBootServices was called
+    if let Err(e) = exit_and_jump(image_handle, &mut system_table, &kernel_image, &handoff_params) {
+        log_error("handoff", &alloc::format!("{}", e));
     }
+    fatal_reset(&mut system_table, "Kernel handoff failed");
+}
 
-    // Set up proper stack and registers for kernel entry
-    unsafe {
-        // Allocate a small stack for the kernel (16KB should be enough for minimal kernel)
-        let stack_size = 16 * 1024;
-        let stack_ptr = 0x200000 as *mut u8; // 2MB mark - safe area
+/// Per-phase breakdown for diagnostic mode; ExitBootServices is only in the handoff
+fn display_boot_timing(system_table: &mut SystemTable<Boot>, timeline: &BootTimeline) {
+    let mut ui = Ui::new(system_table);
+    ui.section("Boot timing").unwrap_or(());
+    let tsc = &timeline.tsc;
+    let line = alloc::format!(
+        "{}.{:03} MHz via {} (+/-{} ppm)",
+        tsc.hz / 1_000_000, tsc.hz / 1000 % 1000, tsc_method::name(tsc.method), tsc.error_ppm
+    );
+    ui.kv("TSC", &line).unwrap_or(());
+    for p in timeline.completed() {
+        let us = timeline.us(p);
+        let line = alloc::format!("{}.{:03} ms", us / 1000, us % 1000);
+        ui.kv(boot_phase::name(p.phase), &line).unwrap_or(());
+    }
+    let since_entry = ticks_to_us(rdtsc().saturating_sub(timeline.entry_tsc()), timeline.tsc.hz);
+    let line = alloc::format!("{}.{:03} ms", since_entry / 1000, since_entry % 1000);
+    ui.kv("since loader entry", &line).unwrap_or(());
+}
 
-        // Initialize stack with pattern (optional debug aid)
-        core::ptr::write_bytes(stack_ptr, 0xCC, stack_size);
+/// Memory test totals and the first failing pages
+fn display_memtest_report(system_table: &mut SystemTable<Boot>, report: &Report) {
+    const SHOWN: usize = 16;
+    let mut ui = Ui::new(system_table);
+    ui.section("Memory test").unwrap_or(());
+    let s = &report.summary;
+    let line = alloc::format!(
+        "{} MiB x {} pass(es), {} MiB skipped",
+        s.tested_bytes >> 20, s.passes, s.skipped_bytes >> 20
+    );
+    ui.kv("Tested", &line).unwrap_or(());
+    if report.passed() {
+        ui.ok("No errors found").unwrap_or(());
+        return;
+    }
+    ui.fail(&alloc::format!("{} errors on {} pages", s.error_count, report.pages.len())).unwrap_or(());
+    for p in report.pages.iter().take(SHOWN) {
+        let line = alloc::format!("{} errors, bits {:#018x}", p.errors, p.failed_bits);
+        ui.kv(&alloc::format!("{:#014x}", p.phys), &line).unwrap_or(());
+    }
+    if report.pages.len() > SHOWN {
+        ui.info(&alloc::format!("{} more pages not shown", report.pages.len() - SHOWN)).unwrap_or(());
+    }
+}
 
-        // Calculate stack top (stack grows downward)
-        let stack_top = stack_ptr.add(stack_size);
+/// SMBIOS identity and populated DIMM slots, for diagnostic mode
+fn display_system_identity(system_table: &mut SystemTable<Boot>, hardware: &HardwareInfo) {
+    let mut ui = Ui::new(system_table);
+    ui.section("System identity").unwrap_or(());
+    let Some(inv) = &hardware.smbios else {
+        ui.kv("SMBIOS", "not available").unwrap_or(());
+        return;
+    };
+    let id = &inv.identity;
+    ui.kv("SMBIOS", &alloc::format!("{}.{}", id.major, id.minor)).unwrap_or(());
+    ui.kv("Manufacturer", text(&id.manufacturer)).unwrap_or(());
+    ui.kv("Product", text(&id.product)).unwrap_or(());
+    ui.kv("Serial", text(&id.serial)).unwrap_or(());
+    let uuid = hardware.machine_uuid().map_or_else(|| "not set".into(), |u| format_uuid(&u));
+    ui.kv("UUID", &uuid).unwrap_or(());
+    ui.kv("BIOS", &alloc::format!("{} {}", text(&id.bios_vendor), text(&id.bios_version))).unwrap_or(());
+    for d in inv.memory.iter().filter(|d| d.size != 0) {
+        let line = alloc::format!("{} MiB {} MT/s {} {}", d.size >> 20, d.speed_mts, text(&d.manufacturer), text(&d.part_number));
+        ui.kv(text(&d.locator), &line).unwrap_or(());
+    }
+}
 
-        // DEBUG: Send message right before kernel call
-        let msg2 = b"CALLING KERNEL NOW!\r\n";
-        for &byte in msg2 {
-            loop {
-                let mut status: u8;
-                core::arch::asm!("in al, dx", in("dx") 0x3fdu16, out("al") status);
-                if (status & 0x20) != 0 {
-                    break;
-                }
-            }
-            core::arch::asm!("out dx, al", in("dx") 0x3f8u16, in("al") byte);
+/// PCI functions with their class and assigned BARs, for diagnostic mode
+fn display_pci_inventory(system_table: &mut SystemTable<Boot>, functions: &[PciFunction]) {
+    let mut ui = Ui::new(system_table);
+    ui.section("PCI devices").unwrap_or(());
+    for d in functions {
+        let addr = alloc::format!("{:04x}:{:02x}:{:02x}.{}", d.segment, d.bus, d.device, d.function);
+        let mut line = alloc::format!("[{:04x}:{:04x}] {}", d.vendor_id, d.device_id, class_name(d));
+        for (i, bar) in d.bars.iter().enumerate().filter(|(_, b)| b.size != 0) {
+            let kind = if bar.flags & pci_bar_flags::IO != 0 { "io" } else { "mem" };
+            line.push_str(&alloc::format!(" bar{}={}@{:#x}+{:#x}", i, kind, bar.base, bar.size));
         }
+        ui.kv(&addr, &line).unwrap_or(());
+    }
+    if functions.is_empty() {
+        ui.kv("PCI", "no functions found").unwrap_or(());
+    }
+}
 
-        // Jump to kernel with proper stack setup
-        core::arch::asm!(
-            "mov rsp, {stack_top}",      // Set up stack pointer
-            "push rbp",                   // Save frame pointer
-            "mov rbp, rsp",              // Set up frame pointer
-            "call {kernel_entry}",       // Call kernel
-            stack_top = in(reg) stack_top,
-            kernel_entry = in(reg) kernel_entry as usize,
-            in("rdi") handoff_ptr,       // First argument in System V ABI
-            options(noreturn)
-        );
+fn display_storage_inventory(system_table: &mut SystemTable<Boot>, inv: &storage::Inventory) {
+    let mut ui = Ui::new(system_table);
+    ui.section("Storage").unwrap_or(());
+    for (i, d) in inv.devices.iter().enumerate() {
+        let path = core::str::from_utf8(&d.device_path).unwrap_or("").trim_end_matches('\0');
+        let removable = if d.flags & block_flags::REMOVABLE != 0 { ", removable" } else { "" };
+        let gpt = match (d.gpt_flags & gpt_flags::PRIMARY_OK != 0, d.gpt_flags & gpt_flags::BACKUP_OK != 0) {
+            (true, true) => ", GPT",
+            (true, false) => ", GPT (backup bad)",
+            (false, true) => ", GPT (primary bad)",
+            (false, false) => "",
+        };
+        let line = alloc::format!("{} MiB{}{} {}", d.size() >> 20, removable, gpt, path);
+        ui.kv(&alloc::format!("disk{}", i), &line).unwrap_or(());
+    }
+    for p in &inv.partitions {
+        let size = match inv.devices.get(p.device as usize) {
+            Some(d) => alloc::format!("{} MiB", (p.blocks() * d.block_size as u64) >> 20),
+            None => alloc::string::String::from("?"),
+        };
+        let line = alloc::format!("'{}' {} ({})", partition_name(p), format_guid(&p.unique_guid), size);
+        ui.kv(&alloc::format!("disk{}p{}", p.device, p.number), &line).unwrap_or(());
+    }
+    if inv.devices.is_empty() {
+        ui.kv("Storage", "no block devices found").unwrap_or(());
     }
 }
 
-/// Initialize graphics mode for better user experience
-fn initialize_graphics(system_table: &mut SystemTable<Boot>) {
+/// Initialize graphics mode for better user experience; returns the active framebuffer
+fn initialize_graphics(system_table: &mut SystemTable<Boot>) -> Option<FramebufferInfo> {
     // Try to find graphics protocol handles
     let graphics_initialized = {
         let bs = system_table.boot_services();
@@ -715,6 +1039,13 @@ fn initialize_graphics(system_table: &mut SystemTable<Boot>) {
             .unwrap_or(());
         log_warn("graphics", "No graphics support available");
     }
+
+    // Read back the mode now in effect (the exclusive GOP handle above is closed)
+    if graphics_initialized > 0 {
+        query_framebuffer(system_table)
+    } else {
+        None
+    }
 }
 
 /// Find the best available graphics mode (preferably high resolution)
@@ -0,0 +1,491 @@
+//! Built-in memory test (`BootEntryType::MemoryTest`).
+//!
+//! Runs walking-ones, moving-inversions and address-in-address patterns over
+//! the EfiConventionalMemory ranges of the UEFI memory map. Each range is
+//! claimed with AllocatePages at its own address in chunks of `CHUNK_PAGES`,
+//! tested and released again, so nothing the firmware or the loader owns is
+//! touched. A chunk firmware refuses (usually because the loader's own pool
+//! grew into it after the map was read) is counted as skipped, not tested.
+//!
+//! Options come from the entry's command line, whitespace separated:
+//!
+//! ```text
+//!   passes=N        repeat every pattern N times (1..=16, default 1)
+//!   tests=LIST      comma separated subset of walk,inv,addr (default all)
+//!   max-mb=N        stop after N MiB of usable memory
+//!   handoff         reserve failing pages and hand them to the kernel
+//!   halt            stop after the report instead of booting
+//! ```
+//!
+//! With `handoff` every failing page is re-allocated as EfiUnusableMemory,
+//! which the NONOS memory map reports as `region_kind::BAD`, and the list
+//! goes into `tag::BAD_PAGES` (layout: `nonos_handoff::memtest`).
+
+#![allow(dead_code)]
+
+use crate::log::logger::{log_error, log_info, log_warn};
+use alloc::format;
+use alloc::vec::Vec;
+use core::fmt;
+use nonos_handoff::memtest::{bad_page_flags, bad_pages_flags, memtest_test, BadPage, BadPagesTag};
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType};
+
+const PAGE_SIZE: u64 = 0x1000;
+/// Pages claimed and tested at a time (64 MiB)
+const CHUNK_PAGES: u64 = 0x4000;
+/// Watchdog the UEFI spec has firmware arm before a boot option, restored after the test
+const WATCHDOG_SECS: usize = 300;
+/// Most failing pages the handoff list carries; further failures are only counted
+pub const MAX_BAD_PAGES: usize = 1024;
+/// Mismatches kept with their values for the report; the rest are only counted
+pub const MAX_MISMATCHES: usize = 32;
+pub const MAX_PASSES: u32 = 16;
+
+/// Memory test settings from the boot entry's command line (see module docs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemtestOptions {
+    pub passes: u32,
+    /// `memtest_test` bits
+    pub tests: u16,
+    /// Bytes of usable memory to test, 0 for all of it
+    pub limit: u64,
+    pub handoff: bool,
+    pub halt: bool,
+}
+
+impl Default for MemtestOptions {
+    fn default() -> Self {
+        MemtestOptions { passes: 1, tests: memtest_test::ALL, limit: 0, handoff: false, halt: false }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemtestError<'a> {
+    UnknownOption(&'a str),
+    BadValue(&'a str),
+}
+
+impl fmt::Display for MemtestError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MemtestError::UnknownOption(t) => write!(f, "unknown memory test option '{}'", t),
+            MemtestError::BadValue(t) => write!(f, "bad value in memory test option '{}'", t),
+        }
+    }
+}
+
+/// Parse the entry command line; an empty line means the defaults
+pub fn parse_options(cmdline: &str) -> Result<MemtestOptions, MemtestError<'_>> {
+    let mut opts = MemtestOptions::default();
+    for token in cmdline.split_whitespace() {
+        let (key, value) = token.split_once('=').unwrap_or((token, ""));
+        match (key, value.is_empty()) {
+            ("handoff", true) => opts.handoff = true,
+            ("halt", true) => opts.halt = true,
+            ("passes", false) => {
+                opts.passes = value.parse().ok().filter(|n| (1..=MAX_PASSES).contains(n)).ok_or(MemtestError::BadValue(token))?;
+            }
+            ("max-mb", false) => {
+                let mb: u64 = value.parse().ok().filter(|&n| n != 0).ok_or(MemtestError::BadValue(token))?;
+                opts.limit = mb.checked_mul(1 << 20).ok_or(MemtestError::BadValue(token))?;
+            }
+            ("tests", false) => {
+                opts.tests = 0;
+                for name in value.split(',') {
+                    opts.tests |= match name {
+                        "walk" => memtest_test::WALKING_ONES,
+                        "inv" => memtest_test::MOVING_INVERSIONS,
+                        "addr" => memtest_test::ADDRESS,
+                        _ => return Err(MemtestError::BadValue(token)),
+                    };
+                }
+            }
+            _ => return Err(MemtestError::UnknownOption(token)),
+        }
+    }
+    Ok(opts)
+}
+
+/// Memory under test, one 64-bit word at a time
+pub trait Words {
+    fn words(&self) -> usize;
+    /// Physical address of word `i`
+    fn addr(&self, i: usize) -> u64;
+    fn read(&self, i: usize) -> u64;
+    fn write(&mut self, i: usize, value: u64);
+}
+
+/// A claimed, identity-mapped physical range
+struct PhysRange {
+    base: u64,
+    words: usize,
+}
+
+impl Words for PhysRange {
+    fn words(&self) -> usize {
+        self.words
+    }
+
+    fn addr(&self, i: usize) -> u64 {
+        self.base + i as u64 * 8
+    }
+
+    fn read(&self, i: usize) -> u64 {
+        // safe: `run` allocated the range for us and UEFI identity maps it; i < words
+        unsafe { core::ptr::read_volatile(self.addr(i) as *const u64) }
+    }
+
+    fn write(&mut self, i: usize, value: u64) {
+        // safe: as in `read`
+        unsafe { core::ptr::write_volatile(self.addr(i) as *mut u64, value) }
+    }
+}
+
+/// One word that read back wrong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    pub addr: u64,
+    pub expected: u64,
+    pub actual: u64,
+    /// `memtest_test` bit of the pattern
+    pub test: u16,
+}
+
+/// Results across all passes; `summary` and `pages` are the `tag::BAD_PAGES` payload
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub summary: BadPagesTag,
+    /// Failing pages sorted by address, at most `MAX_BAD_PAGES`
+    pub pages: Vec<BadPage>,
+    /// The first `MAX_MISMATCHES` mismatches
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl Report {
+    pub fn new(opts: &MemtestOptions) -> Self {
+        Report {
+            summary: BadPagesTag { passes: opts.passes, tests: opts.tests, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    pub fn passed(&self) -> bool {
+        self.summary.error_count == 0
+    }
+
+    fn record(&mut self, m: Mismatch) {
+        self.summary.error_count += 1;
+        if self.mismatches.len() < MAX_MISMATCHES {
+            self.mismatches.push(m);
+        }
+        let phys = m.addr & !(PAGE_SIZE - 1);
+        let i = match self.pages.binary_search_by_key(&phys, |p| p.phys) {
+            Ok(i) => i,
+            Err(_) if self.pages.len() == MAX_BAD_PAGES => {
+                self.summary.flags |= bad_pages_flags::TRUNCATED;
+                return;
+            }
+            Err(i) => {
+                self.pages.insert(i, BadPage { phys, ..Default::default() });
+                self.summary.page_count = self.pages.len() as u32;
+                i
+            }
+        };
+        let page = &mut self.pages[i];
+        page.failed_bits |= m.expected ^ m.actual;
+        page.errors = page.errors.saturating_add(1);
+        page.tests |= m.test;
+    }
+}
+
+fn verify<W: Words>(w: &W, i: usize, expected: u64, test: u16, report: &mut Report) {
+    let actual = w.read(i);
+    if actual != expected {
+        report.record(Mismatch { addr: w.addr(i), expected, actual, test });
+    }
+}
+
+/// A single set bit rotated through every word, once per bit position, so
+/// each data line is seen high with all its neighbours low
+pub fn walking_ones<W: Words>(w: &mut W, report: &mut Report) {
+    let pattern = |i: usize, shift: u32| 1u64.rotate_left((i as u32).wrapping_add(shift));
+    for shift in 0..64 {
+        for i in 0..w.words() {
+            w.write(i, pattern(i, shift));
+        }
+        for i in 0..w.words() {
+            verify(w, i, pattern(i, shift), memtest_test::WALKING_ONES, report);
+        }
+    }
+}
+
+/// Fill with `p`; ascending, check `p` and write `!p`; descending, check `!p`
+/// and write `p`; check `p`. Catches cells disturbed by writes to their
+/// neighbours on either side. Runs with all-zeros and 0x55.. as `p`.
+pub fn moving_inversions<W: Words>(w: &mut W, report: &mut Report) {
+    const TEST: u16 = memtest_test::MOVING_INVERSIONS;
+    for p in [0, 0x5555_5555_5555_5555] {
+        for i in 0..w.words() {
+            w.write(i, p);
+        }
+        for i in 0..w.words() {
+            verify(w, i, p, TEST, report);
+            w.write(i, !p);
+        }
+        for i in (0..w.words()).rev() {
+            verify(w, i, !p, TEST, report);
+            w.write(i, p);
+        }
+        for i in 0..w.words() {
+            verify(w, i, p, TEST, report);
+        }
+    }
+}
+
+/// Every word holds its own address, then its complement; a stuck or shorted
+/// address line makes two words share a cell and one of them reads back wrong
+pub fn address_in_address<W: Words>(w: &mut W, report: &mut Report) {
+    for invert in [0, !0u64] {
+        for i in 0..w.words() {
+            w.write(i, w.addr(i) ^ invert);
+        }
+        for i in 0..w.words() {
+            verify(w, i, w.addr(i) ^ invert, memtest_test::ADDRESS, report);
+        }
+    }
+}
+
+/// The patterns selected by `tests`, in a fixed order
+pub fn run_patterns<W: Words>(w: &mut W, tests: u16, report: &mut Report) {
+    if tests & memtest_test::WALKING_ONES != 0 {
+        walking_ones(w, report);
+    }
+    if tests & memtest_test::MOVING_INVERSIONS != 0 {
+        moving_inversions(w, report);
+    }
+    if tests & memtest_test::ADDRESS != 0 {
+        address_in_address(w, report);
+    }
+}
+
+/// EfiConventionalMemory ranges as (base, pages), at most `limit` bytes
+/// (0 = no limit). Page 0 is left out.
+fn usable_ranges(bs: &BootServices, limit: u64) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let sizes = bs.memory_map_size();
+    let buf_size = sizes.map_size + sizes.entry_size * 8;
+    let pages = buf_size.div_ceil(PAGE_SIZE as usize);
+    let Ok(ptr) = bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages) else {
+        return ranges;
+    };
+    // safe: just allocated, buf_size bytes fit in `pages` pages
+    let buf = unsafe { core::slice::from_raw_parts_mut(ptr as *mut u8, buf_size) };
+    if let Ok(map) = bs.memory_map(buf) {
+        let mut left = if limit == 0 { u64::MAX } else { limit / PAGE_SIZE };
+        for d in map.entries().filter(|d| d.ty == MemoryType::CONVENTIONAL) {
+            let (mut base, mut count) = (d.phys_start, d.page_count);
+            if base == 0 && count > 0 {
+                base += PAGE_SIZE;
+                count -= 1;
+            }
+            let count = count.min(left);
+            if count == 0 {
+                continue;
+            }
+            ranges.push((base, count));
+            left -= count;
+        }
+    }
+    let _ = bs.free_pages(ptr, pages);
+    ranges.sort_unstable();
+    ranges
+}
+
+/// Run the test with boot services up. Returns after every pass; the
+/// memory is free again except for the page-sized map buffer.
+pub fn run(st: &mut SystemTable<Boot>, opts: &MemtestOptions) -> Report {
+    let bs = st.boot_services();
+    let ranges = usable_ranges(bs, opts.limit);
+    let total: u64 = ranges.iter().map(|r| r.1 * PAGE_SIZE).sum();
+    log_info("memtest", &format!("Testing {} MiB in {} ranges, {} pass(es)", total >> 20, ranges.len(), opts.passes));
+    // firmware arms a five minute watchdog before starting a boot option
+    let watchdog_off = bs.set_watchdog_timer(0, 0x10000, None).is_ok();
+    if !watchdog_off {
+        log_warn("memtest", "Could not disable the watchdog timer");
+    }
+
+    let mut report = Report::new(opts);
+    for pass in 0..opts.passes {
+        let errors_before = report.summary.error_count;
+        let (mut tested, mut skipped) = (0u64, 0u64);
+        for &(base, pages) in &ranges {
+            let end = base + pages * PAGE_SIZE;
+            let mut at = base;
+            while at < end {
+                let n = ((end - at) / PAGE_SIZE).min(CHUNK_PAGES);
+                let bytes = n * PAGE_SIZE;
+                match bs.allocate_pages(AllocateType::Address(at), MemoryType::LOADER_DATA, n as usize) {
+                    Ok(_) => {
+                        let mut w = PhysRange { base: at, words: (bytes / 8) as usize };
+                        run_patterns(&mut w, opts.tests, &mut report);
+                        let _ = bs.free_pages(at, n as usize);
+                        tested += bytes;
+                    }
+                    Err(_) => skipped += bytes,
+                }
+                at += bytes;
+            }
+        }
+        if pass == 0 {
+            report.summary.tested_bytes = tested;
+            report.summary.skipped_bytes = skipped;
+        }
+        log_info(
+            "memtest",
+            &format!("Pass {}/{}: {} MiB tested, {} errors", pass + 1, opts.passes, tested >> 20, report.summary.error_count - errors_before),
+        );
+    }
+
+    for m in &report.mismatches {
+        log_error("memtest", &format!("{:#014x}: expected {:#018x} read {:#018x}", m.addr, m.expected, m.actual));
+    }
+    if report.summary.flags & bad_pages_flags::TRUNCATED != 0 {
+        log_warn("memtest", &format!("More than {} bad pages; list truncated", MAX_BAD_PAGES));
+    }
+    // the boot continues under the firmware's usual watchdog; `halt` waits for a manual reset
+    if watchdog_off && !opts.halt && bs.set_watchdog_timer(WATCHDOG_SECS, 0x10000, None).is_err() {
+        log_warn("memtest", "Could not re-arm the watchdog timer");
+    }
+    report
+}
+
+/// Allocate every failing page as EfiUnusableMemory so no later allocation,
+/// ours or the kernel's, lands on it. Returns how many were reserved.
+pub fn reserve_bad_pages(bs: &BootServices, report: &mut Report) -> usize {
+    let mut reserved = 0;
+    for p in report.pages.iter_mut() {
+        match bs.allocate_pages(AllocateType::Address(p.phys), MemoryType::UNUSABLE, 1) {
+            Ok(_) => {
+                p.flags |= bad_page_flags::RESERVED;
+                reserved += 1;
+            }
+            Err(e) => log_warn("memtest", &format!("Could not reserve bad page {:#x}: {:?}", p.phys, e.status())),
+        }
+    }
+    reserved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Host memory with an optional stuck-at-one mask on one word and an
+    /// optional pair of words that share a cell
+    struct Faulty {
+        base: u64,
+        cells: Vec<u64>,
+        stuck: Option<(usize, u64)>,
+        alias: Option<(usize, usize)>,
+    }
+
+    impl Faulty {
+        fn new(base: u64, words: usize) -> Self {
+            Faulty { base, cells: alloc::vec![0; words], stuck: None, alias: None }
+        }
+
+        fn cell(&self, i: usize) -> usize {
+            match self.alias {
+                Some((a, b)) if i == a => b,
+                _ => i,
+            }
+        }
+    }
+
+    impl Words for Faulty {
+        fn words(&self) -> usize {
+            self.cells.len()
+        }
+
+        fn addr(&self, i: usize) -> u64 {
+            self.base + i as u64 * 8
+        }
+
+        fn read(&self, i: usize) -> u64 {
+            let v = self.cells[self.cell(i)];
+            match self.stuck {
+                Some((j, mask)) if j == i => v | mask,
+                _ => v,
+            }
+        }
+
+        fn write(&mut self, i: usize, value: u64) {
+            let c = self.cell(i);
+            self.cells[c] = value;
+        }
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(parse_options(""), Ok(MemtestOptions::default()));
+        let o = parse_options("passes=3 tests=inv,addr max-mb=512 handoff halt").unwrap();
+        assert_eq!(o.passes, 3);
+        assert_eq!(o.tests, memtest_test::MOVING_INVERSIONS | memtest_test::ADDRESS);
+        assert_eq!(o.limit, 512 << 20);
+        assert!(o.handoff && o.halt);
+        assert_eq!(parse_options("passes=0"), Err(MemtestError::BadValue("passes=0")));
+        assert_eq!(parse_options("tests=walk,rows"), Err(MemtestError::BadValue("tests=walk,rows")));
+        assert_eq!(parse_options("handoff=1"), Err(MemtestError::UnknownOption("handoff=1")));
+        assert_eq!(parse_options("quick"), Err(MemtestError::UnknownOption("quick")));
+    }
+
+    #[test]
+    fn stuck_bit_is_caught_by_every_pattern() {
+        let opts = MemtestOptions::default();
+        let mut good = Faulty::new(0x10_0000, 1024);
+        let mut report = Report::new(&opts);
+        run_patterns(&mut good, opts.tests, &mut report);
+        assert!(report.passed());
+
+        // word 600 is on the second page
+        let mut bad = Faulty::new(0x10_0000, 1024);
+        bad.stuck = Some((600, 1 << 17));
+        let mut report = Report::new(&opts);
+        run_patterns(&mut bad, opts.tests, &mut report);
+        assert!(!report.passed());
+        assert_eq!(report.pages.len(), 1);
+        let page = report.pages[0];
+        assert_eq!(page.phys, 0x10_1000);
+        assert_eq!(page.failed_bits, 1 << 17);
+        assert_eq!(page.tests, memtest_test::ALL);
+        assert_eq!(u64::from(page.errors), report.summary.error_count);
+        assert_eq!(report.mismatches[0].addr, 0x10_0000 + 600 * 8);
+    }
+
+    #[test]
+    fn aliased_words_fail_address_test() {
+        let mut mem = Faulty::new(0x20_0000, 2048);
+        mem.alias = Some((10, 1034));
+        let mut report = Report::new(&MemtestOptions::default());
+        address_in_address(&mut mem, &mut report);
+        assert_eq!(report.summary.page_count, 1);
+        assert_eq!(report.pages[0].phys, 0x20_0000);
+        assert_eq!(report.pages[0].tests, memtest_test::ADDRESS);
+    }
+
+    #[test]
+    fn bad_page_list_is_sorted_and_capped() {
+        let mut report = Report::new(&MemtestOptions::default());
+        let m = |addr| Mismatch { addr, expected: 0, actual: 1, test: memtest_test::ADDRESS };
+        for page in (0..MAX_BAD_PAGES as u64).rev().chain(MAX_BAD_PAGES as u64..MAX_BAD_PAGES as u64 + 8) {
+            report.record(m(page * PAGE_SIZE + 8));
+        }
+        report.record(m(0x10));
+        assert_eq!(report.pages.len(), MAX_BAD_PAGES);
+        assert!(report.pages.windows(2).all(|w| w[0].phys < w[1].phys));
+        assert_eq!(report.pages[0].errors, 2);
+        assert_eq!(report.summary.flags, bad_pages_flags::TRUNCATED);
+        assert_eq!(report.summary.error_count, MAX_BAD_PAGES as u64 + 9);
+        assert_eq!(report.mismatches.len(), MAX_MISMATCHES);
+    }
+}
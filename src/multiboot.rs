@@ -1,16 +1,62 @@
 //! Multi-Boot Support for NØNOS Bootloader (Minimal UEFI-Compatible)
 //!
 //! This module provides minimal multi-boot capabilities without heap allocation
+//!
+//! Boot entries are read from `NonosBootEntry0000`..`NonosBootEntry000F` UEFI
+//! variables under `config::NONOS_VENDOR`. Each variable holds one entry:
+//!
+//! ```text
+//!   u8  entry_type   (BootEntryType discriminant)
+//!   u8  flags        (bit0 = enabled, bit1 = default)
+//!   u16 reserved
+//!   name\0 path\0 command_line\0   (ASCII, each truncated to its buffer)
+//! ```
+//!
+//! A `MemoryTest` entry needs no path; its command line holds the memory
+//! test options (see `memtest`). Unless a variable defines one, the menu also
+//! offers a built-in `Memory test` entry (`MEMTEST_ENTRY_ID`) that runs every
+//! pattern once and hands the bad pages to the kernel. It is only listed when
+//! other entries are configured or diagnostic output is on, so a machine
+//! without entries still boots the default without a menu wait.
+//!
+//! Paths may start with `PARTUUID=<guid>:` or `PARTLABEL=<name>:` to read
+//! from a GPT partition other than the boot volume (see `storage`).
+//!
+//! A `NonOsKernel` entry whose path is `PARTUUID=<guid>` (no file part) or
+//! `PARTTYPE=<guid|nonos>` boots a capsule written straight to that raw
+//! partition and falls back to the kernel slots if none verifies.
 
 #![allow(dead_code)]
 
-use crate::config::BootloaderConfig;
-use crate::log::logger::{log_debug, log_info};
+use crate::config::{BootloaderConfig, NONOS_VENDOR};
+use crate::log::logger::{log_debug, log_info, log_warn};
+use crate::timing::{spin_us, tsc_hz, Deadline};
+use crate::ui::Ui;
+use alloc::format;
+use heapless::Vec as FixedVec;
 use uefi::cstr16;
 use uefi::prelude::*;
+use uefi::proto::console::text::Key;
+use uefi::CStr16;
+
+/// Maximum number of boot entries read from variables
+pub const MAX_BOOT_ENTRIES: usize = 16;
+/// Variable entries plus the built-in memory test
+const MAX_MENU_ENTRIES: usize = MAX_BOOT_ENTRIES + 1;
+/// Id of the built-in memory test entry, outside the variable range
+pub const MEMTEST_ENTRY_ID: u32 = 0x100;
+
+/// Selection keys for the listed entries, in order
+const MENU_KEYS: &[u8] = b"123456789abcdefgh";
+/// Keyboard poll interval while the menu waits
+const MENU_POLL_US: u64 = 20_000;
+
+/// Boot entry flag bits (variable encoding)
+pub const ENTRY_FLAG_ENABLED: u8 = 1 << 0;
+pub const ENTRY_FLAG_DEFAULT: u8 = 1 << 1;
 
 /// Boot entry types supported by the multi-boot system
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BootEntryType {
     NonOsKernel,         // NØNOS native kernel
     UefiApplication,     // UEFI application
@@ -18,6 +64,7 @@ pub enum BootEntryType {
     LinuxKernel,         // Linux kernel with boot protocol
     ChainloadBootloader, // Chainload another bootloader
     RecoveryMode,        // Emergency recovery boot
+    MemoryTest,          // Built-in memory diagnostic
 }
 
 /// Boot entry information (stack-based, no heap allocation)
@@ -52,6 +99,81 @@ impl Default for BootEntry {
     }
 }
 
+impl BootEntryType {
+    /// Decode the entry type byte used in `NonosBootEntryXXXX` variables
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            0 => Some(BootEntryType::NonOsKernel),
+            1 => Some(BootEntryType::UefiApplication),
+            2 => Some(BootEntryType::MultibootKernel),
+            3 => Some(BootEntryType::LinuxKernel),
+            4 => Some(BootEntryType::ChainloadBootloader),
+            5 => Some(BootEntryType::RecoveryMode),
+            6 => Some(BootEntryType::MemoryTest),
+            _ => None,
+        }
+    }
+}
+
+impl BootEntry {
+    /// Parse an entry from its UEFI variable payload (see module docs)
+    pub fn from_variable(id: u32, data: &[u8]) -> Option<Self> {
+        if data.len() < 4 {
+            return None;
+        }
+        let mut entry = BootEntry {
+            id,
+            entry_type: BootEntryType::from_u8(data[0])?,
+            enabled: data[1] & ENTRY_FLAG_ENABLED != 0,
+            default: data[1] & ENTRY_FLAG_DEFAULT != 0,
+            ..Default::default()
+        };
+
+        let mut fields = data[4..].split(|&b| b == 0);
+        copy_field(&mut entry.name, fields.next()?);
+        copy_field(&mut entry.path, fields.next()?);
+        copy_field(&mut entry.command_line, fields.next().unwrap_or(&[]));
+
+        if entry.path[0] == 0 && entry.entry_type != BootEntryType::MemoryTest {
+            return None;
+        }
+        Some(entry)
+    }
+
+    /// The built-in memory test: default patterns and passes, bad pages handed off
+    pub fn builtin_memtest() -> Self {
+        let mut entry = BootEntry { id: MEMTEST_ENTRY_ID, entry_type: BootEntryType::MemoryTest, ..Default::default() };
+        copy_field(&mut entry.name, b"Memory test");
+        copy_field(&mut entry.command_line, b"handoff");
+        entry
+    }
+
+    pub fn name_str(&self) -> &str {
+        field_str(&self.name)
+    }
+
+    pub fn path_str(&self) -> &str {
+        field_str(&self.path)
+    }
+
+    pub fn command_line_str(&self) -> &str {
+        field_str(&self.command_line)
+    }
+}
+
+/// Copy an ASCII field into a fixed buffer, keeping a trailing NUL
+fn copy_field(dst: &mut [u8], src: &[u8]) {
+    let n = src.len().min(dst.len() - 1);
+    dst[..n].copy_from_slice(&src[..n]);
+    dst[n..].fill(0);
+}
+
+/// View a NUL-terminated fixed buffer as `&str` (empty on invalid UTF-8)
+fn field_str(buf: &[u8]) -> &str {
+    let end = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    core::str::from_utf8(&buf[..end]).unwrap_or("")
+}
+
 /// Multi-boot manager structure (minimal implementation)
 #[derive(Debug)]
 pub struct MultiBootManager {
@@ -59,6 +181,7 @@ pub struct MultiBootManager {
     pub boot_timeout: u32,
     pub last_selected_entry: Option<u32>,
     pub recovery_mode_available: bool,
+    pub entries: FixedVec<BootEntry, MAX_MENU_ENTRIES>,
 }
 
 impl Default for MultiBootManager {
@@ -68,14 +191,20 @@ impl Default for MultiBootManager {
             boot_timeout: 10,
             last_selected_entry: None,
             recovery_mode_available: false,
+            entries: FixedVec::new(),
         }
     }
 }
 
 impl MultiBootManager {
     /// Create new multi-boot manager (minimal implementation)
-    pub fn new(system_table: &mut SystemTable<Boot>) -> Self {
-        let manager = Self::default();
+    pub fn new(system_table: &mut SystemTable<Boot>, config: &BootloaderConfig) -> Self {
+        let mut manager = Self::default();
+        let loaded = manager.load_boot_entries(system_table);
+        let has_memtest = manager.entries.iter().any(|e| e.entry_type == BootEntryType::MemoryTest);
+        if (loaded > 0 || config.diagnostic_output) && !has_memtest {
+            manager.add_entry(BootEntry::builtin_memtest());
+        }
 
         system_table
             .stdout()
@@ -92,22 +221,114 @@ impl MultiBootManager {
             .output_string(cstr16!("=========================================\r\n"))
             .unwrap_or(());
 
-        log_info("multiboot", "Multi-boot system initialized");
+        log_info(
+            "multiboot",
+            &format!("Multi-boot system initialized ({} entries)", loaded),
+        );
         manager
     }
 
-    /// Display boot menu (minimal implementation - returns default)
+    /// Register a boot entry; returns false when the table is full
+    pub fn add_entry(&mut self, entry: BootEntry) -> bool {
+        if entry.default && self.default_entry_id.is_none() {
+            self.default_entry_id = Some(entry.id);
+        }
+        self.entries.push(entry).is_ok()
+    }
+
+    /// Read `NonosBootEntry0000`..`NonosBootEntry000F` and register valid entries
+    pub fn load_boot_entries(&mut self, system_table: &mut SystemTable<Boot>) -> usize {
+        let rt = system_table.runtime_services();
+        let mut loaded = 0;
+
+        for id in 0..MAX_BOOT_ENTRIES as u32 {
+            let mut name_buf = [0u16; 24];
+            let name = match CStr16::from_str_with_buf(
+                &format!("NonosBootEntry{:04X}", id),
+                &mut name_buf,
+            ) {
+                Ok(n) => n,
+                Err(_) => continue,
+            };
+
+            let mut data = [0u8; 4 + 64 + 256 + 256];
+            let payload = match rt.get_variable(name, &NONOS_VENDOR, &mut data) {
+                Ok((payload, _)) => payload,
+                Err(_) => continue,
+            };
+
+            match BootEntry::from_variable(id, payload) {
+                Some(entry) => {
+                    log_debug("multiboot", &format!("Boot entry {} loaded", id));
+                    if self.add_entry(entry) {
+                        loaded += 1;
+                    }
+                }
+                None => log_warn("multiboot", &format!("Boot entry {} malformed", id)),
+            }
+        }
+
+        loaded
+    }
+
+    /// List the entries and wait for a selection key; Enter boots the
+    /// default. With auto boot on, the default also boots once
+    /// `boot_timeout_seconds` pass on the calibrated clock; with it off the
+    /// menu waits for a key. Returns the selected entry id.
     pub fn display_boot_menu(
         &self,
         system_table: &mut SystemTable<Boot>,
-        _config: &BootloaderConfig,
+        config: &BootloaderConfig,
     ) -> u32 {
-        system_table
-            .stdout()
-            .output_string(cstr16!("   [INFO] Using default boot entry\r\n"))
-            .unwrap_or(());
+        let default = self.default_entry_id.unwrap_or(0);
+        let listed = self.entries.iter().filter(|e| e.enabled).take(MENU_KEYS.len());
+        if listed.clone().next().is_none() || (config.auto_boot_enabled && config.boot_timeout_seconds == 0) {
+            system_table
+                .stdout()
+                .output_string(cstr16!("   [INFO] Using default boot entry\r\n"))
+                .unwrap_or(());
+            log_info("multiboot", "Default boot entry selected");
+            return default;
+        }
+
+        {
+            let mut ui = Ui::new(system_table);
+            ui.section("Boot menu").unwrap_or(());
+            let default_name = self.get_entry_info(default).map_or("NONOS kernel", |e| e.name_str());
+            ui.kv("Enter", default_name).unwrap_or(());
+            for (key, entry) in MENU_KEYS.iter().zip(listed.clone()) {
+                let key = [*key];
+                ui.kv(core::str::from_utf8(&key).unwrap_or("?"), entry.name_str()).unwrap_or(());
+            }
+            if config.auto_boot_enabled {
+                ui.info(&format!("Booting the default in {} s", config.boot_timeout_seconds)).unwrap_or(());
+            } else {
+                ui.info("Auto boot is off; waiting for a selection").unwrap_or(());
+            }
+        }
+
+        let deadline = config
+            .auto_boot_enabled
+            .then(|| Deadline::after_us(config.boot_timeout_seconds as u64 * 1_000_000));
+        while !deadline.map_or(false, |d| d.expired()) {
+            match system_table.stdin().read_key() {
+                Ok(Some(Key::Printable(c))) => {
+                    let c = char::from(c);
+                    if c == '\r' {
+                        break;
+                    }
+                    let picked = MENU_KEYS.iter().position(|&k| k as char == c.to_ascii_lowercase());
+                    if let Some(entry) = picked.and_then(|i| listed.clone().nth(i)) {
+                        log_info("multiboot", &format!("Boot entry {} selected", entry.id));
+                        return entry.id;
+                    }
+                }
+                Ok(Some(_)) => {}
+                _ => spin_us(MENU_POLL_US, tsc_hz()),
+            }
+        }
         log_info("multiboot", "Default boot entry selected");
-        0 // Return default entry
+        default
     }
 
     /// Save boot preferences (stub)
@@ -129,8 +350,8 @@ impl MultiBootManager {
         log_debug("multiboot", "Boot preferences loaded");
     }
 
-    /// Get selected entry information (stub)
-    pub fn get_entry_info(&self, _entry_id: u32) -> Option<&BootEntry> {
-        None // No entries in minimal implementation
+    /// Get selected entry information
+    pub fn get_entry_info(&self, entry_id: u32) -> Option<&BootEntry> {
+        self.entries.iter().find(|e| e.id == entry_id && e.enabled)
     }
 }
@@ -0,0 +1,822 @@
+//! Multiboot2 kernel loading for `BootEntryType::MultibootKernel`.
+//!
+//! - Header: searched in the first 32 KiB (8-byte aligned), checksum
+//!   verified, tags parsed. Address tags place a raw image; otherwise the
+//!   file is loaded as ELF by physical address.
+//! - Entry: EFI amd64 entry tag (with the EFI boot services tag) is called
+//!   in long mode with boot services live; otherwise we ExitBootServices
+//!   and drop to 32-bit protected mode for the entry-address / ELF entry.
+//! - MBI: cmdline, loader name, modules, basic meminfo, memory map,
+//!   framebuffer, ACPI old/new RSDP, EFI64 system table, EFI memory map and
+//!   (EFI entry only) image handle + "boot services not terminated".
+//!
+//! Modules are named by `module=<path>` tokens in the entry command line;
+//! each one is verified like the kernel and measured into PCR 9.
+
+#![allow(dead_code)]
+
+use crate::bytes::{le16, le32};
+use crate::chainload::{read_verified, ChainloadError};
+use crate::hardware::{discover_acpi_rsdp, query_framebuffer, query_pixel_bitmask};
+use crate::handoff::handoff::pixel_format;
+use crate::loader::LoaderError;
+use crate::log::logger::{log_error, log_info};
+use crate::multiboot::{BootEntry, BootEntryType};
+use alloc::format;
+use core::convert::Infallible;
+use core::fmt;
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryDescriptor, MemoryType, PhysicalAddress};
+use xmas_elf::program::Type as PhType;
+use xmas_elf::ElfFile;
+
+const PAGE_SIZE: usize = 0x1000;
+
+pub const MB2_HEADER_MAGIC: u32 = 0xE852_50D6;
+pub const MB2_BOOTLOADER_MAGIC: u32 = 0x36D7_6289;
+const MB2_SEARCH: usize = 32 * 1024;
+const MB2_ARCH_I386: u32 = 0;
+
+/* header tag types */
+const HTAG_END: u16 = 0;
+const HTAG_INFO_REQUEST: u16 = 1;
+const HTAG_ADDRESS: u16 = 2;
+const HTAG_ENTRY: u16 = 3;
+const HTAG_CONSOLE: u16 = 4;
+const HTAG_FRAMEBUFFER: u16 = 5;
+const HTAG_MODULE_ALIGN: u16 = 6;
+const HTAG_EFI_BS: u16 = 7;
+const HTAG_ENTRY_EFI32: u16 = 8;
+const HTAG_ENTRY_EFI64: u16 = 9;
+const HTAG_RELOCATABLE: u16 = 10;
+const HTAG_OPTIONAL: u16 = 1;
+
+/* information tag types */
+pub const TAG_END: u32 = 0;
+pub const TAG_CMDLINE: u32 = 1;
+pub const TAG_LOADER_NAME: u32 = 2;
+pub const TAG_MODULE: u32 = 3;
+pub const TAG_BASIC_MEMINFO: u32 = 4;
+pub const TAG_MMAP: u32 = 6;
+pub const TAG_FRAMEBUFFER: u32 = 8;
+pub const TAG_EFI64: u32 = 12;
+pub const TAG_ACPI_OLD: u32 = 14;
+pub const TAG_ACPI_NEW: u32 = 15;
+pub const TAG_EFI_MMAP: u32 = 17;
+pub const TAG_EFI_BS: u32 = 18;
+pub const TAG_EFI64_IH: u32 = 20;
+
+/// Information tags this loader can provide (for info-request checks)
+const SUPPORTED_TAGS: &[u32] = &[
+    TAG_CMDLINE, TAG_LOADER_NAME, TAG_MODULE, TAG_BASIC_MEMINFO, TAG_MMAP, TAG_FRAMEBUFFER,
+    TAG_EFI64, TAG_ACPI_OLD, TAG_ACPI_NEW, TAG_EFI_MMAP, TAG_EFI_BS, TAG_EFI64_IH,
+];
+
+const MMAP_AVAILABLE: u32 = 1;
+const MMAP_RESERVED: u32 = 2;
+const MMAP_ACPI: u32 = 3;
+const MMAP_NVS: u32 = 4;
+const MMAP_BAD: u32 = 5;
+
+/// framebuffer_type of a direct-colour framebuffer tag
+const FB_TYPE_DIRECT_RGB: u8 = 1;
+
+const MBI_PAGES: usize = 16;
+const MAX_MODULES: usize = 8;
+const PCR_KERNEL: u32 = 4;
+const PCR_MODULE: u32 = 9;
+
+#[derive(Debug)]
+pub enum Multiboot2Error {
+    WrongEntryType,
+    File(LoaderError),
+    Verify(ChainloadError),
+    NoHeader,
+    BadHeader(&'static str),
+    UnsupportedRequest(u32),
+    BadElf(&'static str),
+    Allocation(Status),
+    MbiOverflow,
+    TrampolineAbove4G,
+}
+
+impl fmt::Display for Multiboot2Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Multiboot2Error::WrongEntryType => write!(f, "entry is not a Multiboot2 kernel"),
+            Multiboot2Error::File(e) => write!(f, "read failed: {}", e),
+            Multiboot2Error::Verify(e) => write!(f, "verification failed: {}", e),
+            Multiboot2Error::NoHeader => write!(f, "no Multiboot2 header in first 32 KiB"),
+            Multiboot2Error::BadHeader(s) => write!(f, "bad Multiboot2 header: {}", s),
+            Multiboot2Error::UnsupportedRequest(t) => write!(f, "kernel requires unsupported tag {}", t),
+            Multiboot2Error::BadElf(s) => write!(f, "bad ELF: {}", s),
+            Multiboot2Error::Allocation(s) => write!(f, "allocation failed: {:?}", s),
+            Multiboot2Error::MbiOverflow => write!(f, "boot information exceeds buffer"),
+            Multiboot2Error::TrampolineAbove4G => write!(f, "32-bit trampoline not below 4 GiB"),
+        }
+    }
+}
+
+impl From<ChainloadError> for Multiboot2Error {
+    fn from(e: ChainloadError) -> Self {
+        match e {
+            ChainloadError::File(e) => Multiboot2Error::File(e),
+            e => Multiboot2Error::Verify(e),
+        }
+    }
+}
+
+/// Address tag contents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddressTag {
+    pub header_addr: u32,
+    pub load_addr: u32,
+    pub load_end_addr: u32,
+    pub bss_end_addr: u32,
+}
+
+/// Parsed Multiboot2 header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Mb2Header {
+    pub offset: usize,
+    pub address: Option<AddressTag>,
+    pub entry: Option<u32>,
+    pub efi64_entry: Option<u32>,
+    pub efi_boot_services: bool,
+    pub wants_framebuffer: bool,
+    pub module_align: bool,
+    /// First required info tag we cannot provide, if any
+    pub unsupported: Option<u32>,
+}
+
+/// Locate and parse the Multiboot2 header.
+pub fn find_header(image: &[u8]) -> Result<Mb2Header, Multiboot2Error> {
+    let limit = image.len().min(MB2_SEARCH);
+    let mut off = 0;
+    while off + 16 <= limit {
+        if le32(image, off) == MB2_HEADER_MAGIC {
+            let arch = le32(image, off + 4);
+            let len = le32(image, off + 8);
+            let sum = le32(image, off + 12);
+            if MB2_HEADER_MAGIC.wrapping_add(arch).wrapping_add(len).wrapping_add(sum) == 0 {
+                if arch != MB2_ARCH_I386 {
+                    return Err(Multiboot2Error::BadHeader("unsupported architecture"));
+                }
+                return parse_tags(image, off, len as usize);
+            }
+        }
+        off += 8;
+    }
+    Err(Multiboot2Error::NoHeader)
+}
+
+fn parse_tags(image: &[u8], start: usize, len: usize) -> Result<Mb2Header, Multiboot2Error> {
+    let end = start.checked_add(len).filter(|&e| e <= image.len()).ok_or(Multiboot2Error::BadHeader("length"))?;
+    let mut hdr = Mb2Header { offset: start, ..Default::default() };
+    let mut off = start + 16;
+
+    while off + 8 <= end {
+        let ty = le16(image, off);
+        let flags = le16(image, off + 2);
+        let size = le32(image, off + 4) as usize;
+        if size < 8 || off + size > end {
+            return Err(Multiboot2Error::BadHeader("tag size"));
+        }
+        match ty {
+            HTAG_END => break,
+            HTAG_INFO_REQUEST => {
+                let mut p = off + 8;
+                while p + 4 <= off + size {
+                    let req = le32(image, p);
+                    if flags & HTAG_OPTIONAL == 0 && !SUPPORTED_TAGS.contains(&req) && hdr.unsupported.is_none() {
+                        hdr.unsupported = Some(req);
+                    }
+                    p += 4;
+                }
+            }
+            HTAG_ADDRESS if size >= 24 => {
+                hdr.address = Some(AddressTag {
+                    header_addr: le32(image, off + 8),
+                    load_addr: le32(image, off + 12),
+                    load_end_addr: le32(image, off + 16),
+                    bss_end_addr: le32(image, off + 20),
+                });
+            }
+            HTAG_ENTRY if size >= 12 => hdr.entry = Some(le32(image, off + 8)),
+            HTAG_ENTRY_EFI64 if size >= 12 => hdr.efi64_entry = Some(le32(image, off + 8)),
+            HTAG_EFI_BS => hdr.efi_boot_services = true,
+            HTAG_FRAMEBUFFER => hdr.wants_framebuffer = true,
+            HTAG_MODULE_ALIGN => hdr.module_align = true,
+            HTAG_CONSOLE | HTAG_ENTRY_EFI32 | HTAG_RELOCATABLE => {}
+            _ if flags & HTAG_OPTIONAL == 0 => return Err(Multiboot2Error::BadHeader("unknown required tag")),
+            _ => {}
+        }
+        off += (size + 7) & !7;
+    }
+    Ok(hdr)
+}
+
+/// Sequential 8-byte aligned MBI tag writer over a fixed buffer.
+pub struct MbiWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> MbiWriter<'a> {
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        // total_size + reserved are patched in finish()
+        MbiWriter { buf, pos: 8 }
+    }
+
+    fn put(&mut self, off: usize, bytes: &[u8]) {
+        self.buf[off..off + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// Write a tag made of a fixed body followed by a variable tail.
+    pub fn tag(&mut self, ty: u32, body: &[u8], tail: &[u8]) -> Result<(), Multiboot2Error> {
+        let size = 8 + body.len() + tail.len();
+        let padded = (size + 7) & !7;
+        if self.pos + padded + 8 > self.buf.len() {
+            return Err(Multiboot2Error::MbiOverflow);
+        }
+        let p = self.pos;
+        self.put(p, &ty.to_le_bytes());
+        self.put(p + 4, &(size as u32).to_le_bytes());
+        self.put(p + 8, body);
+        self.put(p + 8 + body.len(), tail);
+        self.buf[p + size..p + padded].fill(0);
+        self.pos += padded;
+        Ok(())
+    }
+
+    /// String tag (NUL terminated)
+    pub fn string(&mut self, ty: u32, body: &[u8], s: &[u8]) -> Result<(), Multiboot2Error> {
+        let mut tmp = [0u8; 257];
+        let n = s.len().min(256);
+        tmp[..n].copy_from_slice(&s[..n]);
+        self.tag(ty, body, &tmp[..n + 1])
+    }
+
+    /// Reserve a tag of `len` payload bytes and return its payload slice.
+    pub fn reserve(&mut self, ty: u32, len: usize) -> Result<&mut [u8], Multiboot2Error> {
+        let size = 8 + len;
+        let padded = (size + 7) & !7;
+        if self.pos + padded + 8 > self.buf.len() {
+            return Err(Multiboot2Error::MbiOverflow);
+        }
+        let p = self.pos;
+        self.put(p, &ty.to_le_bytes());
+        self.put(p + 4, &(size as u32).to_le_bytes());
+        self.buf[p + 8..p + padded].fill(0);
+        self.pos += padded;
+        Ok(&mut self.buf[p + 8..p + size])
+    }
+
+    /// Terminate with the end tag and patch total_size; returns the size.
+    pub fn finish(mut self) -> usize {
+        let p = self.pos;
+        self.put(p, &TAG_END.to_le_bytes());
+        self.put(p + 4, &8u32.to_le_bytes());
+        self.pos += 8;
+        let total = self.pos as u32;
+        self.put(0, &total.to_le_bytes());
+        self.put(4, &0u32.to_le_bytes());
+        self.pos
+    }
+}
+
+fn mmap_type(ty: MemoryType) -> u32 {
+    match ty {
+        MemoryType::CONVENTIONAL
+        | MemoryType::LOADER_CODE
+        | MemoryType::LOADER_DATA
+        | MemoryType::BOOT_SERVICES_CODE
+        | MemoryType::BOOT_SERVICES_DATA => MMAP_AVAILABLE,
+        MemoryType::ACPI_RECLAIM => MMAP_ACPI,
+        MemoryType::ACPI_NON_VOLATILE => MMAP_NVS,
+        MemoryType::UNUSABLE => MMAP_BAD,
+        _ => MMAP_RESERVED,
+    }
+}
+
+/// Memory-map dependent tags: basic meminfo, mmap and EFI mmap.
+fn write_memory_tags<'m>(
+    w: &mut MbiWriter,
+    entries: impl Iterator<Item = &'m MemoryDescriptor> + Clone,
+) -> Result<(), Multiboot2Error> {
+    let count = entries.clone().count();
+
+    let mut upper_kb = 0u64;
+    let mut next = 0x10_0000u64;
+    for d in entries.clone() {
+        if mmap_type(d.ty) == MMAP_AVAILABLE && d.phys_start == next {
+            next += d.page_count * PAGE_SIZE as u64;
+        }
+    }
+    if next > 0x10_0000 {
+        upper_kb = (next - 0x10_0000) / 1024;
+    }
+    let mut meminfo = [0u8; 8];
+    meminfo[0..4].copy_from_slice(&640u32.to_le_bytes());
+    meminfo[4..8].copy_from_slice(&(upper_kb as u32).to_le_bytes());
+    w.tag(TAG_BASIC_MEMINFO, &meminfo, &[])?;
+
+    let mmap = w.reserve(TAG_MMAP, 8 + count * 24)?;
+    mmap[0..4].copy_from_slice(&24u32.to_le_bytes());
+    for (i, d) in entries.clone().enumerate() {
+        let e = &mut mmap[8 + i * 24..8 + (i + 1) * 24];
+        e[0..8].copy_from_slice(&d.phys_start.to_le_bytes());
+        e[8..16].copy_from_slice(&(d.page_count * PAGE_SIZE as u64).to_le_bytes());
+        e[16..20].copy_from_slice(&mmap_type(d.ty).to_le_bytes());
+    }
+
+    let dsz = core::mem::size_of::<MemoryDescriptor>();
+    let efi = w.reserve(TAG_EFI_MMAP, 8 + count * dsz)?;
+    efi[0..4].copy_from_slice(&(dsz as u32).to_le_bytes());
+    efi[4..8].copy_from_slice(&1u32.to_le_bytes());
+    for (i, d) in entries.enumerate() {
+        // safe: MemoryDescriptor is repr(C) plain data
+        let raw = unsafe { core::slice::from_raw_parts(d as *const _ as *const u8, dsz) };
+        efi[8 + i * dsz..8 + (i + 1) * dsz].copy_from_slice(raw);
+    }
+    Ok(())
+}
+
+/// Pull `module=<path>` tokens out of the command line.
+fn split_modules<'a>(cmdline: &'a str, mods: &mut [&'a str; MAX_MODULES], rest: &mut [u8; 256]) -> (usize, usize) {
+    let mut nmods = 0;
+    let mut n = 0;
+    for tok in cmdline.split_ascii_whitespace() {
+        if let Some(p) = tok.strip_prefix("module=") {
+            if nmods < MAX_MODULES {
+                mods[nmods] = p;
+                nmods += 1;
+            }
+            continue;
+        }
+        if n + tok.len() + 1 >= rest.len() {
+            break;
+        }
+        if n > 0 {
+            rest[n] = b' ';
+            n += 1;
+        }
+        rest[n..n + tok.len()].copy_from_slice(tok.as_bytes());
+        n += tok.len();
+    }
+    (nmods, n)
+}
+
+/// Claim the pages covering `bytes` at `addr`; returns (base, pages)
+fn alloc_at(bs: &uefi::table::boot::BootServices, addr: u64, bytes: usize) -> Result<(u64, usize), Multiboot2Error> {
+    let base = addr & !(PAGE_SIZE as u64 - 1);
+    let pages = ((addr - base) as usize + bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+    bs.allocate_pages(AllocateType::Address(base), MemoryType::LOADER_CODE, pages)
+        .map(|_| (base, pages))
+        .map_err(|e| Multiboot2Error::Allocation(e.status()))
+}
+
+/// Pages claimed for the kernel, its modules and the MBI, released again if
+/// the boot is abandoned
+#[derive(Default)]
+struct Claimed {
+    ranges: [(u64, usize); MAX_MODULES + 2],
+    count: usize,
+}
+
+impl Claimed {
+    fn add(&mut self, range: (u64, usize)) {
+        self.ranges[self.count] = range;
+        self.count += 1;
+    }
+
+    fn free(&self, bs: &uefi::table::boot::BootServices) {
+        for &(base, pages) in &self.ranges[..self.count] {
+            let _ = bs.free_pages(base, pages);
+        }
+    }
+}
+
+/// Bits per pixel and red/green/blue (position, size) pairs of a GOP pixel
+/// format; `None` for BltOnly or a bitmask mode whose masks are unknown
+fn direct_color_layout(format: u32, masks: Option<[u32; 4]>) -> Option<(u8, [u8; 6])> {
+    match format {
+        pixel_format::RGBX8 => Some((32, [0, 8, 8, 8, 16, 8])),
+        pixel_format::BGRX8 => Some((32, [16, 8, 8, 8, 0, 8])),
+        pixel_format::BITMASK => {
+            let [r, g, b, x] = masks?;
+            if r == 0 || g == 0 || b == 0 {
+                return None;
+            }
+            let bits = 32 - (r | g | b | x).leading_zeros();
+            let field = |m: u32| [m.trailing_zeros() as u8, m.count_ones() as u8];
+            let ([rp, rs], [gp, gs], [bp, bs]) = (field(r), field(g), field(b));
+            Some((bits.div_ceil(8) as u8 * 8, [rp, rs, gp, gs, bp, bs]))
+        }
+        _ => None,
+    }
+}
+
+/// Verify a module and copy it to pages below 4 GiB; returns its (start, end)
+fn load_module(st: &mut SystemTable<Boot>, image_handle: Handle, path: &str, measured_boot: bool) -> Result<(u32, u32), Multiboot2Error> {
+    let m = read_verified(st, image_handle, path, PCR_MODULE, b"NONOS multiboot2", measured_boot)?;
+    let bs = st.boot_services();
+    let pages = (m.len + PAGE_SIZE - 1) / PAGE_SIZE;
+    let dst = bs.allocate_pages(AllocateType::MaxAddress(0xFFFF_FFFF), MemoryType::LOADER_DATA, pages);
+    let dst = match dst {
+        Ok(d) => d,
+        Err(e) => {
+            m.free(bs);
+            return Err(Multiboot2Error::Allocation(e.status()));
+        }
+    };
+    // safe: dst spans `pages` pages, source is our file buffer
+    unsafe { core::ptr::copy_nonoverlapping(m.addr as *const u8, dst as *mut u8, m.len) };
+    m.free(bs);
+    Ok((dst as u32, (dst as usize + m.len) as u32))
+}
+
+/// Place the kernel image; returns the ELF entry point when loaded as ELF
+/// and the pages it claimed.
+fn place_kernel(
+    bs: &uefi::table::boot::BootServices,
+    image: &[u8],
+    hdr: &Mb2Header,
+) -> Result<(Option<u32>, (u64, usize)), Multiboot2Error> {
+    if let Some(a) = hdr.address {
+        let header_off = a.header_addr.checked_sub(a.load_addr).ok_or(Multiboot2Error::BadHeader("load_addr after header"))?;
+        let file_start = (hdr.offset as u64)
+            .checked_sub(header_off as u64)
+            .ok_or(Multiboot2Error::BadHeader("load_addr after header"))? as usize;
+        let load_len = if a.load_end_addr == 0 {
+            image.len().saturating_sub(file_start)
+        } else {
+            a.load_end_addr.checked_sub(a.load_addr).ok_or(Multiboot2Error::BadHeader("load_end_addr before load_addr"))? as usize
+        };
+        if file_start.checked_add(load_len).map_or(true, |end| end > image.len()) {
+            return Err(Multiboot2Error::BadHeader("load range exceeds file"));
+        }
+        let mem_len = if a.bss_end_addr > a.load_addr { (a.bss_end_addr - a.load_addr) as usize } else { load_len };
+        let claimed = alloc_at(bs, a.load_addr as u64, mem_len.max(load_len))?;
+        // safe: destination range allocated above
+        unsafe {
+            core::ptr::copy_nonoverlapping(image.as_ptr().add(file_start), a.load_addr as *mut u8, load_len);
+            if mem_len > load_len {
+                core::ptr::write_bytes((a.load_addr as usize + load_len) as *mut u8, 0, mem_len - load_len);
+            }
+        }
+        return Ok((None, claimed));
+    }
+
+    let elf = ElfFile::new(image).map_err(Multiboot2Error::BadElf)?;
+    let entry = elf.header.pt2.entry_point();
+    if entry > u32::MAX as u64 {
+        return Err(Multiboot2Error::BadElf("entry above 4 GiB"));
+    }
+    let loads = || elf.program_iter().filter(|ph| ph.get_type() == Ok(PhType::Load));
+
+    // Segments may share a page (.text/.data), so claim their union once
+    let (mut lo, mut hi) = (u64::MAX, 0u64);
+    for ph in loads() {
+        let (off, filesz, memsz) = (ph.offset() as usize, ph.file_size() as usize, ph.mem_size() as usize);
+        if off.checked_add(filesz).map_or(true, |e| e > image.len()) || filesz > memsz {
+            return Err(Multiboot2Error::BadElf("segment out of bounds"));
+        }
+        let end = ph.physical_addr().checked_add(memsz as u64).ok_or(Multiboot2Error::BadElf("segment out of bounds"))?;
+        lo = lo.min(ph.physical_addr());
+        hi = hi.max(end);
+    }
+    if lo >= hi {
+        return Err(Multiboot2Error::BadElf("no loadable segments"));
+    }
+    let claimed = alloc_at(bs, lo, (hi - lo) as usize)?;
+
+    for ph in loads() {
+        let paddr = ph.physical_addr();
+        let (off, filesz, memsz) = (ph.offset() as usize, ph.file_size() as usize, ph.mem_size() as usize);
+        // safe: every segment lies inside the range allocated above
+        unsafe {
+            core::ptr::copy_nonoverlapping(image.as_ptr().add(off), paddr as *mut u8, filesz);
+            core::ptr::write_bytes((paddr as usize + filesz) as *mut u8, 0, memsz - filesz);
+        }
+    }
+    Ok((Some(entry as u32), claimed))
+}
+
+/* 32-bit protected-mode trampoline: rdi = GDT pointer, rsi = MBI, rdx = entry */
+core::arch::global_asm!(
+    ".pushsection .text.nonos_mb2, \"ax\"",
+    ".global nonos_mb2_enter32",
+    ".code64",
+    "nonos_mb2_enter32:",
+    "    cli",
+    "    lgdt [rdi]",
+    "    mov ebx, esi",
+    "    mov edi, edx",
+    "    push 0x08",
+    "    lea rax, [rip + 2f]",
+    "    push rax",
+    "    retfq",
+    ".code32",
+    "2:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov fs, ax",
+    "    mov gs, ax",
+    "    mov ss, ax",
+    "    mov eax, cr0",
+    "    and eax, 0x7FFFFFFF",
+    "    mov cr0, eax",
+    "    mov ecx, 0xC0000080",
+    "    rdmsr",
+    "    and eax, 0xFFFFFEFF",
+    "    wrmsr",
+    "    mov eax, cr4",
+    "    and eax, 0xFFFFFFDF",
+    "    mov cr4, eax",
+    "    mov eax, 0x36D76289",
+    "    jmp edi",
+    ".code64",
+    ".popsection",
+);
+
+extern "sysv64" {
+    fn nonos_mb2_enter32(gdt: *const GdtPtr, mbi: u32, entry: u32) -> !;
+}
+
+#[repr(C, align(16))]
+struct Gdt32([u64; 3]);
+
+static MB2_GDT: Gdt32 = Gdt32([
+    0,
+    0x00CF_9A00_0000_FFFF, // 0x08: 32-bit code
+    0x00CF_9200_0000_FFFF, // 0x10: data
+]);
+
+#[repr(C, packed)]
+struct GdtPtr {
+    limit: u16,
+    base: u64,
+}
+
+/// Verify, load and boot the Multiboot2 kernel referenced by `entry`.
+/// Only returns on failure; boot services remain usable in that case and
+/// every page claimed for the kernel, its modules and the MBI is freed.
+pub fn boot_multiboot2(
+    image_handle: Handle,
+    st: &mut SystemTable<Boot>,
+    entry: &BootEntry,
+    measured_boot: bool,
+) -> Result<Infallible, Multiboot2Error> {
+    if entry.entry_type != BootEntryType::MultibootKernel {
+        return Err(Multiboot2Error::WrongEntryType);
+    }
+    let mut claimed = Claimed::default();
+    let result = load_and_enter(image_handle, st, entry, measured_boot, &mut claimed);
+    if result.is_err() {
+        claimed.free(st.boot_services());
+    }
+    result
+}
+
+fn load_and_enter(
+    image_handle: Handle,
+    st: &mut SystemTable<Boot>,
+    entry: &BootEntry,
+    measured_boot: bool,
+    claimed: &mut Claimed,
+) -> Result<Infallible, Multiboot2Error> {
+    let mut mod_paths = [""; MAX_MODULES];
+    let mut cmdline = [0u8; 256];
+    let (nmods, cmd_len) = split_modules(entry.command_line_str(), &mut mod_paths, &mut cmdline);
+
+    log_info("multiboot2", &format!("Loading Multiboot2 kernel {}", entry.path_str()));
+    let kernel = read_verified(st, image_handle, entry.path_str(), PCR_KERNEL, b"NONOS multiboot2", measured_boot)?;
+    let hdr = match find_header(kernel.as_slice()) {
+        Ok(h) => h,
+        Err(e) => {
+            kernel.free(st.boot_services());
+            return Err(e);
+        }
+    };
+    if let Some(t) = hdr.unsupported {
+        kernel.free(st.boot_services());
+        return Err(Multiboot2Error::UnsupportedRequest(t));
+    }
+    let use_efi64 = hdr.efi64_entry.is_some() && hdr.efi_boot_services;
+
+    let placed = place_kernel(st.boot_services(), kernel.as_slice(), &hdr);
+    kernel.free(st.boot_services());
+    let (elf_entry, kernel_pages) = placed?;
+    claimed.add(kernel_pages);
+
+    let entry_addr = if use_efi64 {
+        hdr.efi64_entry.unwrap_or(0)
+    } else {
+        match hdr.entry.or(elf_entry) {
+            Some(e) => e,
+            None => return Err(Multiboot2Error::BadHeader("no entry address")),
+        }
+    };
+    if !use_efi64 && (nonos_mb2_enter32 as usize as u64) > u32::MAX as u64 {
+        return Err(Multiboot2Error::TrampolineAbove4G);
+    }
+
+    // Modules, each verified and page aligned below 4 GiB
+    let mut modules = [(0u32, 0u32); MAX_MODULES];
+    for i in 0..nmods {
+        let (start, end) = load_module(st, image_handle, mod_paths[i], measured_boot)?;
+        claimed.add((start as u64, ((end - start) as usize + PAGE_SIZE - 1) / PAGE_SIZE));
+        modules[i] = (start, end);
+    }
+
+    // Platform data gathered while boot services are still up
+    let fb = query_framebuffer(st);
+    let masks = match fb {
+        Some(f) if f.pixel_format == pixel_format::BITMASK => query_pixel_bitmask(st),
+        _ => None,
+    };
+    let rsdp = discover_acpi_rsdp(st);
+    let systab = st.as_ptr() as u64;
+
+    let mbi_addr: PhysicalAddress = st
+        .boot_services()
+        .allocate_pages(AllocateType::MaxAddress(0xFFFF_FFFF), MemoryType::LOADER_DATA, MBI_PAGES)
+        .map_err(|e| Multiboot2Error::Allocation(e.status()))?;
+    claimed.add((mbi_addr, MBI_PAGES));
+    // safe: MBI_PAGES freshly allocated pages
+    let mbi = unsafe { core::slice::from_raw_parts_mut(mbi_addr as *mut u8, MBI_PAGES * PAGE_SIZE) };
+    let mut w = MbiWriter::new(mbi);
+
+    w.string(TAG_CMDLINE, &[], &cmdline[..cmd_len])?;
+    w.string(TAG_LOADER_NAME, &[], b"NONOS Capsule Bootloader")?;
+    for i in 0..nmods {
+        let mut body = [0u8; 8];
+        body[0..4].copy_from_slice(&modules[i].0.to_le_bytes());
+        body[4..8].copy_from_slice(&modules[i].1.to_le_bytes());
+        w.string(TAG_MODULE, &body, mod_paths[i].as_bytes())?;
+    }
+    // BltOnly modes have no linear framebuffer to describe
+    if let Some((fb, (bpp, fields))) = fb.and_then(|fb| Some((fb, direct_color_layout(fb.pixel_format, masks)?))) {
+        let mut body = [0u8; 30];
+        body[0..8].copy_from_slice(&fb.ptr.to_le_bytes());
+        body[8..12].copy_from_slice(&(fb.stride * (bpp as u32 / 8)).to_le_bytes());
+        body[12..16].copy_from_slice(&fb.width.to_le_bytes());
+        body[16..20].copy_from_slice(&fb.height.to_le_bytes());
+        body[20] = bpp;
+        body[21] = FB_TYPE_DIRECT_RGB;
+        body[24..30].copy_from_slice(&fields);
+        w.tag(TAG_FRAMEBUFFER, &body, &[])?;
+    }
+    if let Some(rsdp) = rsdp {
+        // safe: firmware RSDP validated by hardware::discover_acpi_rsdp
+        let v1 = unsafe { core::slice::from_raw_parts(rsdp as *const u8, 20) };
+        w.tag(TAG_ACPI_OLD, &[], v1)?;
+        if v1[15] >= 2 {
+            let len = u32::from_le_bytes([
+                unsafe { *((rsdp + 20) as *const u8) },
+                unsafe { *((rsdp + 21) as *const u8) },
+                unsafe { *((rsdp + 22) as *const u8) },
+                unsafe { *((rsdp + 23) as *const u8) },
+            ]) as usize;
+            // safe: ACPI 2.0+ RSDP spans `length` bytes
+            let v2 = unsafe { core::slice::from_raw_parts(rsdp as *const u8, len.min(64)) };
+            w.tag(TAG_ACPI_NEW, &[], v2)?;
+        }
+    }
+    w.tag(TAG_EFI64, &systab.to_le_bytes(), &[])?;
+
+    if use_efi64 {
+        w.tag(TAG_EFI64_IH, &(image_handle.as_ptr() as u64).to_le_bytes(), &[])?;
+        w.tag(TAG_EFI_BS, &[], &[])?;
+
+        let bs = st.boot_services();
+        let sz = bs.memory_map_size();
+        let mut buf = [0u8; 16 * 1024];
+        let map_len = (sz.map_size + 8 * sz.entry_size).min(buf.len());
+        let map = bs
+            .memory_map(&mut buf[..map_len])
+            .map_err(|e| Multiboot2Error::Allocation(e.status()))?;
+        write_memory_tags(&mut w, map.entries())?;
+        let total = w.finish();
+
+        log_info("multiboot2", &format!("EFI64 entry 0x{:x}, MBI {} bytes @ 0x{:x}", entry_addr, total, mbi_addr));
+        // safe: entry lies in the verified image; boot services stay live by contract
+        unsafe {
+            // rbx is reserved by the compiler, so the MBI goes through esi
+            core::arch::asm!(
+                "mov ebx, esi",
+                "jmp rcx",
+                in("esi") mbi_addr as u32,
+                in("eax") MB2_BOOTLOADER_MAGIC,
+                in("rcx") entry_addr as u64,
+                options(noreturn)
+            );
+        }
+    }
+
+    log_info("multiboot2", &format!("i386 entry 0x{:x}, exiting boot services", entry_addr));
+    // safe: boot services are not touched through `st` after this point
+    let (_rt, mut map) = unsafe { st.unsafe_clone() }.exit_boot_services();
+    map.sort();
+    if write_memory_tags(&mut w, map.entries()).is_err() {
+        log_error("multiboot2", "MBI overflow after ExitBootServices");
+    }
+    w.finish();
+
+    let gdt = GdtPtr { limit: (core::mem::size_of::<Gdt32>() - 1) as u16, base: &MB2_GDT as *const _ as u64 };
+    // safe: trampoline and GDT checked to be below 4 GiB and identity mapped
+    unsafe { nonos_mb2_enter32(&gdt, mbi_addr as u32, entry_addr) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use alloc::vec::Vec;
+
+    fn header(tags: &[u8]) -> Vec<u8> {
+        let len = (16 + tags.len() + 8) as u32;
+        let mut h = Vec::new();
+        h.extend_from_slice(&MB2_HEADER_MAGIC.to_le_bytes());
+        h.extend_from_slice(&MB2_ARCH_I386.to_le_bytes());
+        h.extend_from_slice(&len.to_le_bytes());
+        let sum = 0u32.wrapping_sub(MB2_HEADER_MAGIC.wrapping_add(len));
+        h.extend_from_slice(&sum.to_le_bytes());
+        h.extend_from_slice(tags);
+        h.extend_from_slice(&[0, 0, 0, 0, 8, 0, 0, 0]);
+        h
+    }
+
+    fn htag(ty: u16, flags: u16, body: &[u8]) -> Vec<u8> {
+        let mut t = Vec::new();
+        t.extend_from_slice(&ty.to_le_bytes());
+        t.extend_from_slice(&flags.to_le_bytes());
+        t.extend_from_slice(&((8 + body.len()) as u32).to_le_bytes());
+        t.extend_from_slice(body);
+        while t.len() % 8 != 0 {
+            t.push(0);
+        }
+        t
+    }
+
+    #[test]
+    fn finds_header_with_entry_and_address() {
+        let mut tags = htag(HTAG_ENTRY, 0, &0x10_0040u32.to_le_bytes());
+        let mut addr = Vec::new();
+        for v in [0x10_0000u32, 0x10_0000, 0, 0x20_0000] {
+            addr.extend_from_slice(&v.to_le_bytes());
+        }
+        tags.extend(htag(HTAG_ADDRESS, 0, &addr));
+        let mut img = vec![0u8; 64];
+        img.extend(header(&tags));
+        let hdr = find_header(&img).unwrap();
+        assert_eq!(hdr.offset, 64);
+        assert_eq!(hdr.entry, Some(0x10_0040));
+        assert_eq!(hdr.address.unwrap().bss_end_addr, 0x20_0000);
+    }
+
+    #[test]
+    fn reports_unsupported_required_request() {
+        let mut req = Vec::new();
+        req.extend_from_slice(&TAG_CMDLINE.to_le_bytes());
+        req.extend_from_slice(&21u32.to_le_bytes());
+        let hdr = find_header(&header(&htag(HTAG_INFO_REQUEST, 0, &req))).unwrap();
+        assert_eq!(hdr.unsupported, Some(21));
+
+        let hdr = find_header(&header(&htag(HTAG_INFO_REQUEST, HTAG_OPTIONAL, &req))).unwrap();
+        assert_eq!(hdr.unsupported, None);
+    }
+
+    #[test]
+    fn bad_checksum_is_not_a_header() {
+        let mut img = header(&[]);
+        img[12] ^= 1;
+        assert!(matches!(find_header(&img), Err(Multiboot2Error::NoHeader)));
+    }
+
+    #[test]
+    fn framebuffer_layout_from_pixel_format() {
+        assert_eq!(direct_color_layout(pixel_format::BGRX8, None), Some((32, [16, 8, 8, 8, 0, 8])));
+        assert_eq!(direct_color_layout(pixel_format::BLT_ONLY, None), None);
+        assert_eq!(direct_color_layout(pixel_format::BITMASK, None), None);
+        // 16bpp RGB565
+        let masks = Some([0xF800, 0x07E0, 0x001F, 0]);
+        assert_eq!(direct_color_layout(pixel_format::BITMASK, masks), Some((16, [11, 5, 5, 6, 0, 5])));
+    }
+
+    #[test]
+    fn mbi_writer_aligns_and_terminates() {
+        let mut buf = [0xAAu8; 128];
+        let mut w = MbiWriter::new(&mut buf);
+        w.string(TAG_CMDLINE, &[], b"quiet").unwrap();
+        let total = w.finish();
+        // header 8 + cmdline (8+6 -> 16) + end 8
+        assert_eq!(total, 32);
+        assert_eq!(u32::from_le_bytes(buf[0..4].try_into().unwrap()), 32);
+        assert_eq!(u32::from_le_bytes(buf[12..16].try_into().unwrap()), 14);
+        assert_eq!(&buf[16..22], b"quiet\0");
+        assert_eq!(u32::from_le_bytes(buf[24..28].try_into().unwrap()), TAG_END);
+    }
+}
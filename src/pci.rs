@@ -0,0 +1,496 @@
+//! PCI inventory handed to the kernel (layout: `nonos_handoff::pci`).
+//!
+//! Config space is read through every EFI_PCI_ROOT_BRIDGE_IO_PROTOCOL
+//! instance, over the bus range each root bridge decodes. Firmware without
+//! the protocol falls back to the MCFG ECAM windows, which UEFI keeps
+//! identity mapped. Buses are brute-force scanned rather than followed
+//! through bridges so that functions behind misconfigured bridges still show
+//! up in the inventory.
+//!
+//! BAR sizes are probed the usual way: decoding off, all-ones written, the
+//! read-back mask decoded, original value and command register restored.
+//! The probe runs at TPL_NOTIFY so no driver event touches the function
+//! while one of its BARs reads back as garbage.
+
+#![allow(dead_code)]
+
+use crate::acpi::EcamRegion;
+use crate::config::SecurityPolicy;
+use crate::log::logger::{log_info, log_warn};
+use alloc::format;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::fmt;
+use nonos_handoff::pci::{pci_bar_flags, pci_class, PciBar, PciFunction};
+use r_efi::efi;
+use uefi::prelude::*;
+use uefi::proto::unsafe_protocol;
+use uefi::table::boot::{OpenProtocolAttributes, OpenProtocolParams, Tpl};
+
+const REG_ID: u16 = 0x00;
+const REG_COMMAND: u16 = 0x04;
+const REG_CLASS: u16 = 0x08;
+const REG_HEADER: u16 = 0x0C;
+const REG_BAR0: u16 = 0x10;
+const REG_SUBSYSTEM: u16 = 0x2C;
+const REG_CARDBUS_SUBSYSTEM: u16 = 0x40;
+
+/// Command register: I/O and memory space decode
+const COMMAND_DECODE: u32 = 0x3;
+const HEADER_MULTIFUNCTION: u8 = 0x80;
+
+/// Access to one segment's configuration space
+pub trait ConfigSpace {
+    /// Dword at `reg` (4-byte aligned, below 0x100); all-ones if nothing answers
+    fn read32(&mut self, bus: u8, dev: u8, func: u8, reg: u16) -> u32;
+    fn write32(&mut self, bus: u8, dev: u8, func: u8, reg: u16, value: u32);
+}
+
+/// Every function on `buses`, appended to `out` in bus/device/function order
+pub fn scan<C: ConfigSpace>(cfg: &mut C, segment: u16, buses: (u8, u8), out: &mut Vec<PciFunction>) {
+    for bus in buses.0..=buses.1 {
+        for dev in 0..32 {
+            let Some(f0) = probe(cfg, segment, bus, dev, 0) else { continue };
+            out.push(f0);
+            let header = (cfg.read32(bus, dev, 0, REG_HEADER) >> 16) as u8;
+            if header & HEADER_MULTIFUNCTION == 0 {
+                continue;
+            }
+            out.extend((1..8).filter_map(|func| probe(cfg, segment, bus, dev, func)));
+        }
+    }
+}
+
+fn probe<C: ConfigSpace>(cfg: &mut C, segment: u16, bus: u8, dev: u8, func: u8) -> Option<PciFunction> {
+    let id = cfg.read32(bus, dev, func, REG_ID);
+    let vendor_id = id as u16;
+    if vendor_id == 0xFFFF || vendor_id == 0 {
+        return None;
+    }
+    let class = cfg.read32(bus, dev, func, REG_CLASS);
+    let header_type = (cfg.read32(bus, dev, func, REG_HEADER) >> 16) as u8 & !HEADER_MULTIFUNCTION;
+    let (subsystem, bar_count) = match header_type {
+        0 => (cfg.read32(bus, dev, func, REG_SUBSYSTEM), 6),
+        1 => (0, 2),
+        2 => (cfg.read32(bus, dev, func, REG_CARDBUS_SUBSYSTEM), 0),
+        _ => (0, 0),
+    };
+    Some(PciFunction {
+        segment,
+        bus,
+        device: dev,
+        function: func,
+        header_type,
+        vendor_id,
+        device_id: (id >> 16) as u16,
+        class: (class >> 24) as u8,
+        subclass: (class >> 16) as u8,
+        prog_if: (class >> 8) as u8,
+        revision: class as u8,
+        subsystem_vendor_id: subsystem as u16,
+        subsystem_id: (subsystem >> 16) as u16,
+        reserved: [0; 4],
+        bars: size_bars(cfg, bus, dev, func, bar_count),
+    })
+}
+
+/// Write all-ones to `reg`, read the mask back and restore; returns (original, mask)
+fn probe_reg<C: ConfigSpace>(cfg: &mut C, bus: u8, dev: u8, func: u8, reg: u16) -> (u32, u32) {
+    let orig = cfg.read32(bus, dev, func, reg);
+    cfg.write32(bus, dev, func, reg, 0xFFFF_FFFF);
+    let mask = cfg.read32(bus, dev, func, reg);
+    cfg.write32(bus, dev, func, reg, orig);
+    (orig, mask)
+}
+
+fn size_bars<C: ConfigSpace>(cfg: &mut C, bus: u8, dev: u8, func: u8, count: usize) -> [PciBar; 6] {
+    let mut bars = [PciBar::default(); 6];
+    if count == 0 {
+        return bars;
+    }
+    // status is write-one-to-clear: write the command half only
+    let command = cfg.read32(bus, dev, func, REG_COMMAND) & 0xFFFF;
+    cfg.write32(bus, dev, func, REG_COMMAND, command & !COMMAND_DECODE);
+
+    let mut i = 0;
+    while i < count {
+        let reg = REG_BAR0 + 4 * i as u16;
+        let (orig, mask) = probe_reg(cfg, bus, dev, func, reg);
+        if mask == 0 {
+            i += 1;
+            continue;
+        }
+        if orig & 1 != 0 {
+            let size_mask = mask & 0xFFFC;
+            bars[i] = PciBar {
+                base: (orig & !0x3) as u64,
+                size: if size_mask == 0 { 0 } else { (!size_mask & 0xFFFF) as u64 + 1 },
+                flags: pci_bar_flags::IO,
+                reserved: 0,
+            };
+            i += 1;
+            continue;
+        }
+        let mut flags = if orig & 0x8 != 0 { pci_bar_flags::PREFETCHABLE } else { 0 };
+        let (mut base, mut size_mask) = ((orig & !0xF) as u64, (mask & !0xF) as u64);
+        let is64 = (orig >> 1) & 0x3 == 0x2 && i + 1 < count;
+        if is64 {
+            let (orig_hi, mask_hi) = probe_reg(cfg, bus, dev, func, reg + 4);
+            base |= (orig_hi as u64) << 32;
+            size_mask |= (mask_hi as u64) << 32;
+            flags |= pci_bar_flags::MEM64;
+        } else if size_mask != 0 {
+            size_mask |= 0xFFFF_FFFF_0000_0000;
+        }
+        let size = if size_mask == 0 { 0 } else { (!size_mask).wrapping_add(1) };
+        bars[i] = PciBar { base, size, flags, reserved: 0 };
+        i += if is64 { 2 } else { 1 };
+    }
+    cfg.write32(bus, dev, func, REG_COMMAND, command);
+    bars
+}
+
+/// EFI_PCI_ROOT_BRIDGE_IO_PROTOCOL; only Pci.Read/Write and Configuration are called
+#[repr(C)]
+#[unsafe_protocol("2f707ebb-4a1a-11d4-9a38-0090273fc14d")]
+pub struct PciRootBridgeIo {
+    parent_handle: efi::Handle,
+    poll_mem: usize,
+    poll_io: usize,
+    mem: [usize; 2],
+    io: [usize; 2],
+    pci_read: unsafe extern "efiapi" fn(*mut PciRootBridgeIo, u32, u64, usize, *mut c_void) -> efi::Status,
+    pci_write: unsafe extern "efiapi" fn(*mut PciRootBridgeIo, u32, u64, usize, *mut c_void) -> efi::Status,
+    copy_mem: usize,
+    map: usize,
+    unmap: usize,
+    allocate_buffer: usize,
+    free_buffer: usize,
+    flush: usize,
+    get_attributes: usize,
+    set_attributes: usize,
+    configuration: unsafe extern "efiapi" fn(*mut PciRootBridgeIo, *mut *mut c_void) -> efi::Status,
+    segment_number: u32,
+}
+
+/// EfiPciWidthUint32
+const WIDTH_U32: u32 = 2;
+/// ACPI QWORD address space descriptor, and its bus-number resource type
+const ACPI_QWORD_DESC: u8 = 0x8A;
+const ACPI_QWORD_LEN: usize = 46;
+const ACPI_RESOURCE_BUS: u8 = 2;
+
+struct RootBridge(*mut PciRootBridgeIo);
+
+impl ConfigSpace for RootBridge {
+    fn read32(&mut self, bus: u8, dev: u8, func: u8, reg: u16) -> u32 {
+        let mut value = 0xFFFF_FFFFu32;
+        // safe: live protocol instance held open by the caller; one dword into a local
+        let status = unsafe {
+            ((*self.0).pci_read)(self.0, WIDTH_U32, bridge_address(bus, dev, func, reg), 1, &mut value as *mut u32 as *mut c_void)
+        };
+        if status == efi::Status::SUCCESS { value } else { 0xFFFF_FFFF }
+    }
+
+    fn write32(&mut self, bus: u8, dev: u8, func: u8, reg: u16, mut value: u32) {
+        // safe: as in read32
+        let _ = unsafe {
+            ((*self.0).pci_write)(self.0, WIDTH_U32, bridge_address(bus, dev, func, reg), 1, &mut value as *mut u32 as *mut c_void)
+        };
+    }
+}
+
+/// EFI_PCI_ROOT_BRIDGE_IO_PROTOCOL_PCI_ADDRESS for a register below 0x100
+fn bridge_address(bus: u8, dev: u8, func: u8, reg: u16) -> u64 {
+    (bus as u64) << 24 | (dev as u64) << 16 | (func as u64) << 8 | (reg & 0xFF) as u64
+}
+
+/// Bus range from one QWORD address space descriptor, if it describes buses
+fn bus_range_of(desc: &[u8]) -> Option<(u8, u8)> {
+    if desc.len() < ACPI_QWORD_LEN || desc[0] != ACPI_QWORD_DESC || desc[3] != ACPI_RESOURCE_BUS {
+        return None;
+    }
+    let min = u64::from_le_bytes(desc[14..22].try_into().ok()?);
+    let len = u64::from_le_bytes(desc[38..46].try_into().ok()?);
+    let max = min.checked_add(len.checked_sub(1)?)?;
+    Some((u8::try_from(min).ok()?, max.min(0xFF) as u8))
+}
+
+/// Buses the root bridge decodes; all of them if Configuration() says nothing useful
+fn root_bridge_buses(rb: *mut PciRootBridgeIo) -> (u8, u8) {
+    let mut descs: *mut c_void = core::ptr::null_mut();
+    // safe: live protocol instance; firmware returns a pointer to its own descriptor list
+    let status = unsafe { ((*rb).configuration)(rb, &mut descs) };
+    if status != efi::Status::SUCCESS || descs.is_null() {
+        return (0, 0xFF);
+    }
+    let mut p = descs as *const u8;
+    // a root bridge has a handful of windows; the bound only guards against a missing end tag
+    for _ in 0..32 {
+        // safe: descriptors are QWORD entries terminated by an end tag (0x79), per the protocol
+        let tag = unsafe { *p };
+        if tag != ACPI_QWORD_DESC {
+            break;
+        }
+        // safe: a QWORD descriptor is ACPI_QWORD_LEN bytes
+        let desc = unsafe { core::slice::from_raw_parts(p, ACPI_QWORD_LEN) };
+        if let Some(range) = bus_range_of(desc) {
+            return range;
+        }
+        // safe: next descriptor or the end tag follows
+        p = unsafe { p.add(ACPI_QWORD_LEN) };
+    }
+    (0, 0xFF)
+}
+
+/// MCFG window, identity mapped by firmware
+struct Ecam(EcamRegion);
+
+impl Ecam {
+    fn reg_ptr(&self, bus: u8, dev: u8, func: u8, reg: u16) -> Option<*mut u32> {
+        if bus < self.0.start_bus || bus > self.0.end_bus {
+            return None;
+        }
+        // the MCFG base address is that of bus 0, even when the window starts later
+        let off = (bus as u64) << 20 | (dev as u64) << 15 | (func as u64) << 12 | (reg & 0xFFC) as u64;
+        Some((self.0.base + off) as *mut u32)
+    }
+}
+
+impl ConfigSpace for Ecam {
+    fn read32(&mut self, bus: u8, dev: u8, func: u8, reg: u16) -> u32 {
+        match self.reg_ptr(bus, dev, func, reg) {
+            // safe: inside the ECAM window the MCFG declares, which firmware maps 1:1
+            Some(p) => unsafe { p.read_volatile() },
+            None => 0xFFFF_FFFF,
+        }
+    }
+
+    fn write32(&mut self, bus: u8, dev: u8, func: u8, reg: u16, value: u32) {
+        if let Some(p) = self.reg_ptr(bus, dev, func, reg) {
+            // safe: as in read32
+            unsafe { p.write_volatile(value) }
+        }
+    }
+}
+
+/// Scan every root bridge, or the ECAM windows when firmware has none
+pub fn enumerate(st: &SystemTable<Boot>, ecam: &[EcamRegion]) -> Vec<PciFunction> {
+    let bs = st.boot_services();
+    let mut found = Vec::new();
+    // safe: no driver may touch a function while its BARs are being sized
+    let _tpl = unsafe { bs.raise_tpl(Tpl::NOTIFY) };
+
+    let handles = bs.find_handles::<PciRootBridgeIo>().unwrap_or_default();
+    for &handle in handles.iter() {
+        let params = OpenProtocolParams { handle, agent: bs.image_handle(), controller: None };
+        // safe: GetProtocol leaves the PCI bus driver's binding alone; we only call config accessors
+        let Ok(mut rb) = (unsafe { bs.open_protocol::<PciRootBridgeIo>(params, OpenProtocolAttributes::GetProtocol) }) else {
+            continue;
+        };
+        let raw = &mut *rb as *mut PciRootBridgeIo;
+        // safe: reading a plain field of the protocol instance
+        let segment = unsafe { (*raw).segment_number } as u16;
+        scan(&mut RootBridge(raw), segment, root_bridge_buses(raw), &mut found);
+    }
+    if handles.is_empty() {
+        for region in ecam {
+            scan(&mut Ecam(*region), region.segment, (region.start_bus, region.end_bus), &mut found);
+        }
+    }
+    found
+}
+
+/// Human-readable class for logs and the diagnostic screen
+pub fn class_name(f: &PciFunction) -> &'static str {
+    match (f.class, f.subclass) {
+        (pci_class::STORAGE, 0x01) => "IDE controller",
+        (pci_class::STORAGE, 0x06) => "SATA controller",
+        (pci_class::STORAGE, 0x08) => "NVMe controller",
+        (pci_class::STORAGE, 0x00) => "SCSI controller",
+        (pci_class::NETWORK, 0x00) => "Ethernet controller",
+        (pci_class::DISPLAY, 0x00) => "VGA controller",
+        (pci_class::MULTIMEDIA, 0x03) => "audio device",
+        (pci_class::BRIDGE, 0x00) => "host bridge",
+        (pci_class::BRIDGE, 0x01) => "ISA bridge",
+        (pci_class::BRIDGE, 0x04) => "PCI bridge",
+        (pci_class::SERIAL_BUS, 0x00) => "FireWire controller",
+        (pci_class::SERIAL_BUS, 0x03) => "USB controller",
+        (pci_class::SERIAL_BUS, 0x05) => "SMBus controller",
+        (pci_class::SERIAL_BUS, 0x0A) => "Thunderbolt controller",
+        (pci_class::SYSTEM, 0x06) => "IOMMU",
+        (class, _) => pci_class::name(class),
+    }
+}
+
+/// Classes the loader knows what to expect from, as (class, subclass)
+const KNOWN_CLASSES: &[(u8, u8)] = &[
+    (pci_class::STORAGE, 0x00),
+    (pci_class::STORAGE, 0x01),
+    (pci_class::STORAGE, 0x06),
+    (pci_class::STORAGE, 0x08),
+    (pci_class::NETWORK, 0x00),
+    (pci_class::DISPLAY, 0x00),
+    (pci_class::DISPLAY, 0x80),
+    (pci_class::MULTIMEDIA, 0x03),
+    (pci_class::COMMUNICATION, 0x00),
+    (pci_class::SERIAL_BUS, 0x03),
+    (pci_class::SERIAL_BUS, 0x05),
+    (pci_class::SYSTEM, 0x06),
+];
+
+/// Buses that let hot-plugged peripherals master DMA
+const EXTERNAL_DMA_CLASSES: &[(u8, u8)] = &[(pci_class::SERIAL_BUS, 0x00), (pci_class::SERIAL_BUS, 0x0A)];
+
+/// Functions that can master the bus: everything but bridges, memory
+/// controllers, processors and the legacy system peripherals (PIC, DMA, timer, RTC)
+pub fn dma_capable(f: &PciFunction) -> bool {
+    f.header_type == 0
+        && !matches!(f.class, pci_class::BRIDGE | pci_class::MEMORY | pci_class::PROCESSOR)
+        && !(f.class == pci_class::SYSTEM && f.subclass <= 0x03)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PciPolicyError<'a> {
+    /// DMA-capable function of a class not in `KNOWN_CLASSES`
+    UnknownDmaDevice(&'a PciFunction),
+    /// FireWire or Thunderbolt controller
+    ExternalDma(&'a PciFunction),
+}
+
+impl fmt::Display for PciPolicyError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (what, d) = match self {
+            PciPolicyError::UnknownDmaDevice(d) => ("unknown DMA-capable device", d),
+            PciPolicyError::ExternalDma(d) => ("external DMA port", d),
+        };
+        write!(
+            f,
+            "{} {:04x}:{:02x}:{:02x}.{} [{:04x}:{:04x}] class {:06x} ({})",
+            what, d.segment, d.bus, d.device, d.function, d.vendor_id, d.device_id, d.class_code(), class_name(d)
+        )
+    }
+}
+
+fn violation(f: &PciFunction) -> Option<PciPolicyError<'_>> {
+    let class = (f.class, f.subclass);
+    if EXTERNAL_DMA_CLASSES.contains(&class) {
+        Some(PciPolicyError::ExternalDma(f))
+    } else if dma_capable(f) && !KNOWN_CLASSES.contains(&class) {
+        Some(PciPolicyError::UnknownDmaDevice(f))
+    } else {
+        None
+    }
+}
+
+/// Device policy: `Maximum` refuses the first violation, `Standard` logs them
+/// all, `Relaxed` and `Custom` accept any device.
+pub fn check_policy(functions: &[PciFunction], policy: SecurityPolicy) -> Result<(), PciPolicyError<'_>> {
+    let strict = match policy {
+        SecurityPolicy::Maximum => true,
+        SecurityPolicy::Standard => false,
+        SecurityPolicy::Relaxed | SecurityPolicy::Custom => return Ok(()),
+    };
+    for f in functions {
+        if let Some(v) = violation(f) {
+            if strict {
+                return Err(v);
+            }
+            log_warn("pci", &format!("{}", v));
+        }
+    }
+    Ok(())
+}
+
+/// One log line per function
+pub fn log_inventory(functions: &[PciFunction]) {
+    for d in functions {
+        let master = if dma_capable(d) { " dma" } else { "" };
+        log_info("pci", &format!("{:04x}:{:02x}:{:02x}.{} [{:04x}:{:04x}] {} (class {:06x}){}",
+            d.segment, d.bus, d.device, d.function, d.vendor_id, d.device_id, class_name(d), d.class_code(), master));
+    }
+    log_info("pci", &format!("PCI functions: {}", functions.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    /// Config space of a few functions; BARs answer with their size mask while probed
+    struct Fake {
+        regs: Vec<((u8, u8, u8, u16), u32)>,
+        bar_sizes: Vec<((u8, u8, u8, u16), u32)>,
+    }
+
+    impl ConfigSpace for Fake {
+        fn read32(&mut self, bus: u8, dev: u8, func: u8, reg: u16) -> u32 {
+            let key = (bus, dev, func, reg);
+            self.regs.iter().find(|(k, _)| *k == key).map_or(0xFFFF_FFFF, |(_, v)| *v)
+        }
+
+        fn write32(&mut self, bus: u8, dev: u8, func: u8, reg: u16, value: u32) {
+            let key = (bus, dev, func, reg);
+            let mask = self.bar_sizes.iter().find(|(k, _)| *k == key).map(|(_, m)| *m);
+            if let Some(slot) = self.regs.iter_mut().find(|(k, _)| *k == key) {
+                slot.1 = match (value, mask) {
+                    (0xFFFF_FFFF, Some(m)) => m,
+                    _ => value,
+                };
+            }
+        }
+    }
+
+    #[test]
+    fn scans_multifunction_and_sizes_bars() {
+        let mut cfg = Fake {
+            regs: vec![
+                // 00:00.0 host bridge, single function
+                ((0, 0, 0, REG_ID), 0x1237_8086),
+                ((0, 0, 0, REG_CLASS), 0x0600_0002),
+                ((0, 0, 0, REG_HEADER), 0x0000_0000),
+                // 00:03.0 NVMe, multifunction: 64-bit 16 KiB BAR0, I/O BAR2
+                ((0, 3, 0, REG_ID), 0x0010_1B36),
+                ((0, 3, 0, REG_COMMAND), 0x0010_0007),
+                ((0, 3, 0, REG_CLASS), 0x0108_0202),
+                ((0, 3, 0, REG_HEADER), 0x0080_0000),
+                ((0, 3, 0, REG_SUBSYSTEM), 0x1100_1AF4),
+                ((0, 3, 0, 0x10), 0xFEB0_0004),
+                ((0, 3, 0, 0x14), 0x0000_0001),
+                ((0, 3, 0, 0x18), 0x0000_C041),
+                ((0, 3, 0, 0x1C), 0),
+                ((0, 3, 0, 0x20), 0),
+                ((0, 3, 0, 0x24), 0),
+                // 00:03.2 unknown class 0xFF, no BARs
+                ((0, 3, 2, REG_ID), 0x5678_1234),
+                ((0, 3, 2, REG_CLASS), 0xFF00_0000),
+                ((0, 3, 2, REG_HEADER), 0x0000_0000),
+                ((0, 3, 2, REG_COMMAND), 0),
+            ],
+            bar_sizes: vec![
+                ((0, 3, 0, 0x10), 0xFFFF_C004),
+                ((0, 3, 0, 0x14), 0xFFFF_FFFF),
+                ((0, 3, 0, 0x18), 0xFFFF_FFC1),
+            ],
+        };
+        let mut found = Vec::new();
+        scan(&mut cfg, 0, (0, 0), &mut found);
+        assert_eq!(found.len(), 3);
+        assert_eq!((found[0].class, found[0].subclass), (pci_class::BRIDGE, 0));
+
+        let nvme = &found[1];
+        assert_eq!((nvme.device, nvme.function, nvme.header_type), (3, 0, 0));
+        assert_eq!((nvme.vendor_id, nvme.device_id, nvme.class_code()), (0x1B36, 0x0010, 0x010802));
+        assert_eq!((nvme.subsystem_vendor_id, nvme.subsystem_id), (0x1AF4, 0x1100));
+        assert_eq!(nvme.bars[0], PciBar { base: 0x1_FEB0_0000, size: 0x4000, flags: pci_bar_flags::MEM64, reserved: 0 });
+        assert_eq!(nvme.bars[1], PciBar::default());
+        assert_eq!((nvme.bars[2].base, nvme.bars[2].size, nvme.bars[2].flags), (0xC040, 0x40, pci_bar_flags::IO));
+        // originals and the command register are back
+        assert_eq!(cfg.read32(0, 3, 0, 0x10), 0xFEB0_0004);
+        assert_eq!(cfg.read32(0, 3, 0, REG_COMMAND), 0x0000_0007);
+
+        assert_eq!(check_policy(&found[..2], SecurityPolicy::Maximum), Ok(()));
+        assert_eq!(check_policy(&found, SecurityPolicy::Maximum), Err(PciPolicyError::UnknownDmaDevice(&found[2])));
+        assert_eq!(check_policy(&found, SecurityPolicy::Relaxed), Ok(()));
+    }
+}
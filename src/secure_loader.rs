@@ -934,10 +934,13 @@ impl SecureLoader {
         0x1000000000 // Mock timestamp
     }
 
-    /// Get microsecond timestamp (mock implementation)
+    /// Microseconds on the calibrated TSC; calibrates once if nothing has yet
     fn get_microseconds(&self) -> u64 {
-        // In real implementation, use high-resolution timer
-        0
+        if crate::timing::tsc_hz() == 0 {
+            // safe: boot_services came from a live &BootServices in new() and boot services are still up
+            crate::timing::calibrate_tsc_hz(unsafe { self.boot_services.as_ref() });
+        }
+        crate::timing::now_us()
     }
 
     /// Align value up to boundary
@@ -2,9 +2,13 @@
 
 #![allow(dead_code)]
 
+use crate::hardware::detect_cpu_features;
 use crate::log::logger::{log_debug, log_error, log_info, log_warn};
+use nonos_handoff::cpu::cpu_feature;
 use uefi::cstr16;
 use uefi::prelude::*;
+use uefi::proto::tcg::v2::{HashLogExtendEventFlags, PcrEventInputs, Tcg};
+use uefi::proto::tcg::{EventType, PcrIndex};
 
 #[derive(Debug, Default)]
 pub struct SecurityContext {
@@ -14,6 +18,7 @@ pub struct SecurityContext {
     pub hardware_rng_available: bool,
     pub ed25519_selftest_ok: bool,
     pub blake3_selftest_ok: bool,
+    pub measured_boot_active: bool,
 }
 
 /// Initialize security context, enforce real checks, log all results.
@@ -33,6 +38,9 @@ pub fn initialize_security_subsystem(system_table: &mut SystemTable<Boot>) -> Se
     // Hardware RNG (EFI protocol and CPU features)
     ctx.hardware_rng_available = check_hardware_rng(system_table);
 
+    // Measured boot (TCG2 protocol)
+    ctx.measured_boot_active = check_tpm(system_table);
+
     // Self-tests: cryptography
     ctx.blake3_selftest_ok = blake3_selftest();
     ctx.ed25519_selftest_ok = ed25519_selftest();
@@ -114,32 +122,69 @@ fn check_hardware_rng(system_table: &mut SystemTable<Boot>) -> bool {
     false
 }
 
-#[cfg(target_arch = "x86_64")]
-fn cpu_rng_supported() -> bool {
-    unsafe {
-        let (_, _, ecx, _) = cpuid(1);
-        let rdrand = (ecx & (1 << 30)) != 0;
-        let (_, ebx, _, _) = cpuid(7);
-        let rdseed = (ebx & (1 << 18)) != 0;
-        rdrand || rdseed
+/// Check for a TCG2 (TPM 2.0) protocol instance usable for measurements.
+fn check_tpm(system_table: &mut SystemTable<Boot>) -> bool {
+    let bs = system_table.boot_services();
+    match bs.get_handle_for_protocol::<Tcg>() {
+        Ok(_) => {
+            log_info("tpm", "TCG2 protocol detected, measured boot active");
+            true
+        }
+        Err(_) => {
+            log_warn("tpm", "No TCG2 protocol; measurements disabled");
+            false
+        }
+    }
+}
+
+/// Hash `data` into `pcr` via TCG2 HashLogExtendEvent with a generic event tag.
+pub fn extend_pcr_measurement(system_table: &mut SystemTable<Boot>, pcr: u32, data: &[u8]) -> bool {
+    extend_pcr_measurement_with_event(system_table, pcr, data, b"NONOS measurement")
+}
+
+/// Hash `data` into `pcr` and record `event` in the TCG event log.
+pub fn extend_pcr_measurement_with_event(
+    system_table: &mut SystemTable<Boot>,
+    pcr: u32,
+    data: &[u8],
+    event: &[u8],
+) -> bool {
+    let bs = system_table.boot_services();
+    let handle = match bs.get_handle_for_protocol::<Tcg>() {
+        Ok(h) => h,
+        Err(_) => return false,
+    };
+    let mut tcg = match bs.open_protocol_exclusive::<Tcg>(handle) {
+        Ok(t) => t,
+        Err(e) => {
+            log_error("tpm", &format!("Cannot open TCG2 protocol: {:?}", e.status()));
+            return false;
+        }
+    };
+
+    let mut event_buf = [0u8; 256];
+    let inputs = match PcrEventInputs::new_in_buffer(&mut event_buf, PcrIndex(pcr), EventType::IPL, event) {
+        Ok(i) => i,
+        Err(_) => {
+            log_error("tpm", "TCG event data too large");
+            return false;
+        }
+    };
+
+    match tcg.hash_log_extend_event(HashLogExtendEventFlags::empty(), data, inputs) {
+        Ok(()) => {
+            log_debug("tpm", &format!("PCR {} extended", pcr));
+            true
+        }
+        Err(e) => {
+            log_error("tpm", &format!("PCR {} extend failed: {:?}", pcr, e.status()));
+            false
+        }
     }
 }
 
-#[cfg(target_arch = "x86_64")]
-unsafe fn cpuid(leaf: u32) -> (u32, u32, u32, u32) {
-    let mut eax: u32;
-    let mut ebx: u32;
-    let mut ecx: u32;
-    let mut edx: u32;
-    core::arch::asm!(
-        "cpuid",
-        inout("eax") leaf => eax,
-        lateout("ebx") ebx,
-        lateout("ecx") ecx,
-        lateout("edx") edx,
-        options(nostack, preserves_flags)
-    );
-    (eax, ebx, ecx, edx)
+fn cpu_rng_supported() -> bool {
+    detect_cpu_features().bits & (cpu_feature::RDRAND | cpu_feature::RDSEED) != 0
 }
 
 /// BLAKE3 self-test
@@ -174,5 +219,6 @@ fn display_security_status(sec: &SecurityContext, system_table: &mut SystemTable
     let _ = system_table.stdout().output_string(if sec.hardware_rng_available { cstr16!("HW RNG: AVAILABLE\r\n") } else { cstr16!("HW RNG: MISSING\r\n") });
     let _ = system_table.stdout().output_string(if sec.ed25519_selftest_ok { cstr16!("Ed25519: PASS\r\n") } else { cstr16!("Ed25519: FAIL\r\n") });
     let _ = system_table.stdout().output_string(if sec.blake3_selftest_ok { cstr16!("BLAKE3: PASS\r\n") } else { cstr16!("BLAKE3: FAIL\r\n") });
+    let _ = system_table.stdout().output_string(if sec.measured_boot_active { cstr16!("TPM2: MEASURING\r\n") } else { cstr16!("TPM2: UNAVAILABLE\r\n") });
     let _ = system_table.stdout().output_string(cstr16!("=======================\r\n"));
 }
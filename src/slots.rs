@@ -0,0 +1,332 @@
+//! A/B kernel capsule slots with try counters and automatic rollback.
+//!
+//! Two capsules live on the ESP at `SLOT_PATHS`. Their state is one
+//! non-volatile UEFI variable, `NonosSlotState`:
+//!
+//! ```text
+//!   u32 magic "NSAB"   u8 version (1)   u8 reserved[3]
+//!   slot A: u8 priority, u8 tries_remaining, u8 successful, u8 reserved
+//!   slot B: u8 priority, u8 tries_remaining, u8 successful, u8 reserved
+//! ```
+//!
+//! Selection: the highest-priority slot that is either `successful` or still
+//! has tries left (ties go to A). A slot that has never booted successfully
+//! has its try counter decremented and persisted *before* the jump; once it
+//! reaches zero the slot is demoted to priority 0 and the other slot boots.
+//! A slot whose capsule is missing or fails to verify is demoted immediately;
+//! any other read error only skips the slot for this boot. Likewise a missing
+//! or malformed `NonosSlotState` means a fresh install, but one that cannot be
+//! read is left untouched and the defaults are used without persisting them.
+//!
+//! Kernel contract (all variables under the NONOS vendor GUID
+//! `2a010f0e-6b84-4601-83f3-0a18f01624b7`, `config::NONOS_VENDOR`):
+//! - `NonosBootedSlot` (volatile, BS|RT, u8): slot index the loader booted.
+//! - Once the kernel considers the boot healthy it writes `NonosSlotSuccess`
+//!   (NV|BS|RT, u8 = slot index). On the next boot the loader marks that slot
+//!   `successful` and deletes the variable.
+//!
+//! An updater installs a new capsule into the inactive slot and rewrites
+//! `NonosSlotState` with that slot at a higher priority, `successful = 0` and
+//! `tries_remaining = DEFAULT_TRIES`.
+
+#![allow(dead_code)]
+
+use crate::capsule::Capsule;
+use crate::config::NONOS_VENDOR;
+use crate::loader::{read_file, LoaderError};
+use crate::log::logger::{log_error, log_info, log_warn};
+use alloc::format;
+use uefi::prelude::*;
+use uefi::table::runtime::{RuntimeServices, VariableAttributes};
+use uefi::{cstr16, CStr16};
+
+pub const SLOT_COUNT: usize = 2;
+pub const SLOT_PATHS: [&str; SLOT_COUNT] = ["\\EFI\\nonos\\kernel_a.capsule", "\\EFI\\nonos\\kernel_b.capsule"];
+pub const SLOT_NAMES: [&str; SLOT_COUNT] = ["A", "B"];
+
+pub const SLOT_STATE_MAGIC: u32 = u32::from_le_bytes(*b"NSAB");
+pub const SLOT_STATE_VERSION: u8 = 1;
+pub const DEFAULT_TRIES: u8 = 3;
+const STATE_LEN: usize = 8 + 4 * SLOT_COUNT;
+
+/// Per-slot persisted state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotInfo {
+    pub priority: u8,
+    pub tries_remaining: u8,
+    pub successful: bool,
+}
+
+impl SlotInfo {
+    pub fn bootable(&self) -> bool {
+        self.priority > 0 && (self.successful || self.tries_remaining > 0)
+    }
+}
+
+/// Contents of `NonosSlotState`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlotState {
+    pub slots: [SlotInfo; SLOT_COUNT],
+}
+
+impl Default for SlotState {
+    /// Fresh install: A preferred and trusted, B as a fallback
+    fn default() -> Self {
+        SlotState {
+            slots: [
+                SlotInfo { priority: 15, tries_remaining: 0, successful: true },
+                SlotInfo { priority: 14, tries_remaining: 0, successful: true },
+            ],
+        }
+    }
+}
+
+impl SlotState {
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        if data.len() < STATE_LEN
+            || u32::from_le_bytes([data[0], data[1], data[2], data[3]]) != SLOT_STATE_MAGIC
+            || data[4] != SLOT_STATE_VERSION
+        {
+            return None;
+        }
+        let mut state = SlotState::default();
+        for (i, s) in state.slots.iter_mut().enumerate() {
+            let o = 8 + 4 * i;
+            *s = SlotInfo { priority: data[o], tries_remaining: data[o + 1], successful: data[o + 2] != 0 };
+        }
+        Some(state)
+    }
+
+    pub fn encode(&self) -> [u8; STATE_LEN] {
+        let mut out = [0u8; STATE_LEN];
+        out[0..4].copy_from_slice(&SLOT_STATE_MAGIC.to_le_bytes());
+        out[4] = SLOT_STATE_VERSION;
+        for (i, s) in self.slots.iter().enumerate() {
+            let o = 8 + 4 * i;
+            out[o] = s.priority;
+            out[o + 1] = s.tries_remaining;
+            out[o + 2] = s.successful as u8;
+        }
+        out
+    }
+
+    /// Highest-priority bootable slot
+    pub fn select(&self) -> Option<usize> {
+        (0..SLOT_COUNT)
+            .filter(|&i| self.slots[i].bootable())
+            .fold(None, |best: Option<usize>, i| match best {
+                Some(b) if self.slots[b].priority >= self.slots[i].priority => Some(b),
+                _ => Some(i),
+            })
+    }
+
+    /// Consume one try of a not-yet-successful slot; demote it when exhausted
+    pub fn consume_try(&mut self, slot: usize) {
+        let s = &mut self.slots[slot];
+        if s.successful {
+            return;
+        }
+        s.tries_remaining = s.tries_remaining.saturating_sub(1);
+    }
+
+    /// Drop slots that can never boot again to priority 0
+    pub fn demote_exhausted(&mut self) {
+        for s in self.slots.iter_mut() {
+            if !s.bootable() {
+                s.priority = 0;
+            }
+        }
+    }
+
+    pub fn mark_bad(&mut self, slot: usize) {
+        self.slots[slot] = SlotInfo { priority: 0, tries_remaining: 0, successful: false };
+    }
+
+    pub fn mark_successful(&mut self, slot: usize) {
+        self.slots[slot].successful = true;
+        self.slots[slot].tries_remaining = 0;
+    }
+}
+
+fn nv_attrs() -> VariableAttributes {
+    VariableAttributes::NON_VOLATILE | VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS
+}
+
+/// Persisted state; defaults when the variable is absent or malformed,
+/// `Err` when it exists but could not be read
+fn read_state(rt: &RuntimeServices) -> Result<SlotState, Status> {
+    let mut buf = [0u8; 32];
+    match rt.get_variable(cstr16!("NonosSlotState"), &NONOS_VENDOR, &mut buf) {
+        Ok((data, _)) => Ok(SlotState::decode(data).unwrap_or_else(|| {
+            log_warn("slots", "NonosSlotState malformed, using defaults");
+            SlotState::default()
+        })),
+        // larger than any state we write: corrupt
+        Err(e) if e.status() == Status::BUFFER_TOO_SMALL => {
+            log_warn("slots", "NonosSlotState oversized, using defaults");
+            Ok(SlotState::default())
+        }
+        Err(e) if e.status() == Status::NOT_FOUND => Ok(SlotState::default()),
+        Err(e) => Err(e.status()),
+    }
+}
+
+fn write_state(rt: &RuntimeServices, state: &SlotState) -> bool {
+    rt.set_variable(cstr16!("NonosSlotState"), &NONOS_VENDOR, nv_attrs(), &state.encode()).is_ok()
+}
+
+/// Fold a pending `NonosSlotSuccess` report from the kernel into `state`.
+fn take_success_report(rt: &RuntimeServices, state: &mut SlotState) {
+    let name: &CStr16 = cstr16!("NonosSlotSuccess");
+    let mut buf = [0u8; 4];
+    if let Ok((data, _)) = rt.get_variable(name, &NONOS_VENDOR, &mut buf) {
+        match data.first() {
+            Some(&i) if (i as usize) < SLOT_COUNT => {
+                state.mark_successful(i as usize);
+                log_info("slots", &format!("Kernel confirmed slot {}", SLOT_NAMES[i as usize]));
+            }
+            _ => log_warn("slots", "Ignoring malformed NonosSlotSuccess"),
+        }
+        let _ = rt.delete_variable(name, &NONOS_VENDOR);
+    }
+}
+
+/// Whether a failed capsule read means the slot itself is bad
+fn slot_is_bad(e: &LoaderError) -> bool {
+    match e {
+        LoaderError::UefiError { status, .. } => *status == Status::NOT_FOUND,
+        LoaderError::FileTooLarge { .. } => true,
+        _ => false,
+    }
+}
+
+/// Pick a slot, persist the decremented try counter and load its capsule.
+/// Falls back to the other slot when a capsule is missing or fails verification.
+pub fn load_slot_capsule(st: &mut SystemTable<Boot>, image_handle: Handle) -> Result<Capsule, &'static str> {
+    let (mut state, persist) = match read_state(st.runtime_services()) {
+        Ok(s) => (s, true),
+        Err(status) => {
+            log_warn(
+                "slots",
+                &format!("NonosSlotState unreadable ({:?}), booting defaults without updating it", status),
+            );
+            (SlotState::default(), false)
+        }
+    };
+    if persist {
+        take_success_report(st.runtime_services(), &mut state);
+    }
+    state.demote_exhausted();
+    // slots skipped for this boot only, after a transient read error
+    let mut skipped = [false; SLOT_COUNT];
+
+    loop {
+        let mut candidates = state;
+        for i in (0..SLOT_COUNT).filter(|&i| skipped[i]) {
+            candidates.slots[i].priority = 0;
+        }
+        let Some(slot) = candidates.select() else {
+            if persist {
+                write_state(st.runtime_services(), &state);
+            }
+            log_error("slots", "No bootable kernel slot left");
+            return Err("no bootable kernel slot");
+        };
+
+        let capsule = match read_file(st.boot_services(), image_handle, SLOT_PATHS[slot]) {
+            Err(e) if !slot_is_bad(&e) => {
+                log_warn("slots", &format!("Slot {} unreadable this boot: {}", SLOT_NAMES[slot], e));
+                skipped[slot] = true;
+                continue;
+            }
+            Err(_) => Err("capsule file missing or oversized"),
+            Ok(file) => Capsule::from_blob(file.as_slice()).map_err(|e| {
+                file.free(st.boot_services());
+                e
+            }),
+        };
+
+        match capsule {
+            Ok(c) => {
+                // a try is only spent on a capsule that is about to boot
+                state.consume_try(slot);
+                if persist && !write_state(st.runtime_services(), &state) {
+                    log_warn("slots", "Failed to persist NonosSlotState");
+                }
+                let _ = st.runtime_services().set_variable(
+                    cstr16!("NonosBootedSlot"),
+                    &NONOS_VENDOR,
+                    VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS,
+                    &[slot as u8],
+                );
+                let s = state.slots[slot];
+                log_info(
+                    "slots",
+                    &format!(
+                        "Booting slot {} (priority {}, tries left {}, successful {})",
+                        SLOT_NAMES[slot], s.priority, s.tries_remaining, s.successful
+                    ),
+                );
+                return Ok(c);
+            }
+            Err(e) => {
+                log_error("slots", &format!("Slot {} rejected: {}", SLOT_NAMES[slot], e));
+                state.mark_bad(slot);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(a: (u8, u8, bool), b: (u8, u8, bool)) -> SlotState {
+        SlotState {
+            slots: [
+                SlotInfo { priority: a.0, tries_remaining: a.1, successful: a.2 },
+                SlotInfo { priority: b.0, tries_remaining: b.1, successful: b.2 },
+            ],
+        }
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let s = state((15, 2, false), (14, 0, true));
+        assert_eq!(SlotState::decode(&s.encode()), Some(s));
+        assert_eq!(SlotState::decode(&[0u8; STATE_LEN]), None);
+    }
+
+    #[test]
+    fn new_slot_rolls_back_after_tries() {
+        // B freshly installed with higher priority, A known good
+        let mut s = state((14, 0, true), (15, DEFAULT_TRIES, false));
+        for _ in 0..DEFAULT_TRIES {
+            s.demote_exhausted();
+            assert_eq!(s.select(), Some(1));
+            s.consume_try(1);
+        }
+        s.demote_exhausted();
+        assert_eq!(s.select(), Some(0));
+        assert_eq!(s.slots[1].priority, 0);
+    }
+
+    #[test]
+    fn success_stops_counting() {
+        let mut s = state((14, 0, true), (15, 1, false));
+        s.consume_try(1);
+        s.mark_successful(1);
+        s.demote_exhausted();
+        assert_eq!(s.select(), Some(1));
+        s.consume_try(1);
+        assert_eq!(s.select(), Some(1));
+    }
+
+    #[test]
+    fn no_bootable_slot() {
+        let mut s = state((15, 0, true), (14, 0, false));
+        s.mark_bad(0);
+        s.demote_exhausted();
+        assert_eq!(s.select(), None);
+    }
+}
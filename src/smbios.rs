@@ -0,0 +1,351 @@
+//! SMBIOS identity and memory inventory (layout: `nonos_handoff::smbios`).
+//!
+//! The entry point comes from the UEFI configuration table, `_SM3_` (64-bit
+//! table address) preferred over `_SM_`. Both are checksummed before the
+//! structure table is trusted. Only types 0 (BIOS), 1 (system) and 17
+//! (memory device) are read; the walk stops at the end-of-table structure
+//! or at the first structure that would run past the table.
+//!
+//! The system UUID is the loader's machine identity. SMBIOS 2.6+ stores its
+//! first three fields little endian; `parse` converts it to RFC 4122 byte
+//! order so the same machine reports the same UUID whatever its firmware
+//! version.
+
+#![allow(dead_code)]
+
+use crate::bytes::{u16_at, u32_at, u64_at};
+use alloc::string::String;
+use alloc::vec::Vec;
+use nonos_handoff::smbios::{smbios_flags, SmbiosIdentity, SmbiosMemoryDevice};
+use uefi::prelude::*;
+
+/// Memory devices kept; servers top out well below this
+pub const MAX_MEMORY_DEVICES: usize = 128;
+/// Largest structure table we agree to map as a slice
+const MAX_TABLE_LEN: usize = 1024 * 1024;
+
+const TYPE_BIOS: u8 = 0;
+const TYPE_SYSTEM: u8 = 1;
+const TYPE_MEMORY_DEVICE: u8 = 17;
+const TYPE_END: u8 = 127;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * 1024;
+
+fn checksum_ok(bytes: &[u8]) -> bool {
+    bytes.iter().fold(0u8, |acc, &b| acc.wrapping_add(b)) == 0
+}
+
+/// A checksummed entry point
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntryPoint {
+    /// 2 for `_SM_`, 3 for `_SM3_`
+    pub kind: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub table: u64,
+    /// Exact length for 2.x, an upper bound for 3.x
+    pub table_len: usize,
+}
+
+/// Entry point at the start of `bytes` (at least 32 bytes of it)
+pub fn parse_entry(bytes: &[u8]) -> Option<EntryPoint> {
+    if bytes.starts_with(b"_SM3_") {
+        let len = *bytes.get(6)? as usize;
+        if len < 0x18 || !checksum_ok(bytes.get(..len)?) {
+            return None;
+        }
+        return Some(EntryPoint {
+            kind: 3,
+            major: bytes[7],
+            minor: bytes[8],
+            table: u64_at(bytes, 0x10)?,
+            table_len: u32_at(bytes, 0x0C)? as usize,
+        });
+    }
+    if bytes.starts_with(b"_SM_") {
+        let len = *bytes.get(5)? as usize;
+        // the intermediate `_DMI_` anchor has its own checksum
+        if len < 0x1E || !checksum_ok(bytes.get(..len)?) || bytes.get(0x10..0x15)? != b"_DMI_" {
+            return None;
+        }
+        if !checksum_ok(bytes.get(0x10..0x1F)?) {
+            return None;
+        }
+        return Some(EntryPoint {
+            kind: 2,
+            major: bytes[6],
+            minor: bytes[7],
+            table: u32_at(bytes, 0x18)? as u64,
+            table_len: u16_at(bytes, 0x16)? as usize,
+        });
+    }
+    None
+}
+
+/// One structure: formatted area (header included) and its string set
+#[derive(Debug, Clone, Copy)]
+pub struct Structure<'a> {
+    pub kind: u8,
+    pub handle: u16,
+    pub formatted: &'a [u8],
+    strings: &'a [u8],
+}
+
+impl<'a> Structure<'a> {
+    /// String referenced by the byte at `offset`; `None` for index 0 or a missing string
+    pub fn string(&self, offset: usize) -> Option<&'a [u8]> {
+        let index = *self.formatted.get(offset)? as usize;
+        if index == 0 {
+            return None;
+        }
+        self.strings.split(|&b| b == 0).nth(index - 1).filter(|s| !s.is_empty())
+    }
+
+    fn u8(&self, offset: usize) -> Option<u8> {
+        self.formatted.get(offset).copied()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        u16_at(self.formatted, offset)
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        u32_at(self.formatted, offset)
+    }
+}
+
+/// Structures in table order, up to (not including) end-of-table
+pub fn structures(table: &[u8]) -> impl Iterator<Item = Structure<'_>> {
+    let mut rest = table;
+    core::iter::from_fn(move || {
+        let len = *rest.get(1)? as usize;
+        if len < 4 || rest.len() < len + 2 {
+            return None;
+        }
+        let kind = rest[0];
+        let handle = u16_at(rest, 2)?;
+        // the string set ends with a double NUL; a structure without strings is just that
+        let tail = &rest[len..];
+        let strings_len = tail.windows(2).position(|w| w == [0, 0])?;
+        let s = Structure { kind, handle, formatted: &rest[..len], strings: &tail[..strings_len] };
+        rest = &tail[strings_len + 2..];
+        (kind != TYPE_END).then_some(s)
+    })
+}
+
+/// Printable ASCII copy, trailing blanks dropped, cut to `dst`
+fn copy_str(dst: &mut [u8], src: Option<&[u8]>) {
+    let src = src.unwrap_or(&[]);
+    let end = src.iter().rposition(|&b| b != b' ').map_or(0, |p| p + 1);
+    for (d, &s) in dst.iter_mut().zip(&src[..end]) {
+        *d = if (0x20..0x7F).contains(&s) { s } else { b'?' };
+    }
+}
+
+/// Type 1 UUID in RFC 4122 order; `None` when the firmware left it unset
+fn system_uuid(raw: &[u8], major: u8, minor: u8) -> Option<[u8; 16]> {
+    let mut uuid: [u8; 16] = raw.try_into().ok()?;
+    if uuid.iter().all(|&b| b == 0) || uuid.iter().all(|&b| b == 0xFF) {
+        return None;
+    }
+    if (major, minor) >= (2, 6) {
+        uuid[..4].reverse();
+        uuid[4..6].reverse();
+        uuid[6..8].reverse();
+    }
+    Some(uuid)
+}
+
+/// Type 17 size field: MiB, KiB with bit 15 set, or the extended field
+fn memory_size(s: &Structure) -> u64 {
+    match s.u16(0x0C) {
+        None | Some(0) | Some(0xFFFF) => 0,
+        Some(0x7FFF) => s.u32(0x1C).map_or(0, |mb| (mb & 0x7FFF_FFFF) as u64 * MIB),
+        Some(kb) if kb & 0x8000 != 0 => (kb & 0x7FFF) as u64 * KIB,
+        Some(mb) => mb as u64 * MIB,
+    }
+}
+
+/// Speed field at `offset`, or the 32-bit extended one when it reads 0xFFFF
+fn memory_speed(s: &Structure, offset: usize, extended: usize) -> u32 {
+    match s.u16(offset) {
+        Some(0xFFFF) => s.u32(extended).unwrap_or(0),
+        v => v.unwrap_or(0) as u32,
+    }
+}
+
+fn memory_device(s: &Structure) -> SmbiosMemoryDevice {
+    let mut d = SmbiosMemoryDevice {
+        size: memory_size(s),
+        speed_mts: memory_speed(s, 0x15, 0x54),
+        configured_speed_mts: memory_speed(s, 0x20, 0x58),
+        data_width: s.u16(0x0A).filter(|&w| w != 0xFFFF).unwrap_or(0),
+        memory_type: s.u8(0x12).unwrap_or(0),
+        form_factor: s.u8(0x0E).unwrap_or(0),
+        ..Default::default()
+    };
+    copy_str(&mut d.locator, s.string(0x10));
+    copy_str(&mut d.manufacturer, s.string(0x17));
+    copy_str(&mut d.part_number, s.string(0x1A));
+    d
+}
+
+/// What the loader keeps from the table
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub identity: SmbiosIdentity,
+    pub memory: Vec<SmbiosMemoryDevice>,
+}
+
+impl Inventory {
+    /// Installed memory according to the type 17 structures
+    pub fn installed_memory(&self) -> u64 {
+        self.memory.iter().map(|d| d.size).sum()
+    }
+}
+
+/// Identity and memory devices of a structure table for SMBIOS `major.minor`
+pub fn parse(table: &[u8], major: u8, minor: u8) -> Inventory {
+    let mut inv = Inventory::default();
+    inv.identity.major = major;
+    inv.identity.minor = minor;
+    for s in structures(table) {
+        let id = &mut inv.identity;
+        match s.kind {
+            TYPE_BIOS => {
+                copy_str(&mut id.bios_vendor, s.string(0x04));
+                copy_str(&mut id.bios_version, s.string(0x05));
+            }
+            TYPE_SYSTEM => {
+                copy_str(&mut id.manufacturer, s.string(0x04));
+                copy_str(&mut id.product, s.string(0x05));
+                copy_str(&mut id.serial, s.string(0x07));
+                if let Some(uuid) = s.formatted.get(0x08..0x18).and_then(|raw| system_uuid(raw, major, minor)) {
+                    id.uuid = uuid;
+                    id.flags |= smbios_flags::UUID_VALID;
+                }
+            }
+            TYPE_MEMORY_DEVICE if inv.memory.len() < MAX_MEMORY_DEVICES => inv.memory.push(memory_device(&s)),
+            _ => {}
+        }
+    }
+    inv.identity.memory_device_count = inv.memory.len() as u32;
+    inv
+}
+
+/// Canonical 8-4-4-4-12 text form
+pub fn format_uuid(uuid: &[u8; 16]) -> String {
+    let mut s = String::with_capacity(36);
+    for (i, b) in uuid.iter().enumerate() {
+        if matches!(i, 4 | 6 | 8 | 10) {
+            s.push('-');
+        }
+        s.push_str(&alloc::format!("{:02x}", b));
+    }
+    s
+}
+
+/// Physical address of the SMBIOS entry point (3.x preferred), 0 if absent
+pub fn find_entry(st: &SystemTable<Boot>) -> u64 {
+    let table = st.config_table();
+    table
+        .iter()
+        .find(|e| e.guid == uefi::table::cfg::SMBIOS3_GUID)
+        .or_else(|| table.iter().find(|e| e.guid == uefi::table::cfg::SMBIOS_GUID))
+        .map_or(0, |e| e.address as u64)
+}
+
+/// Parse the table behind the entry point at `entry`
+pub fn discover(entry: u64) -> Option<Inventory> {
+    if entry == 0 {
+        return None;
+    }
+    // safe: firmware config table entry; both entry point formats fit in 32 bytes
+    let head = unsafe { core::slice::from_raw_parts(entry as *const u8, 32) };
+    let ep = parse_entry(head)?;
+    if ep.table == 0 || ep.table_len == 0 {
+        return None;
+    }
+    // safe: the checksummed entry point describes the table; the walk stops at its end tag
+    let table = unsafe { core::slice::from_raw_parts(ep.table as *const u8, ep.table_len.min(MAX_TABLE_LEN)) };
+    Some(parse(table, ep.major, ep.minor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+    use nonos_handoff::smbios::text;
+
+    fn structure(kind: u8, body: &[u8], strings: &[&str]) -> Vec<u8> {
+        let mut s = vec![kind, (4 + body.len()) as u8, 0, 0];
+        s.extend_from_slice(body);
+        for st in strings {
+            s.extend_from_slice(st.as_bytes());
+            s.push(0);
+        }
+        if strings.is_empty() {
+            s.push(0);
+        }
+        s.push(0);
+        s
+    }
+
+    #[test]
+    fn parses_entry_points() {
+        let mut sm3 = [0u8; 32];
+        sm3[..5].copy_from_slice(b"_SM3_");
+        sm3[6] = 0x18;
+        sm3[7..10].copy_from_slice(&[3, 3, 0]);
+        sm3[0x0C..0x10].copy_from_slice(&0x1000u32.to_le_bytes());
+        sm3[0x10..0x18].copy_from_slice(&0x7F00_0000u64.to_le_bytes());
+        sm3[5] = 0u8.wrapping_sub(sm3[..0x18].iter().fold(0u8, |a, &b| a.wrapping_add(b)));
+        let ep = parse_entry(&sm3).unwrap();
+        assert_eq!((ep.kind, ep.major, ep.minor, ep.table, ep.table_len), (3, 3, 3, 0x7F00_0000, 0x1000));
+        sm3[0x10] ^= 1;
+        assert_eq!(parse_entry(&sm3), None);
+    }
+
+    #[test]
+    fn extracts_identity_and_memory() {
+        // QEMU q35 with 2 GiB in one DIMM, SMBIOS 3.0
+        let mut table = structure(TYPE_BIOS, &[1, 2, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0], &["EFI Development Kit II / OVMF", "0.0.0", "02/06/2015"]);
+        let mut system = vec![1, 2, 3, 4];
+        system.extend_from_slice(&[0x33, 0x22, 0x11, 0x00, 0x55, 0x44, 0x77, 0x66, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF]);
+        system.extend_from_slice(&[6, 0, 0]);
+        table.extend(structure(TYPE_SYSTEM, &system, &["QEMU", "Standard PC (Q35 + ICH9, 2009)   ", "pc-q35-8.2", "SN\u{7}1234"]));
+        let mut dimm = vec![0u8; 0x54 - 4];
+        dimm[0x0A - 4..0x0C - 4].copy_from_slice(&64u16.to_le_bytes());
+        dimm[0x0C - 4..0x0E - 4].copy_from_slice(&0x7FFFu16.to_le_bytes());
+        dimm[0x0E - 4] = 0x09;
+        dimm[0x10 - 4] = 1;
+        dimm[0x12 - 4] = 0x07;
+        dimm[0x15 - 4..0x17 - 4].copy_from_slice(&3200u16.to_le_bytes());
+        dimm[0x17 - 4] = 2;
+        dimm[0x1C - 4..0x20 - 4].copy_from_slice(&2048u32.to_le_bytes());
+        table.extend(structure(TYPE_MEMORY_DEVICE, &dimm, &["DIMM 0", "QEMU"]));
+        table.extend(structure(TYPE_END, &[], &[]));
+        // never reached: past end-of-table
+        table.extend(structure(TYPE_MEMORY_DEVICE, &dimm, &[]));
+
+        let inv = parse(&table, 3, 0);
+        let id = &inv.identity;
+        assert_eq!(text(&id.manufacturer), "QEMU");
+        assert_eq!(text(&id.product), "Standard PC (Q35 + ICH9, 2009)");
+        assert_eq!(text(&id.serial), "SN?1234");
+        assert_eq!(text(&id.bios_version), "0.0.0");
+        assert_eq!(format_uuid(&id.uuid().unwrap()), "00112233-4455-6677-8899-aabbccddeeff");
+
+        assert_eq!(id.memory_device_count, 1);
+        let d = &inv.memory[0];
+        assert_eq!((d.size, d.speed_mts, d.data_width, d.form_factor), (2048 * MIB, 3200, 64, 0x09));
+        assert_eq!((text(&d.locator), text(&d.manufacturer), text(&d.part_number)), ("DIMM 0", "QEMU", ""));
+
+        // pre-2.6 firmware stores the UUID as is; an all-zero UUID is not an identity
+        assert_eq!(parse(&table, 2, 5).identity.uuid().unwrap()[0], 0x33);
+        system[4..20].fill(0);
+        let table = structure(TYPE_SYSTEM, &system, &["QEMU"]);
+        assert_eq!(parse(&table, 3, 0).identity.uuid(), None);
+    }
+}
@@ -0,0 +1,363 @@
+//! Application processor bring-up and parking (kernel side: `nonos_handoff::smp`).
+//!
+//! Before ExitBootServices `prepare` lists the MADT processors and reserves
+//! everything the APs will touch: a trampoline page below 1 MiB
+//! (LOADER_CODE), one `ApMailbox` and parking stack per CPU (LOADER_DATA).
+//! After ExitBootServices `start` wakes each enabled AP with INIT-SIPI-SIPI.
+//! Doing it after the exit matters: firmware MP drivers re-park every AP
+//! from their ExitBootServices callback, which would pull APs out of a loop
+//! entered through `MpServices`.
+//!
+//! The trampoline goes straight from real mode to long mode on the BSP's
+//! CR3 (so that must sit below 4 GiB), claims its mailbox from a single
+//! slot with `xchg`, and spins on `goto_address`. APs are started one at a
+//! time; an AP that misses its window finds the slot empty and halts.
+
+#![allow(dead_code)]
+
+use crate::acpi::{self, MadtCpu, MAX_CPUS};
+use crate::log::logger::{log_info, log_warn};
+use crate::timing::{rdtsc, spin_us};
+use alloc::format;
+use core::mem::size_of;
+use core::sync::atomic::{fence, AtomicU64, Ordering};
+use nonos_handoff::smp::{ap_state, smp_cpu_flags, ApMailbox, SmpCpu};
+use uefi::prelude::*;
+use uefi::table::boot::{AllocateType, MemoryType};
+
+/// Parking stack per AP
+const AP_STACK_SIZE: usize = 16 * 1024;
+/// INIT to first SIPI, per the MP specification
+const INIT_DELAY_US: u64 = 10_000;
+const SIPI_DELAY_US: u64 = 200;
+/// How long one AP gets to reach the parking loop
+const PARK_TIMEOUT_US: u64 = 100_000;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const IA32_EFER: u32 = 0xC000_0080;
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+const X2APIC_ID_MSR: u32 = 0x802;
+const X2APIC_ICR_MSR: u32 = 0x830;
+const XAPIC_ID: u64 = 0x20;
+const XAPIC_ICR_LOW: u64 = 0x300;
+const XAPIC_ICR_HIGH: u64 = 0x310;
+const ICR_INIT: u32 = 0x0000_4500;
+const ICR_STARTUP: u32 = 0x0000_4600;
+const ICR_PENDING: u32 = 1 << 12;
+
+/// CR4 bits the AP needs before paging: PAE, PGE, LA57 as on the BSP
+const CR4_AP_MASK: u64 = (1 << 5) | (1 << 7) | (1 << 12);
+/// EFER bits carried over: SCE, LME, NXE
+const EFER_AP_MASK: u64 = (1 << 0) | (1 << 8) | (1 << 11);
+
+/* Trampoline data block, patched after the copy (offsets from the start) */
+const TRAMP_GDTR_BASE: usize = 10;
+const TRAMP_FAR_OFFSET: usize = 16;
+const TRAMP_CR3: usize = 24;
+const TRAMP_CR4: usize = 32;
+const TRAMP_EFER: usize = 40;
+const TRAMP_SLOT: usize = 48;
+
+/* Real mode -> long mode AP trampoline, copied to a page below 1 MiB.
+ * The SIPI starts it at offset 0 with CS = page >> 4. */
+core::arch::global_asm!(
+    ".pushsection .text.nonos_ap, \"ax\"",
+    ".global nonos_ap_trampoline",
+    ".global nonos_ap_trampoline_end",
+    ".balign 16",
+    ".code16",
+    "nonos_ap_trampoline:",
+    "    cli",
+    "    jmp 2f",
+    ".balign 8",
+    "    .word 23",                                 // 8: GDTR limit
+    "    .long 56",                                 // 10: GDTR base, + page
+    "    .word 0",
+    "    .long 3f - nonos_ap_trampoline",           // 16: far jump offset, + page
+    "    .word 0x08",                               // 20: 64-bit code selector
+    "    .word 0",
+    "    .quad 0",                                  // 24: CR3
+    "    .long 0",                                  // 32: CR4
+    "    .long 0",
+    "    .long 0",                                  // 40: EFER low
+    "    .long 0",                                  // 44: EFER high
+    "    .quad 0",                                  // 48: mailbox slot
+    "    .quad 0",                                  // 56: GDT
+    "    .quad 0x00AF9A000000FFFF",
+    "    .quad 0x00CF92000000FFFF",
+    "2:",
+    "    mov ax, cs",
+    "    mov ds, ax",
+    "    lgdt dword ptr [8]",
+    "    mov eax, dword ptr [32]",
+    "    mov cr4, eax",
+    "    mov eax, dword ptr [24]",
+    "    mov cr3, eax",
+    "    mov ecx, 0xC0000080",
+    "    mov eax, dword ptr [40]",
+    "    mov edx, dword ptr [44]",
+    "    wrmsr",
+    "    mov eax, 0x80010033",                      // PG WP NE ET MP PE
+    "    mov cr0, eax",
+    "    jmp fword ptr [16]",
+    ".code64",
+    "3:",
+    "    mov ax, 0x10",
+    "    mov ds, ax",
+    "    mov es, ax",
+    "    mov ss, ax",
+    "    xor edi, edi",
+    "    xchg rdi, qword ptr [rip + nonos_ap_trampoline + 48]",
+    "    test rdi, rdi",
+    "    jz 5f",
+    "    mov rsp, qword ptr [rdi + 24]",            // ApMailbox.stack_top
+    "    mov dword ptr [rdi + 4], 1",               // state = PARKED
+    "4:",
+    "    pause",
+    "    mov rax, qword ptr [rdi + 8]",             // goto_address
+    "    test rax, rax",
+    "    jz 4b",
+    "    mov dword ptr [rdi + 4], 2",               // state = STARTED
+    "    jmp rax",
+    "5:",
+    "    cli",
+    "    hlt",
+    "    jmp 5b",
+    "nonos_ap_trampoline_end:",
+    ".popsection",
+);
+
+extern "C" {
+    static nonos_ap_trampoline: u8;
+    static nonos_ap_trampoline_end: u8;
+}
+
+pub(crate) unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    core::arch::asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi, options(nostack, preserves_flags));
+    ((hi as u64) << 32) | lo as u64
+}
+
+pub(crate) unsafe fn wrmsr(msr: u32, value: u64) {
+    core::arch::asm!("wrmsr", in("ecx") msr, in("eax") value as u32, in("edx") (value >> 32) as u32, options(nostack, preserves_flags));
+}
+
+/// The BSP's local APIC in whichever mode firmware left it
+#[derive(Clone, Copy)]
+enum LocalApic {
+    X2Apic,
+    XApic(u64),
+}
+
+impl LocalApic {
+    fn current() -> Self {
+        // safe: IA32_APIC_BASE exists on every x86_64 CPU
+        let base = unsafe { rdmsr(IA32_APIC_BASE) };
+        if base & APIC_BASE_X2APIC != 0 {
+            LocalApic::X2Apic
+        } else {
+            LocalApic::XApic(base & 0x000F_FFFF_FFFF_F000)
+        }
+    }
+
+    fn id(self) -> u32 {
+        match self {
+            // safe: x2APIC mode is enabled, so the ID MSR exists
+            LocalApic::X2Apic => unsafe { rdmsr(X2APIC_ID_MSR) as u32 },
+            // safe: firmware identity-maps the local APIC page
+            LocalApic::XApic(b) => unsafe { core::ptr::read_volatile((b + XAPIC_ID) as *const u32) >> 24 },
+        }
+    }
+
+    fn can_target(self, apic_id: u32) -> bool {
+        matches!(self, LocalApic::X2Apic) || apic_id < 0xFF
+    }
+
+    fn send_ipi(self, dest: u32, icr: u32) {
+        // order the mailbox slot store before the IPI (x2APIC ICR writes are not serializing)
+        fence(Ordering::SeqCst);
+        match self {
+            // safe: x2APIC mode is enabled; ICR takes the destination in the high half
+            LocalApic::X2Apic => unsafe { wrmsr(X2APIC_ICR_MSR, ((dest as u64) << 32) | icr as u64) },
+            // safe: firmware identity-maps the local APIC page
+            LocalApic::XApic(b) => unsafe {
+                core::ptr::write_volatile((b + XAPIC_ICR_HIGH) as *mut u32, dest << 24);
+                core::ptr::write_volatile((b + XAPIC_ICR_LOW) as *mut u32, icr);
+                while core::ptr::read_volatile((b + XAPIC_ICR_LOW) as *const u32) & ICR_PENDING != 0 {
+                    core::hint::spin_loop();
+                }
+            },
+        }
+    }
+}
+
+/// Everything reserved for the APs before ExitBootServices
+pub struct ApStartup {
+    cpus: &'static mut [SmpCpu],
+    mailboxes: *mut ApMailbox,
+    /// Trampoline page, 0 if the APs cannot be started (they are still listed)
+    trampoline: u64,
+    cr3: u64,
+    cr4: u64,
+    efer: u64,
+    lapic: LocalApic,
+}
+
+/// List the MADT processors and reserve trampoline, mailboxes and stacks
+pub fn prepare(st: &SystemTable<Boot>, rsdp: u64) -> Option<ApStartup> {
+    let madt = acpi::find_table(rsdp, b"APIC")?;
+    let mut found = [MadtCpu::default(); MAX_CPUS];
+    let n = acpi::madt_cpus(madt, &mut found);
+    if n == 0 {
+        return None;
+    }
+    let lapic = LocalApic::current();
+    let bsp = lapic.id();
+
+    // table, then mailboxes (64-byte aligned), then stacks (page aligned)
+    let table_bytes = (n * size_of::<SmpCpu>() + 63) & !63;
+    let stacks_off = (table_bytes + n * size_of::<ApMailbox>()).next_multiple_of(0x1000);
+    let pages = (stacks_off + n * AP_STACK_SIZE).div_ceil(0x1000);
+    let bs = st.boot_services();
+    let region = match bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages) {
+        Ok(r) => r,
+        Err(e) => {
+            log_warn("smp", &format!("AP region allocation failed: {:?}", e.status()));
+            return None;
+        }
+    };
+    // safe: fresh allocation of `pages` pages laid out as computed above
+    let (cpus, mailboxes) = unsafe {
+        core::ptr::write_bytes(region as *mut u8, 0, pages * 0x1000);
+        (
+            core::slice::from_raw_parts_mut(region as *mut SmpCpu, n),
+            (region as usize + table_bytes) as *mut ApMailbox,
+        )
+    };
+    for (i, (cpu, m)) in cpus.iter_mut().zip(&found[..n]).enumerate() {
+        let mut flags = 0;
+        if m.apic_id == bsp {
+            flags |= smp_cpu_flags::BSP;
+        }
+        if m.online_capable {
+            flags |= smp_cpu_flags::ONLINE_CAPABLE;
+        }
+        if m.x2apic {
+            flags |= smp_cpu_flags::X2APIC;
+        }
+        *cpu = SmpCpu { apic_id: m.apic_id, acpi_uid: m.acpi_uid, flags, reserved: 0, mailbox: 0 };
+        // safe: i < n mailboxes were reserved; the memory is zeroed
+        unsafe {
+            let mb = &mut *mailboxes.add(i);
+            mb.apic_id = m.apic_id;
+            mb.stack_top = (region as usize + stacks_off + (i + 1) * AP_STACK_SIZE) as u64;
+        }
+    }
+
+    let (cr3, cr4): (u64, u64);
+    // safe: reading control registers has no side effects
+    unsafe {
+        core::arch::asm!("mov {}, cr3", out(reg) cr3, options(nomem, nostack, preserves_flags));
+        core::arch::asm!("mov {}, cr4", out(reg) cr4, options(nomem, nostack, preserves_flags));
+    }
+    // safe: EFER exists on every x86_64 CPU
+    let efer = unsafe { rdmsr(IA32_EFER) };
+
+    let trampoline = if cr3 >= 1 << 32 {
+        log_warn("smp", "Page tables above 4 GiB; APs left for the kernel to start");
+        0
+    } else {
+        match bs.allocate_pages(AllocateType::MaxAddress(0xF_FFFF), MemoryType::LOADER_CODE, 1) {
+            Ok(p) if p != 0 => p,
+            _ => {
+                log_warn("smp", "No page below 1 MiB for the AP trampoline");
+                0
+            }
+        }
+    };
+
+    log_info("smp", &format!("{} processors in the MADT, trampoline at {:#x}", n, trampoline));
+    Some(ApStartup { cpus, mailboxes, trampoline, cr3: cr3 & !0xFFF, cr4: cr4 & CR4_AP_MASK, efer: efer & EFER_AP_MASK, lapic })
+}
+
+impl ApStartup {
+    pub fn cpu_count(&self) -> usize {
+        self.cpus.len()
+    }
+
+    /// Start and park every enabled AP. Runs after ExitBootServices: only
+    /// plain memory, MSR and local APIC accesses; delays come from the TSC.
+    pub fn start(&mut self, tsc_hz: u64) -> &[SmpCpu] {
+        if self.trampoline == 0 || tsc_hz == 0 {
+            return self.cpus;
+        }
+        let page = self.trampoline;
+        // safe: the trampoline symbols bound one contiguous code blob; page is ours and 4 KiB
+        unsafe {
+            let start = &nonos_ap_trampoline as *const u8;
+            let len = (&nonos_ap_trampoline_end as *const u8).offset_from(start) as usize;
+            core::ptr::copy_nonoverlapping(start, page as *mut u8, len.min(0x1000));
+            let p = page as *mut u8;
+            let gdt = core::ptr::read_unaligned(p.add(TRAMP_GDTR_BASE) as *const u32);
+            core::ptr::write_unaligned(p.add(TRAMP_GDTR_BASE) as *mut u32, gdt + page as u32);
+            let far = core::ptr::read_unaligned(p.add(TRAMP_FAR_OFFSET) as *const u32);
+            core::ptr::write_unaligned(p.add(TRAMP_FAR_OFFSET) as *mut u32, far + page as u32);
+            core::ptr::write_unaligned(p.add(TRAMP_CR3) as *mut u64, self.cr3);
+            core::ptr::write_unaligned(p.add(TRAMP_CR4) as *mut u32, self.cr4 as u32);
+            core::ptr::write_unaligned(p.add(TRAMP_EFER) as *mut u64, self.efer);
+        }
+        // safe: 8-byte aligned slot inside our trampoline page
+        let slot = unsafe { AtomicU64::from_ptr((page as usize + TRAMP_SLOT) as *mut u64) };
+        let vector = (page >> 12) as u32;
+
+        let mut parked = 0;
+        let mut wanted = 0;
+        for i in 0..self.cpus.len() {
+            let cpu = self.cpus[i];
+            if cpu.flags & (smp_cpu_flags::BSP | smp_cpu_flags::ONLINE_CAPABLE) != 0 || !self.lapic.can_target(cpu.apic_id) {
+                continue;
+            }
+            wanted += 1;
+            // safe: i indexes a mailbox reserved in prepare
+            let mb = unsafe { &*self.mailboxes.add(i) };
+            slot.store(mb as *const ApMailbox as u64, Ordering::SeqCst);
+
+            self.lapic.send_ipi(cpu.apic_id, ICR_INIT);
+            spin_us(INIT_DELAY_US, tsc_hz);
+            self.lapic.send_ipi(cpu.apic_id, ICR_STARTUP | vector);
+            spin_us(SIPI_DELAY_US, tsc_hz);
+            if mb.state.load(Ordering::Acquire) != ap_state::PARKED {
+                self.lapic.send_ipi(cpu.apic_id, ICR_STARTUP | vector);
+            }
+
+            let mut ok = wait_parked(mb, tsc_hz);
+            // take the slot back; if the AP already claimed it, it is on its way
+            if !ok && slot.swap(0, Ordering::SeqCst) == 0 {
+                ok = wait_parked(mb, tsc_hz);
+            }
+            if ok {
+                self.cpus[i].flags |= smp_cpu_flags::PARKED;
+                self.cpus[i].mailbox = mb as *const ApMailbox as u64;
+                parked += 1;
+            }
+        }
+        slot.store(0, Ordering::SeqCst);
+        // no heap after ExitBootServices; the SMP tag carries the per-CPU result
+        if parked < wanted {
+            log_warn("smp", "Some APs never reached the parking loop");
+        }
+        self.cpus
+    }
+}
+
+fn wait_parked(mb: &ApMailbox, tsc_hz: u64) -> bool {
+    let limit = (PARK_TIMEOUT_US as u128 * tsc_hz as u128 / 1_000_000) as u64;
+    let start = rdtsc();
+    while rdtsc().wrapping_sub(start) < limit {
+        if mb.state.load(Ordering::Acquire) == ap_state::PARKED {
+            return true;
+        }
+        core::hint::spin_loop();
+    }
+    mb.state.load(Ordering::Acquire) == ap_state::PARKED
+}
@@ -0,0 +1,491 @@
+//! Block device inventory handed to the kernel (layout: `nonos_handoff::storage`).
+//!
+//! Every BlockIO instance that is a whole device is listed with its media
+//! ID, geometry, removable/read-only state and the UEFI text form of its
+//! device path. Firmware's per-partition BlockIO children are skipped; the
+//! partitions come from reading each device's GPT instead (`gpt.rs`), which
+//! checks both the primary and the backup copy.
+//!
+//! Paths read through `loader::read_file` may name a GPT partition instead
+//! of the boot volume:
+//!
+//! ```text
+//!   PARTUUID=<unique partition GUID>:\EFI\nonos\kernel.capsule
+//!   PARTLABEL=<partition name>:\EFI\nonos\kernel.capsule
+//! ```
+//!
+//! A label is resolved through a fresh inventory and must name exactly one
+//! partition. The file is then read from the SimpleFileSystem whose device
+//! path ends in a hard-drive node carrying that partition's GUID.
+//!
+//! A NONOS kernel entry may instead point at a raw partition that holds the
+//! capsule itself (see `loader::partition`):
+//!
+//! ```text
+//!   PARTUUID=<unique partition GUID>      that partition
+//!   PARTTYPE=<type GUID>                  every partition of that type, in disk order
+//!   PARTTYPE=nonos                        same, with NONOS_CAPSULE_TYPE
+//! ```
+
+#![allow(dead_code)]
+
+use crate::bytes::{guid_at, le16, le32, le64};
+use crate::gpt::{self, format_guid, parse_guid, partition_name, BlockRead};
+use crate::log::logger::{log_info, log_warn};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use nonos_handoff::storage::{block_flags, gpt_flags, BlockDevice, GptPartition, DEVICE_PATH_LEN, NONOS_CAPSULE_TYPE};
+use uefi::prelude::*;
+use uefi::proto::media::block::BlockIO;
+use uefi::proto::media::fs::SimpleFileSystem;
+use uefi::proto::unsafe_protocol;
+use uefi::table::boot::{AllocateType, BootServices, MemoryType, OpenProtocolAttributes, OpenProtocolParams, ScopedProtocol};
+
+const PAGE_SIZE: usize = 0x1000;
+/// Device path nodes walked before giving up on a missing end node
+const MAX_PATH_NODES: usize = 64;
+
+const PATH_HARDWARE: u8 = 0x01;
+const PATH_ACPI: u8 = 0x02;
+const PATH_MESSAGING: u8 = 0x03;
+const PATH_MEDIA: u8 = 0x04;
+const PATH_END: u8 = 0x7F;
+const HW_PCI: u8 = 0x01;
+const HW_VENDOR: u8 = 0x04;
+const ACPI_DP: u8 = 0x01;
+const MSG_SCSI: u8 = 0x02;
+const MSG_USB: u8 = 0x05;
+const MSG_VENDOR: u8 = 0x0A;
+const MSG_SATA: u8 = 0x12;
+const MSG_NVME: u8 = 0x17;
+const MSG_SD: u8 = 0x1A;
+const MSG_EMMC: u8 = 0x1D;
+const MEDIA_HARD_DRIVE: u8 = 0x01;
+const MEDIA_CDROM: u8 = 0x02;
+/// EISA IDs of PNP0A03 (PCI) and PNP0A08 (PCIe) host bridges
+const EISA_PNP0A03: u32 = 0x0A03_41D0;
+const EISA_PNP0A08: u32 = 0x0A08_41D0;
+const HD_SIGNATURE_GUID: u8 = 2;
+
+/// Generic EFI_DEVICE_PATH_PROTOCOL header; the nodes follow it in memory
+#[repr(C)]
+#[unsafe_protocol("09576e91-6d3f-11d2-8e39-00a0c969723b")]
+pub struct DevicePathNode {
+    ty: u8,
+    sub_type: u8,
+    length: [u8; 2],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageError {
+    BadGuid,
+    NoSuchPartition,
+    /// More than one partition carries the label
+    AmbiguousLabel,
+    /// The partition exists but firmware exposes no filesystem on it
+    NoFileSystem,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            StorageError::BadGuid => "malformed partition GUID",
+            StorageError::NoSuchPartition => "no such partition",
+            StorageError::AmbiguousLabel => "partition label is not unique",
+            StorageError::NoFileSystem => "no filesystem on partition",
+        };
+        f.write_str(s)
+    }
+}
+
+/// Partition named by a path prefix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionRef<'a> {
+    Guid([u8; 16]),
+    Label(&'a str),
+}
+
+/// Split a `PARTUUID=`/`PARTLABEL=` prefix off `path`; paths without one
+/// refer to the boot volume
+pub fn split_partition_path(path: &str) -> Result<(Option<PartitionRef<'_>>, &str), StorageError> {
+    if let Some(rest) = path.strip_prefix("PARTUUID=") {
+        let (guid, file) = rest.split_once(':').ok_or(StorageError::BadGuid)?;
+        return Ok((Some(PartitionRef::Guid(parse_guid(guid).ok_or(StorageError::BadGuid)?)), file));
+    }
+    if let Some(rest) = path.strip_prefix("PARTLABEL=") {
+        let (label, file) = rest.split_once(':').ok_or(StorageError::NoSuchPartition)?;
+        return Ok((Some(PartitionRef::Label(label)), file));
+    }
+    Ok((None, path))
+}
+
+/// Raw partition(s) named by a NONOS entry path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawSource {
+    Unique([u8; 16]),
+    Type([u8; 16]),
+}
+
+/// `Some` if `path` names raw partitions rather than a file
+pub fn parse_raw_source(path: &str) -> Result<Option<RawSource>, StorageError> {
+    if let Some(guid) = path.strip_prefix("PARTUUID=").filter(|g| !g.contains(':')) {
+        return parse_guid(guid).map(|g| Some(RawSource::Unique(g))).ok_or(StorageError::BadGuid);
+    }
+    match path.strip_prefix("PARTTYPE=") {
+        Some("nonos") => Ok(Some(RawSource::Type(NONOS_CAPSULE_TYPE))),
+        Some(guid) => parse_guid(guid).map(|g| Some(RawSource::Type(g))).ok_or(StorageError::BadGuid),
+        None => Ok(None),
+    }
+}
+
+/// Whole block devices and the partitions of their valid GPTs
+#[derive(Debug, Clone, Default)]
+pub struct Inventory {
+    pub devices: Vec<BlockDevice>,
+    /// Firmware handle of each entry in `devices`
+    pub handles: Vec<Handle>,
+    pub partitions: Vec<GptPartition>,
+}
+
+impl Inventory {
+    /// Partitions matching `src`, in disk order
+    pub fn raw_candidates<'a>(&'a self, src: &'a RawSource) -> impl Iterator<Item = &'a GptPartition> + 'a {
+        self.partitions.iter().filter(move |p| match src {
+            RawSource::Unique(g) => p.unique_guid == *g,
+            RawSource::Type(g) => p.type_guid == *g,
+        })
+    }
+
+    pub fn find(&self, r: &PartitionRef) -> Result<&GptPartition, StorageError> {
+        let mut hits = self.partitions.iter().filter(|p| match r {
+            PartitionRef::Guid(g) => p.unique_guid == *g,
+            PartitionRef::Label(l) => partition_name(p) == *l,
+        });
+        let first = hits.next().ok_or(StorageError::NoSuchPartition)?;
+        match hits.next() {
+            Some(_) => Err(StorageError::AmbiguousLabel),
+            None => Ok(first),
+        }
+    }
+}
+
+/// A whole device's BlockIO. Reads into buffers that miss IoAlign go
+/// through a page-aligned bounce buffer.
+pub struct Media<'a> {
+    bs: &'a BootServices,
+    bio: ScopedProtocol<'a, BlockIO>,
+    media_id: u32,
+    block_size: usize,
+    last_block: u64,
+    io_align: usize,
+}
+
+impl<'a> Media<'a> {
+    pub fn open(bs: &'a BootServices, handle: Handle) -> Option<Self> {
+        let params = OpenProtocolParams { handle, agent: bs.image_handle(), controller: None };
+        // safe: GetProtocol leaves the disk and partition drivers bound; we only read blocks
+        let bio = unsafe { bs.open_protocol::<BlockIO>(params, OpenProtocolAttributes::GetProtocol) }.ok()?;
+        let media = bio.media();
+        let (media_id, block_size, last_block) = (media.media_id(), media.block_size() as usize, media.last_block());
+        let io_align = (media.io_align() as usize).max(1);
+        Some(Media { bs, bio, media_id, block_size, last_block, io_align })
+    }
+}
+
+impl BlockRead for Media<'_> {
+    fn block_size(&self) -> usize {
+        self.block_size
+    }
+
+    fn last_block(&self) -> u64 {
+        self.last_block
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> bool {
+        if buf.as_ptr() as usize % self.io_align == 0 {
+            return self.bio.read_blocks(self.media_id, lba, buf).is_ok();
+        }
+        let pages = buf.len().div_ceil(PAGE_SIZE);
+        let Ok(addr) = self.bs.allocate_pages(AllocateType::AnyPages, MemoryType::LOADER_DATA, pages) else {
+            return false;
+        };
+        // safe: just allocated, buf.len() bytes fit in `pages` pages
+        let bounce = unsafe { core::slice::from_raw_parts_mut(addr as *mut u8, buf.len()) };
+        let ok = self.bio.read_blocks(self.media_id, lba, bounce).is_ok();
+        if ok {
+            buf.copy_from_slice(bounce);
+        }
+        let _ = self.bs.free_pages(addr, pages);
+        ok
+    }
+}
+
+/// Raw device path bytes of `handle`, end node included; empty if it has none
+fn device_path_bytes(bs: &BootServices, handle: Handle) -> Vec<u8> {
+    let params = OpenProtocolParams { handle, agent: bs.image_handle(), controller: None };
+    // safe: GetProtocol only reads the path; the handle's drivers keep ownership
+    let Ok(dp) = (unsafe { bs.open_protocol::<DevicePathNode>(params, OpenProtocolAttributes::GetProtocol) }) else {
+        return Vec::new();
+    };
+    let start = &*dp as *const DevicePathNode as *const u8;
+    let mut len = 0usize;
+    for _ in 0..MAX_PATH_NODES {
+        // safe: every node starts with a 4-byte header; `len` only advances by node lengths
+        let (ty, node_len) = unsafe { (*start.add(len), u16::from_le_bytes([*start.add(len + 2), *start.add(len + 3)])) };
+        if node_len < 4 {
+            return Vec::new();
+        }
+        len += node_len as usize;
+        if ty == PATH_END {
+            // safe: the walk above covered exactly these bytes
+            return unsafe { core::slice::from_raw_parts(start, len) }.to_vec();
+        }
+    }
+    Vec::new()
+}
+
+/// Device path nodes as (type, subtype, node bytes), stopping at the first end node
+fn nodes(path: &[u8]) -> impl Iterator<Item = (u8, u8, &[u8])> {
+    let mut rest = path;
+    core::iter::from_fn(move || {
+        if rest.len() < 4 || rest[0] == PATH_END {
+            return None;
+        }
+        let len = u16::from_le_bytes([rest[2], rest[3]]) as usize;
+        if len < 4 || len > rest.len() {
+            return None;
+        }
+        let (node, tail) = rest.split_at(len);
+        rest = tail;
+        Some((node[0], node[1], node))
+    })
+}
+
+/// UEFI text form (spec §10.6) of the node kinds found on storage paths;
+/// anything else is written as `Path(type,subtype)`
+pub fn device_path_text(path: &[u8]) -> String {
+    let mut s = String::new();
+    for (ty, sub, n) in nodes(path) {
+        if !s.is_empty() {
+            s.push('/');
+        }
+        let _ = match (ty, sub, n.len()) {
+            (PATH_ACPI, ACPI_DP, 12..) => match le32(n, 4) {
+                EISA_PNP0A03 => write!(s, "PciRoot({:#x})", le32(n, 8)),
+                EISA_PNP0A08 => write!(s, "PcieRoot({:#x})", le32(n, 8)),
+                hid => write!(s, "Acpi({:#x},{:#x})", hid, le32(n, 8)),
+            },
+            (PATH_HARDWARE, HW_PCI, 6..) => write!(s, "Pci({:#x},{:#x})", n[5], n[4]),
+            (PATH_HARDWARE, HW_VENDOR, 20..) => write!(s, "VenHw({})", format_guid(&guid_at(n, 4))),
+            (PATH_MESSAGING, MSG_SCSI, 8..) => write!(s, "Scsi({:#x},{:#x})", le16(n, 4), le16(n, 6)),
+            (PATH_MESSAGING, MSG_USB, 6..) => write!(s, "USB({:#x},{:#x})", n[4], n[5]),
+            (PATH_MESSAGING, MSG_VENDOR, 20..) => write!(s, "VenMsg({})", format_guid(&guid_at(n, 4))),
+            (PATH_MESSAGING, MSG_SATA, 10..) => write!(s, "Sata({:#x},{:#x},{:#x})", le16(n, 4), le16(n, 6), le16(n, 8)),
+            (PATH_MESSAGING, MSG_NVME, 8..) => write!(s, "NVMe({:#x})", le32(n, 4)),
+            (PATH_MESSAGING, MSG_SD, 5..) => write!(s, "SD({:#x})", n[4]),
+            (PATH_MESSAGING, MSG_EMMC, 5..) => write!(s, "eMMC({:#x})", n[4]),
+            (PATH_MEDIA, MEDIA_HARD_DRIVE, 42..) => {
+                let (number, start, size) = (le32(n, 4), le64(n, 8), le64(n, 16));
+                match n[41] {
+                    HD_SIGNATURE_GUID => write!(s, "HD({},GPT,{},{:#x},{:#x})", number, format_guid(&guid_at(n, 24)), start, size),
+                    _ => write!(s, "HD({},MBR,{:#x},{:#x},{:#x})", number, le32(n, 24), start, size),
+                }
+            }
+            (PATH_MEDIA, MEDIA_CDROM, 8..) => write!(s, "CDROM({:#x})", le32(n, 4)),
+            _ => write!(s, "Path({},{})", ty, sub),
+        };
+    }
+    s
+}
+
+/// GPT unique GUID of the last hard-drive node on `path`
+pub fn gpt_signature(path: &[u8]) -> Option<[u8; 16]> {
+    nodes(path)
+        .filter(|&(ty, sub, n)| ty == PATH_MEDIA && sub == MEDIA_HARD_DRIVE && n.len() >= 42 && n[41] == HD_SIGNATURE_GUID)
+        .last()
+        .map(|(_, _, n)| guid_at(n, 24))
+}
+
+/// Copy ASCII text into a NUL-padded field; false if it had to be cut
+fn fill_text(dst: &mut [u8; DEVICE_PATH_LEN], text: &str) -> bool {
+    let n = text.len().min(DEVICE_PATH_LEN - 1);
+    dst[..n].copy_from_slice(&text.as_bytes()[..n]);
+    n == text.len()
+}
+
+/// One device: media state, device path and, if media is present, its GPT
+fn describe(bs: &BootServices, handle: Handle, disk: &mut Media, index: u32, partitions: &mut Vec<GptPartition>) -> BlockDevice {
+    let media = disk.bio.media();
+    let mut dev = BlockDevice {
+        media_id: media.media_id(),
+        block_size: media.block_size(),
+        last_block: media.last_block(),
+        ..Default::default()
+    };
+    for (set, bit) in [
+        (media.is_removable_media(), block_flags::REMOVABLE),
+        (media.is_media_present(), block_flags::MEDIA_PRESENT),
+        (media.is_read_only(), block_flags::READ_ONLY),
+    ] {
+        if set {
+            dev.flags |= bit;
+        }
+    }
+    if !fill_text(&mut dev.device_path, &device_path_text(&device_path_bytes(bs, handle))) {
+        dev.flags |= block_flags::PATH_TRUNCATED;
+    }
+    if !media.is_media_present() || dev.block_size == 0 {
+        return dev;
+    }
+
+    let Some(table) = gpt::read_gpt(disk) else { return dev };
+    let path = core::str::from_utf8(&dev.device_path).unwrap_or("").trim_end_matches('\0');
+    for (copy, result, bit) in [("primary", &table.primary, gpt_flags::PRIMARY_OK), ("backup", &table.backup, gpt_flags::BACKUP_OK)] {
+        match result {
+            Ok(_) => dev.gpt_flags |= bit,
+            Err(e) => log_warn("storage", &format!("{}: {} GPT rejected: {}", path, copy, e)),
+        }
+    }
+    if table.mismatch {
+        dev.gpt_flags |= gpt_flags::MISMATCH;
+        log_warn("storage", &format!("{}: primary and backup GPT differ; using the primary", path));
+    }
+    if table.protective_mbr {
+        dev.gpt_flags |= gpt_flags::PROTECTIVE_MBR;
+    }
+    if let Some(h) = table.header() {
+        dev.disk_guid = h.disk_guid;
+    }
+    partitions.extend(table.partitions.into_iter().map(|p| GptPartition { device: index, ..p }));
+    dev
+}
+
+/// Every whole block device and its GPT partitions
+pub fn enumerate(bs: &BootServices) -> Inventory {
+    let mut inv = Inventory::default();
+    let handles = bs.find_handles::<BlockIO>().unwrap_or_default();
+    for &handle in handles.iter() {
+        let Some(mut disk) = Media::open(bs, handle) else { continue };
+        if disk.bio.media().is_logical_partition() {
+            continue;
+        }
+        let dev = describe(bs, handle, &mut disk, inv.devices.len() as u32, &mut inv.partitions);
+        inv.devices.push(dev);
+        inv.handles.push(handle);
+    }
+    inv
+}
+
+/// SimpleFileSystem handle of the partition named by `r`
+pub fn resolve_volume(bs: &BootServices, r: &PartitionRef) -> Result<Handle, StorageError> {
+    let guid = match r {
+        PartitionRef::Guid(g) => *g,
+        PartitionRef::Label(_) => enumerate(bs).find(r)?.unique_guid,
+    };
+    let handles = bs.find_handles::<SimpleFileSystem>().unwrap_or_default();
+    let found = handles.iter().copied().find(|&h| gpt_signature(&device_path_bytes(bs, h)) == Some(guid));
+    match (found, r) {
+        (Some(h), _) => Ok(h),
+        (None, PartitionRef::Label(_)) => Err(StorageError::NoFileSystem),
+        (None, PartitionRef::Guid(_)) => match enumerate(bs).find(r) {
+            Ok(_) => Err(StorageError::NoFileSystem),
+            Err(e) => Err(e),
+        },
+    }
+}
+
+pub fn log_inventory(inv: &Inventory) {
+    for (i, d) in inv.devices.iter().enumerate() {
+        let path = core::str::from_utf8(&d.device_path).unwrap_or("").trim_end_matches('\0');
+        let removable = if d.flags & block_flags::REMOVABLE != 0 { " removable" } else { "" };
+        let gpt = if d.has_gpt() { format!(" gpt {}", format_guid(&d.disk_guid)) } else { String::new() };
+        log_info("storage", &format!("disk{} {} MiB ({} B blocks, media {}){}{} {}", i, d.size() >> 20, d.block_size, d.media_id, removable, gpt, path));
+    }
+    for p in &inv.partitions {
+        log_info(
+            "storage",
+            &format!("disk{}p{} '{}' {} type {} LBA {}..={}", p.device, p.number, partition_name(p), format_guid(&p.unique_guid), format_guid(&p.type_guid), p.first_lba, p.last_lba),
+        );
+    }
+    log_info("storage", &format!("Storage devices: {}, GPT partitions: {}", inv.devices.len(), inv.partitions.len()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    fn node(ty: u8, sub: u8, data: &[u8]) -> Vec<u8> {
+        let mut n = vec![ty, sub];
+        n.extend_from_slice(&((data.len() + 4) as u16).to_le_bytes());
+        n.extend_from_slice(data);
+        n
+    }
+
+    /// PciRoot(0)/Pci(0x1f,0x2)/Sata(0,0xffff,0)/HD(1,GPT,...)
+    fn sata_partition(guid: [u8; 16]) -> Vec<u8> {
+        let mut acpi = EISA_PNP0A03.to_le_bytes().to_vec();
+        acpi.extend_from_slice(&0u32.to_le_bytes());
+        let mut hd = 1u32.to_le_bytes().to_vec();
+        hd.extend_from_slice(&0x800u64.to_le_bytes());
+        hd.extend_from_slice(&0x1000u64.to_le_bytes());
+        hd.extend_from_slice(&guid);
+        hd.extend_from_slice(&[2, HD_SIGNATURE_GUID]);
+        let mut p = node(PATH_ACPI, ACPI_DP, &acpi);
+        p.extend(node(PATH_HARDWARE, HW_PCI, &[2, 0x1F]));
+        p.extend(node(PATH_MESSAGING, MSG_SATA, &[0, 0, 0xFF, 0xFF, 0, 0]));
+        p.extend(node(PATH_MEDIA, MEDIA_HARD_DRIVE, &hd));
+        p.extend(node(PATH_END, 0xFF, &[]));
+        p
+    }
+
+    #[test]
+    fn device_path_text_and_signature() {
+        let guid = parse_guid("0fc63daf-8483-4772-8e79-3d69d8477de4").unwrap();
+        let path = sata_partition(guid);
+        assert_eq!(
+            device_path_text(&path),
+            "PciRoot(0x0)/Pci(0x1f,0x2)/Sata(0x0,0xffff,0x0)/HD(1,GPT,0fc63daf-8483-4772-8e79-3d69d8477de4,0x800,0x1000)"
+        );
+        assert_eq!(gpt_signature(&path), Some(guid));
+        // the whole disk stops before the HD node
+        let disk = &path[..path.len() - 46];
+        assert_eq!(gpt_signature(disk), None);
+        assert_eq!(device_path_text(&node(0x05, 0x01, &[0; 8])), "Path(5,1)");
+        // a zero-length node ends the walk instead of looping
+        assert_eq!(device_path_text(&[PATH_MEDIA, MEDIA_CDROM, 0, 0, 1, 2, 3, 4]), "");
+    }
+
+    #[test]
+    fn partition_prefixes() {
+        let guid = "0fc63daf-8483-4772-8e79-3d69d8477de4";
+        let path = format!("PARTUUID={}:\\EFI\\nonos\\k.capsule", guid);
+        let (r, file) = split_partition_path(&path).unwrap();
+        assert_eq!(r, Some(PartitionRef::Guid(parse_guid(guid).unwrap())));
+        assert_eq!(file, "\\EFI\\nonos\\k.capsule");
+        assert_eq!(split_partition_path("PARTLABEL=nonos-a:/k"), Ok((Some(PartitionRef::Label("nonos-a")), "/k")));
+        assert_eq!(split_partition_path("\\EFI\\x"), Ok((None, "\\EFI\\x")));
+        assert_eq!(split_partition_path("PARTUUID=nope:/k"), Err(StorageError::BadGuid));
+
+        assert_eq!(parse_raw_source(&path), Ok(None));
+        assert_eq!(parse_raw_source(&format!("PARTUUID={}", guid)), Ok(Some(RawSource::Unique(parse_guid(guid).unwrap()))));
+        assert_eq!(parse_raw_source("PARTTYPE=nonos"), Ok(Some(RawSource::Type(NONOS_CAPSULE_TYPE))));
+        assert_eq!(parse_raw_source("PARTTYPE=x"), Err(StorageError::BadGuid));
+        assert_eq!(format_guid(&NONOS_CAPSULE_TYPE), "c4b5b386-9f19-4b03-85b8-67e41a7a13cc");
+
+        let mut inv = Inventory::default();
+        for (i, label) in ["nonos-a", "nonos-b", "nonos-b"].iter().enumerate() {
+            let mut p = GptPartition { number: i as u32 + 1, unique_guid: [i as u8 + 1; 16], ..Default::default() };
+            for (d, c) in p.name.iter_mut().zip(label.encode_utf16()) {
+                *d = c;
+            }
+            inv.partitions.push(p);
+        }
+        assert_eq!(inv.find(&PartitionRef::Label("nonos-a")).map(|p| p.number), Ok(1));
+        assert_eq!(inv.find(&PartitionRef::Label("nonos-b")), Err(StorageError::AmbiguousLabel));
+        assert_eq!(inv.find(&PartitionRef::Guid([3; 16])).map(|p| p.number), Ok(3));
+        assert_eq!(inv.find(&PartitionRef::Label("nonos-c")), Err(StorageError::NoSuchPartition));
+    }
+}
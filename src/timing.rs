@@ -0,0 +1,410 @@
+//! Time sources for the bootloader: TSC reads, TSC frequency and wall clock.
+//!
+//! `calibrate` runs once at entry and every later consumer (phase timings,
+//! the log ring, AP start-up delays, menu timeouts, `now_us`) uses its
+//! result. It tries, in order: CPUID 15h, CPUID 15h with the 16h base
+//! frequency, a measurement against the ACPI PM timer or the HPET, and
+//! finally firmware Stall(). The method and its error bound go to the kernel
+//! in `tag::TSC`.
+
+#![allow(dead_code)]
+
+use crate::acpi::{self, Fadt, Hpet, GAS_IO, GAS_MEMORY};
+use crate::hardware::{cpuid, discover_acpi_rsdp};
+use crate::log::logger::{log_info, log_warn};
+use alloc::format;
+use core::sync::atomic::{AtomicU64, Ordering};
+use nonos_handoff::tags::{boot_phase, PhaseTiming};
+pub use nonos_handoff::tags::{tsc_flags, tsc_method, TscCalibration};
+use uefi::prelude::*;
+use uefi::table::boot::BootServices;
+use uefi::table::runtime::{RuntimeServices, VariableAttributes, VariableVendor};
+use uefi::{cstr16, CStr16, Guid};
+
+/// Stall window used to measure the TSC against firmware Stall()
+const CALIBRATION_STALL_US: usize = 10_000;
+/// Stall() only promises a minimum delay; this is a guess, not a bound
+const STALL_ERROR_PPM: u32 = 10_000;
+/// Window for measuring the TSC against the PM timer or HPET
+const REFERENCE_WINDOW_US: u64 = 20_000;
+/// TSC ticks after which a reference counter that stopped moving is given up (about 2 s at 4 GHz)
+const REFERENCE_SPIN_LIMIT: u64 = 1 << 33;
+
+const PM_TIMER_HZ: u64 = 3_579_545;
+const HPET_CAPABILITIES: u64 = 0x00;
+const HPET_CONFIG: u64 = 0x10;
+const HPET_COUNTER: u64 = 0xF0;
+const HPET_ENABLE: u64 = 1 << 0;
+/// Longest legal HPET period, 100 ns in femtoseconds
+const HPET_MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Serialized TSC read
+#[inline(always)]
+pub fn rdtsc() -> u64 {
+    let lo: u32;
+    let hi: u32;
+    // safe: rdtsc has no memory effects; lfence orders it against earlier loads
+    unsafe {
+        core::arch::asm!("lfence", "rdtsc", out("eax") lo, out("edx") hi, options(nomem, nostack, preserves_flags));
+    }
+    ((hi as u64) << 32) | lo as u64
+}
+
+/// Last calibrated TSC frequency, 0 until a calibration ran
+static TSC_HZ: AtomicU64 = AtomicU64::new(0);
+
+/// Measure the TSC frequency in Hz over a firmware Stall() window.
+pub fn calibrate_tsc_hz(bs: &BootServices) -> u64 {
+    let start = rdtsc();
+    bs.stall(CALIBRATION_STALL_US);
+    let delta = rdtsc().wrapping_sub(start);
+    let hz = delta * (1_000_000 / CALIBRATION_STALL_US as u64);
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    hz
+}
+
+/// Find the TSC frequency by the best method this machine offers and make it
+/// the loader's clock.
+pub fn calibrate(st: &mut SystemTable<Boot>) -> TscCalibration {
+    let (max_leaf, ..) = cpuid(0, 0);
+    let (max_ext, ..) = cpuid(0x8000_0000, 0);
+    let invariant = max_ext >= 0x8000_0007 && cpuid(0x8000_0007, 0).3 & (1 << 8) != 0;
+
+    let leaf15 = if max_leaf >= 0x15 { cpuid(0x15, 0) } else { (0, 0, 0, 0) };
+    let base_mhz = if max_leaf >= 0x16 { cpuid(0x16, 0).0 & 0xFFFF } else { 0 };
+    let found = cpuid_frequency((leaf15.0, leaf15.1, leaf15.2), base_mhz).or_else(|| {
+        let rsdp = discover_acpi_rsdp(st)?;
+        let fadt = acpi::find_table(rsdp, b"FACP").and_then(acpi::parse_fadt);
+        let hpet = acpi::find_table(rsdp, b"HPET").and_then(acpi::parse_hpet);
+        fadt.as_ref().and_then(Reference::pm_timer).or_else(|| hpet.as_ref().and_then(Reference::hpet)).and_then(|r| r.measure())
+    });
+    let (hz, method, error_ppm) = match found {
+        Some(f) if f.0 != 0 => f,
+        _ => (calibrate_tsc_hz(st.boot_services()), tsc_method::STALL, STALL_ERROR_PPM),
+    };
+    let cal = TscCalibration {
+        hz,
+        method: if hz != 0 { method } else { tsc_method::NONE },
+        error_ppm,
+        flags: if invariant { tsc_flags::INVARIANT } else { 0 },
+        reserved: 0,
+    };
+    TSC_HZ.store(hz, Ordering::Relaxed);
+    log_info("timing", &format!(
+        "TSC {}.{:06} MHz via {} (+/-{} ppm){}",
+        hz / 1_000_000, hz % 1_000_000, tsc_method::name(cal.method), error_ppm,
+        if invariant { ", invariant" } else { "" }
+    ));
+    if !invariant {
+        log_warn("timing", "TSC is not invariant; its rate may follow CPU frequency changes");
+    }
+    cal
+}
+
+/// TSC frequency, method and error from CPUID 15h, taking the crystal from
+/// the 16h base frequency (whole MHz) when 15h leaves it out
+fn cpuid_frequency(leaf15: (u32, u32, u32), base_mhz: u32) -> Option<(u64, u32, u32)> {
+    let (denominator, numerator, crystal_hz) = leaf15;
+    if denominator == 0 || numerator == 0 {
+        return None;
+    }
+    if crystal_hz != 0 {
+        return Some((crystal_hz as u64 * numerator as u64 / denominator as u64, tsc_method::CPUID_15H, 0));
+    }
+    // crystal = base * denominator / numerator, so the TSC runs at exactly the base frequency
+    (base_mhz != 0).then(|| (base_mhz as u64 * 1_000_000, tsc_method::CPUID_16H, 500_000 / base_mhz))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Counter {
+    Port(u16),
+    Mmio32(u64),
+    Mmio64(u64),
+}
+
+/// A free-running counter of known frequency to measure the TSC against
+#[derive(Debug, Clone, Copy)]
+struct Reference {
+    counter: Counter,
+    mask: u64,
+    hz: u64,
+    method: u32,
+}
+
+impl Reference {
+    fn pm_timer(fadt: &Fadt) -> Option<Self> {
+        let g = fadt.pm_timer?;
+        let counter = match g.space_id {
+            GAS_IO => Counter::Port(u16::try_from(g.address).ok()?),
+            GAS_MEMORY => Counter::Mmio32(g.address),
+            _ => return None,
+        };
+        let mask = if fadt.pm_timer_32bit { 0xFFFF_FFFF } else { 0xFF_FFFF };
+        Some(Reference { counter, mask, hz: PM_TIMER_HZ, method: tsc_method::PM_TIMER })
+    }
+
+    /// Only a running HPET is used; enabling it is left to the kernel
+    fn hpet(hpet: &Hpet) -> Option<Self> {
+        if hpet.base.space_id != GAS_MEMORY {
+            return None;
+        }
+        let base = hpet.base.address;
+        // safe: the HPET table points at the block's MMIO registers, identity mapped by firmware
+        let (caps, config) = unsafe {
+            (
+                core::ptr::read_volatile((base + HPET_CAPABILITIES) as *const u64),
+                core::ptr::read_volatile((base + HPET_CONFIG) as *const u64),
+            )
+        };
+        let period_fs = caps >> 32;
+        if period_fs == 0 || period_fs > HPET_MAX_PERIOD_FS || config & HPET_ENABLE == 0 {
+            return None;
+        }
+        let (counter, mask) = if hpet.counter_64bit {
+            (Counter::Mmio64(base + HPET_COUNTER), u64::MAX)
+        } else {
+            (Counter::Mmio32(base + HPET_COUNTER), 0xFFFF_FFFF)
+        };
+        Some(Reference { counter, mask, hz: 1_000_000_000_000_000 / period_fs, method: tsc_method::HPET })
+    }
+
+    fn read(&self) -> u64 {
+        // safe: port and MMIO addresses come from the FADT/HPET tables; counter reads have no side effects
+        let v = unsafe {
+            match self.counter {
+                Counter::Port(port) => {
+                    let v: u32;
+                    core::arch::asm!("in eax, dx", in("dx") port, out("eax") v, options(nomem, nostack, preserves_flags));
+                    v as u64
+                }
+                Counter::Mmio32(addr) => core::ptr::read_volatile(addr as *const u32) as u64,
+                Counter::Mmio64(addr) => core::ptr::read_volatile(addr as *const u64),
+            }
+        };
+        v & self.mask
+    }
+
+    /// Spin until the counter is `ticks` past `start`. Returns its value and
+    /// the TSC interval it got there in: from just before the last read that
+    /// had not, to just after the read that had.
+    fn wait(&self, start: u64, ticks: u64) -> Option<(u64, u64, u64)> {
+        let origin = rdtsc();
+        let mut before_prev = origin;
+        loop {
+            let before = rdtsc();
+            let v = self.read();
+            let after = rdtsc();
+            if v.wrapping_sub(start) & self.mask >= ticks {
+                return Some((v, before_prev, after));
+            }
+            if after.wrapping_sub(origin) > REFERENCE_SPIN_LIMIT {
+                return None;
+            }
+            before_prev = before;
+        }
+    }
+
+    /// TSC frequency over `REFERENCE_WINDOW_US`, the method and its error in ppm
+    fn measure(&self) -> Option<(u64, u32, u32)> {
+        // start on a counter edge so the window is a whole number of ticks
+        let (c0, lo0, hi0) = self.wait(self.read(), 1)?;
+        let (c1, lo1, hi1) = self.wait(c0, self.hz * REFERENCE_WINDOW_US / 1_000_000)?;
+        let ref_ticks = c1.wrapping_sub(c0) & self.mask;
+        let tsc_ticks = (lo1 / 2 + hi1 / 2).checked_sub(lo0 / 2 + hi0 / 2).filter(|&t| t != 0)?;
+        let hz = (tsc_ticks as u128 * self.hz as u128 / ref_ticks as u128) as u64;
+        // each edge is only known to within half its interval
+        let slack = (hi0 - lo0) / 2 + (hi1 - lo1) / 2;
+        let error_ppm = (slack as u128 * 1_000_000 / tsc_ticks as u128).min(u32::MAX as u128) as u32;
+        Some((hz, self.method, error_ppm.max(1)))
+    }
+}
+
+/// Frequency from the last calibration, 0 if none ran yet
+pub fn tsc_hz() -> u64 {
+    TSC_HZ.load(Ordering::Relaxed)
+}
+
+/// TSC ticks to microseconds, 0 when `hz` is unknown
+pub fn ticks_to_us(ticks: u64, hz: u64) -> u64 {
+    (ticks as u128 * 1_000_000).checked_div(hz as u128).unwrap_or(0) as u64
+}
+
+/// Microseconds since the TSC started counting (roughly CPU reset)
+pub fn now_us() -> u64 {
+    ticks_to_us(rdtsc(), tsc_hz())
+}
+
+/// Busy-wait on the TSC; works after ExitBootServices, returns at once when `hz` is 0
+pub fn spin_us(us: u64, hz: u64) {
+    let ticks = (us as u128 * hz as u128 / 1_000_000) as u64;
+    let start = rdtsc();
+    while rdtsc().wrapping_sub(start) < ticks {
+        core::hint::spin_loop();
+    }
+}
+
+/// A point `us` microseconds ahead on the calibrated TSC, for timeouts that
+/// poll something else (keyboard, devices) while they wait
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    start: u64,
+    ticks: u64,
+}
+
+impl Deadline {
+    /// Already expired when no calibration ran
+    pub fn after_us(us: u64) -> Self {
+        Deadline { start: rdtsc(), ticks: (us as u128 * tsc_hz() as u128 / 1_000_000) as u64 }
+    }
+
+    pub fn expired(&self) -> bool {
+        rdtsc().wrapping_sub(self.start) >= self.ticks
+    }
+}
+
+/// TSC marks for each `boot_phase`, exported as the TIMING handoff tag.
+#[derive(Debug, Clone, Copy)]
+pub struct BootTimeline {
+    /// TSC frequency and how it was obtained, handed over as `tag::TSC`
+    pub tsc: TscCalibration,
+    phases: [PhaseTiming; boot_phase::COUNT],
+}
+
+impl BootTimeline {
+    /// `entry_tsc` is read first thing in efi_main, before any firmware call
+    pub fn new(entry_tsc: u64, tsc: TscCalibration) -> Self {
+        let mut phases = [PhaseTiming::default(); boot_phase::COUNT];
+        for (i, p) in phases.iter_mut().enumerate() {
+            p.phase = i as u32;
+        }
+        phases[boot_phase::LOADER as usize].start_tsc = entry_tsc;
+        BootTimeline { tsc, phases }
+    }
+
+    pub fn begin(&mut self, phase: u32) {
+        if let Some(p) = self.phases.get_mut(phase as usize) {
+            p.start_tsc = rdtsc();
+            p.end_tsc = 0;
+        }
+    }
+
+    pub fn end(&mut self, phase: u32) {
+        if let Some(p) = self.phases.get_mut(phase as usize) {
+            p.end_tsc = rdtsc();
+        }
+    }
+
+    pub fn entry_tsc(&self) -> u64 {
+        self.phases[boot_phase::LOADER as usize].start_tsc
+    }
+
+    /// Phases with both marks, `LOADER` first once it has ended
+    pub fn completed(&self) -> impl Iterator<Item = &PhaseTiming> {
+        self.phases.iter().filter(|p| p.start_tsc != 0 && p.end_tsc >= p.start_tsc)
+    }
+
+    /// Copy completed phases into `out`; returns how many were written
+    pub fn export(&self, out: &mut [PhaseTiming]) -> usize {
+        let mut n = 0;
+        for (slot, p) in out.iter_mut().zip(self.completed()) {
+            *slot = *p;
+            n += 1;
+        }
+        n
+    }
+
+    pub fn us(&self, p: &PhaseTiming) -> u64 {
+        p.micros(self.tsc.hz)
+    }
+}
+
+/// systemd boot loader interface vendor GUID (LoaderTimeInitUSec and friends)
+const LOADER_VENDOR: Guid = uefi::guid!("4a67b082-0a4c-41cf-b6c7-440b29bb8c4f");
+
+pub const LOADER_TIME_INIT: &CStr16 = cstr16!("LoaderTimeInitUSec");
+pub const LOADER_TIME_EXEC: &CStr16 = cstr16!("LoaderTimeExecUSec");
+
+/// Publish a systemd-style timestamp variable: decimal microseconds, UTF-16, NUL terminated.
+pub fn set_loader_time_var(rt: &RuntimeServices, name: &CStr16, usec: u64) -> bool {
+    let mut digits = [0u8; 20];
+    let mut n = 0;
+    let mut v = usec;
+    loop {
+        digits[n] = b'0' + (v % 10) as u8;
+        n += 1;
+        v /= 10;
+        if v == 0 {
+            break;
+        }
+    }
+    let mut buf = [0u8; 42];
+    for (i, d) in digits[..n].iter().rev().enumerate() {
+        buf[i * 2] = *d;
+    }
+    let attrs = VariableAttributes::BOOTSERVICE_ACCESS | VariableAttributes::RUNTIME_ACCESS;
+    rt.set_variable(name, &VariableVendor(LOADER_VENDOR), attrs, &buf[..(n + 1) * 2]).is_ok()
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Milliseconds since the Unix epoch from the firmware RTC (UTC adjusted), 0 if unavailable.
+pub fn unix_epoch_ms(rt: &RuntimeServices) -> u64 {
+    let Ok(t) = rt.get_time() else { return 0 };
+    let days = days_from_civil(t.year() as i64, t.month() as u32, t.day() as u32);
+    let mut secs = days * 86_400 + t.hour() as i64 * 3600 + t.minute() as i64 * 60 + t.second() as i64;
+    // time_zone is minutes *from* UTC to local (UEFI: local = UTC - time_zone)
+    if let Some(tz) = t.time_zone() {
+        secs += tz as i64 * 60;
+    }
+    if secs < 0 {
+        return 0;
+    }
+    secs as u64 * 1000 + (t.nanosecond() / 1_000_000) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11_017);
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+    }
+
+    #[test]
+    fn cpuid_leaves() {
+        // 24 MHz crystal, TSC = crystal * 188 / 2
+        assert_eq!(cpuid_frequency((2, 188, 24_000_000), 0), Some((2_256_000_000, tsc_method::CPUID_15H, 0)));
+        assert_eq!(cpuid_frequency((2, 188, 0), 2_300), Some((2_300_000_000, tsc_method::CPUID_16H, 217)));
+        assert_eq!(cpuid_frequency((2, 188, 0), 0), None);
+        assert_eq!(cpuid_frequency((0, 0, 24_000_000), 2_300), None);
+    }
+
+    #[test]
+    fn timeline_exports_only_completed_phases() {
+        let mut t = BootTimeline::new(1, TscCalibration { hz: 1_000_000, ..Default::default() });
+        t.begin(boot_phase::CONFIG);
+        t.end(boot_phase::CONFIG);
+        t.begin(boot_phase::LOAD);
+        let mut out = [PhaseTiming::default(); boot_phase::COUNT];
+        assert_eq!(t.export(&mut out), 1);
+        assert_eq!(out[0].phase, boot_phase::CONFIG);
+
+        t.end(boot_phase::LOADER);
+        assert_eq!(t.export(&mut out), 2);
+        assert_eq!(out[0].phase, boot_phase::LOADER);
+        assert_eq!(ticks_to_us(3_000, 1_000_000), 3_000);
+        assert_eq!(ticks_to_us(3_000, 0), 0);
+    }
+}